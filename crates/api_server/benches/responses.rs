@@ -2,6 +2,7 @@ use std::str::FromStr;
 
 use api_server::db::models::{SurveyPatch, SurveyQuestions};
 use api_server::questions::{Choice, QMultipleChoice, QRating, QText, SurveyQuestion};
+use api_server::survey::SurveyRef;
 use api_server::test_helpers::*;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use pprof::criterion::{Output, PProfProfiler};
@@ -60,7 +61,7 @@ fn survey_text_questions(c: &mut Criterion) {
             let resp = client
                 .post(uri!(
                     "/api",
-                    api_server::survey_response::create_survey_response(survey_id)
+                    api_server::survey_response::create_survey_response(survey_id, _)
                 ))
                 .header(rocket::http::ContentType::JSON)
                 .body(
@@ -123,7 +124,7 @@ fn survey_rating_questions(c: &mut Criterion) {
             let resp = client
                 .post(uri!(
                     "/api",
-                    api_server::survey_response::create_survey_response(survey_id)
+                    api_server::survey_response::create_survey_response(survey_id, _)
                 ))
                 .header(rocket::http::ContentType::JSON)
                 .body(
@@ -237,7 +238,7 @@ fn survey_response_stress(c: &mut Criterion) {
             let resp = client
                 .post(uri!(
                     "/api",
-                    api_server::survey_response::create_survey_response(survey_id)
+                    api_server::survey_response::create_survey_response(survey_id, _)
                 ))
                 .header(rocket::http::ContentType::JSON)
                 .body(
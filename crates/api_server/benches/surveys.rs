@@ -1,5 +1,6 @@
 use api_server::db::models::SurveyPatch;
 use api_server::questions::{Choice, QMultipleChoice, QRating, QText, SurveyQuestion};
+use api_server::survey::SurveyRef;
 use api_server::test_helpers::*;
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use pprof::criterion::{Output, PProfProfiler};
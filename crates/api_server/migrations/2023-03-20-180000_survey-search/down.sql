@@ -0,0 +1,3 @@
+DROP INDEX surveys_owner_id_idx;
+DROP INDEX surveys_search_idx;
+DROP FUNCTION survey_search_document(TEXT, TEXT, JSONB);
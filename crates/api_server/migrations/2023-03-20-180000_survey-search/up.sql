@@ -0,0 +1,13 @@
+-- Builds the full text search document for a survey from its title, description, and
+-- question prompts. It needs to be a function so that the index and the queries that
+-- use it are guaranteed to share the same expression.
+CREATE FUNCTION survey_search_document(title TEXT, description TEXT, questions JSONB)
+	RETURNS tsvector AS $$
+	SELECT setweight(to_tsvector('english', title), 'A')
+		|| setweight(to_tsvector('english', description), 'B')
+		|| setweight(to_tsvector('english', jsonb_path_query_array(questions, '$[*].question.content.prompt')), 'C');
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE INDEX surveys_search_idx ON surveys
+	USING GIN (survey_search_document(title, description, questions));
+CREATE INDEX surveys_owner_id_idx ON surveys (owner_id);
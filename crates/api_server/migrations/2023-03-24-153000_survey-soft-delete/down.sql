@@ -0,0 +1,2 @@
+DROP INDEX surveys_deleted_at_idx;
+ALTER TABLE surveys DROP COLUMN deleted_at;
@@ -0,0 +1,2 @@
+ALTER TABLE surveys ADD COLUMN deleted_at TIMESTAMP;
+CREATE INDEX surveys_deleted_at_idx ON surveys (deleted_at) WHERE deleted_at IS NOT NULL;
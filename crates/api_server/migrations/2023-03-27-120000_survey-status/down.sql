@@ -0,0 +1,3 @@
+ALTER TABLE surveys ADD COLUMN published BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE surveys SET published = status <> 'draft';
+ALTER TABLE surveys DROP COLUMN status;
@@ -0,0 +1,4 @@
+ALTER TABLE surveys ADD COLUMN status TEXT NOT NULL DEFAULT 'draft'
+	CHECK (status IN ('draft', 'open', 'closed', 'archived'));
+UPDATE surveys SET status = 'open' WHERE published;
+ALTER TABLE surveys DROP COLUMN published;
@@ -0,0 +1,2 @@
+DROP TABLE notification_preferences;
+ALTER TABLE surveys DROP COLUMN response_limit;
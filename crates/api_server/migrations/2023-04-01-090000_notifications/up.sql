@@ -0,0 +1,17 @@
+ALTER TABLE surveys ADD COLUMN response_limit INTEGER CHECK (response_limit > 0);
+
+CREATE TABLE notification_preferences (
+	survey_id INTEGER PRIMARY KEY REFERENCES surveys (id) ON DELETE CASCADE,
+	email TEXT NOT NULL,
+	digest TEXT NOT NULL DEFAULT 'off'
+		CHECK (digest IN ('off', 'hourly', 'daily')),
+	limit_alert BOOLEAN NOT NULL DEFAULT TRUE,
+	last_digest_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX notification_preferences_digest_idx ON notification_preferences (last_digest_at)
+	WHERE digest <> 'off';
+
+SELECT diesel_manage_updated_at('notification_preferences');
@@ -0,0 +1,3 @@
+ALTER TABLE responses DROP COLUMN invitation_id;
+DROP TABLE invitations;
+ALTER TABLE surveys DROP COLUMN invite_only;
@@ -0,0 +1,21 @@
+ALTER TABLE surveys ADD COLUMN invite_only BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE invitations (
+	id SERIAL PRIMARY KEY,
+	survey_id INTEGER NOT NULL REFERENCES surveys (id) ON DELETE CASCADE,
+	token TEXT NOT NULL UNIQUE,
+	label TEXT,
+	email TEXT,
+	max_uses INTEGER NOT NULL DEFAULT 1 CHECK (max_uses > 0),
+	uses INTEGER NOT NULL DEFAULT 0 CHECK (uses >= 0),
+	revoked_at TIMESTAMPTZ,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX invitations_survey_id_idx ON invitations (survey_id);
+
+SELECT diesel_manage_updated_at('invitations');
+
+ALTER TABLE responses
+	ADD COLUMN invitation_id INTEGER REFERENCES invitations (id) ON DELETE SET NULL;
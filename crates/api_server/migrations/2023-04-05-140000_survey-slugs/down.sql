@@ -0,0 +1 @@
+ALTER TABLE surveys DROP COLUMN slug;
@@ -0,0 +1,9 @@
+ALTER TABLE surveys ADD COLUMN slug TEXT;
+-- letters only, so that no slug can be mistaken for an id
+UPDATE surveys SET slug = translate(
+	substr(md5(random()::text || id::text), 1, 16),
+	'0123456789',
+	'ghjkmnpqrs'
+);
+ALTER TABLE surveys ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE surveys ADD CONSTRAINT surveys_slug_key UNIQUE (slug);
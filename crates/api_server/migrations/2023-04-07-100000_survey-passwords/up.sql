@@ -0,0 +1 @@
+ALTER TABLE surveys ADD COLUMN access_password_hash TEXT;
@@ -0,0 +1,25 @@
+-- only used with the `postgres` rate limit store, so that every instance shares the limits
+
+CREATE TABLE rate_limit_buckets (
+	key TEXT PRIMARY KEY,
+	tokens DOUBLE PRECISION NOT NULL,
+	refilled_at TIMESTAMPTZ NOT NULL,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX rate_limit_buckets_refilled_at_idx ON rate_limit_buckets (refilled_at);
+
+SELECT diesel_manage_updated_at('rate_limit_buckets');
+
+CREATE TABLE login_failures (
+	key TEXT PRIMARY KEY,
+	failures INTEGER NOT NULL CHECK (failures > 0),
+	last_failed_at TIMESTAMPTZ NOT NULL,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX login_failures_last_failed_at_idx ON login_failures (last_failed_at);
+
+SELECT diesel_manage_updated_at('login_failures');
@@ -0,0 +1,8 @@
+ALTER TABLE responses
+	DROP COLUMN fingerprint,
+	DROP COLUMN respondent_cookie,
+	DROP COLUMN duplicate;
+
+ALTER TABLE surveys
+	DROP COLUMN reject_duplicates,
+	DROP COLUMN duplicate_check;
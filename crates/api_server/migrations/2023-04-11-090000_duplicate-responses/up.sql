@@ -0,0 +1,16 @@
+ALTER TABLE surveys
+	ADD COLUMN duplicate_check TEXT NOT NULL DEFAULT 'off'
+		CHECK (duplicate_check IN ('off', 'cookie', 'fingerprint', 'both')),
+	ADD COLUMN reject_duplicates BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- the fingerprint is a keyed hash, so the address and user agent it was made from can't be
+-- read back from it
+ALTER TABLE responses
+	ADD COLUMN duplicate BOOLEAN NOT NULL DEFAULT FALSE,
+	ADD COLUMN respondent_cookie TEXT,
+	ADD COLUMN fingerprint TEXT;
+
+CREATE INDEX responses_respondent_cookie_idx ON responses (survey_id, respondent_cookie)
+	WHERE respondent_cookie IS NOT NULL;
+CREATE INDEX responses_fingerprint_idx ON responses (survey_id, fingerprint)
+	WHERE fingerprint IS NOT NULL;
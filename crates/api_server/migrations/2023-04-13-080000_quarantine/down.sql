@@ -0,0 +1,4 @@
+DROP INDEX responses_quarantined_idx;
+
+ALTER TABLE responses
+	DROP COLUMN quarantine_reason;
@@ -0,0 +1,7 @@
+-- responses that failed a spam check are kept apart until the owner of the survey releases
+-- or discards them
+ALTER TABLE responses
+	ADD COLUMN quarantine_reason TEXT;
+
+CREATE INDEX responses_quarantined_idx ON responses (survey_id)
+	WHERE quarantine_reason IS NOT NULL;
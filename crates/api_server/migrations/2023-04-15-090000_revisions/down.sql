@@ -0,0 +1,9 @@
+DROP TRIGGER bump_revision ON responses;
+DROP TRIGGER bump_revision ON surveys;
+DROP FUNCTION bump_revision();
+
+ALTER TABLE responses
+	DROP COLUMN revision;
+
+ALTER TABLE surveys
+	DROP COLUMN revision;
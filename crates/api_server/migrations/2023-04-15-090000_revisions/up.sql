@@ -0,0 +1,24 @@
+-- bumped on every change, so that edits can be checked against the revision they were based on
+ALTER TABLE surveys
+	ADD COLUMN revision INT NOT NULL DEFAULT 1;
+
+ALTER TABLE responses
+	ADD COLUMN revision INT NOT NULL DEFAULT 1;
+
+CREATE FUNCTION bump_revision() RETURNS trigger AS $$
+BEGIN
+	IF (
+		NEW IS DISTINCT FROM OLD AND
+		NEW.revision IS NOT DISTINCT FROM OLD.revision
+	) THEN
+		NEW.revision := OLD.revision + 1;
+	END IF;
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER bump_revision BEFORE UPDATE ON surveys
+	FOR EACH ROW EXECUTE PROCEDURE bump_revision();
+
+CREATE TRIGGER bump_revision BEFORE UPDATE ON responses
+	FOR EACH ROW EXECUTE PROCEDURE bump_revision();
@@ -0,0 +1 @@
+DROP TABLE survey_history;
@@ -0,0 +1,12 @@
+-- who did what to a survey, kept until the survey is purged
+CREATE TABLE survey_history (
+	id SERIAL PRIMARY KEY,
+	survey_id INTEGER NOT NULL REFERENCES surveys(id) ON DELETE CASCADE,
+	-- the entries outlive the account that made them
+	actor_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+	action TEXT NOT NULL,
+	changes JSONB NOT NULL DEFAULT '{}',
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX survey_history_survey_idx ON survey_history (survey_id, id);
@@ -0,0 +1,2 @@
+DROP TABLE response_history;
+DROP FUNCTION reject_history_update();
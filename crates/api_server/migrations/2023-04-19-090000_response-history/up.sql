@@ -0,0 +1,21 @@
+-- every edit of a response, so that owners can see what was answered before
+CREATE TABLE response_history (
+	id SERIAL PRIMARY KEY,
+	responder_uuid UUID NOT NULL REFERENCES responses(responder_uuid) ON DELETE CASCADE,
+	-- the revision of the response the edit made
+	revision INTEGER NOT NULL,
+	changes JSONB NOT NULL DEFAULT '{}',
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX response_history_responder_idx ON response_history (responder_uuid, id);
+
+-- the history is append only, entries only go away along with their response
+CREATE FUNCTION reject_history_update() RETURNS trigger AS $$
+BEGIN
+	RAISE EXCEPTION 'response history can not be changed';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER reject_history_update BEFORE UPDATE ON response_history
+	FOR EACH ROW EXECUTE PROCEDURE reject_history_update();
@@ -0,0 +1,2 @@
+-- bumped to revoke every token of the user, like when the password changes
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
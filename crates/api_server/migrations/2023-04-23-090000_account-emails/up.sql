@@ -0,0 +1,18 @@
+-- only ever set to an address that was verified, so that nobody can claim someone else's
+ALTER TABLE users ADD COLUMN email TEXT UNIQUE;
+
+-- single use tokens sent by email, to verify an address or reset a password. Only a hash
+-- of each token is kept.
+CREATE TABLE account_tokens (
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	purpose TEXT NOT NULL,
+	token_hash TEXT NOT NULL UNIQUE,
+	-- the address being verified
+	email TEXT,
+	expires_at TIMESTAMPTZ NOT NULL,
+	used_at TIMESTAMPTZ,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX account_tokens_user_idx ON account_tokens (user_id, purpose);
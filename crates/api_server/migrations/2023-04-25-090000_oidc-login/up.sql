@@ -0,0 +1,23 @@
+-- accounts at an OpenID Connect provider, which log in as the user they're linked to
+CREATE TABLE external_identities (
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	-- the `iss` and `sub` claims of the ID token, which identify the account together
+	issuer TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	UNIQUE (issuer, subject)
+);
+
+CREATE INDEX external_identities_user_idx ON external_identities (user_id);
+
+-- logins that were sent to the provider and haven't come back yet
+CREATE TABLE oidc_logins (
+	id SERIAL PRIMARY KEY,
+	state TEXT NOT NULL UNIQUE,
+	-- the PKCE verifier, whose hash was sent along as the code challenge
+	code_verifier TEXT NOT NULL,
+	nonce TEXT NOT NULL,
+	expires_at TIMESTAMPTZ NOT NULL,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+ALTER TABLE notification_preferences ADD COLUMN email TEXT NOT NULL DEFAULT '';
+
+UPDATE notification_preferences
+SET email = users.email
+FROM surveys
+JOIN users ON users.id = surveys.owner_id
+WHERE surveys.id = notification_preferences.survey_id AND users.email IS NOT NULL;
+
+ALTER TABLE notification_preferences ALTER COLUMN email DROP DEFAULT;
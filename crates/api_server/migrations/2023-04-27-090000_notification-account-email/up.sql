@@ -0,0 +1,2 @@
+-- notifications go to the verified email address of the owner's account
+ALTER TABLE notification_preferences DROP COLUMN email;
@@ -0,0 +1,2 @@
+-- the tokens can't be recovered from their hashes, so old invitations stop working
+ALTER TABLE invitations RENAME COLUMN token_hash TO token;
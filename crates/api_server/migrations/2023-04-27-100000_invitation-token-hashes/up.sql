@@ -0,0 +1,4 @@
+-- only a hash of each token is kept, like for account tokens
+ALTER TABLE invitations RENAME COLUMN token TO token_hash;
+
+UPDATE invitations SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
@@ -0,0 +1 @@
+ALTER TABLE surveys DROP COLUMN access_password_version;
@@ -0,0 +1,2 @@
+-- goes up whenever the access password changes, so that tokens for an old password stop working
+ALTER TABLE surveys ADD COLUMN access_password_version INTEGER NOT NULL DEFAULT 0;
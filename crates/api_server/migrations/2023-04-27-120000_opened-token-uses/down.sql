@@ -0,0 +1 @@
+DROP TABLE opened_token_uses;
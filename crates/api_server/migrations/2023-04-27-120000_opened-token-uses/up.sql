@@ -0,0 +1,7 @@
+-- opened tokens that a response was sent with, so that each only counts for one response.
+-- They are kept until the token expires.
+CREATE TABLE opened_token_uses (
+	jti UUID PRIMARY KEY,
+	survey_id INTEGER NOT NULL REFERENCES surveys(id) ON DELETE CASCADE,
+	expires_at TIMESTAMPTZ NOT NULL
+);
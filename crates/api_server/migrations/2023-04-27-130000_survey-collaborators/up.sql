@@ -0,0 +1,9 @@
+-- users that the owner of a survey lets edit its questions along with them
+CREATE TABLE survey_collaborators (
+	survey_id INTEGER NOT NULL REFERENCES surveys(id) ON DELETE CASCADE,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	PRIMARY KEY (survey_id, user_id)
+);
+
+CREATE INDEX survey_collaborators_user_idx ON survey_collaborators (user_id);
@@ -0,0 +1,4 @@
+DROP INDEX responses_accepted_idx;
+
+ALTER TABLE responses
+	DROP COLUMN accepted_at;
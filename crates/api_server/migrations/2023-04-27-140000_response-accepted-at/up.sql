@@ -0,0 +1,7 @@
+-- when a response was accepted: right away, or when it was released from quarantine
+ALTER TABLE responses
+	ADD COLUMN accepted_at TIMESTAMPTZ;
+
+UPDATE responses SET accepted_at = created_at WHERE quarantine_reason IS NULL;
+
+CREATE INDEX responses_accepted_idx ON responses (survey_id, accepted_at);
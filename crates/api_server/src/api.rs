@@ -1,11 +1,49 @@
 use std::io::Cursor;
 
 use rocket::http::{ContentType, Header, Status};
-use rocket::request::Request;
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome, Request};
 use rocket::response::{self, Responder, Response};
+use rocket::State;
 use serde::Serialize;
 
 use crate::cache::Cacheable;
+use crate::db::Storage;
+use crate::live::LiveEvents;
+use crate::mailer::Outbox;
+use crate::survey::collab::Collaboration;
+use crate::survey::SurveyConfig;
+use crate::survey_response::Challenges;
+use crate::webhook::WebhookWorker;
+
+/// The database connection and the managed state that routes changing surveys and
+/// responses pass the changes on to, as one guard. Routes take apart what they need.
+pub struct Services<'r> {
+    pub db: Storage,
+    pub config: &'r SurveyConfig,
+    pub webhooks: &'r WebhookWorker,
+    pub live: &'r LiveEvents,
+    pub collab: &'r Collaboration,
+    pub outbox: &'r Outbox,
+    pub challenges: &'r Challenges,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Services<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Services {
+            db: try_outcome!(req.guard::<Storage>().await),
+            config: try_outcome!(req.guard::<&State<SurveyConfig>>().await).inner(),
+            webhooks: try_outcome!(req.guard::<&State<WebhookWorker>>().await).inner(),
+            live: try_outcome!(req.guard::<&State<LiveEvents>>().await).inner(),
+            collab: try_outcome!(req.guard::<&State<Collaboration>>().await).inner(),
+            outbox: try_outcome!(req.guard::<&State<Outbox>>().await).inner(),
+            challenges: try_outcome!(req.guard::<&State<Challenges>>().await).inner(),
+        })
+    }
+}
 
 #[derive(Debug)]
 pub enum ApiOkCacheableResource<T> {
@@ -79,8 +117,10 @@ pub fn default_catcher(status: Status, _req: &Request) -> ApiErrorResponse<&'sta
         message: match status.code {
             400 => "Bad Request",
             401 => "Unauthorized",
+            403 => "Forbidden",
             404 => "Not Found",
             405 => "Method Not Allowed",
+            429 => "Too Many Requests",
             500 => "Internal Server Error",
             _ => "Unknown Error",
         },
@@ -1,22 +1,30 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SubsecRound, Utc};
 use rocket::{
     request::{FromRequest, Outcome},
     Request,
 };
+use sha2::{Digest, Sha256};
 
-use crate::db::models::{Survey, SurveyResponse, SurveyResponseUpdateCheck, SurveyUpdateCheck};
+use crate::db::models::{Survey, SurveyResponse};
 
 pub trait Cacheable {
     fn modified_time(&self) -> Option<DateTime<Utc>> {
         None
     }
-    fn etag(&self) -> Option<&String> {
+    fn etag(&self) -> Option<String> {
         None
     }
 
+    /// Whether an ETag from `If-Match` is still current for edits. Only the parts of the
+    /// representation that edits can conflict with have to match, see [`Survey`].
+    fn is_edit_etag_match(&self, etag: &str) -> bool {
+        self.is_etag_match(etag)
+    }
+
     fn is_modified_since(&self, since: impl Into<DateTime<Utc>>) -> bool {
         match self.modified_time() {
-            Some(modified_time) => modified_time > since.into(),
+            // dates in headers only have seconds
+            Some(modified_time) => modified_time.trunc_subsecs(0) > since.into(),
             None => false,
         }
     }
@@ -31,14 +39,19 @@ pub trait Cacheable {
     fn is_cache_fresh(&self, cache_check: CacheCheck) -> bool {
         match cache_check {
             CacheCheck::IfModifiedSince(since) => !self.is_modified_since(since),
-            CacheCheck::IfNoneMatch(etag) => !self.is_etag_match(etag.as_str()),
+            // `If-None-Match` uses the weak comparison
+            CacheCheck::IfNoneMatch(etags) => {
+                any_etag(&etags, true, |etag| self.is_etag_match(etag))
+            }
         }
     }
 
     fn has_no_mid_air_collision(&self, race_check: RaceCheck) -> bool {
         match race_check {
-            RaceCheck::IfUnmodifiedSince(since) => self.is_modified_since(since),
-            RaceCheck::IfMatch(etag) => self.is_etag_match(etag.as_str()),
+            RaceCheck::IfUnmodifiedSince(since) => !self.is_modified_since(since),
+            RaceCheck::IfMatch(etags) => {
+                any_etag(&etags, false, |etag| self.is_edit_etag_match(etag))
+            }
         }
     }
 
@@ -51,33 +64,85 @@ pub trait Cacheable {
     }
 
     fn etag_header(&self) -> Option<String> {
-        self.etag().cloned()
+        self.etag()
+    }
+}
+
+/// Whether any of the ETags in an `If-Match` or `If-None-Match` header is matched. Weak
+/// ETags are only compared when `weak` is set.
+fn any_etag(header: &str, weak: bool, matches: impl Fn(&str) -> bool) -> bool {
+    header.split(',').map(str::trim).any(|etag| {
+        if etag == "*" {
+            return true;
+        }
+        match etag.strip_prefix("W/") {
+            Some(etag) => weak && matches(etag),
+            None => matches(etag),
+        }
+    })
+}
+
+/// `serde_json` keeps the keys of objects sorted, so maps hash the same however they are
+/// ordered.
+fn hash_value(value: &serde_json::Value) -> String {
+    let hash = Sha256::digest(value.to_string().as_bytes());
+    hex::encode(&hash[..16])
+}
+
+/// The response stats that owners get change with every response, but don't conflict with
+/// edits to the survey. They get their own part of the ETag, which `If-Match` ignores.
+const SURVEY_STATS_FIELDS: [&str; 2] = ["response_count", "last_response_at"];
+
+impl Survey {
+    fn stats_hash(&self) -> Option<String> {
+        let value = serde_json::to_value(self).ok()?;
+        let fields = value.as_object()?;
+        let stats = SURVEY_STATS_FIELDS
+            .iter()
+            .filter_map(|field| fields.get(*field).map(|v| (field.to_string(), v.clone())))
+            .collect::<serde_json::Map<_, _>>();
+        (!stats.is_empty()).then(|| hash_value(&stats.into()))
     }
 }
 
 impl Cacheable for Survey {
     fn modified_time(&self) -> Option<DateTime<Utc>> {
         // by pure luck, the updated_at field is actually in UTC
-        Some(DateTime::from_utc(self.updated_at, Utc))
+        let updated_at = DateTime::from_utc(self.updated_at, Utc);
+        // owners also get response stats, which change whenever a response comes in
+        Some(
+            self.last_response_at
+                .map_or(updated_at, |t| t.max(updated_at)),
+        )
     }
-}
 
-impl Cacheable for SurveyResponse {
-    fn modified_time(&self) -> Option<DateTime<Utc>> {
-        Some(self.updated_at)
+    /// The revision of the survey, and a hash of the response stats for its owner.
+    fn etag(&self) -> Option<String> {
+        Some(match self.stats_hash() {
+            Some(stats) => format!("\"{}-{stats}\"", self.revision),
+            None => format!("\"{}\"", self.revision),
+        })
     }
-}
 
-impl Cacheable for SurveyUpdateCheck {
-    fn modified_time(&self) -> Option<DateTime<Utc>> {
-        Some(DateTime::from_utc(self.updated_at, Utc))
+    fn is_edit_etag_match(&self, etag: &str) -> bool {
+        let Some(etag) = etag
+            .strip_prefix('"')
+            .and_then(|etag| etag.strip_suffix('"'))
+        else {
+            return false;
+        };
+        etag.split('-').next() == Some(self.revision.to_string().as_str())
     }
 }
 
-impl Cacheable for SurveyResponseUpdateCheck {
+impl Cacheable for SurveyResponse {
     fn modified_time(&self) -> Option<DateTime<Utc>> {
         Some(self.updated_at)
     }
+
+    fn etag(&self) -> Option<String> {
+        Some(format!("\"{}\"", self.revision))
+    }
 }
 
 pub enum CacheCheck {
@@ -140,8 +205,8 @@ mod tests {
     }
 
     impl Cacheable for TestObj {
-        fn etag(&self) -> Option<&String> {
-            Some(&self.etag)
+        fn etag(&self) -> Option<String> {
+            Some(self.etag.clone())
         }
 
         fn modified_time(&self) -> Option<DateTime<Utc>> {
@@ -169,6 +234,40 @@ mod tests {
         assert!(!obj.is_etag_match("test2"));
     }
 
+    #[test]
+    fn test_etag_lists() {
+        let obj = TestObj {
+            etag: "\"a\"".to_string(),
+            modified_time: Utc::now(),
+        };
+        assert!(obj.is_cache_fresh(CacheCheck::IfNoneMatch("\"b\", \"a\"".to_string())));
+        assert!(obj.is_cache_fresh(CacheCheck::IfNoneMatch("W/\"a\"".to_string())));
+        assert!(obj.is_cache_fresh(CacheCheck::IfNoneMatch("*".to_string())));
+        assert!(!obj.is_cache_fresh(CacheCheck::IfNoneMatch("\"b\"".to_string())));
+
+        assert!(obj.has_no_mid_air_collision(RaceCheck::IfMatch("\"b\", \"a\"".to_string())));
+        // `If-Match` never matches weak ETags
+        assert!(!obj.has_no_mid_air_collision(RaceCheck::IfMatch("W/\"a\"".to_string())));
+        assert!(!obj.has_no_mid_air_collision(RaceCheck::IfMatch("\"b\"".to_string())));
+    }
+
+    #[test]
+    fn test_unmodified_since_same_second() {
+        let obj = TestObj {
+            etag: "test".to_string(),
+            modified_time: Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap()
+                + chrono::Duration::milliseconds(500),
+        };
+        let since = DateTime::parse_from_rfc2822(&obj.last_modified_header().unwrap())
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(obj.has_no_mid_air_collision(RaceCheck::IfUnmodifiedSince(since)));
+        assert!(obj.is_cache_fresh(CacheCheck::IfModifiedSince(since)));
+        assert!(!obj.has_no_mid_air_collision(RaceCheck::IfUnmodifiedSince(
+            since - chrono::Duration::seconds(1)
+        )));
+    }
+
     #[test]
     fn test_build_headers() {
         let obj = TestObj {
@@ -0,0 +1,20 @@
+use rocket::figment::Figment;
+use serde::de::DeserializeOwned;
+
+/// Reads the section of `Rocket.toml` under `key`, like `cors` for `[default.cors]`. The
+/// default is only used when there is no such section; one that can't be read is logged
+/// and `None` is returned, so that a typo doesn't quietly turn a feature off.
+pub(crate) fn section<T: DeserializeOwned + Default>(figment: &Figment, key: &str) -> Option<T> {
+    match figment.find_value(key) {
+        Err(e) if e.missing() => return Some(T::default()),
+        Err(e) => {
+            error!("invalid `{key}` configuration: {e}");
+            return None;
+        }
+        Ok(_) => {}
+    }
+    figment
+        .extract_inner(key)
+        .map_err(|e| error!("invalid `{key}` configuration: {e}"))
+        .ok()
+}
@@ -1,7 +1,114 @@
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::http::Header;
-use rocket::response::status::NoContent;
-use rocket::{Request, Response};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, Responder};
+use rocket::{Build, Request, Response, Rocket};
+use serde::Deserialize;
+
+use crate::config;
+
+/// Configured under `[default.cors]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins like `https://surveys.example.com`, or patterns like `https://*.example.com`,
+    /// where `*` stands for anything but a `/`. `*` on its own allows every origin, which
+    /// can't be combined with `allow_credentials`.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Request headers that cross-origin requests may send.
+    pub allowed_headers: Vec<String>,
+    /// Response headers that cross-origin requests may read.
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// Seconds that browsers may cache the answer to a preflight.
+    pub max_age: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: strings(&["GET", "POST", "PUT", "PATCH", "DELETE"]),
+            allowed_headers: strings(&[
+                "Authorization",
+                "Content-Type",
+                "If-Match",
+                "If-None-Match",
+                "If-Modified-Since",
+                "If-Unmodified-Since",
+                "X-Survey-Access",
+                "X-Survey-Opened",
+                "X-Survey-Honeypot",
+                "X-Survey-Proof",
+            ]),
+            exposed_headers: strings(&[
+                "ETag",
+                "Last-Modified",
+                "Location",
+                "Retry-After",
+                "X-Survey-Opened",
+                "X-Survey-Proof-Of-Work",
+            ]),
+            allow_credentials: true,
+            max_age: 2 * 60 * 60,
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| origin_matches(allowed, origin))
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    fn allows_header(&self, header: &str) -> bool {
+        self.allowed_headers
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(header))
+    }
+}
+
+/// Whether `origin` is `allowed`, or matches it when it is a pattern.
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    if allowed == "*" {
+        return true;
+    }
+    let mut parts = allowed.split('*');
+    let Some(first) = parts.next() else {
+        return false;
+    };
+    let Some(mut rest) = origin.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        // the last part has to be at the very end, the ones before it as early as possible
+        let found = if parts.peek().is_none() {
+            rest.len()
+                .checked_sub(part.len())
+                .filter(|&at| rest[at..] == *part)
+        } else {
+            rest.find(part)
+        };
+        let Some(at) = found else {
+            return false;
+        };
+        if rest[..at].is_empty() || rest[..at].contains('/') {
+            return false;
+        }
+        rest = &rest[at + part.len()..];
+    }
+    rest.is_empty()
+}
 
 pub struct Cors;
 
@@ -10,21 +117,323 @@ impl Fairing for Cors {
     fn info(&self) -> Info {
         Info {
             name: "Add CORS headers to responses",
-            kind: Kind::Response,
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let Some(config) = config::section::<CorsConfig>(rocket.figment(), "cors") else {
+            return Err(rocket);
+        };
+        // every site could then make requests with the cookies of the user and read the
+        // answers
+        if config.allow_credentials && config.allowed_origins.iter().any(|o| o == "*") {
+            error!(
+                "CORS can't allow every origin with `allow_credentials`, list the origins instead"
+            );
+            return Err(rocket);
+        }
+        Ok(rocket.manage(config))
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        // the headers depend on the origin, so caches have to keep them apart
+        response.adjoin_header(Header::new("Vary", "Origin"));
+
+        let Some(config) = request.rocket().state::<CorsConfig>() else {
+            return;
+        };
+        let Some(origin) = request.headers().get_one("Origin") else {
+            return;
+        };
+        if !config.allows_origin(origin) {
+            return;
+        }
+        response.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            origin.to_string(),
+        ));
+        if config.allow_credentials {
+            response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+        if !config.exposed_headers.is_empty() {
+            response.set_header(Header::new(
+                "Access-Control-Expose-Headers",
+                config.exposed_headers.join(", "),
+            ));
+        }
+    }
+}
+
+/// A preflight request, which is only let through when the policy allows its origin,
+/// method and headers.
+pub struct Preflight<'r> {
+    config: &'r CorsConfig,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preflight<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(config) = req.rocket().state::<CorsConfig>() else {
+            error!("CORS config is not managed, attach `cors::Cors`");
+            return Outcome::Failure((Status::InternalServerError, ()));
+        };
+        let headers = req.headers();
+        let (Some(origin), Some(method)) = (
+            headers.get_one("Origin"),
+            headers.get_one("Access-Control-Request-Method"),
+        ) else {
+            return Outcome::Failure((Status::BadRequest, ()));
+        };
+        let allowed = config.allows_origin(origin)
+            && config.allows_method(method)
+            && headers
+                .get("Access-Control-Request-Headers")
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .filter(|header| !header.is_empty())
+                .all(|header| config.allows_header(header));
+        if !allowed {
+            return Outcome::Failure((Status::Forbidden, ()));
         }
+        Outcome::Success(Preflight { config })
     }
+}
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Methods", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Expose-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
-        response.set_header(Header::new("Access-Control-Max-Age", "7200"));
+impl<'r> Responder<'r, 'static> for Preflight<'r> {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .status(Status::NoContent)
+            .header(Header::new(
+                "Access-Control-Allow-Methods",
+                self.config.allowed_methods.join(", "),
+            ))
+            .header(Header::new(
+                "Access-Control-Allow-Headers",
+                self.config.allowed_headers.join(", "),
+            ))
+            .header(Header::new(
+                "Access-Control-Max-Age",
+                self.config.max_age.to_string(),
+            ))
+            .ok()
     }
 }
 
 #[options("/<_..>")]
-pub fn handle_preflight() -> NoContent {
-    NoContent
+pub fn handle_preflight(preflight: Preflight<'_>) -> Preflight<'_> {
+    preflight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::local::blocking::Client;
+
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_origin_matches() {
+        assert!(origin_matches(
+            "https://surveys.example.com",
+            "https://surveys.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://surveys.example.com",
+            "https://surveys.example.com.evil.com"
+        ));
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://surveys.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://evil.com/.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://example.com.evil.com"
+        ));
+        assert!(origin_matches(
+            "http://localhost:*",
+            "http://localhost:5173"
+        ));
+        assert!(origin_matches("*", "https://anything.com"));
+    }
+
+    fn test_rocket_with_cors(db_name: &String) -> Rocket<Build> {
+        let rocket = test_rocket(db_name);
+        let config = rocket
+            .figment()
+            .clone()
+            .merge((
+                "cors.allowed_origins",
+                [
+                    "https://surveys.example.com",
+                    "https://*.preview.example.com",
+                ],
+            ))
+            .merge(("cors.max_age", 60));
+        rocket.configure(config)
+    }
+
+    #[test]
+    fn test_cors_headers() {
+        run_test_with_db(|db_name| {
+            let client =
+                Client::tracked(test_rocket_with_cors(db_name)).expect("valid rocket instance");
+
+            let response = client
+                .get(uri!("/api", crate::api::health))
+                .header(Header::new("Origin", "https://pr-1.preview.example.com"))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(
+                response.headers().get_one("Access-Control-Allow-Origin"),
+                Some("https://pr-1.preview.example.com")
+            );
+            assert_eq!(
+                response
+                    .headers()
+                    .get_one("Access-Control-Allow-Credentials"),
+                Some("true")
+            );
+            assert_eq!(response.headers().get_one("Vary"), Some("Origin"));
+
+            // other origins get the response, but browsers won't let them read it
+            let response = client
+                .get(uri!("/api", crate::api::health))
+                .header(Header::new("Origin", "https://evil.com"))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(
+                response.headers().get_one("Access-Control-Allow-Origin"),
+                None
+            );
+            assert_eq!(response.headers().get_one("Vary"), Some("Origin"));
+        });
+    }
+
+    #[test]
+    fn test_wildcard_with_credentials() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("cors.allowed_origins", ["*"]));
+            let Err(error) = Client::tracked(rocket.configure(config.clone())) else {
+                panic!("the wildcard is refused");
+            };
+            assert!(matches!(
+                error.kind(),
+                rocket::error::ErrorKind::FailedFairings(_)
+            ));
+
+            let rocket = test_rocket(db_name);
+            let config = config.merge(("cors.allow_credentials", false));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+            let response = client
+                .get(uri!("/api", crate::api::health))
+                .header(Header::new("Origin", "https://anything.com"))
+                .dispatch();
+            assert_eq!(
+                response.headers().get_one("Access-Control-Allow-Origin"),
+                Some("https://anything.com")
+            );
+            assert_eq!(
+                response
+                    .headers()
+                    .get_one("Access-Control-Allow-Credentials"),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("cors.allowed_origins", "https://example.com"));
+            let Err(error) = Client::tracked(rocket.configure(config)) else {
+                panic!("a section that can't be read is refused");
+            };
+            assert!(matches!(
+                error.kind(),
+                rocket::error::ErrorKind::FailedFairings(_)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_preflight() {
+        run_test_with_db(|db_name| {
+            let client =
+                Client::tracked(test_rocket_with_cors(db_name)).expect("valid rocket instance");
+
+            let preflight = |origin: &str, method: &str, headers: &str| {
+                client
+                    .options("/api/survey/1")
+                    .header(Header::new("Origin", origin.to_owned()))
+                    .header(Header::new(
+                        "Access-Control-Request-Method",
+                        method.to_owned(),
+                    ))
+                    .header(Header::new(
+                        "Access-Control-Request-Headers",
+                        headers.to_owned(),
+                    ))
+                    .dispatch()
+            };
+
+            let response = preflight(
+                "https://surveys.example.com",
+                "PATCH",
+                "authorization, content-type",
+            );
+            assert_eq!(response.status(), Status::NoContent);
+            assert_eq!(
+                response.headers().get_one("Access-Control-Allow-Origin"),
+                Some("https://surveys.example.com")
+            );
+            assert_eq!(
+                response.headers().get_one("Access-Control-Max-Age"),
+                Some("60")
+            );
+            assert!(response
+                .headers()
+                .get_one("Access-Control-Allow-Methods")
+                .unwrap()
+                .contains("PATCH"));
+
+            assert_eq!(
+                preflight("https://evil.com", "PATCH", "").status(),
+                Status::Forbidden
+            );
+            assert_eq!(
+                preflight("https://surveys.example.com", "TRACE", "").status(),
+                Status::Forbidden
+            );
+            assert_eq!(
+                preflight("https://surveys.example.com", "GET", "X-Custom").status(),
+                Status::Forbidden
+            );
+
+            let response = client
+                .options("/api/survey/1")
+                .header(Header::new("Origin", "https://surveys.example.com"))
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+        });
+    }
 }
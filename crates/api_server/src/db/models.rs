@@ -1,19 +1,55 @@
-use std::{collections::HashMap, io::Write};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
 
 use diesel::{
-    deserialize::FromSql,
+    deserialize::{FromSql, Queryable},
     pg::{Pg, PgValue},
     serialize::ToSql,
-    sql_types::Jsonb,
+    sql_types::{Jsonb, Text},
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    db::schema::{responses, surveys, users},
+    db::schema::{
+        account_tokens, external_identities, invitations, notification_preferences, oidc_logins,
+        response_history, responses, survey_history, surveys, users, webhook_deliveries, webhooks,
+    },
     questions::SurveyQuestion,
 };
 
+/// Stores an enum in a `TEXT` column as one of the given strings, and gives it an `as_str`
+/// that returns the same string.
+macro_rules! text_enum {
+    ($type:ident, $name:literal { $($variant:ident => $value:literal,)+ }) => {
+        impl $type {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($type::$variant => $value,)+
+                }
+            }
+        }
+
+        impl FromSql<Text, Pg> for $type {
+            fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+                match std::str::from_utf8(value.as_bytes())? {
+                    $($value => Ok($type::$variant),)+
+                    other => Err(format!(concat!("unknown ", $name, " {:?}"), other).into()),
+                }
+            }
+        }
+
+        impl ToSql<Text, Pg> for $type {
+            fn to_sql(&self, out: &mut diesel::serialize::Output<Pg>) -> diesel::serialize::Result {
+                out.write_all(self.as_str().as_bytes())?;
+                Ok(diesel::serialize::IsNull::No)
+            }
+        }
+    };
+}
+
 #[derive(Queryable)]
 pub struct User {
     pub id: i32,
@@ -21,6 +57,10 @@ pub struct User {
     pub password_hash: String,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    /// Goes up whenever the tokens of the user are revoked.
+    pub token_version: i32,
+    /// Only ever a verified address, which password resets are sent to.
+    pub email: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -30,51 +70,301 @@ pub struct NewUser {
     pub password_hash: String,
 }
 
+/// Where a survey is in its lifecycle.
+///
+/// A survey starts out as a draft, is opened to start collecting responses, then closed
+/// once it's done. Closed surveys can still be viewed and exported, and can be archived
+/// to hide them from the list of surveys.
 #[typeshare]
-#[derive(Queryable, Serialize, Deserialize)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+    FromFormField,
+    UriDisplayQuery,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum SurveyStatus {
+    #[default]
+    #[field(value = "draft")]
+    Draft,
+    #[field(value = "open")]
+    Open,
+    #[field(value = "closed")]
+    Closed,
+    #[field(value = "archived")]
+    Archived,
+}
+
+impl SurveyStatus {
+    /// Whether anyone can view the survey, not just its owner. This is what `published`
+    /// used to mean before surveys had a lifecycle.
+    pub fn is_published(&self) -> bool {
+        *self != SurveyStatus::Draft
+    }
+
+    pub fn accepts_responses(&self) -> bool {
+        *self == SurveyStatus::Open
+    }
+
+    pub fn can_transition_to(&self, next: SurveyStatus) -> bool {
+        use SurveyStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Open)
+                | (Open, Draft)
+                | (Open, Closed)
+                | (Closed, Open)
+                | (Closed, Archived)
+                | (Archived, Closed)
+        ) || *self == next
+    }
+}
+
+text_enum!(SurveyStatus, "survey status" {
+    Draft => "draft",
+    Open => "open",
+    Closed => "closed",
+    Archived => "archived",
+});
+
+#[typeshare]
+#[derive(Serialize, Deserialize)]
 pub struct Survey {
     pub id: i32,
+    /// Used in public links instead of the id, so that surveys can't be enumerated.
+    pub slug: String,
     pub title: String,
     pub description: String,
+    /// Kept for older clients, this is true for any survey that is not a draft.
     pub published: bool,
+    pub status: SurveyStatus,
     pub owner_id: i32,
     pub questions: SurveyQuestions,
     #[typeshare(serialized_as = "String")]
     pub created_at: chrono::NaiveDateTime,
     #[typeshare(serialized_as = "String")]
     pub updated_at: chrono::NaiveDateTime,
+    /// The survey is closed once it has this many responses.
+    pub response_limit: Option<i32>,
+    /// Responding requires an invitation token.
+    pub invite_only: bool,
+    /// Respondents have to exchange the access password for a token first.
+    pub password_protected: bool,
+    /// Goes up whenever the access password changes, to tell tokens for an old one apart.
+    #[serde(skip)]
+    pub access_password_version: i32,
+    /// How repeat responses from the same respondent are recognized.
+    pub duplicate_check: DuplicateCheck,
+    /// Whether repeat responses are rejected, instead of only being flagged.
+    pub reject_duplicates: bool,
+    /// Goes up by one with every change.
+    pub revision: i32,
+    /// Only included when the survey is requested by its owner.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[typeshare(serialized_as = "Option<u32>")]
+    pub response_count: Option<i64>,
+    /// Only included when the survey is requested by its owner.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[typeshare(serialized_as = "Option<String>")]
+    pub last_response_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-/// Used to minimize the amount of data we query from the database
-/// when checking for mid-air collisions and other preconditions.
-#[derive(Queryable)]
-#[diesel(table_name=surveys)]
-pub struct SurveyUpdateCheck {
-    pub published: bool,
-    pub owner_id: i32,
-    pub updated_at: chrono::NaiveDateTime,
+impl Queryable<surveys::SqlType, Pg> for Survey {
+    type Row = (
+        i32,
+        String,
+        String,
+        i32,
+        SurveyQuestions,
+        chrono::NaiveDateTime,
+        chrono::NaiveDateTime,
+        Option<chrono::NaiveDateTime>,
+        SurveyStatus,
+        Option<i32>,
+        bool,
+        String,
+        Option<String>,
+        DuplicateCheck,
+        bool,
+        i32,
+        i32,
+    );
+
+    fn build(row: Self::Row) -> diesel::deserialize::Result<Self> {
+        // soft deleted surveys are never served, so `deleted_at` is not exposed
+        let (
+            id,
+            title,
+            description,
+            owner_id,
+            questions,
+            created_at,
+            updated_at,
+            _,
+            status,
+            response_limit,
+            invite_only,
+            slug,
+            access_password_hash,
+            duplicate_check,
+            reject_duplicates,
+            revision,
+            access_password_version,
+        ) = row;
+        Ok(Self {
+            id,
+            slug,
+            title,
+            description,
+            published: status.is_published(),
+            status,
+            owner_id,
+            questions,
+            created_at,
+            updated_at,
+            response_limit,
+            invite_only,
+            // the hash itself is only read when checking a password
+            password_protected: access_password_hash.is_some(),
+            access_password_version,
+            duplicate_check,
+            reject_duplicates,
+            revision,
+            response_count: None,
+            last_response_at: None,
+        })
+    }
+}
+
+impl Survey {
+    pub fn with_response_stats(mut self, stats: ResponseStats) -> Self {
+        self.response_count = Some(stats.response_count);
+        self.last_response_at = stats.last_response_at;
+        self
+    }
+}
+
+/// Aggregated figures about the responses to a survey.
+#[derive(Debug, Queryable)]
+pub struct ResponseStats {
+    pub response_count: i64,
+    pub last_response_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 /// Represents a partial update to a survey
 #[typeshare]
-#[derive(AsChangeset, Serialize, Deserialize, Default)]
-#[diesel(table_name=surveys)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct SurveyPatch {
     pub title: Option<String>,
     pub description: Option<String>,
+    /// Kept for older clients, publishing opens the survey and unpublishing turns it back
+    /// into a draft. Ignored if `status` is set.
     pub published: Option<bool>,
+    pub status: Option<SurveyStatus>,
     pub questions: Option<SurveyQuestions>,
+    /// Set to `null` to remove the limit.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_some"
+    )]
+    #[typeshare(serialized_as = "Option<u32>")]
+    pub response_limit: Option<Option<i32>>,
+    pub invite_only: Option<bool>,
+    pub slug: Option<String>,
+    /// Set to `null` to remove the password.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_some"
+    )]
+    #[typeshare(serialized_as = "Option<String>")]
+    pub access_password: Option<Option<String>>,
+    pub duplicate_check: Option<DuplicateCheck>,
+    pub reject_duplicates: Option<bool>,
+}
+
+/// Tells a field that was left out, which deserializes to `None`, apart from one that was
+/// set to `null`, which deserializes to `Some(None)`.
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl SurveyPatch {
+    /// The status the survey should end up in, if the patch changes it.
+    pub fn new_status(&self) -> Option<SurveyStatus> {
+        self.status.or(match self.published {
+            Some(true) => Some(SurveyStatus::Open),
+            Some(false) => Some(SurveyStatus::Draft),
+            None => None,
+        })
+    }
+}
+
+/// The columns of a [`SurveyPatch`] that are written to the database
+#[derive(AsChangeset)]
+#[diesel(table_name=surveys)]
+pub struct SurveyChangeset {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub status: Option<SurveyStatus>,
+    pub questions: Option<SurveyQuestions>,
+    pub response_limit: Option<Option<i32>>,
+    pub invite_only: Option<bool>,
+    pub slug: Option<String>,
+    /// Hashed from [`SurveyPatch::access_password`] by the caller, so that it is left out
+    /// by the conversion.
+    pub access_password_hash: Option<Option<String>>,
+    /// Bumped by the caller whenever the access password changes.
+    pub access_password_version: Option<i32>,
+    pub duplicate_check: Option<DuplicateCheck>,
+    pub reject_duplicates: Option<bool>,
+}
+
+impl From<SurveyPatch> for SurveyChangeset {
+    fn from(patch: SurveyPatch) -> Self {
+        Self {
+            status: patch.new_status(),
+            title: patch.title,
+            description: patch.description,
+            questions: patch.questions,
+            response_limit: patch.response_limit,
+            invite_only: patch.invite_only,
+            slug: patch.slug,
+            access_password_hash: None,
+            access_password_version: None,
+            duplicate_check: patch.duplicate_check,
+            reject_duplicates: patch.reject_duplicates,
+        }
+    }
 }
 
 #[derive(Insertable)]
 #[diesel(table_name=surveys)]
 pub struct NewSurvey {
     owner_id: i32,
+    slug: String,
 }
 
 impl NewSurvey {
     pub fn new(owner_id: i32) -> Self {
-        Self { owner_id }
+        Self {
+            owner_id,
+            slug: crate::survey::slug::generate_slug(),
+        }
     }
 }
 
@@ -84,10 +374,32 @@ impl NewSurvey {
 #[diesel(table_name=surveys)]
 pub struct ListedSurvey {
     pub id: i32,
+    pub slug: String,
     pub title: String,
     pub description: String,
+    /// Kept for older clients, this is true for any survey that is not a draft.
     pub published: bool,
+    pub status: SurveyStatus,
     pub owner_id: i32,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::NaiveDateTime,
+    #[typeshare(serialized_as = "String")]
+    pub updated_at: chrono::NaiveDateTime,
+    #[typeshare(serialized_as = "u32")]
+    pub response_count: i64,
+    #[typeshare(serialized_as = "Option<String>")]
+    pub last_response_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the survey was moved to the trash, if it has been.
+    #[typeshare(serialized_as = "Option<String>")]
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A page of surveys, along with the cursor to use to get the next page.
+#[typeshare]
+#[derive(Serialize, Deserialize)]
+pub struct SurveyList {
+    pub surveys: Vec<ListedSurvey>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, AsExpression, FromSqlRow, Default)]
@@ -157,14 +469,27 @@ pub struct SurveyResponse {
     pub created_at: chrono::DateTime<chrono::Utc>,
     #[typeshare(serialized_as = "String")]
     pub updated_at: chrono::DateTime<chrono::Utc>,
-}
-
-/// Used to minimize the amount of data we query from the database
-/// when checking for mid-air collisions.
-#[derive(Queryable)]
-#[diesel(table_name=responses)]
-pub struct SurveyResponseUpdateCheck {
-    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// The invitation that was used to respond, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invitation_id: Option<i32>,
+    /// Whether the respondent had already responded to the survey. Kept from respondents,
+    /// owners find it in the export.
+    #[serde(skip)]
+    pub duplicate: bool,
+    #[serde(skip)]
+    pub respondent_cookie: Option<String>,
+    #[serde(skip)]
+    pub fingerprint: Option<String>,
+    /// Why the response looked like spam, if it did. Kept from respondents, so that bots
+    /// can't tell which check they failed.
+    #[serde(skip)]
+    pub quarantine_reason: Option<String>,
+    /// Goes up by one with every change.
+    pub revision: i32,
+    /// When the response was accepted, which is when it was created unless it was
+    /// quarantined. `None` while it is quarantined.
+    #[serde(skip)]
+    pub accepted_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Insertable)]
@@ -173,6 +498,12 @@ pub struct NewSurveyResponse {
     pub survey_id: i32,
     pub responder_uuid: Uuid,
     pub content: SurveyResponses,
+    pub invitation_id: Option<i32>,
+    pub duplicate: bool,
+    pub respondent_cookie: Option<String>,
+    pub fingerprint: Option<String>,
+    pub quarantine_reason: Option<String>,
+    pub accepted_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, AsExpression, FromSqlRow)]
@@ -213,3 +544,489 @@ impl ToSql<Jsonb, Pg> for SurveyResponses {
 pub struct PatchSurveyResponse {
     pub content: SurveyResponses,
 }
+
+/// Something that happened to a survey that webhooks can subscribe to.
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum WebhookEvent {
+    #[serde(rename = "response.created")]
+    ResponseCreated,
+    #[serde(rename = "response.updated")]
+    ResponseUpdated,
+    #[serde(rename = "survey.published")]
+    SurveyPublished,
+}
+
+text_enum!(WebhookEvent, "webhook event" {
+    ResponseCreated => "response.created",
+    ResponseUpdated => "response.updated",
+    SurveyPublished => "survey.published",
+});
+
+/// A subscription to the events of a survey, which are POSTed to `url`.
+#[typeshare]
+#[derive(Queryable, Serialize, Deserialize)]
+#[diesel(table_name=webhooks)]
+pub struct Webhook {
+    pub id: i32,
+    pub survey_id: i32,
+    pub url: String,
+    /// Only included when the webhook is created.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    #[typeshare(serialized_as = "Option<String>")]
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[typeshare(serialized_as = "String")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=webhooks)]
+pub struct NewWebhook {
+    pub survey_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Waiting for its first attempt, or for a retry.
+    Pending,
+    Succeeded,
+    /// Gave up after too many attempts.
+    Failed,
+}
+
+text_enum!(DeliveryStatus, "delivery status" {
+    Pending => "pending",
+    Succeeded => "succeeded",
+    Failed => "failed",
+});
+
+/// An entry in the delivery log of a webhook.
+#[typeshare]
+#[derive(Queryable, Serialize, Deserialize)]
+#[diesel(table_name=webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: WebhookEvent,
+    #[typeshare(serialized_as = "any")]
+    pub payload: serde_json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    #[typeshare(serialized_as = "String")]
+    pub next_attempt_at: chrono::DateTime<chrono::Utc>,
+    /// The HTTP status code of the last attempt, if it got that far.
+    pub response_status: Option<i32>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[typeshare(serialized_as = "String")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=webhook_deliveries)]
+pub struct NewWebhookDelivery {
+    pub webhook_id: i32,
+    pub event: WebhookEvent,
+    pub payload: serde_json::Value,
+}
+
+/// How to tell that a response comes from someone who already responded to the survey.
+#[typeshare]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateCheck {
+    #[default]
+    Off,
+    /// A signed cookie that is set on the first response.
+    Cookie,
+    /// A hash of the address and user agent of the respondent.
+    Fingerprint,
+    /// Either of the above.
+    Both,
+}
+
+impl DuplicateCheck {
+    pub fn uses_cookie(&self) -> bool {
+        matches!(self, DuplicateCheck::Cookie | DuplicateCheck::Both)
+    }
+
+    pub fn uses_fingerprint(&self) -> bool {
+        matches!(self, DuplicateCheck::Fingerprint | DuplicateCheck::Both)
+    }
+}
+
+text_enum!(DuplicateCheck, "duplicate check" {
+    Off => "off",
+    Cookie => "cookie",
+    Fingerprint => "fingerprint",
+    Both => "both",
+});
+
+/// How often the owner of a survey is emailed about new responses.
+#[typeshare]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestFrequency {
+    #[default]
+    Off,
+    Hourly,
+    Daily,
+}
+
+impl DigestFrequency {
+    /// How long to wait between digests, or `None` if they are turned off.
+    pub fn period(&self) -> Option<chrono::Duration> {
+        match self {
+            DigestFrequency::Off => None,
+            DigestFrequency::Hourly => Some(chrono::Duration::hours(1)),
+            DigestFrequency::Daily => Some(chrono::Duration::days(1)),
+        }
+    }
+}
+
+text_enum!(DigestFrequency, "digest frequency" {
+    Off => "off",
+    Hourly => "hourly",
+    Daily => "daily",
+});
+
+/// What the owner of a survey wants to be emailed about.
+#[typeshare]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
+#[diesel(table_name=notification_preferences)]
+pub struct NotificationPreferences {
+    pub survey_id: i32,
+    pub digest: DigestFrequency,
+    /// Whether to send an email when the survey reaches its response limit.
+    pub limit_alert: bool,
+    /// The next digest covers the responses created after this.
+    #[typeshare(serialized_as = "String")]
+    pub last_digest_at: chrono::DateTime<chrono::Utc>,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[typeshare(serialized_as = "String")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name=notification_preferences)]
+pub struct NewNotificationPreferences {
+    pub survey_id: i32,
+    pub digest: DigestFrequency,
+    pub limit_alert: bool,
+}
+
+/// A token that lets someone respond to an invite-only survey, a limited number of times.
+#[typeshare]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
+#[diesel(table_name=invitations)]
+pub struct Invitation {
+    pub id: i32,
+    pub survey_id: i32,
+    /// The token itself is only shown when the invitation is created.
+    #[serde(skip)]
+    pub token_hash: String,
+    /// Shown in the export, to tell who responded.
+    pub label: Option<String>,
+    pub email: Option<String>,
+    pub max_uses: i32,
+    pub uses: i32,
+    /// Revoked invitations can't be used anymore.
+    #[typeshare(serialized_as = "Option<String>")]
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[typeshare(serialized_as = "String")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=invitations)]
+pub struct NewInvitation {
+    pub survey_id: i32,
+    pub token_hash: String,
+    pub label: Option<String>,
+    pub email: Option<String>,
+    pub max_uses: i32,
+}
+
+/// What was done to a survey, as recorded in its history.
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    Edit,
+    Publish,
+    Delete,
+    Restore,
+    ClearResponses,
+    Revert,
+    /// The survey was given to another user, when its owner deleted their account.
+    Transfer,
+}
+
+text_enum!(HistoryAction, "history action" {
+    Edit => "edit",
+    Publish => "publish",
+    Delete => "delete",
+    Restore => "restore",
+    ClearResponses => "clear_responses",
+    Revert => "revert",
+    Transfer => "transfer",
+});
+
+/// The value of a field before and after a change.
+#[typeshare]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    #[typeshare(serialized_as = "any")]
+    pub before: serde_json::Value,
+    #[typeshare(serialized_as = "any")]
+    pub after: serde_json::Value,
+}
+
+/// The fields that changed, by name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Jsonb)]
+#[typeshare(serialized_as = "HashMap<String, FieldChange>")]
+pub struct SurveyChanges(pub BTreeMap<String, FieldChange>);
+
+impl SurveyChanges {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromSql<Jsonb, Pg> for SurveyChanges {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        let value = <serde_json::Value as FromSql<Jsonb, Pg>>::from_sql(value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for SurveyChanges {
+    fn to_sql(&self, out: &mut diesel::serialize::Output<Pg>) -> diesel::serialize::Result {
+        out.write_all(&[1])?;
+        serde_json::to_writer(out, self)
+            .map(|_| diesel::serialize::IsNull::No)
+            .map_err(Into::into)
+    }
+}
+
+/// An entry in the history of a survey.
+#[typeshare]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
+#[diesel(table_name=survey_history)]
+pub struct SurveyHistoryEntry {
+    pub id: i32,
+    pub survey_id: i32,
+    /// The user that did it, unless their account was deleted since.
+    pub actor_id: Option<i32>,
+    pub action: HistoryAction,
+    pub changes: SurveyChanges,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=survey_history)]
+pub struct NewSurveyHistoryEntry {
+    pub survey_id: i32,
+    pub actor_id: Option<i32>,
+    pub action: HistoryAction,
+    pub changes: SurveyChanges,
+}
+
+/// The answer to a question before and after a response was edited. An answer that is
+/// missing on one side was added or removed by the edit.
+#[typeshare]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnswerChange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<crate::questions::Response>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<crate::questions::Response>,
+}
+
+/// The answers that changed, by question UUID.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Jsonb)]
+#[typeshare(serialized_as = "HashMap<String, AnswerChange>")]
+pub struct ResponseChanges(pub BTreeMap<Uuid, AnswerChange>);
+
+impl ResponseChanges {
+    /// The answers that differ between two versions of a response.
+    pub fn between(before: &SurveyResponses, after: &SurveyResponses) -> Self {
+        let questions = before.0.keys().chain(after.0.keys());
+        Self(
+            questions
+                .filter_map(|uuid| {
+                    let change = AnswerChange {
+                        before: before.0.get(uuid).cloned(),
+                        after: after.0.get(uuid).cloned(),
+                    };
+                    (change.before != change.after).then_some((*uuid, change))
+                })
+                .collect(),
+        )
+    }
+
+    /// Undoes the changes on `responses`, bringing it back to before the edit.
+    pub fn undo(&self, responses: &mut SurveyResponses) {
+        for (uuid, change) in self.0.iter() {
+            match &change.before {
+                Some(before) => responses.0.insert(*uuid, before.clone()),
+                None => responses.0.remove(uuid),
+            };
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromSql<Jsonb, Pg> for ResponseChanges {
+    fn from_sql(value: PgValue) -> diesel::deserialize::Result<Self> {
+        let value = <serde_json::Value as FromSql<Jsonb, Pg>>::from_sql(value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for ResponseChanges {
+    fn to_sql(&self, out: &mut diesel::serialize::Output<Pg>) -> diesel::serialize::Result {
+        out.write_all(&[1])?;
+        serde_json::to_writer(out, self)
+            .map(|_| diesel::serialize::IsNull::No)
+            .map_err(Into::into)
+    }
+}
+
+/// An edit in the history of a response.
+#[typeshare]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
+#[diesel(table_name=response_history)]
+pub struct ResponseHistoryEntry {
+    pub id: i32,
+    #[typeshare(serialized_as = "String")]
+    pub responder_uuid: Uuid,
+    /// The revision of the response the edit made.
+    pub revision: i32,
+    pub changes: ResponseChanges,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=response_history)]
+pub struct NewResponseHistoryEntry {
+    pub responder_uuid: Uuid,
+    pub revision: i32,
+    pub changes: ResponseChanges,
+}
+
+/// What a token sent by email is good for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum AccountTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+text_enum!(AccountTokenPurpose, "account token purpose" {
+    VerifyEmail => "verify_email",
+    ResetPassword => "reset_password",
+});
+
+#[derive(Debug, Queryable)]
+#[diesel(table_name=account_tokens)]
+pub struct AccountToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub purpose: AccountTokenPurpose,
+    pub token_hash: String,
+    /// The address being verified.
+    pub email: Option<String>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=account_tokens)]
+pub struct NewAccountToken {
+    pub user_id: i32,
+    pub purpose: AccountTokenPurpose,
+    pub token_hash: String,
+    pub email: Option<String>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// An account at an OpenID Connect provider, which logs in as the linked user.
+#[derive(Debug, Queryable)]
+#[diesel(table_name=external_identities)]
+pub struct ExternalIdentity {
+    pub id: i32,
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=external_identities)]
+pub struct NewExternalIdentity {
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// A login that was sent to the OpenID Connect provider, kept until it comes back.
+#[derive(Debug, Queryable)]
+#[diesel(table_name=oidc_logins)]
+pub struct OidcLogin {
+    pub id: i32,
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name=oidc_logins)]
+pub struct NewOidcLogin {
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Someone that the owner of a survey lets edit its questions along with them.
+#[typeshare]
+#[derive(Debug, Queryable, Serialize, Deserialize)]
+pub struct Collaborator {
+    pub user_id: i32,
+    pub username: String,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
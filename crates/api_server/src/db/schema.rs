@@ -1,5 +1,103 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    account_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        purpose -> Text,
+        token_hash -> Text,
+        email -> Nullable<Text>,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    external_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        issuer -> Text,
+        subject -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    invitations (id) {
+        id -> Int4,
+        survey_id -> Int4,
+        token_hash -> Text,
+        label -> Nullable<Text>,
+        email -> Nullable<Text>,
+        max_uses -> Int4,
+        uses -> Int4,
+        revoked_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    login_failures (key) {
+        key -> Text,
+        failures -> Int4,
+        last_failed_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    notification_preferences (survey_id) {
+        survey_id -> Int4,
+        digest -> Text,
+        limit_alert -> Bool,
+        last_digest_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    oidc_logins (id) {
+        id -> Int4,
+        state -> Text,
+        code_verifier -> Text,
+        nonce -> Text,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    opened_token_uses (jti) {
+        jti -> Uuid,
+        survey_id -> Int4,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    rate_limit_buckets (key) {
+        key -> Text,
+        tokens -> Float8,
+        refilled_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    response_history (id) {
+        id -> Int4,
+        responder_uuid -> Uuid,
+        revision -> Int4,
+        changes -> Jsonb,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     responses (responder_uuid) {
         survey_id -> Int4,
@@ -7,6 +105,32 @@ diesel::table! {
         content -> Jsonb,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        invitation_id -> Nullable<Int4>,
+        duplicate -> Bool,
+        respondent_cookie -> Nullable<Text>,
+        fingerprint -> Nullable<Text>,
+        quarantine_reason -> Nullable<Text>,
+        revision -> Int4,
+        accepted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    survey_collaborators (survey_id, user_id) {
+        survey_id -> Int4,
+        user_id -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    survey_history (id) {
+        id -> Int4,
+        survey_id -> Int4,
+        actor_id -> Nullable<Int4>,
+        action -> Text,
+        changes -> Jsonb,
+        created_at -> Timestamptz,
     }
 }
 
@@ -15,11 +139,20 @@ diesel::table! {
         id -> Int4,
         title -> Text,
         description -> Text,
-        published -> Bool,
         owner_id -> Int4,
         questions -> Jsonb,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+        status -> Text,
+        response_limit -> Nullable<Int4>,
+        invite_only -> Bool,
+        slug -> Text,
+        access_password_hash -> Nullable<Text>,
+        duplicate_check -> Text,
+        reject_duplicates -> Bool,
+        revision -> Int4,
+        access_password_version -> Int4,
     }
 }
 
@@ -30,10 +163,70 @@ diesel::table! {
         password_hash -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        token_version -> Int4,
+        email -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        event -> Text,
+        payload -> Jsonb,
+        status -> Text,
+        attempts -> Int4,
+        next_attempt_at -> Timestamptz,
+        response_status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        survey_id -> Int4,
+        url -> Text,
+        secret -> Text,
+        events -> Array<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(account_tokens -> users (user_id));
+diesel::joinable!(external_identities -> users (user_id));
+diesel::joinable!(invitations -> surveys (survey_id));
+diesel::joinable!(notification_preferences -> surveys (survey_id));
+diesel::joinable!(opened_token_uses -> surveys (survey_id));
+diesel::joinable!(response_history -> responses (responder_uuid));
+diesel::joinable!(responses -> invitations (invitation_id));
 diesel::joinable!(responses -> surveys (survey_id));
+diesel::joinable!(survey_collaborators -> surveys (survey_id));
+diesel::joinable!(survey_collaborators -> users (user_id));
+diesel::joinable!(survey_history -> surveys (survey_id));
+diesel::joinable!(survey_history -> users (actor_id));
 diesel::joinable!(surveys -> users (owner_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+diesel::joinable!(webhooks -> surveys (survey_id));
 
-diesel::allow_tables_to_appear_in_same_query!(responses, surveys, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    account_tokens,
+    external_identities,
+    invitations,
+    login_failures,
+    notification_preferences,
+    oidc_logins,
+    opened_token_uses,
+    rate_limit_buckets,
+    response_history,
+    responses,
+    survey_collaborators,
+    survey_history,
+    surveys,
+    users,
+    webhook_deliveries,
+    webhooks,
+);
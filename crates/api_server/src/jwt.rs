@@ -1,4 +1,6 @@
+use diesel::prelude::*;
 use rocket::{
+    fairing::AdHoc,
     http::Status,
     request::{FromRequest, Outcome},
     Request,
@@ -6,18 +8,28 @@ use rocket::{
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::db::{schema, Storage};
+
 /// Represents the claims in a JWT.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     user_id: i32,
     /// Expiration time (as UTC timestamp)
     exp: u64,
+    /// The token version of the user when the token was issued. Tokens from before the
+    /// version went up are revoked.
+    #[serde(default)]
+    ver: i32,
 }
 
 impl Claims {
-    pub fn new(user_id: i32) -> Self {
+    pub fn new(user_id: i32, token_version: i32) -> Self {
         let exp = jsonwebtoken::get_current_timestamp() + 2630000;
-        Self { user_id, exp }
+        Self {
+            user_id,
+            exp,
+            ver: token_version,
+        }
     }
 
     pub fn user_id(&self) -> i32 {
@@ -28,46 +40,121 @@ impl Claims {
     pub fn exp(&self) -> u64 {
         self.exp
     }
-}
-
-#[derive(Debug, Serialize, Deserialize, Error)]
-pub enum JwtError {
-    #[error("Invalid token")]
-    InvalidToken,
-    #[error("Expired token")]
-    ExpiredToken,
-}
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for Claims {
-    type Error = JwtError;
 
-    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    /// Reads and checks the token in the `Authorization` header.
+    fn decode(req: &Request<'_>) -> Result<Self, (Status, JwtError)> {
         let Some(auth_header) = req.headers().get_one("Authorization") else {
-            return Outcome::Failure((Status::Unauthorized, JwtError::InvalidToken));
+            return Err((Status::Unauthorized, JwtError::InvalidToken));
         };
         if !auth_header.starts_with("Bearer") {
-            return Outcome::Failure((Status::BadRequest, JwtError::InvalidToken));
+            return Err((Status::BadRequest, JwtError::InvalidToken));
         }
         let Some(token) = auth_header.split(' ').last() else {
-            return Outcome::Failure((Status::BadRequest, JwtError::InvalidToken));
+            return Err((Status::BadRequest, JwtError::InvalidToken));
         };
         let key = req.rocket().config().secret_key.to_string();
         let key = jsonwebtoken::DecodingKey::from_secret(key.as_bytes());
         let validation = jsonwebtoken::Validation::default();
 
         let Ok(token_data) = jsonwebtoken::decode::<Claims>(token, &key, &validation) else {
-            return Outcome::Failure((Status::Unauthorized, JwtError::InvalidToken));
+            return Err((Status::Unauthorized, JwtError::InvalidToken));
         };
 
         if jsonwebtoken::get_current_timestamp() >= token_data.claims.exp {
-            return Outcome::Failure((Status::Unauthorized, JwtError::ExpiredToken));
+            return Err((Status::Unauthorized, JwtError::ExpiredToken));
         }
 
-        Outcome::Success(token_data.claims)
+        Ok(token_data.claims)
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Error)]
+pub enum JwtError {
+    #[error("Invalid token")]
+    InvalidToken,
+    #[error("Expired token")]
+    ExpiredToken,
+    #[error("Revoked token")]
+    RevokedToken,
+    #[error("Could not check the token")]
+    Unavailable,
+}
+
+/// What [`stage`] found out about the token of the request. Only current tokens are let
+/// through, so a token that wasn't checked, because the stage isn't attached, is turned
+/// away like one that couldn't be.
+#[derive(Debug, Clone, Copy, Default)]
+enum TokenCheck {
+    #[default]
+    Unchecked,
+    Current,
+    Revoked,
+    Failed,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Claims {
+    type Error = JwtError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let claims = match Claims::decode(req) {
+            Ok(claims) => claims,
+            Err(failure) => return Outcome::Failure(failure),
+        };
+        match req.local_cache(TokenCheck::default) {
+            TokenCheck::Current => Outcome::Success(claims),
+            TokenCheck::Revoked => Outcome::Failure((Status::Unauthorized, JwtError::RevokedToken)),
+            TokenCheck::Unchecked => {
+                error!("token version wasn't checked, attach `jwt::stage`");
+                Outcome::Failure((Status::InternalServerError, JwtError::Unavailable))
+            }
+            TokenCheck::Failed => {
+                Outcome::Failure((Status::InternalServerError, JwtError::Unavailable))
+            }
+        }
+    }
+}
+
+/// Checks the version of the token against the one of its user, so that changing the
+/// password logs out everywhere else. This is done before routing, so that the [`Claims`]
+/// guard doesn't need a connection on top of the one of the route.
+///
+/// Tokens of users that were deleted are revoked too. If the version can't be checked, the
+/// token is turned away rather than trusted.
+pub fn stage() -> AdHoc {
+    AdHoc::on_request("Token Revocation", |req, _| {
+        Box::pin(async move {
+            let Ok(claims) = Claims::decode(req) else {
+                return;
+            };
+            let Some(db) = Storage::get_one(req.rocket()).await else {
+                error!("no database connection to check the token version with");
+                req.local_cache(|| TokenCheck::Failed);
+                return;
+            };
+            let user_id = claims.user_id;
+            let version = db
+                .run(move |conn| {
+                    schema::users::table
+                        .find(user_id)
+                        .select(schema::users::token_version)
+                        .first::<i32>(conn)
+                        .optional()
+                })
+                .await;
+            let check = match version {
+                Ok(Some(version)) if version == claims.ver => TokenCheck::Current,
+                Ok(_) => TokenCheck::Revoked,
+                Err(e) => {
+                    error!("failed to check the token version: {e:?}");
+                    TokenCheck::Failed
+                }
+            };
+            req.local_cache(|| check);
+        })
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use jsonwebtoken::EncodingKey;
@@ -77,7 +164,11 @@ mod tests {
 
     #[test]
     fn user_id_types_match() {
-        let claims = Claims { user_id: 1, exp: 1 };
+        let claims = Claims {
+            user_id: 1,
+            exp: 1,
+            ver: 0,
+        };
         use crate::db::models::User;
         let user = User {
             id: 0,
@@ -85,6 +176,8 @@ mod tests {
             password_hash: "".to_owned(),
             created_at: chrono::NaiveDateTime::MIN,
             updated_at: chrono::NaiveDateTime::MAX,
+            token_version: 0,
+            email: None,
         };
         // HACK: type_name_of_val is actually in the stdlib, but it's not stable yet.
         fn type_name_of_val<T>(_: T) -> &'static str {
@@ -120,6 +213,21 @@ mod tests {
 
     #[test]
     fn test_accept_valid_jwt() {
+        crate::test_helpers::run_test_with_db(|db_name| {
+            let rocket = crate::test_helpers::test_rocket(db_name).mount("/", routes![test_get]);
+            let client = Client::tracked(rocket).expect("valid rocket instance");
+            let token = crate::test_helpers::create_test_user(&client);
+
+            let mut req = client.get("/");
+            req.add_header(Header::new("Authorization", token));
+            let response = req.dispatch();
+            assert_eq!(response.status(), Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_deny_unchecked_jwt() {
+        // without the stage, the version of the token can't have been checked
         let client = Client::tracked(jwt_rocket::jwt_rocket()).expect("valid rocket instance");
 
         let key =
@@ -127,13 +235,14 @@ mod tests {
         let claims = Claims {
             user_id: 1,
             exp: jsonwebtoken::get_current_timestamp() + 10000000,
+            ver: 0,
         };
         let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
 
         let mut req = client.get("/");
         req.add_header(Header::new("Authorization", format!("Bearer {token}")));
         let response = req.dispatch();
-        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.status(), Status::InternalServerError);
     }
 
     #[test]
@@ -145,6 +254,7 @@ mod tests {
         let claims = Claims {
             user_id: 1,
             exp: jsonwebtoken::get_current_timestamp() + 10000000,
+            ver: 0,
         };
         let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
 
@@ -165,6 +275,7 @@ mod tests {
         let claims = Claims {
             user_id: 1,
             exp: jsonwebtoken::get_current_timestamp() - 200000,
+            ver: 0,
         };
         let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
 
@@ -7,21 +7,39 @@ extern crate diesel;
 
 pub mod api;
 mod cache;
+mod config;
 mod cors;
 pub mod db;
 pub mod jwt;
+pub mod live;
+pub mod mailer;
+pub mod notifications;
 pub mod questions;
+pub mod rate_limit;
 pub mod survey;
 pub mod survey_response;
 // #[cfg(any(test, bench))]
 pub mod test_helpers;
 pub mod user;
 pub mod validate;
+pub mod webhook;
 
 #[launch]
 pub fn rocket() -> _ {
     rocket::build()
         .attach(db::stage())
+        .attach(jwt::stage())
+        .attach(rocket::fairing::AdHoc::config::<survey::SurveyConfig>())
+        .attach(survey::trash::stage())
+        .attach(survey::collab::stage())
+        .attach(webhook::stage())
+        .attach(live::stage())
+        .attach(mailer::stage())
+        .attach(user::account::stage())
+        .attach(user::oidc::stage())
+        .attach(notifications::stage())
+        .attach(survey_response::challenges::stage())
+        .attach(rate_limit::RateLimiting)
         .attach(cors::Cors)
         .mount("/", routes![cors::handle_preflight])
         .mount(
@@ -31,16 +49,52 @@ pub fn rocket() -> _ {
                 user::register_user,
                 user::login_user,
                 user::list_surveys,
+                user::change_password,
+                user::rename_user,
+                user::delete_account,
+                user::set_email,
+                user::verify_email,
+                user::request_password_reset,
+                user::reset_password,
+                user::oidc_login,
+                user::oidc_callback,
                 survey::create_survey,
                 survey::get_survey,
                 survey::get_survey_auth,
+                survey::request_survey_access,
                 survey::edit_survey,
                 survey::delete_survey,
+                survey::restore_survey,
+                survey::get_survey_history,
+                survey::revert_survey,
+                survey::purge_survey,
                 survey::export_responses,
+                survey::import_questions,
+                survey::edit_questions,
+                survey::collaborate,
+                survey::focus,
+                survey::list_collaborators,
+                survey::add_collaborator,
+                survey::remove_collaborator,
+                survey::create_invitations,
+                survey::list_invitations,
+                survey::revoke_invitation,
                 survey_response::create_survey_response,
                 survey_response::edit_survey_response,
                 survey_response::get_survey_response,
+                survey_response::get_response_history,
                 survey_response::clear_survey_responses,
+                survey_response::list_quarantined_responses,
+                survey_response::release_quarantined_response,
+                survey_response::discard_quarantined_response,
+                live::live_responses,
+                notifications::get_notifications,
+                notifications::set_notifications,
+                notifications::delete_notifications,
+                webhook::create_webhook,
+                webhook::list_webhooks,
+                webhook::delete_webhook,
+                webhook::list_webhook_deliveries,
             ],
         )
         .register("/", catchers![api::default_catcher])
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rocket::fairing::AdHoc;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{Shutdown, State};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::ApiErrorResponse;
+use crate::config;
+use crate::db::Storage;
+use crate::jwt::Claims;
+use crate::survey::SurveyError;
+
+/// The Postgres channel events are sent through with the `postgres` backend.
+const NOTIFY_CHANNEL: &str = "survey_live_events";
+/// How long to wait before reconnecting when the connection used to LISTEN is lost.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Configured under `[default.live]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LiveConfig {
+    pub backend: LiveBackend,
+    /// How many events a slow subscriber can fall behind before it misses some. At least 1.
+    pub capacity: usize,
+}
+
+impl Default for LiveConfig {
+    fn default() -> Self {
+        Self {
+            backend: LiveBackend::Memory,
+            capacity: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveBackend {
+    /// Events only reach subscribers connected to the same instance.
+    Memory,
+    /// Events go through Postgres LISTEN/NOTIFY, so they reach every instance.
+    Postgres,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LiveEventKind {
+    #[serde(rename = "response.created")]
+    ResponseCreated,
+    #[serde(rename = "response.updated")]
+    ResponseUpdated,
+    #[serde(rename = "responses.cleared")]
+    ResponsesCleared,
+}
+
+impl LiveEventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LiveEventKind::ResponseCreated => "response.created",
+            LiveEventKind::ResponseUpdated => "response.updated",
+            LiveEventKind::ResponsesCleared => "responses.cleared",
+        }
+    }
+}
+
+/// Something that happened to the responses of a survey. Only ids are sent, so that
+/// events stay well under the size limit of a NOTIFY payload.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveEvent {
+    pub event: LiveEventKind,
+    pub survey_id: i32,
+    /// Not set when the responses are cleared.
+    #[typeshare(serialized_as = "Option<String>")]
+    pub responder_uuid: Option<Uuid>,
+    #[typeshare(serialized_as = "String")]
+    pub occurred_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl LiveEvent {
+    pub fn new(event: LiveEventKind, survey_id: i32, responder_uuid: Option<Uuid>) -> Self {
+        Self {
+            event,
+            survey_id,
+            responder_uuid,
+            occurred_at: chrono::Utc::now(),
+        }
+    }
+}
+
+/// A channel for each survey that is being watched, so that subscribers only get the
+/// events of their own survey.
+struct Channels {
+    capacity: usize,
+    senders: Mutex<HashMap<i32, broadcast::Sender<LiveEvent>>>,
+}
+
+impl Channels {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            senders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn send(&self, event: LiveEvent) {
+        let mut senders = self.senders.lock().unwrap();
+        let survey_id = event.survey_id;
+        if let Some(sender) = senders.get(&survey_id) {
+            // an error means that everyone stopped watching
+            if sender.send(event).is_err() {
+                senders.remove(&survey_id);
+            }
+        }
+    }
+
+    fn subscribe(&self, survey_id: i32) -> broadcast::Receiver<LiveEvent> {
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|_, sender| sender.receiver_count() > 0);
+        senders
+            .entry(survey_id)
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+}
+
+/// Fans out [`LiveEvent`]s to everyone watching [`live_responses`].
+pub struct LiveEvents {
+    backend: LiveBackend,
+    channels: Arc<Channels>,
+    listening: Arc<AtomicBool>,
+}
+
+impl LiveEvents {
+    /// Sends an event to the subscribers. This is best effort, so failures are only logged.
+    pub async fn publish(&self, db: &Storage, event: LiveEvent) {
+        match self.backend {
+            LiveBackend::Memory => self.channels.send(event),
+            LiveBackend::Postgres => {
+                let payload = match serde_json::to_string(&event) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        error!("failed to serialize live event: {e:?}");
+                        return;
+                    }
+                };
+                let notified = db
+                    .run(move |conn| {
+                        diesel::sql_query("SELECT pg_notify($1, $2)")
+                            .bind::<Text, _>(NOTIFY_CHANNEL)
+                            .bind::<Text, _>(payload)
+                            .execute(conn)
+                    })
+                    .await;
+                if let Err(e) = notified {
+                    error!("failed to notify live event: {e:?}");
+                }
+            }
+        }
+    }
+
+    pub fn subscribe(&self, survey_id: i32) -> broadcast::Receiver<LiveEvent> {
+        self.channels.subscribe(survey_id)
+    }
+
+    /// Whether events published now will reach the subscribers. This is only false while
+    /// the `postgres` backend is (re)connecting.
+    pub fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::Relaxed)
+    }
+}
+
+/// Streams an event whenever a response to the survey is created, edited or cleared.
+///
+/// If the client falls too far behind, a `lagged` event is sent in place of the events it
+/// missed, and it should refetch whatever it is showing.
+#[get("/survey/<survey_id>/live")]
+pub async fn live_responses(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+    live: &State<LiveEvents>,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], ApiErrorResponse<SurveyError>> {
+    let survey = crate::survey::get_survey_from_db(&db, survey_id)
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::NotFound
+        })?;
+
+    if survey.owner_id != claims.user_id() {
+        return Err(SurveyError::NotOwner.into());
+    }
+
+    let mut events = live.subscribe(survey_id);
+    Ok(EventStream! {
+        loop {
+            let event = rocket::tokio::select! {
+                event = events.recv() => event,
+                _ = &mut shutdown => break,
+            };
+            match event {
+                Ok(event) => yield Event::json(&event).event(event.event.as_str()),
+                Err(RecvError::Lagged(_)) => yield Event::empty().event("lagged"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Relays notifications from Postgres to the local subscribers, reconnecting whenever the
+/// connection is lost.
+async fn listen(
+    url: String,
+    channels: Arc<Channels>,
+    listening: Arc<AtomicBool>,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        let result = rocket::tokio::select! {
+            result = relay_notifications(&url, &channels, &listening) => result,
+            _ = &mut shutdown => return,
+        };
+        listening.store(false, Ordering::Relaxed);
+        if let Err(e) = result {
+            error!("lost the connection for live events: {e:?}");
+        }
+        rocket::tokio::select! {
+            _ = rocket::tokio::time::sleep(RECONNECT_DELAY) => {},
+            _ = &mut shutdown => return,
+        }
+    }
+}
+
+async fn relay_notifications(
+    url: &str,
+    channels: &Channels,
+    listening: &AtomicBool,
+) -> anyhow::Result<()> {
+    use tokio_postgres::AsyncMessage;
+
+    let (client, mut connection) = tokio_postgres::connect(url, tokio_postgres::NoTls).await?;
+
+    // the connection has to be polled for the LISTEN to go through
+    let query = format!("LISTEN {NOTIFY_CHANNEL}");
+    let listen = client.batch_execute(&query);
+    rocket::tokio::pin!(listen);
+    loop {
+        rocket::tokio::select! {
+            result = &mut listen => {
+                result?;
+                break;
+            }
+            message = std::future::poll_fn(|cx| connection.poll_message(cx)) => {
+                if message.transpose()?.is_none() {
+                    anyhow::bail!("connection closed");
+                }
+            }
+        }
+    }
+    listening.store(true, Ordering::Relaxed);
+
+    while let Some(message) = std::future::poll_fn(|cx| connection.poll_message(cx))
+        .await
+        .transpose()?
+    {
+        if let AsyncMessage::Notification(notification) = message {
+            match serde_json::from_str::<LiveEvent>(notification.payload()) {
+                Ok(event) => channels.send(event),
+                Err(e) => error!("received a bad live event: {e:?}"),
+            }
+        }
+    }
+    anyhow::bail!("connection closed")
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Live Events", |rocket| async {
+        let Some(config) = config::section::<LiveConfig>(rocket.figment(), "live") else {
+            return Err(rocket);
+        };
+        if config.capacity == 0 {
+            warn!("live.capacity has to be at least 1, using 1");
+        }
+        let channels = Arc::new(Channels::new(config.capacity.max(1)));
+        let listening = Arc::new(AtomicBool::new(config.backend == LiveBackend::Memory));
+
+        let rocket = rocket.manage(LiveEvents {
+            backend: config.backend,
+            channels: channels.clone(),
+            listening: listening.clone(),
+        });
+        if config.backend != LiveBackend::Postgres {
+            return Ok(rocket);
+        }
+
+        Ok(
+            rocket.attach(AdHoc::on_liftoff("Live Events Listener", |rocket| {
+                Box::pin(async move {
+                    let url = match rocket
+                        .figment()
+                        .extract_inner::<String>("databases.survey_app.url")
+                    {
+                        Ok(url) => url,
+                        Err(e) => {
+                            error!("no database url to listen for live events on: {e:?}");
+                            return;
+                        }
+                    };
+                    rocket::tokio::spawn(listen(url, channels, listening, rocket.shutdown()));
+                })
+            })),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::io::BufReader;
+
+    use rocket::local::blocking::Client;
+
+    use crate::db::models::SurveyResponses;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn check_live_events(client: &Client) {
+        let token = create_test_user(client);
+        let survey_id = make_survey(client, &token);
+        let other_survey_id = make_survey(client, &token);
+        publish_survey(client, &token, survey_id);
+        publish_survey(client, &token, other_survey_id);
+
+        let response = client
+            .get(uri!("/api", live_responses(survey_id)).to_string())
+            .header(rocket::http::Header::new("Authorization", token.clone()))
+            .dispatch();
+        assert_eq!(response.status(), rocket::http::Status::Ok);
+        let mut stream = BufReader::new(response);
+
+        for id in [other_survey_id, survey_id] {
+            client
+                .post(
+                    uri!(
+                        "/api",
+                        crate::survey_response::create_survey_response(id, _)
+                    )
+                    .to_string(),
+                )
+                .header(rocket::http::ContentType::JSON)
+                .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                .dispatch();
+        }
+
+        let (name, data) = next_event(&mut stream);
+        assert_eq!(name, "response.created");
+        let event = serde_json::from_str::<LiveEvent>(&data).unwrap();
+        assert_eq!(event.survey_id, survey_id);
+        assert!(event.responder_uuid.is_some());
+
+        client
+            .delete(
+                uri!(
+                    "/api",
+                    crate::survey_response::clear_survey_responses(survey_id)
+                )
+                .to_string(),
+            )
+            .header(rocket::http::Header::new("Authorization", token))
+            .dispatch();
+
+        let (name, data) = next_event(&mut stream);
+        assert_eq!(name, "responses.cleared");
+        let event = serde_json::from_str::<LiveEvent>(&data).unwrap();
+        assert_eq!(event.responder_uuid, None);
+    }
+
+    #[test]
+    fn test_live_events() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+            check_live_events(&client);
+        });
+    }
+
+    #[test]
+    fn test_live_events_zero_capacity() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket.figment().clone().merge(("live.capacity", 0));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+            check_live_events(&client);
+        });
+    }
+
+    #[test]
+    fn test_live_events_postgres() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket.figment().clone().merge(("live.backend", "postgres"));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            let live = client.rocket().state::<LiveEvents>().unwrap();
+            for _ in 0..100 {
+                if live.is_listening() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            assert!(live.is_listening());
+
+            check_live_events(&client);
+        });
+    }
+
+    #[test]
+    fn test_live_events_not_owner() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let token = create_test_user(&client);
+            let response = client
+                .get(uri!("/api", live_responses(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Forbidden);
+        });
+    }
+}
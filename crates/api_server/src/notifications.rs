@@ -0,0 +1,721 @@
+use std::time::Duration;
+
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use rocket::{fairing::AdHoc, http::Status, serde::json::Json};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    api::ApiErrorResponse,
+    db::{
+        models::{DigestFrequency, NewNotificationPreferences, NotificationPreferences},
+        schema, Storage,
+    },
+    jwt::Claims,
+    mailer::{Email, Outbox},
+    survey::{check_survey_owner, OwnerCheckError},
+};
+
+/// How often to look for digests that are due.
+const DIGEST_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum NotificationError {
+    #[error("Survey not found")]
+    SurveyNotFound,
+    #[error("Not survey owner")]
+    NotSurveyOwner,
+    #[error("Notifications are not set up for this survey")]
+    NotConfigured,
+    #[error("The account has no verified email address")]
+    EmailNotVerified,
+    #[error("Internal error")]
+    Unknown,
+}
+
+impl From<NotificationError> for ApiErrorResponse<NotificationError> {
+    fn from(value: NotificationError) -> Self {
+        let status = match &value {
+            NotificationError::SurveyNotFound => Status::NotFound,
+            NotificationError::NotSurveyOwner => Status::Forbidden,
+            NotificationError::NotConfigured => Status::NotFound,
+            NotificationError::EmailNotVerified => Status::Conflict,
+            NotificationError::Unknown => Status::InternalServerError,
+        };
+        ApiErrorResponse {
+            status,
+            message: value,
+        }
+    }
+}
+
+impl From<OwnerCheckError> for ApiErrorResponse<NotificationError> {
+    fn from(value: OwnerCheckError) -> Self {
+        match value {
+            OwnerCheckError::NotFound => NotificationError::SurveyNotFound,
+            OwnerCheckError::NotOwner => NotificationError::NotSurveyOwner,
+            OwnerCheckError::Unknown => NotificationError::Unknown,
+        }
+        .into()
+    }
+}
+
+/// The body of a request to set up the notifications of a survey. Notifications are sent
+/// to the verified email address of the owner's account.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationParams {
+    /// Digests are off unless this is set.
+    #[serde(default)]
+    pub digest: DigestFrequency,
+    /// Defaults to true.
+    pub limit_alert: Option<bool>,
+}
+
+#[get("/survey/<survey_id>/notifications")]
+pub async fn get_notifications(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<NotificationPreferences>, ApiErrorResponse<NotificationError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let preferences = db
+        .run(move |conn| {
+            schema::notification_preferences::table
+                .find(survey_id)
+                .first::<NotificationPreferences>(conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            NotificationError::Unknown
+        })?
+        .ok_or(NotificationError::NotConfigured)?;
+
+    Ok(Json(preferences))
+}
+
+#[put("/survey/<survey_id>/notifications", data = "<params>")]
+pub async fn set_notifications(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+    params: Json<NotificationParams>,
+) -> Result<Json<NotificationPreferences>, ApiErrorResponse<NotificationError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    // only ever send to an address the owner proved they have, so that surveys can't be
+    // used to send mail to anyone else
+    let user_id = claims.user_id();
+    db.run(move |conn| {
+        schema::users::table
+            .find(user_id)
+            .select(schema::users::email)
+            .first::<Option<String>>(conn)
+    })
+    .await
+    .map_err(|e| {
+        error!("{e:?}");
+        NotificationError::Unknown
+    })?
+    .ok_or(NotificationError::EmailNotVerified)?;
+
+    let params = params.into_inner();
+    let new_preferences = NewNotificationPreferences {
+        survey_id,
+        digest: params.digest,
+        limit_alert: params.limit_alert.unwrap_or(true),
+    };
+    let preferences = db
+        .run(move |conn| {
+            diesel::insert_into(schema::notification_preferences::table)
+                .values(&new_preferences)
+                .on_conflict(schema::notification_preferences::survey_id)
+                .do_update()
+                .set(&new_preferences)
+                .get_result::<NotificationPreferences>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            NotificationError::Unknown
+        })?;
+
+    Ok(Json(preferences))
+}
+
+#[delete("/survey/<survey_id>/notifications")]
+pub async fn delete_notifications(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<()>, ApiErrorResponse<NotificationError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let deleted = db
+        .run(move |conn| {
+            diesel::delete(schema::notification_preferences::table.find(survey_id)).execute(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            NotificationError::Unknown
+        })?;
+    if deleted == 0 {
+        return Err(NotificationError::NotConfigured.into());
+    }
+
+    Ok(Json(()))
+}
+
+/// Lets the owner know that a survey was closed because it reached its response limit, if
+/// they asked to be. Failures are only logged, since the survey is closed either way.
+pub(crate) async fn send_limit_alert(db: &Storage, outbox: &Outbox, survey_id: i32) {
+    let recipient = db
+        .run(move |conn| {
+            schema::notification_preferences::table
+                .inner_join(schema::surveys::table.inner_join(schema::users::table))
+                .filter(schema::notification_preferences::survey_id.eq(survey_id))
+                .filter(schema::notification_preferences::limit_alert)
+                .filter(schema::users::email.is_not_null())
+                .select((
+                    schema::users::email.assume_not_null(),
+                    schema::surveys::title,
+                    schema::surveys::response_limit,
+                ))
+                .first::<(String, String, Option<i32>)>(conn)
+                .optional()
+        })
+        .await;
+
+    let (to, title, limit) = match recipient {
+        Ok(Some(recipient)) => recipient,
+        Ok(None) => return,
+        Err(e) => {
+            error!("failed to look up the response limit alert: {e:?}");
+            return;
+        }
+    };
+    let email = Email {
+        to,
+        subject: format!("\"{title}\" reached its response limit"),
+        body: format!(
+            "\"{title}\" has received {} responses, which is its response limit, so it \
+            has been closed.\n\nRaise or remove the limit before reopening it to collect \
+            more responses.\n",
+            limit.unwrap_or_default()
+        ),
+    };
+    if let Err(e) = outbox.send(email).await {
+        error!("failed to send the response limit alert for survey {survey_id}: {e:?}");
+    }
+}
+
+/// A summary of the responses a survey received since the last digest.
+#[derive(Debug)]
+pub(crate) struct Digest {
+    pub survey_id: i32,
+    pub email: String,
+    pub title: String,
+    pub new_responses: i64,
+    /// When the previous digest was sent.
+    pub since: chrono::DateTime<chrono::Utc>,
+    /// When this digest was claimed, which the next one will start from.
+    pub until: chrono::DateTime<chrono::Utc>,
+}
+
+impl Digest {
+    fn email(&self) -> Email {
+        let responses = if self.new_responses == 1 {
+            "1 new response".to_string()
+        } else {
+            format!("{} new responses", self.new_responses)
+        };
+        Email {
+            to: self.email.clone(),
+            subject: format!("{responses} to \"{}\"", self.title),
+            body: format!(
+                "\"{}\" received {responses} since {} UTC.\n",
+                self.title,
+                self.since.format("%Y-%m-%d %H:%M")
+            ),
+        }
+    }
+}
+
+// the digest query groups by the survey along with what the email needs from it
+diesel::allow_columns_to_appear_in_same_group_by_clause!(
+    schema::surveys::id,
+    schema::surveys::title,
+    schema::users::email,
+    schema::notification_preferences::last_digest_at,
+);
+
+/// Claims the digests that are due, so that no other instance sends them too. Surveys
+/// without new responses are skipped, but still have to wait a full period before their
+/// next digest. Responses count from when they were accepted, so that ones released from
+/// quarantine make it into the next digest.
+pub(crate) fn claim_due_digests(conn: &mut PgConnection) -> QueryResult<Vec<Digest>> {
+    use schema::notification_preferences::dsl::*;
+
+    let claimed_at = chrono::Utc::now();
+    let due = |frequency: DigestFrequency| {
+        digest.eq(frequency).and(
+            last_digest_at.le(claimed_at
+                - frequency
+                    .period()
+                    .expect("digests that are not off have a period")),
+        )
+    };
+    conn.build_transaction().read_write().run(|conn| {
+        let claimed = notification_preferences
+            .filter(
+                due(DigestFrequency::Hourly)
+                    .or(due(DigestFrequency::Daily))
+                    .and(
+                        survey_id.eq_any(
+                            schema::surveys::table
+                                .filter(schema::surveys::deleted_at.is_null())
+                                .select(schema::surveys::id),
+                        ),
+                    ),
+            )
+            .select((survey_id, last_digest_at))
+            .for_update()
+            .skip_locked()
+            .load::<(i32, chrono::DateTime<chrono::Utc>)>(conn)?;
+
+        let claimed_ids = claimed.iter().map(|(id, ..)| *id).collect::<Vec<_>>();
+        // counted before the claim moves `last_digest_at`, which is where each one starts
+        let counts = schema::responses::table
+            .inner_join(
+                schema::surveys::table
+                    .inner_join(schema::users::table)
+                    .inner_join(notification_preferences),
+            )
+            .filter(schema::surveys::id.eq_any(&claimed_ids))
+            .filter(schema::responses::accepted_at.gt(last_digest_at.nullable()))
+            .filter(schema::responses::accepted_at.le(claimed_at))
+            .filter(schema::users::email.is_not_null())
+            .group_by((
+                schema::surveys::id,
+                schema::surveys::title,
+                schema::users::email,
+                last_digest_at,
+            ))
+            .select((
+                schema::surveys::id,
+                schema::surveys::title,
+                schema::users::email,
+                last_digest_at,
+                count_star(),
+            ))
+            .load::<(
+                i32,
+                String,
+                Option<String>,
+                chrono::DateTime<chrono::Utc>,
+                i64,
+            )>(conn)?;
+
+        diesel::update(notification_preferences)
+            .filter(survey_id.eq_any(&claimed_ids))
+            .set(last_digest_at.eq(claimed_at))
+            .execute(conn)?;
+
+        let digests = counts
+            .into_iter()
+            .filter_map(
+                |(claimed_survey_id, title, owner_email, since, new_responses)| {
+                    Some(Digest {
+                        survey_id: claimed_survey_id,
+                        email: owner_email?,
+                        title,
+                        new_responses,
+                        since,
+                        until: claimed_at,
+                    })
+                },
+            )
+            .collect();
+        Ok(digests)
+    })
+}
+
+/// Puts a digest that could not be sent back, so that it is retried on the next check.
+fn unclaim_digest(conn: &mut PgConnection, claimed: &Digest) -> QueryResult<usize> {
+    use schema::notification_preferences::dsl::*;
+
+    diesel::update(notification_preferences.find(claimed.survey_id))
+        .filter(last_digest_at.eq(claimed.until))
+        .set(last_digest_at.eq(claimed.since))
+        .execute(conn)
+}
+
+pub(crate) async fn send_digest(outbox: &Outbox, digest: &Digest) -> anyhow::Result<()> {
+    outbox.send(digest.email()).await
+}
+
+/// Periodically emails the owners of surveys that asked for digests about the responses
+/// their surveys received since the last one.
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Notification Digests", |rocket| {
+        Box::pin(async move {
+            let Some(pool) = Storage::pool(rocket).cloned() else {
+                error!("database pool is not available, digests will not be sent");
+                return;
+            };
+            let Some(outbox) = rocket.state::<Outbox>().cloned() else {
+                error!("mailer is not available, digests will not be sent");
+                return;
+            };
+            let mut shutdown = rocket.shutdown();
+
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(DIGEST_CHECK_INTERVAL);
+                loop {
+                    rocket::tokio::select! {
+                        _ = interval.tick() => {},
+                        _ = &mut shutdown => break,
+                    }
+
+                    let Some(conn) = pool.get().await else {
+                        continue;
+                    };
+                    let digests = match conn.run(claim_due_digests).await {
+                        Ok(digests) => digests,
+                        Err(e) => {
+                            error!("failed to claim digests: {e:?}");
+                            continue;
+                        }
+                    };
+                    // don't hold on to a connection while waiting on the mail server
+                    drop(conn);
+
+                    for digest in digests {
+                        let Err(e) = send_digest(&outbox, &digest).await else {
+                            continue;
+                        };
+                        error!(
+                            "failed to send digest for survey {}: {e:?}",
+                            digest.survey_id
+                        );
+                        let Some(conn) = pool.get().await else {
+                            continue;
+                        };
+                        if let Err(e) = conn.run(move |conn| unclaim_digest(conn, &digest)).await {
+                            error!("failed to put back digest: {e:?}");
+                        }
+                    }
+                }
+            });
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use rocket::local::blocking::Client;
+    use uuid::Uuid;
+
+    use crate::db::models::{Survey, SurveyPatch, SurveyResponses, SurveyStatus};
+    use crate::mailer::FileMailer;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn mail_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("survey_app_mail_{}", uuid::Uuid::new_v4()))
+    }
+
+    fn respond(client: &Client, survey_id: i32) -> rocket::http::Status {
+        client
+            .post(
+                uri!(
+                    "/api",
+                    crate::survey_response::create_survey_response(survey_id, _)
+                )
+                .to_string(),
+            )
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+            .dispatch()
+            .status()
+    }
+
+    /// Gives the owner of the survey a verified email address, as if they had clicked the
+    /// link in the verification email.
+    fn verify_owner_email(db_name: &str, survey_id: i32, email: &str) {
+        let mut conn =
+            PgConnection::establish(&format!("postgres://vscode:notsecure@db/{db_name}"))
+                .expect("Failed to connect to database");
+        diesel::update(schema::users::table)
+            .filter(
+                schema::users::id.eq_any(
+                    schema::surveys::table
+                        .find(survey_id)
+                        .select(schema::surveys::owner_id),
+                ),
+            )
+            .set(schema::users::email.eq(email))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    fn set_preferences(
+        client: &Client,
+        token: &str,
+        survey_id: i32,
+        params: &NotificationParams,
+    ) -> rocket::http::Status {
+        client
+            .put(uri!("/api", set_notifications(survey_id)).to_string())
+            .header(rocket::http::ContentType::JSON)
+            .header(rocket::http::Header::new("Authorization", token.to_owned()))
+            .body(serde_json::to_vec(params).unwrap())
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn test_notification_preferences() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let response = client
+                .get(uri!("/api", get_notifications(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotFound);
+
+            let params = NotificationParams {
+                digest: DigestFrequency::Daily,
+                limit_alert: None,
+            };
+            let status = set_preferences(&client, &token, survey_id, &params);
+            assert_eq!(status, rocket::http::Status::Conflict);
+
+            verify_owner_email(db_name, survey_id, "owner@example.com");
+            let status = set_preferences(&client, &token, survey_id, &params);
+            assert_eq!(status, rocket::http::Status::Ok);
+
+            let response = client
+                .get(uri!("/api", get_notifications(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            let preferences = response.into_json::<NotificationPreferences>().unwrap();
+            assert_eq!(preferences.digest, DigestFrequency::Daily);
+            assert!(preferences.limit_alert);
+
+            let other_token = create_test_user(&client);
+            let status = set_preferences(&client, &other_token, survey_id, &params);
+            assert_eq!(status, rocket::http::Status::Forbidden);
+
+            let response = client
+                .delete(uri!("/api", delete_notifications(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            let response = client
+                .get(uri!("/api", get_notifications(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotFound);
+        });
+    }
+
+    #[test]
+    fn test_response_limit_alert() {
+        run_test_with_db(|db_name| {
+            let mail_dir = mail_dir();
+            let client = Client::tracked(test_rocket_with_mailer(db_name, &mail_dir))
+                .expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            verify_owner_email(db_name, survey_id, "owner@example.com");
+            let params = NotificationParams {
+                digest: DigestFrequency::Off,
+                limit_alert: Some(true),
+            };
+            set_preferences(&client, &token, survey_id, &params);
+            let response = client
+                .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&SurveyPatch {
+                        status: Some(SurveyStatus::Open),
+                        response_limit: Some(Some(2)),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            assert_eq!(respond(&client, survey_id), rocket::http::Status::Ok);
+            assert!(read_mail(&mail_dir).is_empty());
+            assert_eq!(respond(&client, survey_id), rocket::http::Status::Ok);
+            assert_eq!(respond(&client, survey_id), rocket::http::Status::Forbidden);
+
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey_auth(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            let survey = response.into_json::<Survey>().unwrap();
+            assert_eq!(survey.status, SurveyStatus::Closed);
+            assert_eq!(survey.response_count, Some(2));
+
+            let mail = read_mail(&mail_dir);
+            std::fs::remove_dir_all(&mail_dir).ok();
+            assert_eq!(mail.len(), 1);
+            assert!(mail[0].contains("To: owner@example.com"));
+            assert!(mail[0].contains("reached its response limit"));
+        });
+    }
+
+    #[test]
+    fn test_digests() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let quiet_survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            verify_owner_email(db_name, survey_id, "owner@example.com");
+            for id in [survey_id, quiet_survey_id] {
+                let params = NotificationParams {
+                    digest: DigestFrequency::Hourly,
+                    limit_alert: None,
+                };
+                set_preferences(&client, &token, id, &params);
+            }
+            respond(&client, survey_id);
+            respond(&client, survey_id);
+
+            let mut conn =
+                PgConnection::establish(&format!("postgres://vscode:notsecure@db/{db_name}"))
+                    .expect("Failed to connect to database");
+            // nothing is due until an hour after the preferences were set
+            assert!(claim_due_digests(&mut conn).unwrap().is_empty());
+
+            let an_hour_ago = chrono::Utc::now() - chrono::Duration::minutes(61);
+            diesel::update(schema::notification_preferences::table)
+                .set(schema::notification_preferences::last_digest_at.eq(an_hour_ago))
+                .execute(&mut conn)
+                .unwrap();
+            let digests = claim_due_digests(&mut conn).unwrap();
+            assert_eq!(digests.len(), 1);
+            assert_eq!(digests[0].survey_id, survey_id);
+            assert_eq!(digests[0].new_responses, 2);
+            assert_eq!(digests[0].email, "owner@example.com");
+            // both surveys were claimed, so neither is due again
+            assert!(claim_due_digests(&mut conn).unwrap().is_empty());
+
+            let mail_dir = mail_dir();
+            let outbox = Outbox::new(
+                "Survey App <noreply@localhost>".parse().unwrap(),
+                Arc::new(FileMailer::new(&mail_dir)),
+            );
+            rocket::tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(send_digest(&outbox, &digests[0]))
+                .unwrap();
+            let mail = read_mail(&mail_dir);
+            std::fs::remove_dir_all(&mail_dir).ok();
+            assert_eq!(mail.len(), 1);
+            assert!(mail[0].contains("2 new responses"));
+        });
+    }
+
+    #[test]
+    fn test_digest_counts_released_responses() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            verify_owner_email(db_name, survey_id, "owner@example.com");
+            let params = NotificationParams {
+                digest: DigestFrequency::Hourly,
+                limit_alert: None,
+            };
+            set_preferences(&client, &token, survey_id, &params);
+            respond(&client, survey_id);
+            respond(&client, survey_id);
+
+            let mut conn =
+                PgConnection::establish(&format!("postgres://vscode:notsecure@db/{db_name}"))
+                    .expect("Failed to connect to database");
+            let responders = schema::responses::table
+                .select(schema::responses::responder_uuid)
+                .load::<Uuid>(&mut conn)
+                .unwrap();
+            // one response was quarantined, the other accepted before the last digest
+            diesel::update(schema::responses::table.find(responders[0]))
+                .set((
+                    schema::responses::quarantine_reason.eq("honeypot was filled in"),
+                    schema::responses::accepted_at.eq(None::<chrono::DateTime<chrono::Utc>>),
+                ))
+                .execute(&mut conn)
+                .unwrap();
+            let now = chrono::Utc::now();
+            diesel::update(schema::responses::table.find(responders[1]))
+                .set(schema::responses::accepted_at.eq(now - chrono::Duration::minutes(90)))
+                .execute(&mut conn)
+                .unwrap();
+            diesel::update(schema::notification_preferences::table)
+                .set(
+                    schema::notification_preferences::last_digest_at
+                        .eq(now - chrono::Duration::hours(2)),
+                )
+                .execute(&mut conn)
+                .unwrap();
+            let digests = claim_due_digests(&mut conn).unwrap();
+            assert_eq!(digests.len(), 1);
+            assert_eq!(digests[0].new_responses, 1);
+
+            // released long after it was created, it counts for the next digest
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        crate::survey_response::quarantine::release_quarantined_response(
+                            survey_id,
+                            responders[0]
+                        )
+                    )
+                    .to_string(),
+                )
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            diesel::update(schema::notification_preferences::table)
+                .set(
+                    schema::notification_preferences::last_digest_at
+                        .eq(now - chrono::Duration::minutes(61)),
+                )
+                .execute(&mut conn)
+                .unwrap();
+            let digests = claim_due_digests(&mut conn).unwrap();
+            assert_eq!(digests.len(), 1);
+            assert_eq!(digests[0].new_responses, 1);
+        });
+    }
+}
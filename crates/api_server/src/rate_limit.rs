@@ -0,0 +1,907 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Build, Orbit, Request, Response, Rocket};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config;
+use crate::db::{schema, Storage};
+
+/// How often buckets and login failures that have been forgotten are cleaned up.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// How often a login attempt is retried when another attempt for the same username and
+/// address is counted at the same time.
+const LOGIN_ATTEMPT_RETRIES: usize = 3;
+
+/// The routes that take the [`RateLimit`] guard, which are the only ones that can be
+/// limited. Limits for any other route are refused at ignition, since they would do
+/// nothing.
+pub const LIMITED_ROUTES: &[&str] = &[
+    "create_survey_response",
+    "register_user",
+    "login_user",
+    "request_survey_access",
+    "change_password",
+    "delete_account",
+    "set_email",
+    "request_password_reset",
+    "reset_password",
+    "oidc_login",
+    "oidc_callback",
+];
+
+/// Configured under `[default.rate_limit]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub store: RateLimitStore,
+    /// Limits by route name, like `login_user`, out of the [`LIMITED_ROUTES`]. Routes that
+    /// aren't listed aren't limited.
+    pub limits: HashMap<String, Limit>,
+    pub login_backoff: LoginBackoffConfig,
+    /// A header like `X-Real-IP` that the proxy in front of the server sets to the client
+    /// address. Clients can send any header themselves, so none is believed unless it is
+    /// named here, and the address of the connection is used instead.
+    pub trusted_proxy_header: Option<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let limits = [
+            ("create_survey_response", Limit::new(20, 10)),
+            ("register_user", Limit::new(5, 2)),
+            ("login_user", Limit::new(10, 10)),
+            ("request_survey_access", Limit::new(10, 5)),
+            ("change_password", Limit::new(5, 2)),
+            ("delete_account", Limit::new(5, 2)),
+            ("set_email", Limit::new(5, 2)),
+            ("request_password_reset", Limit::new(5, 2)),
+            ("reset_password", Limit::new(10, 5)),
+            ("oidc_login", Limit::new(10, 10)),
+            ("oidc_callback", Limit::new(10, 10)),
+        ];
+        Self {
+            enabled: true,
+            store: RateLimitStore::Memory,
+            limits: limits
+                .into_iter()
+                .map(|(route, limit)| (route.to_string(), limit))
+                .collect(),
+            login_backoff: LoginBackoffConfig::default(),
+            trusted_proxy_header: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStore {
+    /// Every instance keeps its own limits.
+    Memory,
+    /// The limits are kept in Postgres, so that they are shared by every instance.
+    Postgres,
+}
+
+/// A token bucket, which holds up to `burst` requests and refills at `per_minute`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Limit {
+    pub fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// After `free_attempts` failed logins for a username from one address, each further
+/// attempt has to wait twice as long as the last one, starting at `base_delay` seconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoginBackoffConfig {
+    pub free_attempts: u32,
+    pub base_delay: u64,
+    pub max_delay: u64,
+    /// Failures are forgotten after this many seconds without another one.
+    pub forget_after: u64,
+}
+
+impl Default for LoginBackoffConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: 5,
+            base_delay: 1,
+            max_delay: 15 * 60,
+            forget_after: 60 * 60,
+        }
+    }
+}
+
+impl LoginBackoffConfig {
+    /// How long to wait after the last of `failures` failed logins.
+    fn delay(&self, failures: u32) -> Duration {
+        let Some(doublings) = failures.checked_sub(self.free_attempts) else {
+            return Duration::ZERO;
+        };
+        let delay = self
+            .base_delay
+            .saturating_mul(1u64.checked_shl(doublings).unwrap_or(u64::MAX));
+        Duration::from_secs(delay.min(self.max_delay))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn full(limit: &Limit, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            refilled_at: now,
+        }
+    }
+
+    fn refilled(&self, limit: &Limit, now: DateTime<Utc>) -> f64 {
+        let elapsed = (now - self.refilled_at).to_std().unwrap_or_default();
+        (self.tokens + elapsed.as_secs_f64() * limit.per_second()).min(f64::from(limit.burst))
+    }
+
+    /// A full bucket is the same as no bucket at all, so it doesn't need to be kept.
+    fn is_full(&self, limit: &Limit, now: DateTime<Utc>) -> bool {
+        self.refilled(limit, now) >= f64::from(limit.burst)
+    }
+
+    /// Takes a token for one request, or returns how long it will be until there is one.
+    fn take(&mut self, limit: &Limit, now: DateTime<Utc>) -> Result<(), Duration> {
+        self.tokens = self.refilled(limit, now);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else if limit.per_minute == 0 {
+            Err(Duration::MAX)
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.per_second(),
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LoginFailures {
+    failures: u32,
+    last_failed_at: DateTime<Utc>,
+}
+
+impl LoginFailures {
+    fn is_forgotten(&self, forget_after: u64, now: DateTime<Utc>) -> bool {
+        let since_last_failure = (now - self.last_failed_at).to_std().unwrap_or_default();
+        since_last_failure.as_secs() > forget_after
+    }
+
+    /// Counts another attempt after these failures, unless it has to wait longer; then
+    /// returns how much longer.
+    fn attempt(
+        previous: Option<Self>,
+        config: &LoginBackoffConfig,
+        now: DateTime<Utc>,
+    ) -> Result<Self, Duration> {
+        let failures = match previous {
+            Some(previous) if !previous.is_forgotten(config.forget_after, now) => {
+                let since_last_failure =
+                    (now - previous.last_failed_at).to_std().unwrap_or_default();
+                match config
+                    .delay(previous.failures)
+                    .checked_sub(since_last_failure)
+                {
+                    Some(remaining) if !remaining.is_zero() => return Err(remaining),
+                    _ => previous.failures,
+                }
+            }
+            _ => 0,
+        };
+        Ok(Self {
+            failures: failures.saturating_add(1),
+            last_failed_at: now,
+        })
+    }
+}
+
+impl From<(i32, DateTime<Utc>)> for LoginFailures {
+    fn from((failures, last_failed_at): (i32, DateTime<Utc>)) -> Self {
+        Self {
+            failures: failures.unsigned_abs(),
+            last_failed_at,
+        }
+    }
+}
+
+/// Keeps the token buckets and failed logins, in memory or in Postgres. Clones share the
+/// same memory store.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: Arc<RateLimitConfig>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+    login_failures: Arc<Mutex<HashMap<String, LoginFailures>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            buckets: Default::default(),
+            login_failures: Default::default(),
+        }
+    }
+
+    /// Takes a token from the bucket for `key`.
+    async fn take(&self, db: Option<&Storage>, key: String, limit: Limit) -> Result<(), Duration> {
+        let now = Utc::now();
+        let Some(db) = db else {
+            let mut buckets = self.buckets.lock().unwrap();
+            return buckets
+                .entry(key)
+                .or_insert_with(|| Bucket::full(&limit, now))
+                .take(&limit, now);
+        };
+
+        db.run(move |conn| {
+            use schema::rate_limit_buckets::dsl;
+
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    let mut bucket = dsl::rate_limit_buckets
+                        .find(&key)
+                        .select((dsl::tokens, dsl::refilled_at))
+                        .for_update()
+                        .first::<(f64, DateTime<Utc>)>(conn)
+                        .optional()?
+                        .map(|(tokens, refilled_at)| Bucket {
+                            tokens,
+                            refilled_at,
+                        })
+                        .unwrap_or_else(|| Bucket::full(&limit, now));
+                    let taken = bucket.take(&limit, now);
+                    diesel::insert_into(dsl::rate_limit_buckets)
+                        .values((
+                            dsl::key.eq(&key),
+                            dsl::tokens.eq(bucket.tokens),
+                            dsl::refilled_at.eq(bucket.refilled_at),
+                        ))
+                        .on_conflict(dsl::key)
+                        .do_update()
+                        .set((
+                            dsl::tokens.eq(bucket.tokens),
+                            dsl::refilled_at.eq(bucket.refilled_at),
+                        ))
+                        .execute(conn)?;
+                    Ok(taken)
+                })
+        })
+        .await
+        .unwrap_or_else(|e| {
+            // don't lock everyone out because the store is unavailable
+            error!("failed to check rate limit: {e:?}");
+            Ok(())
+        })
+    }
+
+    /// Counts a login attempt as failed, until it is cleared by a successful one. The
+    /// check and the count happen at once, so that attempts made at the same time can't
+    /// all get past the backoff.
+    async fn count_login_attempt(&self, db: Option<&Storage>, key: String) -> Result<(), Duration> {
+        let now = Utc::now();
+        let config = self.config.login_backoff.clone();
+        let Some(db) = db else {
+            let mut login_failures = self.login_failures.lock().unwrap();
+            let previous = login_failures.get(&key).copied();
+            login_failures.insert(key, LoginFailures::attempt(previous, &config, now)?);
+            return Ok(());
+        };
+
+        db.run(move |conn| {
+            use schema::login_failures::dsl;
+
+            for _ in 0..LOGIN_ATTEMPT_RETRIES {
+                let previous = dsl::login_failures
+                    .find(&key)
+                    .select((dsl::failures, dsl::last_failed_at))
+                    .first::<(i32, DateTime<Utc>)>(conn)
+                    .optional()?;
+                let counted =
+                    LoginFailures::attempt(previous.map(LoginFailures::from), &config, now);
+                let Ok(counted) = counted else {
+                    return Ok(counted.map(|_| ()));
+                };
+                let failures = i32::try_from(counted.failures).unwrap_or(i32::MAX);
+                // only counts if nobody else counted an attempt since it was read
+                let updated = match previous {
+                    Some((previous_failures, previous_failed_at)) => {
+                        diesel::update(dsl::login_failures.find(&key))
+                            .filter(dsl::failures.eq(previous_failures))
+                            .filter(dsl::last_failed_at.eq(previous_failed_at))
+                            .set((
+                                dsl::failures.eq(failures),
+                                dsl::last_failed_at.eq(counted.last_failed_at),
+                            ))
+                            .returning(dsl::failures)
+                            .get_result::<i32>(conn)
+                            .optional()?
+                    }
+                    None => diesel::insert_into(dsl::login_failures)
+                        .values((
+                            dsl::key.eq(&key),
+                            dsl::failures.eq(failures),
+                            dsl::last_failed_at.eq(counted.last_failed_at),
+                        ))
+                        .on_conflict_do_nothing()
+                        .returning(dsl::failures)
+                        .get_result::<i32>(conn)
+                        .optional()?,
+                };
+                if updated.is_some() {
+                    return Ok(Ok(()));
+                }
+            }
+            // lost every race, so other attempts are coming in as fast as they can
+            Ok(Err(Duration::from_secs(1)))
+        })
+        .await
+        .unwrap_or_else(|e: diesel::result::Error| {
+            // don't lock everyone out because the store is unavailable
+            error!("failed to count login attempt: {e:?}");
+            Ok(())
+        })
+    }
+
+    async fn clear_login_failures(&self, db: Option<&Storage>, key: String) {
+        let Some(db) = db else {
+            self.login_failures.lock().unwrap().remove(&key);
+            return;
+        };
+
+        let cleared = db
+            .run(move |conn| diesel::delete(schema::login_failures::table.find(key)).execute(conn))
+            .await;
+        if let Err(e) = cleared {
+            error!("failed to clear login failures: {e:?}");
+        }
+    }
+
+    /// Forgets buckets that would be full again by now, and login failures that are past
+    /// `forget_after`.
+    fn cleanup_memory(&self) {
+        let now = Utc::now();
+        let limits = &self.config.limits;
+        self.buckets.lock().unwrap().retain(|key, bucket| {
+            let route = key.split(':').next().unwrap_or_default();
+            limits
+                .get(route)
+                .is_some_and(|limit| !bucket.is_full(limit, now))
+        });
+        let forget_after = self.config.login_backoff.forget_after;
+        self.login_failures
+            .lock()
+            .unwrap()
+            .retain(|_, failures| !failures.is_forgotten(forget_after, now));
+    }
+}
+
+fn cleanup_postgres(conn: &mut PgConnection, forget_after: u64) -> QueryResult<usize> {
+    // buckets refill in well under an hour with any sensible limit
+    let buckets = diesel::delete(schema::rate_limit_buckets::table)
+        .filter(schema::rate_limit_buckets::refilled_at.lt(Utc::now() - chrono::Duration::hours(1)))
+        .execute(conn)?;
+    let forget_after = chrono::Duration::from_std(Duration::from_secs(forget_after))
+        .unwrap_or_else(|_| chrono::Duration::max_value());
+    let login_failures = diesel::delete(schema::login_failures::table)
+        .filter(schema::login_failures::last_failed_at.lt(Utc::now() - forget_after))
+        .execute(conn)?;
+    Ok(buckets + login_failures)
+}
+
+/// The number of seconds for the `Retry-After` header of a limited request.
+#[derive(Default)]
+struct RetryAfter(AtomicU64);
+
+impl RetryAfter {
+    fn set(&self, delay: Duration) {
+        // round up, so that retrying right on time works
+        let secs = delay
+            .as_secs()
+            .saturating_add(u64::from(delay.subsec_nanos() > 0));
+        self.0.store(secs.max(1), Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Error)]
+pub enum RateLimitError {
+    #[error("Too many requests")]
+    TooManyRequests,
+    #[error("Rate limiter is not available")]
+    Unavailable,
+}
+
+/// The address of the client, from the [`RateLimitConfig::trusted_proxy_header`] if there
+/// is one, or else the connection. Proxies add themselves to the end of lists like
+/// `X-Forwarded-For`, so the last address is the one that the trusted proxy saw.
+pub(crate) fn client_ip(req: &Request<'_>) -> Option<IpAddr> {
+    let header = req
+        .rocket()
+        .state::<RateLimiter>()
+        .and_then(|limiter| limiter.config.trusted_proxy_header.as_deref());
+    match header {
+        Some(header) => req
+            .headers()
+            .get(header)
+            .last()
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok()),
+        None => req.remote().map(|remote| remote.ip()),
+    }
+}
+
+/// Limits a route to its configured [`Limit`] per client address. It also gives the route
+/// access to the login backoff.
+///
+/// This guard should come before the `Storage` guard of a route, because with the
+/// `postgres` store it briefly needs a connection of its own.
+pub struct RateLimit<'r> {
+    limiter: &'r RateLimiter,
+    retry_after: &'r RetryAfter,
+    client: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit<'r> {
+    type Error = RateLimitError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(limiter) = req.rocket().state::<RateLimiter>() else {
+            error!("rate limiter is not managed, attach `rate_limit::RateLimiting`");
+            return Outcome::Failure((Status::InternalServerError, RateLimitError::Unavailable));
+        };
+        let rate_limit = RateLimit {
+            limiter,
+            retry_after: req.local_cache(RetryAfter::default),
+            client: client_ip(req)
+                .as_ref()
+                .map(IpAddr::to_string)
+                .unwrap_or_default(),
+        };
+        if !limiter.config.enabled {
+            return Outcome::Success(rate_limit);
+        }
+        let Some(route) = req.route().and_then(|route| route.name.as_deref()) else {
+            return Outcome::Success(rate_limit);
+        };
+        let Some(limit) = limiter.config.limits.get(route) else {
+            return Outcome::Success(rate_limit);
+        };
+
+        let db = match limiter.config.store {
+            RateLimitStore::Memory => None,
+            RateLimitStore::Postgres => req.guard::<Storage>().await.succeeded(),
+        };
+        let key = format!("{route}:{}", rate_limit.client);
+        match limiter.take(db.as_ref(), key, *limit).await {
+            Ok(()) => Outcome::Success(rate_limit),
+            Err(delay) => {
+                rate_limit.retry_after.set(delay);
+                Outcome::Failure((Status::TooManyRequests, RateLimitError::TooManyRequests))
+            }
+        }
+    }
+}
+
+impl RateLimit<'_> {
+    fn store<'a>(&self, db: &'a Storage) -> Option<&'a Storage> {
+        match self.limiter.config.store {
+            RateLimitStore::Memory => None,
+            RateLimitStore::Postgres => Some(db),
+        }
+    }
+
+    fn login_key(&self, username: &str) -> String {
+        format!("{}:{}", username.to_lowercase(), self.client)
+    }
+
+    /// Makes sure the client has waited long enough since its last failed login as
+    /// `username`, and counts this attempt as failed until [`Self::clear_login_failures`]
+    /// is called. Otherwise, returns how much longer it has to wait, which is also sent in
+    /// the `Retry-After` header.
+    pub async fn check_login_backoff(&self, db: &Storage, username: &str) -> Result<(), Duration> {
+        if !self.limiter.config.enabled {
+            return Ok(());
+        }
+        self.limiter
+            .count_login_attempt(self.store(db), self.login_key(username))
+            .await
+            .inspect_err(|remaining| self.retry_after.set(*remaining))
+    }
+
+    pub async fn clear_login_failures(&self, db: &Storage, username: &str) {
+        if self.limiter.config.enabled {
+            self.limiter
+                .clear_login_failures(self.store(db), self.login_key(username))
+                .await;
+        }
+    }
+}
+
+/// Manages the [`RateLimiter`], cleans up after it, and adds `Retry-After` to limited
+/// responses.
+pub struct RateLimiting;
+
+#[rocket::async_trait]
+impl Fairing for RateLimiting {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limiting",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let Some(config) = config::section::<RateLimitConfig>(rocket.figment(), "rate_limit")
+        else {
+            return Err(rocket);
+        };
+        for route in config.limits.keys() {
+            if !LIMITED_ROUTES.contains(&route.as_str()) {
+                error!(
+                    "`{route}` can't be rate limited, only these routes take the guard: {}",
+                    LIMITED_ROUTES.join(", ")
+                );
+                return Err(rocket);
+            }
+            if !rocket.routes().any(|r| r.name.as_deref() == Some(route)) {
+                error!("`{route}` is rate limited, but isn't mounted");
+                return Err(rocket);
+            }
+        }
+        Ok(rocket.manage(RateLimiter::new(config)))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(limiter) = rocket.state::<RateLimiter>().cloned() else {
+            return;
+        };
+        if !limiter.config.enabled {
+            return;
+        }
+        let pool = match limiter.config.store {
+            RateLimitStore::Memory => None,
+            RateLimitStore::Postgres => {
+                let Some(pool) = Storage::pool(rocket).cloned() else {
+                    error!("database pool is not available, rate limits will not be cleaned up");
+                    return;
+                };
+                Some(pool)
+            }
+        };
+        let mut shutdown = rocket.shutdown();
+
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(CLEANUP_INTERVAL);
+            loop {
+                rocket::tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = &mut shutdown => break,
+                }
+
+                let Some(pool) = &pool else {
+                    limiter.cleanup_memory();
+                    continue;
+                };
+                let Some(conn) = pool.get().await else {
+                    continue;
+                };
+                let forget_after = limiter.config.login_backoff.forget_after;
+                if let Err(e) = conn
+                    .run(move |conn| cleanup_postgres(conn, forget_after))
+                    .await
+                {
+                    error!("failed to clean up rate limits: {e:?}");
+                }
+            }
+        });
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if res.status() != Status::TooManyRequests {
+            return;
+        }
+        let retry_after = req
+            .local_cache(RetryAfter::default)
+            .0
+            .load(Ordering::Relaxed);
+        if retry_after > 0 {
+            res.set_header(Header::new("Retry-After", retry_after.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+
+    use rocket::local::blocking::Client;
+
+    use crate::db::models::SurveyResponses;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn test_rocket_with_limits(db_name: &String, store: &str) -> Rocket<Build> {
+        let rocket = test_rocket(db_name);
+        let config = rocket
+            .figment()
+            .clone()
+            .merge(("rate_limit.enabled", true))
+            .merge(("rate_limit.store", store))
+            .merge(("rate_limit.limits.create_survey_response.burst", 2))
+            .merge(("rate_limit.limits.create_survey_response.per_minute", 1))
+            .merge(("rate_limit.login_backoff.free_attempts", 2))
+            .merge(("rate_limit.login_backoff.base_delay", 60));
+        rocket.configure(config)
+    }
+
+    fn respond(client: &Client, survey_id: i32, remote: &str) -> (Status, Option<String>) {
+        let response = client
+            .post(
+                uri!(
+                    "/api",
+                    crate::survey_response::create_survey_response(survey_id, _)
+                )
+                .to_string(),
+            )
+            .remote(remote.parse::<SocketAddr>().unwrap())
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+            .dispatch();
+        let retry_after = response.headers().get_one("Retry-After").map(str::to_owned);
+        (response.status(), retry_after)
+    }
+
+    fn check_response_limit(store: &str) {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket_with_limits(db_name, store))
+                .expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+
+            assert_eq!(respond(&client, survey_id, "10.0.0.1:1000").0, Status::Ok);
+            assert_eq!(respond(&client, survey_id, "10.0.0.1:1000").0, Status::Ok);
+            let (status, retry_after) = respond(&client, survey_id, "10.0.0.1:1000");
+            assert_eq!(status, Status::TooManyRequests);
+            let retry_after = retry_after.unwrap().parse::<u64>().unwrap();
+            assert!((1..=60).contains(&retry_after));
+
+            // every address gets its own bucket
+            assert_eq!(respond(&client, survey_id, "10.0.0.2:1000").0, Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_rate_limit_memory_store() {
+        check_response_limit("memory");
+    }
+
+    #[test]
+    fn test_rate_limit_postgres_store() {
+        check_response_limit("postgres");
+    }
+
+    #[test]
+    fn test_trusted_proxy_header() {
+        run_test_with_db(|db_name| {
+            let respond = |client: &Client, survey_id: i32, real_ip: &str| {
+                client
+                    .post(
+                        uri!(
+                            "/api",
+                            crate::survey_response::create_survey_response(survey_id, _)
+                        )
+                        .to_string(),
+                    )
+                    .remote("10.0.0.1:1000".parse::<SocketAddr>().unwrap())
+                    .header(rocket::http::Header::new("X-Real-IP", real_ip.to_owned()))
+                    .header(rocket::http::ContentType::JSON)
+                    .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                    .dispatch()
+                    .status()
+            };
+
+            // clients can't get a new bucket by making up a header
+            let client = Client::tracked(test_rocket_with_limits(db_name, "memory"))
+                .expect("valid rocket instance");
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            assert_eq!(respond(&client, survey_id, "192.0.2.1"), Status::Ok);
+            assert_eq!(respond(&client, survey_id, "192.0.2.2"), Status::Ok);
+            assert_eq!(
+                respond(&client, survey_id, "192.0.2.3"),
+                Status::TooManyRequests
+            );
+            drop(client);
+
+            // unless the header is set by a trusted proxy
+            let rocket = test_rocket_with_limits(db_name, "memory");
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("rate_limit.trusted_proxy_header", "X-Real-IP"));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+            assert_eq!(respond(&client, survey_id, "192.0.2.1"), Status::Ok);
+            assert_eq!(respond(&client, survey_id, "192.0.2.1"), Status::Ok);
+            assert_eq!(
+                respond(&client, survey_id, "192.0.2.1"),
+                Status::TooManyRequests
+            );
+            assert_eq!(respond(&client, survey_id, "192.0.2.2"), Status::Ok);
+        });
+    }
+
+    fn check_login_backoff(store: &str) {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket_with_limits(db_name, store))
+                .expect("valid rocket instance");
+
+            let username = format!("test_user_{}", uuid::Uuid::new_v4());
+            let response = client
+                .post(uri!("/api", crate::user::register_user))
+                .header(rocket::http::ContentType::JSON)
+                .body(format!(
+                    r#"{{"username": "{username}", "password": "hunter2"}}"#
+                ))
+                .dispatch();
+            assert_eq!(response.status(), Status::Created);
+
+            let login = |password: &str| {
+                let response = client
+                    .post(uri!("/api", crate::user::login_user))
+                    .header(rocket::http::ContentType::JSON)
+                    .body(format!(
+                        r#"{{"username": "{username}", "password": "{password}"}}"#
+                    ))
+                    .dispatch();
+                let retry_after = response.headers().get_one("Retry-After").map(str::to_owned);
+                (response.status(), retry_after)
+            };
+
+            // logging in clears the failures
+            assert_eq!(login("wrong").0, Status::BadRequest);
+            assert_eq!(login("hunter2").0, Status::Ok);
+            assert_eq!(login("wrong").0, Status::BadRequest);
+            assert_eq!(login("wrong").0, Status::BadRequest);
+            // even the right password has to wait now
+            let (status, retry_after) = login("hunter2");
+            assert_eq!(status, Status::TooManyRequests);
+            assert_eq!(retry_after.as_deref(), Some("60"));
+        });
+    }
+
+    #[test]
+    fn test_login_backoff_memory_store() {
+        check_login_backoff("memory");
+    }
+
+    #[test]
+    fn test_login_backoff_postgres_store() {
+        check_login_backoff("postgres");
+    }
+
+    #[test]
+    fn test_limited_routes_take_the_guard() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let mut config = rocket.figment().clone().merge(("rate_limit.enabled", true));
+            for route in LIMITED_ROUTES {
+                config = config
+                    .merge((format!("rate_limit.limits.{route}.burst"), 0))
+                    .merge((format!("rate_limit.limits.{route}.per_minute"), 0));
+            }
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            for name in LIMITED_ROUTES {
+                let route = client
+                    .rocket()
+                    .routes()
+                    .find(|r| r.name.as_deref() == Some(*name))
+                    .unwrap();
+                // any value will do for the parameters, the guard comes first
+                let path = route
+                    .uri
+                    .path()
+                    .split('/')
+                    .map(|segment| {
+                        if segment.starts_with('<') {
+                            "1"
+                        } else {
+                            segment
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let status = client
+                    .req(route.method, path)
+                    .header(rocket::http::ContentType::JSON)
+                    .body("{}")
+                    .dispatch()
+                    .status();
+                assert_eq!(status, Status::TooManyRequests, "{name}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_unknown_limited_route() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("rate_limit.limits.get_survey.burst", 1))
+                .merge(("rate_limit.limits.get_survey.per_minute", 1));
+            let Err(error) = Client::tracked(rocket.configure(config)) else {
+                panic!("a route without the guard shouldn't be limited");
+            };
+            assert!(matches!(
+                error.kind(),
+                rocket::error::ErrorKind::FailedFairings(_)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = Limit::new(2, 60);
+        let now = Utc::now();
+        let mut bucket = Bucket::full(&limit, now);
+        assert!(bucket.take(&limit, now).is_ok());
+        assert!(bucket.take(&limit, now).is_ok());
+        assert_eq!(bucket.take(&limit, now), Err(Duration::from_secs(1)));
+        assert!(bucket
+            .take(&limit, now + chrono::Duration::seconds(1))
+            .is_ok());
+        assert!(bucket.is_full(&limit, now + chrono::Duration::seconds(3)));
+
+        let backoff = LoginBackoffConfig {
+            free_attempts: 2,
+            base_delay: 10,
+            max_delay: 30,
+            forget_after: 60,
+        };
+        assert_eq!(backoff.delay(1), Duration::ZERO);
+        assert_eq!(backoff.delay(2), Duration::from_secs(10));
+        assert_eq!(backoff.delay(3), Duration::from_secs(20));
+        assert_eq!(backoff.delay(4), Duration::from_secs(30));
+        assert_eq!(backoff.delay(100), Duration::from_secs(30));
+    }
+}
@@ -1,22 +1,75 @@
+use diesel::dsl::{count_star, max};
 use diesel::prelude::*;
-use rocket::{http::Status, response::status::Created, serde::json::Json};
+use rocket::{
+    config::SecretKey, http::Status, response::status::Created, serde::json::Json, State,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
-    api::{ApiErrorResponse, ApiOkCacheableResource},
+    api::{ApiErrorResponse, ApiOkCacheableResource, Services},
     cache::{CacheCheck, Cacheable, RaceCheck},
     db::{
-        models::{NewSurvey, Survey, SurveyPatch, SurveyUpdateCheck},
+        models::{
+            HistoryAction, NewSurvey, ResponseStats, Survey, SurveyChangeset, SurveyPatch,
+            SurveyStatus, WebhookEvent,
+        },
         schema, Storage,
     },
     jwt::Claims,
+    survey::collab::CollabEvent,
+    survey_response::challenges::{Challenges, WithChallenge},
+    user::hash_password,
     validate::{Validate, ValidationError},
+    webhook,
 };
 
+pub(crate) mod access;
+pub(crate) mod collab;
 pub(crate) mod export;
-
+pub(crate) mod history;
+pub(crate) mod import;
+pub(crate) mod invitations;
+pub(crate) mod operations;
+pub(crate) mod slug;
+pub(crate) mod trash;
+
+pub use access::{request_survey_access, SurveyAccess};
+pub use collab::{add_collaborator, collaborate, focus, list_collaborators, remove_collaborator};
 pub use export::export_responses;
+pub use history::{get_survey_history, revert_survey};
+pub use import::import_questions;
+pub use invitations::{create_invitations, list_invitations, revoke_invitation};
+pub use operations::edit_questions;
+pub use slug::SurveyRef;
+pub use trash::{purge_survey, restore_survey};
+
+/// Configured at the top level of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SurveyConfig {
+    /// Whether the public routes accept the sequential id of a survey, instead of only its
+    /// slug. Owners can always use the id.
+    pub public_survey_ids: bool,
+    /// How many seconds a token for a password protected survey lasts.
+    pub survey_access_ttl: u64,
+    /// Whether edits have to send `If-Match` or `If-Unmodified-Since`, so that they can't
+    /// overwrite changes they haven't seen.
+    pub require_preconditions: bool,
+    /// How many days surveys stay in the trash before they are purged, at least 1.
+    pub trash_retention_days: i32,
+}
+
+impl Default for SurveyConfig {
+    fn default() -> Self {
+        Self {
+            public_survey_ids: true,
+            survey_access_ttl: 3600,
+            require_preconditions: false,
+            trash_retention_days: 30,
+        }
+    }
+}
 
 #[derive(Debug, Error, Serialize, Deserialize)]
 pub enum SurveyError {
@@ -28,10 +81,22 @@ pub enum SurveyError {
     NotOwner,
     #[error("Not found")]
     NotFound,
+    #[error("Not in the trash")]
+    NotDeleted,
+    #[error("Can't change the survey to that status")]
+    InvalidStatusChange,
+    #[error("Slug is already used by another survey")]
+    SlugTaken,
+    #[error("Survey requires an access token")]
+    PasswordRequired,
+    #[error("Wrong password")]
+    InvalidPassword,
     #[error("Validation error")]
     ValidationError(Vec<ValidationError>),
     #[error("Data race")]
     RaceError,
+    #[error("Edits require If-Match or If-Unmodified-Since")]
+    PreconditionRequired,
     #[error("Internal error")]
     Unknown,
 }
@@ -43,8 +108,14 @@ impl From<SurveyError> for ApiErrorResponse<SurveyError> {
             SurveyError::NotPublished => Status::Forbidden,
             SurveyError::NotOwner => Status::Forbidden,
             SurveyError::NotFound => Status::NotFound,
+            SurveyError::NotDeleted => Status::Conflict,
+            SurveyError::InvalidStatusChange => Status::Conflict,
+            SurveyError::SlugTaken => Status::Conflict,
+            SurveyError::PasswordRequired => Status::Unauthorized,
+            SurveyError::InvalidPassword => Status::Forbidden,
             SurveyError::ValidationError(_) => Status::UnprocessableEntity,
             SurveyError::RaceError => Status::PreconditionFailed,
+            SurveyError::PreconditionRequired => Status::PreconditionRequired,
             SurveyError::Unknown => Status::InternalServerError,
         };
         ApiErrorResponse {
@@ -88,21 +159,54 @@ pub async fn create_survey(
     Ok(Created::new(resource_uri).body(Json(survey)))
 }
 
-#[get("/survey/<survey_id>")]
+#[get("/survey/<survey_ref>")]
 pub async fn get_survey_auth(
-    survey_id: i32,
-    db: Storage,
+    survey_ref: SurveyRef,
+    services: Services<'_>,
     claims: Option<Claims>,
+    access: Option<SurveyAccess>,
     cache_check: Option<CacheCheck>,
-) -> Result<ApiOkCacheableResource<Survey>, ApiErrorResponse<SurveyError>> {
-    let survey = get_survey_from_db(&db, survey_id).await.map_err(|e| {
+    secret: &SecretKey,
+) -> Result<WithChallenge<ApiOkCacheableResource<Survey>>, ApiErrorResponse<SurveyError>> {
+    let Services {
+        db,
+        config,
+        challenges,
+        ..
+    } = services;
+    let survey_id = survey_ref
+        .resolve(&db, true)
+        .await
+        .ok_or(SurveyError::NotFound)?;
+    let mut survey = get_survey_from_db(&db, survey_id).await.map_err(|e| {
         error!("{e:?}");
         SurveyError::NotFound
     })?;
 
+    let is_owner = claims.as_ref().map(|c| c.user_id()) == Some(survey.owner_id);
+    if survey_ref.is_id() && !config.public_survey_ids && !is_owner {
+        return Err(SurveyError::NotFound.into());
+    }
+
+    if is_owner {
+        let stats = get_response_stats_from_db(&db, survey_id)
+            .await
+            .map_err(|e| {
+                error!("{e:?}");
+                SurveyError::Unknown
+            })?;
+        survey = survey.with_response_stats(stats);
+    } else if !access::has_access(&survey, access.as_ref()) {
+        return Err(SurveyError::PasswordRequired.into());
+    }
+
     if let Some(cache_check) = cache_check {
         if survey.is_cache_fresh(cache_check) {
-            return Ok(ApiOkCacheableResource::NotModified);
+            return Ok(challenges.issue(
+                &secret.to_string(),
+                survey_id,
+                ApiOkCacheableResource::NotModified,
+            ));
         }
     }
 
@@ -114,23 +218,43 @@ pub async fn get_survey_auth(
         return Err(SurveyError::NotPublished.into());
     }
 
-    Ok(ApiOkCacheableResource::Ok(survey))
+    Ok(challenges.issue(
+        &secret.to_string(),
+        survey_id,
+        ApiOkCacheableResource::Ok(survey),
+    ))
 }
 
-#[get("/survey/<survey_id>", rank = 2)]
+#[get("/survey/<survey_ref>", rank = 2)]
 pub async fn get_survey(
-    survey_id: i32,
+    survey_ref: SurveyRef,
     db: Storage,
+    config: &State<SurveyConfig>,
+    access: Option<SurveyAccess>,
     cache_check: Option<CacheCheck>,
-) -> Result<ApiOkCacheableResource<Survey>, ApiErrorResponse<SurveyError>> {
+    challenges: &State<Challenges>,
+    secret: &SecretKey,
+) -> Result<WithChallenge<ApiOkCacheableResource<Survey>>, ApiErrorResponse<SurveyError>> {
+    let survey_id = survey_ref
+        .resolve(&db, config.public_survey_ids)
+        .await
+        .ok_or(SurveyError::NotFound)?;
     let survey = get_survey_from_db(&db, survey_id).await.map_err(|e| {
         error!("{e:?}");
         SurveyError::NotFound
     })?;
 
+    if !access::has_access(&survey, access.as_ref()) {
+        return Err(SurveyError::PasswordRequired.into());
+    }
+
     if let Some(cache_check) = cache_check {
         if survey.is_cache_fresh(cache_check) {
-            return Ok(ApiOkCacheableResource::NotModified);
+            return Ok(challenges.issue(
+                &secret.to_string(),
+                survey_id,
+                ApiOkCacheableResource::NotModified,
+            ));
         }
     }
 
@@ -138,75 +262,138 @@ pub async fn get_survey(
         return Err(SurveyError::NotPublished.into());
     }
 
-    Ok(ApiOkCacheableResource::Ok(survey))
+    Ok(challenges.issue(
+        &secret.to_string(),
+        survey_id,
+        ApiOkCacheableResource::Ok(survey),
+    ))
 }
 
 #[patch("/survey/<survey_id>", data = "<new_survey>")]
 pub async fn edit_survey(
     survey_id: i32,
     claims: Claims,
-    db: Storage,
+    services: Services<'_>,
     new_survey: Json<SurveyPatch>,
     race_check: Option<RaceCheck>,
 ) -> Result<Json<()>, ApiErrorResponse<SurveyError>> {
-    let survey = db
-        .run(move |conn| {
-            schema::surveys::dsl::surveys
-                .find(survey_id)
-                .select((
-                    crate::db::schema::surveys::published,
-                    crate::db::schema::surveys::owner_id,
-                    crate::db::schema::surveys::updated_at,
-                ))
-                .first::<SurveyUpdateCheck>(conn)
-        })
-        .await
-        .map_err(|e| {
-            error!("{e:?}");
-            SurveyError::NotFound
-        })?;
-
-    if survey.owner_id != claims.user_id() {
-        return Err(SurveyError::NotOwner.into());
+    let Services {
+        db,
+        config,
+        webhooks,
+        collab,
+        ..
+    } = services;
+    if race_check.is_none() && config.require_preconditions {
+        return Err(SurveyError::PreconditionRequired.into());
     }
 
-    if let Some(race_check) = race_check {
-        if !survey.has_no_mid_air_collision(race_check) {
-            return Err(SurveyError::RaceError.into());
-        }
-    }
+    let survey = get_survey_from_db(&db, survey_id).await.map_err(|e| {
+        error!("{e:?}");
+        SurveyError::NotFound
+    })?;
 
-    if survey.published && new_survey.questions.is_some() {
-        return Err(SurveyError::CantEditPublished.into());
+    if survey.owner_id != claims.user_id() {
+        return Err(SurveyError::NotOwner.into());
     }
 
     new_survey.validate()?;
 
-    db.run(move |conn| -> anyhow::Result<()> {
-        conn.build_transaction()
-            .read_write()
-            .run::<_, diesel::result::Error, _>(|conn| {
-                schema::surveys::table
-                    .for_update()
-                    .find(survey_id)
-                    .load::<Survey>(conn)?;
-                diesel::update(schema::surveys::table)
-                    .filter(schema::surveys::id.eq(survey_id))
-                    .set(new_survey.into_inner())
-                    .execute(conn)?;
-                Ok(())
-            })?;
-        Ok(())
-    })
-    .await
-    .map_err(|e| {
-        error!("{e:?}");
-        SurveyError::Unknown
-    })?;
+    let new_status = new_survey.new_status();
+    let replacing_questions = new_survey.questions.is_some();
+    let user_id = claims.user_id();
+    let mut new_survey = new_survey.into_inner();
+    let access_password_hash = match new_survey.access_password.take() {
+        Some(Some(password)) => Some(Some(hash_password(&password).map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?)),
+        Some(None) => Some(None),
+        None => None,
+    };
+    let mut changeset = SurveyChangeset {
+        access_password_hash,
+        ..SurveyChangeset::from(new_survey)
+    };
+    let (updated, publishing) = db
+        .run(
+            move |conn| -> anyhow::Result<Result<(Survey, bool), SurveyError>> {
+                conn.build_transaction()
+                    .read_write()
+                    .run::<_, diesel::result::Error, _>(|conn| {
+                        // checked against the locked row, so no other edit can come in between
+                        let current = schema::surveys::table
+                            .for_update()
+                            .find(survey_id)
+                            .first::<Survey>(conn)?;
+                        if let Some(race_check) = race_check {
+                            if !current.has_no_mid_air_collision(race_check) {
+                                return Ok(Err(SurveyError::RaceError));
+                            }
+                        }
+                        if let Some(new_status) = new_status {
+                            if !current.status.can_transition_to(new_status) {
+                                return Ok(Err(SurveyError::InvalidStatusChange));
+                            }
+                        }
+                        if current.status.is_published() && replacing_questions {
+                            return Ok(Err(SurveyError::CantEditPublished));
+                        }
+                        let publishing = current.status == SurveyStatus::Draft
+                            && new_status == Some(SurveyStatus::Open);
+                        // tokens for the old password stop working
+                        if changeset.access_password_hash.is_some() {
+                            changeset.access_password_version =
+                                Some(current.access_password_version + 1);
+                        }
+                        let updated = diesel::update(schema::surveys::table)
+                            .filter(schema::surveys::id.eq(survey_id))
+                            .set(changeset)
+                            .get_result::<Survey>(conn)?;
+                        let action = if publishing {
+                            HistoryAction::Publish
+                        } else {
+                            HistoryAction::Edit
+                        };
+                        history::record_edit(conn, user_id, action, &current, &updated)?;
+                        if publishing {
+                            webhook::enqueue_event(
+                                conn,
+                                survey_id,
+                                WebhookEvent::SurveyPublished,
+                                &updated,
+                            )?;
+                        }
+                        Ok(Ok((updated, publishing)))
+                    })
+                    .map_err(Into::into)
+            },
+        )
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            match e.downcast_ref::<diesel::result::Error>() {
+                Some(diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation,
+                    info,
+                )) if info.constraint_name() == Some("surveys_slug_key") => SurveyError::SlugTaken,
+                _ => SurveyError::Unknown,
+            }
+        })??;
+    if publishing {
+        webhooks.wake();
+    }
+    if replacing_questions {
+        collab.publish(
+            survey_id,
+            CollabEvent::questions(None, Vec::new(), &updated),
+        );
+    }
 
     Ok(Json(()))
 }
 
+/// Moves the survey to the trash. It can be restored with [`restore_survey`] until it is purged.
 #[delete("/survey/<survey_id>")]
 pub async fn delete_survey(
     survey_id: i32,
@@ -222,10 +409,23 @@ pub async fn delete_survey(
         return Err(SurveyError::NotOwner.into());
     }
 
+    let user_id = claims.user_id();
     db.run(move |conn| -> anyhow::Result<()> {
-        diesel::delete(schema::surveys::table)
-            .filter(schema::surveys::id.eq(survey_id))
-            .execute(conn)?;
+        conn.build_transaction()
+            .read_write()
+            .run::<_, diesel::result::Error, _>(|conn| {
+                diesel::update(schema::surveys::table)
+                    .filter(schema::surveys::id.eq(survey_id))
+                    .set(schema::surveys::deleted_at.eq(diesel::dsl::now))
+                    .execute(conn)?;
+                history::record(
+                    conn,
+                    survey_id,
+                    user_id,
+                    HistoryAction::Delete,
+                    Default::default(),
+                )
+            })?;
         Ok(())
     })
     .await
@@ -237,19 +437,81 @@ pub async fn delete_survey(
     Ok(Json(()))
 }
 
+/// Why whoever the claims are for can't manage a survey, see [`check_survey_owner`].
+#[derive(Debug)]
+pub(crate) enum OwnerCheckError {
+    NotFound,
+    NotOwner,
+    Unknown,
+}
+
+impl From<OwnerCheckError> for ApiErrorResponse<SurveyError> {
+    fn from(value: OwnerCheckError) -> Self {
+        match value {
+            OwnerCheckError::NotFound => SurveyError::NotFound,
+            OwnerCheckError::NotOwner => SurveyError::NotOwner,
+            OwnerCheckError::Unknown => SurveyError::Unknown,
+        }
+        .into()
+    }
+}
+
+/// Checks that the survey isn't in the trash and belongs to whoever the claims are for.
+pub(crate) async fn check_survey_owner(
+    db: &Storage,
+    survey_id: i32,
+    claims: &Claims,
+) -> Result<(), OwnerCheckError> {
+    let owner_id = db
+        .run(move |conn| {
+            schema::surveys::table
+                .find(survey_id)
+                .filter(schema::surveys::deleted_at.is_null())
+                .select(schema::surveys::owner_id)
+                .first::<i32>(conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            OwnerCheckError::Unknown
+        })?
+        .ok_or(OwnerCheckError::NotFound)?;
+    if owner_id != claims.user_id() {
+        return Err(OwnerCheckError::NotOwner);
+    }
+    Ok(())
+}
+
 pub(crate) async fn get_survey_from_db(db: &Storage, survey_id: i32) -> anyhow::Result<Survey> {
     db.run(move |conn| {
         let survey = schema::surveys::dsl::surveys
             .find(survey_id)
+            .filter(schema::surveys::deleted_at.is_null())
             .first::<Survey>(conn)?;
         Ok(survey)
     })
     .await
 }
 
+pub(crate) async fn get_response_stats_from_db(
+    db: &Storage,
+    survey_id: i32,
+) -> anyhow::Result<ResponseStats> {
+    db.run(move |conn| {
+        let stats = schema::responses::table
+            .filter(schema::responses::survey_id.eq(survey_id))
+            .filter(schema::responses::quarantine_reason.is_null())
+            .select((count_star(), max(schema::responses::created_at)))
+            .first::<ResponseStats>(conn)?;
+        Ok(stats)
+    })
+    .await
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::db::models::SurveyQuestions;
+    use crate::db::models::{SurveyQuestions, SurveyStatus};
 
     use super::*;
     use crate::test_helpers::*;
@@ -314,7 +576,7 @@ mod tests {
 
             let token = create_test_user(&client);
             let survey_id = make_survey(&client, &token);
-            let token = make_jwt(&client, 58008);
+            let token = create_test_user(&client);
 
             let response = client
                 .get(uri!("/api", get_survey(survey_id)).to_string())
@@ -352,7 +614,7 @@ mod tests {
             let survey_id = make_survey(&client, &token);
             publish_survey(&client, &token, survey_id);
 
-            let token = make_jwt(&client, 58008);
+            let token = create_test_user(&client);
 
             let response = client
                 .get(uri!("/api", get_survey(survey_id)).to_string())
@@ -381,6 +643,13 @@ mod tests {
                         title: Some("test".to_owned()),
                         description: Some(":)".to_owned()),
                         published: Some(true),
+                        status: None,
+                        response_limit: None,
+                        invite_only: None,
+                        slug: None,
+                        access_password: None,
+                        duplicate_check: None,
+                        reject_duplicates: None,
                         questions: Some(SurveyQuestions(vec![])),
                     })
                     .unwrap(),
@@ -465,7 +734,7 @@ mod tests {
             let survey_id = make_survey(&client, &token);
             publish_survey(&client, &token, survey_id);
 
-            let token = make_jwt(&client, 58008);
+            let token = create_test_user(&client);
 
             let response = client
                 .patch(uri!("/api", edit_survey(survey_id)).to_string())
@@ -483,4 +752,325 @@ mod tests {
             assert_eq!(response.status(), rocket::http::Status::Forbidden);
         });
     }
+
+    #[test]
+    fn test_get_survey_response_stats_only_for_owner() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        crate::survey_response::create_survey_response(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .body("{}")
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            let response = client
+                .get(uri!("/api", get_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            let survey = response.into_json::<Survey>().unwrap();
+            assert_eq!(survey.response_count, Some(1));
+            assert!(survey.last_response_at.is_some());
+
+            let response = client
+                .get(uri!("/api", get_survey(survey_id)).to_string())
+                .dispatch();
+            let survey = response.into_json::<Survey>().unwrap();
+            assert_eq!(survey.response_count, None);
+            assert_eq!(survey.last_response_at, None);
+        });
+    }
+    #[test]
+    fn test_survey_lifecycle() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            assert_eq!(
+                set_survey_status(&client, &token, survey_id, SurveyStatus::Closed),
+                rocket::http::Status::Conflict
+            );
+            assert_eq!(
+                set_survey_status(&client, &token, survey_id, SurveyStatus::Open),
+                rocket::http::Status::Ok
+            );
+            assert_eq!(
+                set_survey_status(&client, &token, survey_id, SurveyStatus::Closed),
+                rocket::http::Status::Ok
+            );
+
+            let response = client
+                .get(uri!("/api", get_survey(survey_id)).to_string())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            let survey = response.into_json::<Survey>().unwrap();
+            assert_eq!(survey.status, SurveyStatus::Closed);
+            assert!(survey.published);
+
+            let response = client
+                .patch(uri!("/api", edit_survey(survey_id)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&SurveyPatch {
+                        questions: Some(SurveyQuestions(vec![])),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Forbidden);
+
+            assert_eq!(
+                set_survey_status(&client, &token, survey_id, SurveyStatus::Archived),
+                rocket::http::Status::Ok
+            );
+            assert_eq!(
+                set_survey_status(&client, &token, survey_id, SurveyStatus::Draft),
+                rocket::http::Status::Conflict
+            );
+        });
+    }
+
+    #[test]
+    fn test_survey_slug() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+
+            let edit_slug = |slug: &str| {
+                client
+                    .patch(uri!("/api", edit_survey(survey_id)).to_string())
+                    .header(rocket::http::ContentType::JSON)
+                    .header(rocket::http::Header::new("Authorization", token.clone()))
+                    .body(
+                        serde_json::to_vec(&SurveyPatch {
+                            slug: Some(slug.to_owned()),
+                            ..Default::default()
+                        })
+                        .unwrap(),
+                    )
+                    .dispatch()
+                    .status()
+            };
+
+            assert_eq!(edit_slug("1234"), rocket::http::Status::UnprocessableEntity);
+            assert_eq!(
+                edit_slug("Team Offsite"),
+                rocket::http::Status::UnprocessableEntity
+            );
+            assert_eq!(edit_slug("team-offsite"), rocket::http::Status::Ok);
+
+            let response = client
+                .get(uri!("/api", get_survey("team-offsite")).to_string())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            assert_eq!(response.into_json::<Survey>().unwrap().id, survey_id);
+
+            let other_survey = make_survey(&client, &token);
+            let response = client
+                .patch(uri!("/api", edit_survey(other_survey)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&SurveyPatch {
+                        slug: Some("team-offsite".to_owned()),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Conflict);
+
+            let response = client
+                .get(uri!("/api", get_survey("no-such-survey")).to_string())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotFound);
+        });
+    }
+
+    #[test]
+    fn test_public_survey_ids_disabled() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket.figment().clone().merge(("public_survey_ids", false));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+
+            let response = client
+                .get(uri!("/api", get_survey(survey_id)).to_string())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotFound);
+
+            let response = client
+                .get(uri!("/api", get_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            let survey = response.into_json::<Survey>().unwrap();
+
+            let response = client
+                .get(uri!("/api", get_survey(survey.slug.as_str())).to_string())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_survey_etags() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+
+            let get_etag = |token: Option<&str>| {
+                let mut request = client.get(uri!("/api", get_survey(survey_id)).to_string());
+                if let Some(token) = token {
+                    request = request
+                        .header(rocket::http::Header::new("Authorization", token.to_owned()));
+                }
+                let response = request.dispatch();
+                assert_eq!(response.status(), rocket::http::Status::Ok);
+                response.headers().get_one("ETag").unwrap().to_owned()
+            };
+            let edit = |etag: &str, title: &str| {
+                client
+                    .patch(uri!("/api", edit_survey(survey_id)).to_string())
+                    .header(rocket::http::ContentType::JSON)
+                    .header(rocket::http::Header::new("Authorization", token.clone()))
+                    .header(rocket::http::Header::new("If-Match", etag.to_owned()))
+                    .body(
+                        serde_json::to_vec(&SurveyPatch {
+                            title: Some(title.to_owned()),
+                            ..Default::default()
+                        })
+                        .unwrap(),
+                    )
+                    .dispatch()
+                    .status()
+            };
+
+            let etag = get_etag(None);
+            let response = client
+                .get(uri!("/api", get_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("If-None-Match", etag.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotModified);
+
+            // the owner gets response stats, which change the ETag but don't conflict
+            // with edits
+            let owner_etag = get_etag(Some(&token));
+            assert_ne!(owner_etag, etag);
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        crate::survey_response::create_survey_response(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .body("{}")
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            let response = client
+                .get(uri!("/api", get_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .header(rocket::http::Header::new(
+                    "If-None-Match",
+                    owner_etag.clone(),
+                ))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            assert_eq!(edit(&owner_etag, "first"), rocket::http::Status::Ok);
+
+            // the edit changed the survey, so the old ETags are stale
+            assert_eq!(
+                edit(&etag, "second"),
+                rocket::http::Status::PreconditionFailed
+            );
+            assert_eq!(
+                edit(&owner_etag, "second"),
+                rocket::http::Status::PreconditionFailed
+            );
+            assert_eq!(edit(&get_etag(None), "second"), rocket::http::Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_require_preconditions() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("require_preconditions", true));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let get_survey = || -> Survey {
+                client
+                    .get(uri!("/api", get_survey(survey_id)).to_string())
+                    .header(rocket::http::Header::new("Authorization", token.clone()))
+                    .dispatch()
+                    .into_json()
+                    .unwrap()
+            };
+            let edit = |precondition: Option<rocket::http::Header<'static>>| {
+                let mut request = client
+                    .patch(uri!("/api", edit_survey(survey_id)).to_string())
+                    .header(rocket::http::ContentType::JSON)
+                    .header(rocket::http::Header::new("Authorization", token.clone()))
+                    .body(
+                        serde_json::to_vec(&SurveyPatch {
+                            title: Some("edited".to_owned()),
+                            ..Default::default()
+                        })
+                        .unwrap(),
+                    );
+                if let Some(precondition) = precondition {
+                    request = request.header(precondition);
+                }
+                request.dispatch().status()
+            };
+
+            assert_eq!(edit(None), rocket::http::Status::PreconditionRequired);
+
+            let survey = get_survey();
+            let if_match = |revision: i32| {
+                Some(rocket::http::Header::new(
+                    "If-Match",
+                    format!("\"{revision}\""),
+                ))
+            };
+            assert_eq!(edit(if_match(survey.revision)), rocket::http::Status::Ok);
+            let edited = get_survey();
+            assert_eq!(edited.title, "edited");
+            assert_eq!(edited.revision, survey.revision + 1);
+            assert_eq!(
+                edit(if_match(survey.revision)),
+                rocket::http::Status::PreconditionFailed
+            );
+        });
+    }
 }
@@ -0,0 +1,348 @@
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use rocket::config::SecretKey;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::{Request, State};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{Survey, SurveyStatus};
+use crate::db::{schema, Storage};
+use crate::jwt::JwtError;
+use crate::rate_limit::RateLimit;
+use crate::survey::{SurveyConfig, SurveyError, SurveyRef};
+use crate::user::verify_password;
+
+/// The header that respondents send the token from [`request_survey_access`] in. It is kept
+/// apart from `Authorization`, so that it can't be mistaken for a login.
+pub const SURVEY_ACCESS_HEADER: &str = "X-Survey-Access";
+/// The audience of access tokens, so that they can't be taken for any other kind of token.
+const ACCESS_AUDIENCE: &str = "survey-access";
+
+#[typeshare]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SurveyAccessParams {
+    pub password: String,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurveyAccessToken {
+    /// Sent back in the `X-Survey-Access` header.
+    pub token: String,
+    /// Expiration time (as UTC timestamp)
+    #[typeshare(serialized_as = "number")]
+    pub exp: u64,
+}
+
+/// The claims in a survey access token. Only lets the bearer see and respond to one survey,
+/// until its access password changes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SurveyAccess {
+    aud: String,
+    survey_id: i32,
+    /// The [`Survey::access_password_version`] the token was issued for.
+    password_version: i32,
+    /// Expiration time (as UTC timestamp)
+    exp: u64,
+}
+
+impl SurveyAccess {
+    pub fn survey_id(&self) -> i32 {
+        self.survey_id
+    }
+}
+
+/// Access tokens are signed with their own key, derived from the secret key, so that they
+/// can't be used as logins or the other way around.
+fn access_key(secret: &str) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(ACCESS_AUDIENCE.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SurveyAccess {
+    type Error = JwtError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(token) = req.headers().get_one(SURVEY_ACCESS_HEADER) else {
+            return Outcome::Forward(());
+        };
+        let key = access_key(&req.rocket().config().secret_key.to_string());
+        let key = jsonwebtoken::DecodingKey::from_secret(&key);
+        let mut validation = jsonwebtoken::Validation::default();
+        validation.set_audience(&[ACCESS_AUDIENCE]);
+
+        let Ok(token_data) = jsonwebtoken::decode::<SurveyAccess>(token, &key, &validation) else {
+            return Outcome::Failure((Status::Unauthorized, JwtError::InvalidToken));
+        };
+
+        if jsonwebtoken::get_current_timestamp() >= token_data.claims.exp {
+            return Outcome::Failure((Status::Unauthorized, JwtError::ExpiredToken));
+        }
+
+        Outcome::Success(token_data.claims)
+    }
+}
+
+/// Whether `access` lets its bearer into the survey. Surveys without a password let
+/// anyone in.
+pub(crate) fn has_access(survey: &Survey, access: Option<&SurveyAccess>) -> bool {
+    !survey.password_protected
+        || access.is_some_and(|access| {
+            access.survey_id == survey.id
+                && access.password_version == survey.access_password_version
+        })
+}
+
+/// Exchanges the access password of a survey for a token, which lasts for
+/// `survey_access_ttl` seconds.
+#[post("/survey/<survey_ref>/access", data = "<params>")]
+pub async fn request_survey_access(
+    _rate_limit: RateLimit<'_>,
+    survey_ref: SurveyRef,
+    db: Storage,
+    config: &State<SurveyConfig>,
+    secret: &SecretKey,
+    params: Json<SurveyAccessParams>,
+) -> Result<Json<SurveyAccessToken>, ApiErrorResponse<SurveyError>> {
+    let survey_id = survey_ref
+        .resolve(&db, config.public_survey_ids)
+        .await
+        .ok_or(SurveyError::NotFound)?;
+    let password = params.into_inner().password;
+
+    let password_version = db
+        .run(move |conn| -> Result<i32, SurveyError> {
+            let (status, access_password_hash, password_version) = schema::surveys::table
+                .find(survey_id)
+                .filter(schema::surveys::deleted_at.is_null())
+                .select((
+                    schema::surveys::status,
+                    schema::surveys::access_password_hash,
+                    schema::surveys::access_password_version,
+                ))
+                .first::<(SurveyStatus, Option<String>, i32)>(conn)
+                .map_err(|e| {
+                    error!("{e:?}");
+                    SurveyError::NotFound
+                })?;
+            if !status.is_published() {
+                return Err(SurveyError::NotPublished);
+            }
+            let Some(access_password_hash) = access_password_hash else {
+                return Ok(password_version);
+            };
+            verify_password(&password, &access_password_hash).map_err(|e| match e {
+                ::password_hash::Error::Password => SurveyError::InvalidPassword,
+                _ => {
+                    error!("{e:?}");
+                    SurveyError::Unknown
+                }
+            })?;
+            Ok(password_version)
+        })
+        .await?;
+
+    let claims = SurveyAccess {
+        aud: ACCESS_AUDIENCE.to_string(),
+        survey_id,
+        password_version,
+        exp: jsonwebtoken::get_current_timestamp() + config.survey_access_ttl,
+    };
+    let key = jsonwebtoken::EncodingKey::from_secret(&access_key(&secret.to_string()));
+    let token =
+        jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?;
+
+    Ok(Json(SurveyAccessToken {
+        token,
+        exp: claims.exp,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    use crate::db::models::{Survey, SurveyPatch, SurveyResponses};
+    use crate::test_helpers::*;
+
+    fn set_password(client: &Client, token: &str, survey_id: i32, password: Option<&str>) {
+        let response = client
+            .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+            .header(rocket::http::ContentType::JSON)
+            .header(Header::new("Authorization", token.to_owned()))
+            .body(
+                serde_json::to_vec(&SurveyPatch {
+                    access_password: Some(password.map(str::to_owned)),
+                    ..Default::default()
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    fn request_access(client: &Client, survey_id: i32, password: &str) -> Option<String> {
+        let response = client
+            .post(uri!("/api", request_survey_access(survey_id)).to_string())
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                serde_json::to_vec(&SurveyAccessParams {
+                    password: password.to_owned(),
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        if response.status() == Status::Forbidden {
+            return None;
+        }
+        assert_eq!(response.status(), Status::Ok);
+        Some(response.into_json::<SurveyAccessToken>().unwrap().token)
+    }
+
+    #[test]
+    fn test_password_protected_survey() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            set_password(&client, &token, survey_id, Some("hunter2"));
+
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+
+            // the owner doesn't need the password
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert!(response.into_json::<Survey>().unwrap().password_protected);
+
+            let respond = |access: Option<&str>| {
+                let mut request = client
+                    .post(
+                        uri!(
+                            "/api",
+                            crate::survey_response::create_survey_response(survey_id, _)
+                        )
+                        .to_string(),
+                    )
+                    .header(rocket::http::ContentType::JSON)
+                    .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap());
+                if let Some(access) = access {
+                    request = request.header(Header::new(SURVEY_ACCESS_HEADER, access.to_owned()));
+                }
+                request.dispatch().status()
+            };
+            assert_eq!(respond(None), Status::Unauthorized);
+
+            assert_eq!(request_access(&client, survey_id, "hunter3"), None);
+            let access = request_access(&client, survey_id, "hunter2").unwrap();
+
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .header(Header::new(SURVEY_ACCESS_HEADER, access.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(respond(Some(&access)), Status::Ok);
+
+            // tokens are only good for the survey they were issued for
+            let other_survey = make_survey(&client, &token);
+            publish_survey(&client, &token, other_survey);
+            set_password(&client, &token, other_survey, Some("hunter2"));
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(other_survey)).to_string())
+                .header(Header::new(SURVEY_ACCESS_HEADER, access.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+
+            // changing the password revokes the tokens for the old one
+            set_password(&client, &token, survey_id, Some("hunter3"));
+            assert_eq!(respond(Some(&access)), Status::Unauthorized);
+            let access = request_access(&client, survey_id, "hunter3").unwrap();
+            assert_eq!(respond(Some(&access)), Status::Ok);
+
+            set_password(&client, &token, survey_id, None);
+            assert_eq!(respond(None), Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_no_access_to_draft_survey() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            set_password(&client, &token, survey_id, Some("hunter2"));
+
+            let response = client
+                .post(uri!("/api", request_survey_access(survey_id)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .body(
+                    serde_json::to_vec(&SurveyAccessParams {
+                        password: "hunter2".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+            assert_eq!(
+                response.into_string().unwrap(),
+                r#"{"message":"NotPublished"}"#
+            );
+
+            publish_survey(&client, &token, survey_id);
+            assert!(request_access(&client, survey_id, "hunter2").is_some());
+        });
+    }
+
+    #[test]
+    fn test_invalid_access_token() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            set_password(&client, &token, survey_id, Some("hunter2"));
+
+            // a login token is not an access token
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .header(Header::new(
+                    SURVEY_ACCESS_HEADER,
+                    token.replace("Bearer ", ""),
+                ))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+
+            let response = client
+                .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .header(Header::new("Authorization", token))
+                .body(r#"{"access_password": ""}"#)
+                .dispatch();
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+        });
+    }
+}
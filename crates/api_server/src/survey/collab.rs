@@ -0,0 +1,852 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use diesel::prelude::*;
+use rocket::fairing::AdHoc;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{Shutdown, State};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{Collaborator, Survey, SurveyQuestions};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::survey::operations::QuestionOperation;
+use crate::survey::{check_survey_owner, SurveyError};
+use crate::validate::ValidationError;
+
+/// How many events a slow editor can fall behind before it misses some.
+const CAPACITY: usize = 256;
+
+/// Someone with the survey open in the editor. Every open editor is its own session, so
+/// the same account can edit from several places at once.
+#[typeshare]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Editor {
+    #[typeshare(serialized_as = "String")]
+    pub session: Uuid,
+    pub user_id: i32,
+    pub username: String,
+    /// The question the editor is working on, if any.
+    #[typeshare(serialized_as = "Option<String>")]
+    pub question: Option<Uuid>,
+}
+
+/// Sent to everyone editing a survey through [`collaborate`].
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "content")]
+pub enum CollabEvent {
+    /// Sent first, with everything needed to start editing.
+    Snapshot {
+        questions: SurveyQuestions,
+        revision: i32,
+        editors: Vec<Editor>,
+    },
+    /// The questions were changed, through [`edit_questions`] or by replacing them.
+    /// Editors can drop changes with a revision they already have.
+    ///
+    /// [`edit_questions`]: crate::survey::edit_questions
+    Questions {
+        /// The editor that made the change, if it came from one.
+        #[typeshare(serialized_as = "Option<String>")]
+        session: Option<Uuid>,
+        /// Empty when the questions were replaced as a whole.
+        operations: Vec<QuestionOperation>,
+        questions: SurveyQuestions,
+        revision: i32,
+    },
+    /// An editor joined or moved to another question.
+    Presence(Editor),
+    Left {
+        #[typeshare(serialized_as = "String")]
+        session: Uuid,
+    },
+}
+
+impl CollabEvent {
+    pub fn questions(
+        session: Option<Uuid>,
+        operations: Vec<QuestionOperation>,
+        survey: &Survey,
+    ) -> Self {
+        CollabEvent::Questions {
+            session,
+            operations,
+            questions: survey.questions.clone(),
+            revision: survey.revision,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CollabEvent::Snapshot { .. } => "snapshot",
+            CollabEvent::Questions { .. } => "questions",
+            CollabEvent::Presence(_) => "presence",
+            CollabEvent::Left { .. } => "left",
+        }
+    }
+}
+
+/// An editor and the stream it has open. A client that reconnects with the same session
+/// gets a new connection, so that its old stream going away doesn't take the session along.
+struct Connected {
+    connection: u64,
+    editor: Editor,
+}
+
+/// Fans out [`CollabEvent`]s to the editors of a survey, and keeps track of who they are.
+///
+/// Like the `memory` backend of the live events, this only reaches editors connected to the
+/// same instance.
+pub struct Collaboration {
+    sender: broadcast::Sender<(i32, CollabEvent)>,
+    editors: Mutex<HashMap<i32, Vec<Connected>>>,
+    next_connection: AtomicU64,
+}
+
+impl Collaboration {
+    fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            editors: Mutex::default(),
+            next_connection: AtomicU64::new(0),
+        }
+    }
+
+    /// Sends an event to the editors of the survey, if there are any.
+    pub fn publish(&self, survey_id: i32, event: CollabEvent) {
+        // an error only means that nobody is editing
+        let _ = self.sender.send((survey_id, event));
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<(i32, CollabEvent)> {
+        self.sender.subscribe()
+    }
+
+    fn editors(&self, survey_id: i32) -> Vec<Editor> {
+        let editors = self.editors.lock().unwrap();
+        editors
+            .get(&survey_id)
+            .map(|editors| editors.iter().map(|c| c.editor.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Adds the editor to the survey, taking over its session if it was already there.
+    /// Returns the new connection.
+    fn join(&self, survey_id: i32, editor: Editor) -> u64 {
+        let connection = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        let mut editors = self.editors.lock().unwrap();
+        let survey_editors = editors.entry(survey_id).or_default();
+        survey_editors.retain(|c| c.editor.session != editor.session);
+        survey_editors.push(Connected {
+            connection,
+            editor: editor.clone(),
+        });
+        drop(editors);
+        self.publish(survey_id, CollabEvent::Presence(editor));
+        connection
+    }
+
+    /// Whether the connection is still editing the survey. It stops when the session is
+    /// taken over by a reconnect, or when its user can't edit the survey anymore.
+    fn is_connected(&self, survey_id: i32, connection: u64) -> bool {
+        let editors = self.editors.lock().unwrap();
+        editors
+            .get(&survey_id)
+            .is_some_and(|editors| editors.iter().any(|c| c.connection == connection))
+    }
+
+    /// Moves an editor of `user_id` to another question. Returns whether the session is
+    /// editing the survey.
+    fn focus(&self, survey_id: i32, user_id: i32, session: Uuid, question: Option<Uuid>) -> bool {
+        let mut editors = self.editors.lock().unwrap();
+        let Some(editor) = editors
+            .get_mut(&survey_id)
+            .and_then(|editors| editors.iter_mut().find(|c| c.editor.session == session))
+            .map(|c| &mut c.editor)
+            .filter(|editor| editor.user_id == user_id)
+        else {
+            return false;
+        };
+        editor.question = question;
+        let editor = editor.clone();
+        drop(editors);
+        self.publish(survey_id, CollabEvent::Presence(editor));
+        true
+    }
+
+    /// Takes the editors that match out of the survey, and tells the others that they left.
+    fn remove(&self, survey_id: i32, matches: impl Fn(&Connected) -> bool) {
+        let mut editors = self.editors.lock().unwrap();
+        let Some(survey_editors) = editors.get_mut(&survey_id) else {
+            return;
+        };
+        let (left, stayed) = std::mem::take(survey_editors)
+            .into_iter()
+            .partition::<Vec<_>, _>(matches);
+        if stayed.is_empty() {
+            editors.remove(&survey_id);
+        } else {
+            *survey_editors = stayed;
+        }
+        drop(editors);
+        for c in left {
+            let session = c.editor.session;
+            self.publish(survey_id, CollabEvent::Left { session });
+        }
+    }
+
+    /// Takes the connection out of the survey. Nothing happens if its session was taken
+    /// over by a reconnect in the meantime.
+    fn leave(&self, survey_id: i32, connection: u64) {
+        self.remove(survey_id, |c| c.connection == connection);
+    }
+
+    /// Takes every editor of `user_id` out of the survey. Their streams end with the next
+    /// event.
+    fn disconnect_user(&self, survey_id: i32, user_id: i32) {
+        self.remove(survey_id, |c| c.editor.user_id == user_id);
+    }
+}
+
+/// Takes the editor out of the survey once its stream is dropped, which is also what
+/// happens when the client disconnects.
+struct Session<'r> {
+    collab: &'r Collaboration,
+    survey_id: i32,
+    connection: u64,
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        self.collab.leave(self.survey_id, self.connection);
+    }
+}
+
+/// Whether `user_id` can edit the questions of the survey, as its owner or as one of its
+/// collaborators.
+pub(crate) fn can_edit_questions(
+    conn: &mut PgConnection,
+    survey: &Survey,
+    user_id: i32,
+) -> QueryResult<bool> {
+    if survey.owner_id == user_id {
+        return Ok(true);
+    }
+    diesel::select(diesel::dsl::exists(
+        schema::survey_collaborators::table.find((survey.id, user_id)),
+    ))
+    .get_result(conn)
+}
+
+/// Opens the survey in the editor as `session`, a UUID made up by the client. The owner of
+/// the survey and its collaborators can open it. The first
+/// event is a `snapshot` of the questions and the other editors, followed by `questions`
+/// whenever they change and `presence` and `left` as editors come and go.
+///
+/// Changes are made with [`edit_questions`], passing the same `session`, and [`focus`]
+/// tells the others which question is being edited. If the client falls too far behind, a
+/// `lagged` event is sent in place of the events it missed, and it should reconnect. The
+/// stream ends when another stream is opened with the same session, or when the user is no
+/// longer a collaborator.
+///
+/// [`edit_questions`]: crate::survey::edit_questions
+#[get("/survey/<survey_id>/collaborate?<session>")]
+pub async fn collaborate<'r>(
+    survey_id: i32,
+    session: Uuid,
+    claims: Claims,
+    db: Storage,
+    collab: &'r State<Collaboration>,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![Event + 'r], ApiErrorResponse<SurveyError>> {
+    // subscribe before taking the snapshot, so that no change falls in between
+    let mut events = collab.subscribe();
+
+    let user_id = claims.user_id();
+    let (survey, username) = db
+        .run(move |conn| {
+            let Some(survey) = schema::surveys::table
+                .find(survey_id)
+                .filter(schema::surveys::deleted_at.is_null())
+                .first::<Survey>(conn)
+                .optional()?
+            else {
+                return Ok(Err(SurveyError::NotFound));
+            };
+            if !can_edit_questions(conn, &survey, user_id)? {
+                return Ok(Err(SurveyError::NotOwner));
+            }
+            let username = schema::users::table
+                .find(user_id)
+                .select(schema::users::username)
+                .first::<String>(conn)?;
+            QueryResult::Ok(Ok((survey, username)))
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })??;
+
+    let snapshot = CollabEvent::Snapshot {
+        questions: survey.questions,
+        revision: survey.revision,
+        editors: collab.editors(survey_id),
+    };
+    let connection = collab.join(
+        survey_id,
+        Editor {
+            session,
+            user_id,
+            username,
+            question: None,
+        },
+    );
+    let session = Session {
+        collab,
+        survey_id,
+        connection,
+    };
+
+    Ok(EventStream! {
+        let _session = session;
+        yield Event::json(&snapshot).event(snapshot.name());
+        loop {
+            let event = rocket::tokio::select! {
+                event = events.recv() => event,
+                _ = &mut shutdown => break,
+            };
+            match event {
+                Ok((id, _)) if id == survey_id && !collab.is_connected(survey_id, connection) => {
+                    break;
+                }
+                Ok((id, event)) if id == survey_id => {
+                    yield Event::json(&event).event(event.name());
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(_)) => yield Event::empty().event("lagged"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FocusParams {
+    #[typeshare(serialized_as = "Option<String>")]
+    pub question: Option<Uuid>,
+}
+
+/// Tells the other editors which question `session` is working on.
+#[put("/survey/<survey_id>/collaborate?<session>", data = "<params>")]
+pub async fn focus(
+    survey_id: i32,
+    session: Uuid,
+    claims: Claims,
+    collab: &State<Collaboration>,
+    params: Json<FocusParams>,
+) -> Result<Json<()>, ApiErrorResponse<SurveyError>> {
+    // only those who can edit the survey can open the stream, so a session of the user is
+    // enough to check for access
+    if !collab.focus(survey_id, claims.user_id(), session, params.question) {
+        return Err(SurveyError::NotFound.into());
+    }
+
+    Ok(Json(()))
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollaboratorParams {
+    pub username: String,
+}
+
+/// Lists the users that can edit the questions of the survey along with its owner.
+#[get("/survey/<survey_id>/collaborators")]
+pub async fn list_collaborators(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<Vec<Collaborator>>, ApiErrorResponse<SurveyError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let collaborators = db
+        .run(move |conn| {
+            schema::survey_collaborators::table
+                .inner_join(schema::users::table)
+                .filter(schema::survey_collaborators::survey_id.eq(survey_id))
+                .order(schema::survey_collaborators::created_at)
+                .select((
+                    schema::survey_collaborators::user_id,
+                    schema::users::username,
+                    schema::survey_collaborators::created_at,
+                ))
+                .load::<Collaborator>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?;
+
+    Ok(Json(collaborators))
+}
+
+/// Lets another user edit the questions of the survey, through [`collaborate`] and
+/// [`edit_questions`]. Adding someone who already is a collaborator changes nothing.
+///
+/// [`edit_questions`]: crate::survey::edit_questions
+#[post("/survey/<survey_id>/collaborators", data = "<params>")]
+pub async fn add_collaborator(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+    params: Json<CollaboratorParams>,
+) -> Result<Json<Collaborator>, ApiErrorResponse<SurveyError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let owner_id = claims.user_id();
+    let username = params.into_inner().username;
+    let collaborator = db
+        .run(move |conn| {
+            let user_id = schema::users::table
+                .filter(schema::users::username.eq(&username))
+                .select(schema::users::id)
+                .first::<i32>(conn)
+                .optional()?;
+            let message = match user_id {
+                None => "no user has this name",
+                Some(user_id) if user_id == owner_id => "the owner can already edit the survey",
+                Some(user_id) => {
+                    diesel::insert_into(schema::survey_collaborators::table)
+                        .values((
+                            schema::survey_collaborators::survey_id.eq(survey_id),
+                            schema::survey_collaborators::user_id.eq(user_id),
+                        ))
+                        .on_conflict_do_nothing()
+                        .execute(conn)?;
+                    let collaborator = schema::survey_collaborators::table
+                        .inner_join(schema::users::table)
+                        .filter(schema::survey_collaborators::survey_id.eq(survey_id))
+                        .filter(schema::survey_collaborators::user_id.eq(user_id))
+                        .select((
+                            schema::survey_collaborators::user_id,
+                            schema::users::username,
+                            schema::survey_collaborators::created_at,
+                        ))
+                        .first::<Collaborator>(conn)?;
+                    return Ok(Ok(collaborator));
+                }
+            };
+            QueryResult::Ok(Err(SurveyError::ValidationError(vec![
+                ValidationError::BadValue {
+                    field: "username".to_string(),
+                    message: message.to_string(),
+                },
+            ])))
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })??;
+
+    Ok(Json(collaborator))
+}
+
+/// Takes away the access of a collaborator. Any editors they have open on the survey are
+/// closed.
+#[delete("/survey/<survey_id>/collaborators/<user_id>")]
+pub async fn remove_collaborator(
+    survey_id: i32,
+    user_id: i32,
+    claims: Claims,
+    db: Storage,
+    collab: &State<Collaboration>,
+) -> Result<Json<()>, ApiErrorResponse<SurveyError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let deleted = db
+        .run(move |conn| {
+            diesel::delete(schema::survey_collaborators::table.find((survey_id, user_id)))
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?;
+    if deleted == 0 {
+        return Err(SurveyError::NotFound.into());
+    }
+    collab.disconnect_user(survey_id, user_id);
+
+    Ok(Json(()))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Collaborative Editing", |rocket| async {
+        rocket.manage(Collaboration::new())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufReader, Read};
+
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::{Client, LocalResponse};
+
+    use crate::db::models::SurveyPatch;
+    use crate::questions::{QText, Question, SurveyQuestion};
+    use crate::test_helpers::*;
+
+    fn open<'c>(
+        client: &'c Client,
+        token: &str,
+        survey_id: i32,
+        session: Uuid,
+    ) -> LocalResponse<'c> {
+        client
+            .get(uri!("/api", collaborate(survey_id, session)).to_string())
+            .header(Header::new("Authorization", token.to_owned()))
+            .dispatch()
+    }
+
+    fn next_collab_event(stream: &mut BufReader<LocalResponse>) -> CollabEvent {
+        let (name, data) = next_event(stream);
+        let event = serde_json::from_str::<CollabEvent>(&data).unwrap();
+        assert_eq!(event.name(), name);
+        event
+    }
+
+    #[test]
+    fn test_collaborate() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+            let response = open(&client, &token, survey_id, first);
+            assert_eq!(response.status(), Status::Ok);
+            let mut stream = BufReader::new(response);
+            let CollabEvent::Snapshot {
+                editors, revision, ..
+            } = next_collab_event(&mut stream)
+            else {
+                panic!("expected a snapshot");
+            };
+            assert!(editors.is_empty());
+            let CollabEvent::Presence(editor) = next_collab_event(&mut stream) else {
+                panic!("expected the editor to join");
+            };
+            assert_eq!(editor.session, first);
+
+            // a second editor sees the first one, and the first one sees it join
+            let mut other_stream = BufReader::new(open(&client, &token, survey_id, second));
+            let CollabEvent::Snapshot { editors, .. } = next_collab_event(&mut other_stream) else {
+                panic!("expected a snapshot");
+            };
+            assert_eq!(editors, [editor]);
+            let CollabEvent::Presence(editor) = next_collab_event(&mut stream) else {
+                panic!("expected the other editor to join");
+            };
+            assert_eq!(editor.session, second);
+            next_collab_event(&mut other_stream);
+
+            let question = SurveyQuestion {
+                uuid: Uuid::new_v4(),
+                required: false,
+                question: Question::Text(QText {
+                    prompt: "prompt".to_string(),
+                    description: String::new(),
+                    multiline: false,
+                }),
+            };
+            let response = client
+                .put(uri!("/api", focus(survey_id, second)).to_string())
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&FocusParams {
+                        question: Some(question.uuid),
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let CollabEvent::Presence(editor) = next_collab_event(&mut stream) else {
+                panic!("expected the other editor to move");
+            };
+            assert_eq!(editor.question, Some(question.uuid));
+
+            let response = client
+                .patch(
+                    uri!(
+                        "/api",
+                        crate::survey::operations::edit_questions(survey_id, Some(second))
+                    )
+                    .to_string(),
+                )
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&[QuestionOperation::Add {
+                        question: question.clone(),
+                        index: None,
+                    }])
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let CollabEvent::Questions {
+                session,
+                operations,
+                questions,
+                revision: added_revision,
+            } = next_collab_event(&mut stream)
+            else {
+                panic!("expected the questions to change");
+            };
+            assert_eq!(session, Some(second));
+            assert_eq!(operations.len(), 1);
+            assert_eq!(questions.0.len(), 1);
+            assert!(added_revision > revision);
+
+            // replacing the questions reaches the editors too
+            let response = client
+                .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&SurveyPatch {
+                        questions: Some(SurveyQuestions::new()),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let CollabEvent::Questions {
+                session,
+                operations,
+                questions,
+                ..
+            } = next_collab_event(&mut stream)
+            else {
+                panic!("expected the questions to change");
+            };
+            assert_eq!(session, None);
+            assert!(operations.is_empty());
+            assert!(questions.0.is_empty());
+
+            drop(other_stream);
+            loop {
+                match next_collab_event(&mut stream) {
+                    CollabEvent::Left { session } => {
+                        assert_eq!(session, second);
+                        break;
+                    }
+                    CollabEvent::Questions { .. } => {}
+                    event => panic!("unexpected event {event:?}"),
+                }
+            }
+            let response = client
+                .put(uri!("/api", focus(survey_id, second)).to_string())
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(serde_json::to_vec(&FocusParams { question: None }).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+        });
+    }
+
+    fn add(client: &Client, token: &str, survey_id: i32, username: &str) -> Status {
+        client
+            .post(uri!("/api", add_collaborator(survey_id)).to_string())
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", token.to_owned()))
+            .body(
+                serde_json::to_vec(&CollaboratorParams {
+                    username: username.to_string(),
+                })
+                .unwrap(),
+            )
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn test_collaborators() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = register(&client, "owner", "test");
+            let survey_id = make_survey(&client, &token);
+            let session = Uuid::new_v4();
+            let mut stream = BufReader::new(open(&client, &token, survey_id, session));
+            next_collab_event(&mut stream);
+            next_collab_event(&mut stream);
+
+            let other_token = register(&client, "other", "test");
+            let response = open(&client, &other_token, survey_id, Uuid::new_v4());
+            assert_eq!(response.status(), Status::Forbidden);
+            let response = client
+                .put(uri!("/api", focus(survey_id, session)).to_string())
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", other_token.clone()))
+                .body(serde_json::to_vec(&FocusParams { question: None }).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+
+            // only the owner adds collaborators, and only other users that exist
+            assert_eq!(
+                add(&client, &other_token, survey_id, "other"),
+                Status::Forbidden
+            );
+            assert_eq!(
+                add(&client, &token, survey_id, "nobody"),
+                Status::UnprocessableEntity
+            );
+            assert_eq!(
+                add(&client, &token, survey_id, "owner"),
+                Status::UnprocessableEntity
+            );
+            assert_eq!(add(&client, &token, survey_id, "other"), Status::Ok);
+            assert_eq!(add(&client, &token, survey_id, "other"), Status::Ok);
+            let collaborators = client
+                .get(uri!("/api", list_collaborators(survey_id)).to_string())
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch()
+                .into_json::<Vec<Collaborator>>()
+                .unwrap();
+            assert_eq!(collaborators.len(), 1);
+            assert_eq!(collaborators[0].username, "other");
+
+            // a collaborator edits along with the owner
+            let other_session = Uuid::new_v4();
+            let response = open(&client, &other_token, survey_id, other_session);
+            assert_eq!(response.status(), Status::Ok);
+            let mut other_stream = BufReader::new(response);
+            next_collab_event(&mut other_stream);
+            let CollabEvent::Presence(editor) = next_collab_event(&mut stream) else {
+                panic!("expected the collaborator to join");
+            };
+            assert_eq!(editor.username, "other");
+            let response = client
+                .patch(
+                    uri!(
+                        "/api",
+                        crate::survey::operations::edit_questions(survey_id, Some(other_session))
+                    )
+                    .to_string(),
+                )
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", other_token.clone()))
+                .body(
+                    serde_json::to_vec(&[QuestionOperation::Add {
+                        question: SurveyQuestion {
+                            uuid: Uuid::new_v4(),
+                            required: false,
+                            question: Question::Text(QText {
+                                prompt: "prompt".to_string(),
+                                description: String::new(),
+                                multiline: false,
+                            }),
+                        },
+                        index: None,
+                    }])
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let CollabEvent::Questions { session, .. } = next_collab_event(&mut stream) else {
+                panic!("expected the questions to change");
+            };
+            assert_eq!(session, Some(other_session));
+
+            // removing the collaborator closes their editor
+            let response = client
+                .delete(uri!("/api", remove_collaborator(survey_id, editor.user_id)).to_string())
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let mut rest = String::new();
+            other_stream.read_to_string(&mut rest).unwrap();
+            let CollabEvent::Left { session } = next_collab_event(&mut stream) else {
+                panic!("expected the collaborator to leave");
+            };
+            assert_eq!(session, other_session);
+            let response = open(&client, &other_token, survey_id, Uuid::new_v4());
+            assert_eq!(response.status(), Status::Forbidden);
+        });
+    }
+
+    #[test]
+    fn test_reconnect() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let session = Uuid::new_v4();
+            let mut stream = BufReader::new(open(&client, &token, survey_id, session));
+            next_collab_event(&mut stream);
+            next_collab_event(&mut stream);
+
+            // the new stream takes over the session, which ends the old one
+            let mut new_stream = BufReader::new(open(&client, &token, survey_id, session));
+            next_collab_event(&mut new_stream);
+            let mut rest = String::new();
+            stream.read_to_string(&mut rest).unwrap();
+            drop(stream);
+
+            let mut other_stream = BufReader::new(open(&client, &token, survey_id, Uuid::new_v4()));
+            let CollabEvent::Snapshot { editors, .. } = next_collab_event(&mut other_stream) else {
+                panic!("expected a snapshot");
+            };
+            assert_eq!(editors.len(), 1);
+            assert_eq!(editors[0].session, session);
+        });
+    }
+
+    #[test]
+    fn test_import_reaches_editors() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let mut stream = BufReader::new(open(&client, &token, survey_id, Uuid::new_v4()));
+            next_collab_event(&mut stream);
+            next_collab_event(&mut stream);
+
+            let response = client
+                .post(uri!("/api", crate::survey::import::import_questions(survey_id)).to_string())
+                .header(ContentType::new("text", "csv"))
+                .header(Header::new("Authorization", token))
+                .body("type,prompt\nText,Name?\n")
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let CollabEvent::Questions {
+                session, questions, ..
+            } = next_collab_event(&mut stream)
+            else {
+                panic!("expected the questions to change");
+            };
+            assert_eq!(session, None);
+            assert_eq!(questions.len(), 1);
+        });
+    }
+}
@@ -1,19 +1,33 @@
 use std::io::Cursor;
 
 use diesel::prelude::*;
+use rocket::form::FromFormField;
 use rocket::http::{ContentType, Header, Status};
 use rocket::response::Responder;
 
 use crate::api::ApiErrorResponse;
-use crate::db::models::{Survey, SurveyResponse};
+use crate::db::models::{DuplicateCheck, Survey, SurveyResponse};
 use crate::db::Storage;
 use crate::jwt::Claims;
 use crate::questions::{Question, Response};
 use crate::survey::{get_survey_from_db, SurveyError};
+use crate::survey_response::history;
 
-#[get("/survey/<survey_id>/export")]
+/// Which version of the responses that were edited to export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromFormField, UriDisplayQuery)]
+pub enum ExportedSubmission {
+    /// What the respondents first submitted.
+    #[field(value = "first")]
+    First,
+    #[default]
+    #[field(value = "latest")]
+    Latest,
+}
+
+#[get("/survey/<survey_id>/export?<submission>")]
 pub async fn export_responses(
     survey_id: i32,
+    submission: Option<ExportedSubmission>,
     claims: Claims,
     db: Storage,
 ) -> Result<ExportedResults, ApiErrorResponse<SurveyError>> {
@@ -26,11 +40,23 @@ pub async fn export_responses(
         return Err(SurveyError::NotOwner.into());
     }
 
-    let responses: Vec<SurveyResponse> = db
+    let submission = submission.unwrap_or_default();
+    let responses: Vec<ExportedResponse> = db
         .run(move |conn| {
-            crate::db::schema::responses::dsl::responses
+            let mut responses = crate::db::schema::responses::dsl::responses
+                .left_join(crate::db::schema::invitations::table)
                 .filter(crate::db::schema::responses::survey_id.eq(survey_id))
-                .load::<SurveyResponse>(conn)
+                .filter(crate::db::schema::responses::quarantine_reason.is_null())
+                .select((
+                    crate::db::schema::responses::all_columns,
+                    crate::db::schema::invitations::label.nullable(),
+                    crate::db::schema::invitations::email.nullable(),
+                ))
+                .load::<ExportedResponse>(conn)?;
+            if submission == ExportedSubmission::First {
+                history::first_submissions(conn, responses.iter_mut().map(|(r, ..)| r))?;
+            }
+            Ok::<_, diesel::result::Error>(responses)
         })
         .await
         .map_err(|e| {
@@ -58,13 +84,29 @@ pub async fn export_responses(
     })
 }
 
+/// A response along with the label and email of the invitation it was made with.
+type ExportedResponse = (SurveyResponse, Option<String>, Option<String>);
+
 fn write_csv_rows<C: std::io::Write>(
     wtr: &mut csv::Writer<C>,
     survey: &Survey,
-    responses: &Vec<SurveyResponse>,
+    responses: &Vec<ExportedResponse>,
 ) -> anyhow::Result<()> {
+    // the invitation column is only there for surveys that have used invitations
+    let with_invitations =
+        survey.invite_only || responses.iter().any(|(r, ..)| r.invitation_id.is_some());
+    // and the duplicate column only for surveys that look for duplicates
+    let with_duplicates = survey.duplicate_check != DuplicateCheck::Off
+        || responses.iter().any(|(r, ..)| r.duplicate);
+
     // write header
     wtr.write_field("responder")?;
+    if with_invitations {
+        wtr.write_field("invitation")?;
+    }
+    if with_duplicates {
+        wtr.write_field("duplicate")?;
+    }
     wtr.write_field("created_at")?;
     wtr.write_field("updated_at")?;
     for question in survey.questions.iter() {
@@ -78,8 +120,15 @@ fn write_csv_rows<C: std::io::Write>(
     wtr.write_record(None::<&[u8]>)?;
 
     // write rows
-    for response in responses {
+    for (response, label, email) in responses {
         wtr.write_field(response.responder_uuid.to_string())?;
+        if with_invitations {
+            // fall back to the email, so that every invitee can be told apart
+            wtr.write_field(label.as_deref().or(email.as_deref()).unwrap_or_default())?;
+        }
+        if with_duplicates {
+            wtr.write_field(response.duplicate.to_string())?;
+        }
         wtr.write_field(response.created_at.to_string())?;
         wtr.write_field(response.updated_at.to_string())?;
 
@@ -159,6 +208,7 @@ mod tests {
 
     use std::str::FromStr;
 
+    use crate::survey::SurveyRef;
     use crate::{
         db::models::{SurveyPatch, SurveyQuestions},
         questions::{Choice, QMultipleChoice, QRating, QText, Question, SurveyQuestion},
@@ -184,6 +234,13 @@ mod tests {
                         title: Some("test".to_owned()),
                         description: None,
                         published: Some(true),
+                        status: None,
+                        response_limit: None,
+                        invite_only: None,
+                        slug: None,
+                        access_password: None,
+                        duplicate_check: None,
+                        reject_duplicates: None,
                         questions: Some(SurveyQuestions(vec![
                             SurveyQuestion {
                                 uuid: Uuid::from_str("00000000-0000-0000-0000-000000000000")
@@ -242,7 +299,7 @@ mod tests {
                 .post(
                     uri!(
                         "/api",
-                        crate::survey_response::create_survey_response(survey_id)
+                        crate::survey_response::create_survey_response(survey_id, _)
                     )
                     .to_string(),
                 )
@@ -278,7 +335,7 @@ mod tests {
             assert_eq!(response.status(), rocket::http::Status::Ok);
 
             let response = client
-                .get(uri!("/api", export_responses(survey_id)).to_string())
+                .get(uri!("/api", export_responses(survey_id, _)).to_string())
                 .header(rocket::http::ContentType::JSON)
                 .header(rocket::http::Header::new("Authorization", token))
                 .dispatch();
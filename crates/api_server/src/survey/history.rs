@@ -0,0 +1,478 @@
+use diesel::prelude::*;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::de::DeserializeOwned;
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{
+    FieldChange, HistoryAction, NewSurveyHistoryEntry, Survey, SurveyChanges, SurveyChangeset,
+    SurveyHistoryEntry, SurveyPatch, SurveyStatus,
+};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::survey::collab::{CollabEvent, Collaboration};
+use crate::survey::{get_survey_from_db, SurveyError};
+use crate::validate::Validate;
+
+/// The fields of a survey that are recorded in its history, which are the ones that a
+/// [`SurveyPatch`] can change. The access password itself is never recorded, only whether
+/// there is one.
+const HISTORY_FIELDS: [&str; 10] = [
+    "title",
+    "description",
+    "slug",
+    "status",
+    "questions",
+    "response_limit",
+    "invite_only",
+    "password_protected",
+    "duplicate_check",
+    "reject_duplicates",
+];
+
+/// The fields that make up the definition of a survey, which [`revert_survey`] goes back to.
+const DEFINITION_FIELDS: [&str; 3] = ["title", "description", "questions"];
+
+/// The recorded fields that differ between two versions of a survey.
+pub(crate) fn diff(before: &Survey, after: &Survey) -> SurveyChanges {
+    let (Ok(before), Ok(after)) = (serde_json::to_value(before), serde_json::to_value(after))
+    else {
+        return SurveyChanges::default();
+    };
+    SurveyChanges(
+        HISTORY_FIELDS
+            .iter()
+            .filter_map(|field| {
+                let before = before.get(field).cloned().unwrap_or_default();
+                let after = after.get(field).cloned().unwrap_or_default();
+                (before != after).then(|| (field.to_string(), FieldChange { before, after }))
+            })
+            .collect(),
+    )
+}
+
+/// Adds an entry to the history of a survey. It should be recorded in the same transaction
+/// as the change itself.
+pub(crate) fn record(
+    conn: &mut PgConnection,
+    survey_id: i32,
+    actor_id: i32,
+    action: HistoryAction,
+    changes: SurveyChanges,
+) -> QueryResult<()> {
+    diesel::insert_into(schema::survey_history::table)
+        .values(NewSurveyHistoryEntry {
+            survey_id,
+            actor_id: Some(actor_id),
+            action,
+            changes,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records an edit from `before` to `after`, unless nothing that is recorded changed.
+pub(crate) fn record_edit(
+    conn: &mut PgConnection,
+    actor_id: i32,
+    action: HistoryAction,
+    before: &Survey,
+    after: &Survey,
+) -> QueryResult<()> {
+    let changes = diff(before, after);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    record(conn, after.id, actor_id, action, changes)
+}
+
+/// Lists what was done to the survey, newest first.
+#[get("/survey/<survey_id>/history")]
+pub async fn get_survey_history(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<Vec<SurveyHistoryEntry>>, ApiErrorResponse<SurveyError>> {
+    let survey = get_survey_from_db(&db, survey_id).await.map_err(|e| {
+        error!("{e:?}");
+        SurveyError::NotFound
+    })?;
+
+    if survey.owner_id != claims.user_id() {
+        return Err(SurveyError::NotOwner.into());
+    }
+
+    let entries = db
+        .run(move |conn| {
+            schema::survey_history::table
+                .filter(schema::survey_history::survey_id.eq(survey_id))
+                .order(schema::survey_history::id.desc())
+                .load::<SurveyHistoryEntry>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?;
+
+    Ok(Json(entries))
+}
+
+/// Reads one of the [`DEFINITION_FIELDS`] back. A value that doesn't fit the field fails
+/// the whole revert, rather than leaving the survey half reverted.
+fn definition_field<T: DeserializeOwned>(
+    definition: &serde_json::Map<String, serde_json::Value>,
+    name: &str,
+) -> QueryResult<T> {
+    let value = definition.get(name).cloned().unwrap_or_default();
+    serde_json::from_value(value).map_err(|e| diesel::result::Error::DeserializationError(e.into()))
+}
+
+/// Brings the title, description and questions of a draft survey back to what they were
+/// right after the history entry. Everything done since is undone, and the revert itself
+/// is recorded, so it can be reverted in turn.
+#[post("/survey/<survey_id>/history/<entry_id>/revert")]
+pub async fn revert_survey(
+    survey_id: i32,
+    entry_id: i32,
+    claims: Claims,
+    db: Storage,
+    collab: &State<Collaboration>,
+) -> Result<Json<()>, ApiErrorResponse<SurveyError>> {
+    let user_id = claims.user_id();
+    let reverted = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    let Some(survey) = schema::surveys::table
+                        .for_update()
+                        .find(survey_id)
+                        .filter(schema::surveys::deleted_at.is_null())
+                        .first::<Survey>(conn)
+                        .optional()?
+                    else {
+                        return Ok(Err(SurveyError::NotFound));
+                    };
+                    if survey.owner_id != user_id {
+                        return Ok(Err(SurveyError::NotOwner));
+                    }
+                    if survey.status != SurveyStatus::Draft {
+                        return Ok(Err(SurveyError::CantEditPublished));
+                    }
+                    let entry = schema::survey_history::table
+                        .find(entry_id)
+                        .filter(schema::survey_history::survey_id.eq(survey_id))
+                        .select(schema::survey_history::id)
+                        .first::<i32>(conn)
+                        .optional()?;
+                    if entry.is_none() {
+                        return Ok(Err(SurveyError::NotFound));
+                    }
+
+                    // undo the later changes, newest first
+                    let later = schema::survey_history::table
+                        .filter(schema::survey_history::survey_id.eq(survey_id))
+                        .filter(schema::survey_history::id.gt(entry_id))
+                        .order(schema::survey_history::id.desc())
+                        .select(schema::survey_history::changes)
+                        .load::<SurveyChanges>(conn)?;
+                    let mut definition = serde_json::to_value(&survey)
+                        .map_err(|e| diesel::result::Error::SerializationError(e.into()))?
+                        .as_object()
+                        .cloned()
+                        .unwrap_or_default();
+                    for changes in later {
+                        for field in DEFINITION_FIELDS {
+                            if let Some(change) = changes.0.get(field) {
+                                definition.insert(field.to_string(), change.before.clone());
+                            }
+                        }
+                    }
+                    let patch = SurveyPatch {
+                        title: Some(definition_field(&definition, "title")?),
+                        description: Some(definition_field(&definition, "description")?),
+                        questions: Some(definition_field(&definition, "questions")?),
+                        ..Default::default()
+                    };
+                    if let Err(e) = patch.validate() {
+                        return Ok(Err(SurveyError::ValidationError(e)));
+                    }
+
+                    let updated = diesel::update(schema::surveys::table)
+                        .filter(schema::surveys::id.eq(survey_id))
+                        .set(SurveyChangeset::from(patch))
+                        .get_result::<Survey>(conn)?;
+                    record_edit(conn, user_id, HistoryAction::Revert, &survey, &updated)?;
+                    Ok(Ok(updated))
+                })
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })??;
+    collab.publish(
+        survey_id,
+        CollabEvent::questions(None, Vec::new(), &reverted),
+    );
+
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use uuid::Uuid;
+
+    use crate::questions::{QText, Question, SurveyQuestion};
+    use crate::survey::operations::QuestionOperation;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn history(client: &Client, token: &str, survey_id: i32) -> Vec<SurveyHistoryEntry> {
+        let response = client
+            .get(uri!("/api", get_survey_history(survey_id)).to_string())
+            .header(Header::new("Authorization", token.to_owned()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().unwrap()
+    }
+
+    fn edit(client: &Client, token: &str, survey_id: i32, patch: SurveyPatch) {
+        let response = client
+            .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", token.to_owned()))
+            .body(serde_json::to_vec(&patch).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    fn revert(client: &Client, token: &str, survey_id: i32, entry_id: i32) -> Status {
+        client
+            .post(uri!("/api", revert_survey(survey_id, entry_id)).to_string())
+            .header(Header::new("Authorization", token.to_owned()))
+            .dispatch()
+            .status()
+    }
+
+    fn get_survey(client: &Client, token: &str, survey_id: i32) -> Survey {
+        client
+            .get(uri!("/api", crate::survey::get_survey_auth(survey_id)).to_string())
+            .header(Header::new("Authorization", token.to_owned()))
+            .dispatch()
+            .into_json()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_history_and_revert() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            edit(
+                &client,
+                &token,
+                survey_id,
+                SurveyPatch {
+                    title: Some("first".to_owned()),
+                    ..Default::default()
+                },
+            );
+            let response = client
+                .patch(
+                    uri!(
+                        "/api",
+                        crate::survey::operations::edit_questions(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&[QuestionOperation::Add {
+                        question: SurveyQuestion {
+                            uuid: Uuid::new_v4(),
+                            required: false,
+                            question: Question::Text(QText {
+                                prompt: "prompt".to_string(),
+                                description: String::new(),
+                                multiline: false,
+                            }),
+                        },
+                        index: None,
+                    }])
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            edit(
+                &client,
+                &token,
+                survey_id,
+                SurveyPatch {
+                    title: Some("second".to_owned()),
+                    ..Default::default()
+                },
+            );
+
+            let entries = history(&client, &token, survey_id);
+            assert_eq!(entries.len(), 3);
+            assert!(entries.iter().all(|e| e.action == HistoryAction::Edit));
+            let first = &entries[2];
+            assert_eq!(first.changes.0["title"].after, "first");
+            assert!(first.actor_id.is_some());
+            assert_eq!(
+                entries[1].changes.0.keys().collect::<Vec<_>>(),
+                ["questions"]
+            );
+            assert_eq!(entries[0].changes.0["title"].before, "first");
+
+            // reverting undoes everything after the entry, and is recorded itself
+            assert_eq!(revert(&client, &token, survey_id, first.id), Status::Ok);
+            let survey = get_survey(&client, &token, survey_id);
+            assert_eq!(survey.title, "first");
+            assert!(survey.questions.0.is_empty());
+            let entries = history(&client, &token, survey_id);
+            assert_eq!(entries.len(), 4);
+            assert_eq!(entries[0].action, HistoryAction::Revert);
+            assert_eq!(entries[0].changes.0["title"].after, "first");
+
+            // and can be reverted in turn
+            assert_eq!(
+                revert(&client, &token, survey_id, entries[1].id),
+                Status::Ok
+            );
+            let survey = get_survey(&client, &token, survey_id);
+            assert_eq!(survey.title, "second");
+            assert_eq!(survey.questions.0.len(), 1);
+
+            let other_token = create_test_user(&client);
+            assert_eq!(
+                revert(&client, &other_token, survey_id, first.id),
+                Status::Forbidden
+            );
+            let response = client
+                .get(uri!("/api", get_survey_history(survey_id)).to_string())
+                .header(Header::new("Authorization", other_token))
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+            let other_survey_id = make_survey(&client, &token);
+            assert_eq!(
+                revert(&client, &token, other_survey_id, first.id),
+                Status::NotFound
+            );
+
+            publish_survey(&client, &token, survey_id);
+            assert_eq!(
+                revert(&client, &token, survey_id, first.id),
+                Status::Forbidden
+            );
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        crate::survey_response::create_survey_response(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .header(ContentType::JSON)
+                .body("{}")
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let response = client
+                .delete(
+                    uri!(
+                        "/api",
+                        crate::survey_response::clear_survey_responses(survey_id)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let response = client
+                .delete(uri!("/api", crate::survey::delete_survey(survey_id)).to_string())
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let response = client
+                .post(uri!("/api", crate::survey::trash::restore_survey(survey_id)).to_string())
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+
+            let entries = history(&client, &token, survey_id);
+            let actions = entries.iter().map(|e| e.action).collect::<Vec<_>>();
+            assert_eq!(
+                actions[..4],
+                [
+                    HistoryAction::Restore,
+                    HistoryAction::Delete,
+                    HistoryAction::ClearResponses,
+                    HistoryAction::Publish
+                ]
+            );
+            assert_eq!(entries[2].changes.0["responses"].before, 1);
+            assert_eq!(entries[3].changes.0["status"].after, "open");
+        });
+    }
+
+    #[test]
+    fn test_revert_unreadable_history() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            edit(
+                &client,
+                &token,
+                survey_id,
+                SurveyPatch {
+                    title: Some("first".to_owned()),
+                    ..Default::default()
+                },
+            );
+            edit(
+                &client,
+                &token,
+                survey_id,
+                SurveyPatch {
+                    title: Some("second".to_owned()),
+                    description: Some("described".to_owned()),
+                    ..Default::default()
+                },
+            );
+
+            // the title that the revert would go back to is no longer a string
+            let entries = history(&client, &token, survey_id);
+            let mut changes = entries[0].changes.clone();
+            changes.0.get_mut("title").unwrap().before = serde_json::json!(5);
+            let mut conn =
+                PgConnection::establish(&format!("postgres://vscode:notsecure@db/{db_name}"))
+                    .expect("Failed to connect to database");
+            diesel::update(schema::survey_history::table.find(entries[0].id))
+                .set(schema::survey_history::changes.eq(changes))
+                .execute(&mut conn)
+                .unwrap();
+
+            // nothing is reverted, not even the description
+            assert_eq!(
+                revert(&client, &token, survey_id, entries[1].id),
+                Status::InternalServerError
+            );
+            let survey = get_survey(&client, &token, survey_id);
+            assert_eq!(survey.title, "second");
+            assert_eq!(survey.description, "described");
+            assert_eq!(history(&client, &token, survey_id).len(), 2);
+        });
+    }
+}
@@ -0,0 +1,496 @@
+use diesel::prelude::*;
+use rocket::serde::json::Json;
+use rocket::State;
+use uuid::Uuid;
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{HistoryAction, Survey, SurveyPatch, SurveyQuestions};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::questions::{Choice, QMultipleChoice, QRating, QText, Question, SurveyQuestion};
+use crate::survey::collab::{CollabEvent, Collaboration};
+use crate::survey::{get_survey_from_db, history, SurveyError};
+use crate::validate::{Validate, ValidationError};
+
+/// Separates the individual choices in the `choices` column.
+const CHOICE_SEPARATOR: char = '|';
+
+/// Imports questions from a CSV document and appends them to the survey.
+///
+/// The CSV must have a header row. The `type` and `prompt` columns are required, and
+/// `description`, `required`, `choices`, `scale`, `multiline`, and `multiple` are optional.
+/// Choices are separated by `|`, and `scale` is the maximum rating of a rating question.
+#[post("/survey/<survey_id>/import", data = "<csv>")]
+pub async fn import_questions(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+    collab: &State<Collaboration>,
+    csv: String,
+) -> Result<Json<SurveyQuestions>, ApiErrorResponse<SurveyError>> {
+    let survey = get_survey_from_db(&db, survey_id).await.map_err(|e| {
+        error!("{e:?}");
+        SurveyError::NotFound
+    })?;
+
+    if survey.owner_id != claims.user_id() {
+        return Err(SurveyError::NotOwner.into());
+    }
+
+    let imported = parse_questions_csv(csv.as_bytes())?;
+
+    let new_questions = imported.clone();
+    let user_id = claims.user_id();
+    let updated = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    // checked against the locked row, so the survey can't be published in between
+                    let survey = schema::surveys::table
+                        .for_update()
+                        .find(survey_id)
+                        .first::<Survey>(conn)?;
+                    if survey.status.is_published() {
+                        return Ok(Err(SurveyError::CantEditPublished));
+                    }
+                    let mut questions = survey.questions.clone();
+                    questions.0.extend(new_questions.0);
+                    let patch = SurveyPatch {
+                        questions: Some(questions),
+                        ..Default::default()
+                    };
+                    if let Err(errors) = patch.validate() {
+                        return Ok(Err(SurveyError::ValidationError(errors)));
+                    }
+                    let updated = diesel::update(schema::surveys::table)
+                        .filter(schema::surveys::id.eq(survey_id))
+                        .set(schema::surveys::questions.eq(patch.questions.unwrap_or_default()))
+                        .get_result::<Survey>(conn)?;
+                    history::record_edit(conn, user_id, HistoryAction::Edit, &survey, &updated)?;
+                    Ok(Ok(updated))
+                })
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })??;
+    collab.publish(
+        survey_id,
+        CollabEvent::questions(None, Vec::new(), &updated),
+    );
+
+    Ok(Json(imported))
+}
+
+/// Column indices of the recognized headers in an imported CSV.
+struct Columns {
+    kind: usize,
+    prompt: usize,
+    description: Option<usize>,
+    required: Option<usize>,
+    choices: Option<usize>,
+    scale: Option<usize>,
+    multiline: Option<usize>,
+    multiple: Option<usize>,
+}
+
+impl Columns {
+    fn from_headers(headers: &csv::StringRecord) -> Result<Self, Vec<ValidationError>> {
+        let find = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(name))
+        };
+
+        let mut errors = Vec::new();
+        let mut require = |name: &str| {
+            let index = find(name);
+            if index.is_none() {
+                errors.push(ValidationError::Required {
+                    field: name.to_owned(),
+                });
+            }
+            index
+        };
+        let (kind, prompt) = (require("type"), require("prompt"));
+        let (Some(kind), Some(prompt)) = (kind, prompt) else {
+            return Err(errors);
+        };
+
+        Ok(Self {
+            kind,
+            prompt,
+            description: find("description"),
+            required: find("required"),
+            choices: find("choices"),
+            scale: find("scale"),
+            multiline: find("multiline"),
+            multiple: find("multiple"),
+        })
+    }
+}
+
+/// Parses a CSV document into a list of questions with freshly generated UUIDs.
+///
+/// Every row is parsed and validated, so that all of the problems in the document are
+/// reported at once. Errors in rows are wrapped in [`ValidationError::Row`], while problems
+/// with the header are reported as they are, since they aren't about any one row.
+pub(crate) fn parse_questions_csv<R: std::io::Read>(
+    rdr: R,
+) -> Result<SurveyQuestions, Vec<ValidationError>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(rdr);
+
+    let headers = rdr.headers().map_err(|e| {
+        vec![ValidationError::BadValue {
+            field: "csv".to_owned(),
+            message: e.to_string(),
+        }]
+    })?;
+    let columns = Columns::from_headers(headers)?;
+
+    let mut questions = SurveyQuestions::new();
+    let mut errors = Vec::new();
+    for (i, record) in rdr.records().enumerate() {
+        // the header is the first row
+        let mut row = u32::try_from(i + 2).unwrap_or(u32::MAX);
+        let result = record
+            .map_err(|e| {
+                vec![ValidationError::BadValue {
+                    field: "row".to_owned(),
+                    message: e.to_string(),
+                }]
+            })
+            .and_then(|record| {
+                if let Some(line) = record.position().map(|p| p.line()) {
+                    row = u32::try_from(line).unwrap_or(u32::MAX);
+                }
+                parse_row(&columns, &record)
+            });
+        match result {
+            Ok(question) => questions.0.push(question),
+            Err(row_errors) => {
+                errors.extend(row_errors.into_iter().map(|e| ValidationError::Row {
+                    row,
+                    inner: Box::new(e),
+                }));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(questions)
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse_row(
+    columns: &Columns,
+    record: &csv::StringRecord,
+) -> Result<SurveyQuestion, Vec<ValidationError>> {
+    let get = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or("");
+    let mut errors = Vec::new();
+    let mut bool_column = |field: &str, index: Option<usize>| {
+        parse_bool(get(index)).unwrap_or_else(|| {
+            errors.push(ValidationError::BadValue {
+                field: field.to_owned(),
+                message: format!("`{}` is not a boolean", get(index)),
+            });
+            false
+        })
+    };
+    let required = bool_column("required", columns.required);
+    let multiline = bool_column("multiline", columns.multiline);
+    let multiple = bool_column("multiple", columns.multiple);
+
+    let prompt = get(Some(columns.prompt)).to_owned();
+    let description = get(columns.description).to_owned();
+    let kind = get(Some(columns.kind))
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .collect::<String>()
+        .to_lowercase();
+    let question: Option<Question> = match kind.as_str() {
+        "text" => Some(
+            QText {
+                prompt,
+                description,
+                multiline,
+            }
+            .into(),
+        ),
+        "rating" => {
+            let scale = get(columns.scale);
+            match scale.parse::<i64>() {
+                Ok(max_rating) => match u8::try_from(max_rating) {
+                    Ok(max_rating) => Some(
+                        QRating {
+                            prompt,
+                            description,
+                            max_rating,
+                        }
+                        .into(),
+                    ),
+                    Err(_) => {
+                        errors.push(ValidationError::NotInRange {
+                            field: "max_rating".to_owned(),
+                            value: max_rating.clamp(i32::MIN.into(), i32::MAX.into()) as i32,
+                            min: 2,
+                            max: 10,
+                        });
+                        None
+                    }
+                },
+                Err(_) if scale.is_empty() => {
+                    errors.push(ValidationError::Required {
+                        field: "scale".to_owned(),
+                    });
+                    None
+                }
+                Err(_) => {
+                    errors.push(ValidationError::BadValue {
+                        field: "scale".to_owned(),
+                        message: format!("`{scale}` is not a number"),
+                    });
+                    None
+                }
+            }
+        }
+        "multiplechoice" => Some(
+            QMultipleChoice {
+                prompt,
+                description,
+                multiple,
+                choices: get(columns.choices)
+                    .split(CHOICE_SEPARATOR)
+                    .map(str::trim)
+                    .filter(|text| !text.is_empty())
+                    .map(|text| Choice {
+                        uuid: Uuid::new_v4(),
+                        text: text.to_owned(),
+                    })
+                    .collect(),
+            }
+            .into(),
+        ),
+        "" => {
+            errors.push(ValidationError::Required {
+                field: "type".to_owned(),
+            });
+            None
+        }
+        _ => {
+            errors.push(ValidationError::BadValue {
+                field: "type".to_owned(),
+                message: format!(
+                    "`{}` is not one of Text, Rating, or MultipleChoice",
+                    get(Some(columns.kind))
+                ),
+            });
+            None
+        }
+    };
+
+    if let Some(Err(question_errors)) = question.as_ref().map(Validate::validate) {
+        errors.extend(question_errors);
+    }
+
+    match question {
+        Some(question) if errors.is_empty() => Ok(SurveyQuestion {
+            uuid: Uuid::new_v4(),
+            required,
+            question,
+        }),
+        _ => Err(errors),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "" | "false" | "no" | "n" | "0" => Some(false),
+        "true" | "yes" | "y" | "1" | "x" => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn parse_all_question_types() {
+        let csv = "type,prompt,description,required,choices,scale\n\
+            Text,What is your name?,,yes,,\n\
+            Rating,How much do you like this?,1 is bad,no,,5\n\
+            Multiple Choice,Pick one,,true,foo | bar,\n";
+        let questions = parse_questions_csv(csv.as_bytes()).unwrap();
+        assert_eq!(questions.len(), 3);
+
+        assert!(questions.0[0].required);
+        let Question::Text(q) = &questions.0[0].question else {
+            panic!("expected text question: {:?}", questions.0[0]);
+        };
+        assert_eq!(q.prompt, "What is your name?");
+        assert!(!q.multiline);
+
+        assert!(!questions.0[1].required);
+        let Question::Rating(q) = &questions.0[1].question else {
+            panic!("expected rating question: {:?}", questions.0[1]);
+        };
+        assert_eq!(q.description, "1 is bad");
+        assert_eq!(q.max_rating, 5);
+
+        let Question::MultipleChoice(q) = &questions.0[2].question else {
+            panic!("expected multiple choice question: {:?}", questions.0[2]);
+        };
+        let choices = q
+            .choices
+            .iter()
+            .map(|c| c.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(choices, vec!["foo", "bar"]);
+        assert!(!q.multiple);
+    }
+
+    #[test]
+    fn missing_headers_are_reported() {
+        let errors = parse_questions_csv("description,required\n".as_bytes()).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::Required {
+                    field: "type".to_owned()
+                },
+                ValidationError::Required {
+                    field: "prompt".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_rows_are_reported_by_row() {
+        let csv = "type,prompt,required,scale\n\
+            Text,Fine,,\n\
+            Essay,Bad type,,\n\
+            Rating,Bad scale,,20\n\
+            Text,,maybe,\n";
+        let errors = parse_questions_csv(csv.as_bytes()).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::Row {
+                    row: 3,
+                    inner: Box::new(ValidationError::BadValue {
+                        field: "type".to_owned(),
+                        message: "`Essay` is not one of Text, Rating, or MultipleChoice".to_owned(),
+                    }),
+                },
+                ValidationError::Row {
+                    row: 4,
+                    inner: Box::new(ValidationError::NotInRange {
+                        field: "max_rating".to_owned(),
+                        value: 20,
+                        min: 2,
+                        max: 10,
+                    }),
+                },
+                ValidationError::Row {
+                    row: 5,
+                    inner: Box::new(ValidationError::BadValue {
+                        field: "required".to_owned(),
+                        message: "`maybe` is not a boolean".to_owned(),
+                    }),
+                },
+                ValidationError::Row {
+                    row: 5,
+                    inner: Box::new(ValidationError::Required {
+                        field: "prompt".to_owned(),
+                    }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_range_scale_is_reported() {
+        let csv = "type,prompt,scale\nRating,Too big,300\nRating,Not a number,lots\n";
+        let errors = parse_questions_csv(csv.as_bytes()).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::Row {
+                    row: 2,
+                    inner: Box::new(ValidationError::NotInRange {
+                        field: "max_rating".to_owned(),
+                        value: 300,
+                        min: 2,
+                        max: 10,
+                    }),
+                },
+                ValidationError::Row {
+                    row: 3,
+                    inner: Box::new(ValidationError::BadValue {
+                        field: "scale".to_owned(),
+                        message: "`lots` is not a number".to_owned(),
+                    }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn import_appends_questions() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let response = client
+                .post(uri!("/api", import_questions(survey_id)).to_string())
+                .header(rocket::http::ContentType::new("text", "csv"))
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .body("type,prompt,scale\nText,Name?,\nRating,Rate us,5\n")
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            let imported = response.into_json::<SurveyQuestions>().unwrap();
+            assert_eq!(imported.len(), 2);
+
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            let survey = response.into_json::<Survey>().unwrap();
+            assert_eq!(survey.questions.len(), 2);
+            assert_eq!(survey.questions.0[0].uuid, imported.0[0].uuid);
+        });
+    }
+
+    #[test]
+    fn import_bad_rows_unprocessable() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let response = client
+                .post(uri!("/api", import_questions(survey_id)).to_string())
+                .header(rocket::http::ContentType::new("text", "csv"))
+                .header(rocket::http::Header::new("Authorization", token))
+                .body("type,prompt\nText,\n")
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::UnprocessableEntity);
+        });
+    }
+}
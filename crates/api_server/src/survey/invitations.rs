@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use password_hash::rand_core::{OsRng, RngCore};
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{Invitation, NewInvitation};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::survey::{check_survey_owner, SurveyError};
+use crate::user::recovery::hash_token;
+use crate::validate::Validate;
+
+/// The most invitations that can be created with one request.
+pub const MAX_INVITATIONS_PER_REQUEST: i32 = 500;
+
+/// Someone to invite. Both fields are only there to help the owner keep track of who
+/// responded, nothing is sent to the email address.
+#[typeshare]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Invitee {
+    pub label: Option<String>,
+    pub email: Option<String>,
+}
+
+/// The body of a request to create a batch of invitations.
+#[typeshare]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InvitationParams {
+    /// One invitation is created for each invitee.
+    #[serde(default)]
+    pub invitees: Vec<Invitee>,
+    /// How many invitations to create that aren't tied to anyone, on top of the invitees.
+    pub count: Option<i32>,
+    /// How many responses each invitation can be used for. Defaults to 1.
+    pub max_uses: Option<i32>,
+}
+
+/// A new invitation, along with its token. Only a hash of the token is kept, so this is
+/// the only time it can be seen.
+#[typeshare]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedInvitation {
+    pub token: String,
+    pub invitation: Invitation,
+}
+
+#[post("/survey/<survey_id>/invitations", data = "<params>")]
+pub async fn create_invitations(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+    params: Json<InvitationParams>,
+) -> Result<Created<Json<Vec<CreatedInvitation>>>, ApiErrorResponse<SurveyError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    params.validate()?;
+
+    let params = params.into_inner();
+    let max_uses = params.max_uses.unwrap_or(1);
+    let anonymous =
+        std::iter::repeat_with(Invitee::default).take(params.count.unwrap_or(0) as usize);
+    let mut tokens = HashMap::new();
+    let new_invitations = params
+        .invitees
+        .into_iter()
+        .chain(anonymous)
+        .map(|invitee| {
+            let token = generate_token();
+            let token_hash = hash_token(&token);
+            tokens.insert(token_hash.clone(), token);
+            NewInvitation {
+                survey_id,
+                token_hash,
+                label: invitee.label.filter(|l| !l.is_empty()),
+                email: invitee.email.filter(|e| !e.is_empty()),
+                max_uses,
+            }
+        })
+        .collect::<Vec<_>>();
+    let invitations = db
+        .run(move |conn| {
+            diesel::insert_into(schema::invitations::table)
+                .values(&new_invitations)
+                .get_results::<Invitation>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?;
+
+    let created = invitations
+        .into_iter()
+        .map(|invitation| CreatedInvitation {
+            token: tokens.remove(&invitation.token_hash).unwrap_or_default(),
+            invitation,
+        })
+        .collect();
+
+    let resource_uri = uri!(list_invitations(survey_id)).to_string();
+    Ok(Created::new(resource_uri).body(Json(created)))
+}
+
+#[get("/survey/<survey_id>/invitations")]
+pub async fn list_invitations(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<Vec<Invitation>>, ApiErrorResponse<SurveyError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let invitations = db
+        .run(move |conn| {
+            schema::invitations::table
+                .filter(schema::invitations::survey_id.eq(survey_id))
+                .order(schema::invitations::id.asc())
+                .load::<Invitation>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?;
+
+    Ok(Json(invitations))
+}
+
+/// Stops an invitation from being used again. Responses that were already made with it are
+/// kept, along with their label in the export.
+#[delete("/survey/<survey_id>/invitations/<invitation_id>")]
+pub async fn revoke_invitation(
+    survey_id: i32,
+    invitation_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<()>, ApiErrorResponse<SurveyError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let revoked = db
+        .run(move |conn| {
+            diesel::update(schema::invitations::table)
+                .filter(schema::invitations::id.eq(invitation_id))
+                .filter(schema::invitations::survey_id.eq(survey_id))
+                .filter(schema::invitations::revoked_at.is_null())
+                .set(schema::invitations::revoked_at.eq(diesel::dsl::now))
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })?;
+    if revoked == 0 {
+        return Err(SurveyError::NotFound.into());
+    }
+
+    Ok(Json(()))
+}
+
+/// Tokens end up in links, so they are hex encoded.
+fn generate_token() -> String {
+    let mut token = [0u8; 16];
+    OsRng.fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+/// Uses up one response's worth of an invitation, returning its id. Returns `None` if the
+/// token doesn't belong to the survey, was revoked, or has no uses left. This should run
+/// in the same transaction as the response being created.
+pub(crate) fn consume_invitation(
+    conn: &mut PgConnection,
+    survey_id: i32,
+    token: &str,
+) -> QueryResult<Option<i32>> {
+    use schema::invitations::dsl;
+
+    diesel::update(dsl::invitations)
+        .filter(dsl::survey_id.eq(survey_id))
+        .filter(dsl::token_hash.eq(hash_token(token)))
+        .filter(dsl::revoked_at.is_null())
+        .filter(dsl::uses.lt(dsl::max_uses))
+        .set(dsl::uses.eq(dsl::uses + 1))
+        .returning(dsl::id)
+        .get_result::<i32>(conn)
+        .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use rocket::http::Status;
+    use rocket::local::blocking::{Client, LocalResponse};
+
+    use crate::db::models::{SurveyPatch, SurveyResponses, SurveyStatus};
+    use crate::survey::export::ExportedSubmission;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn make_invite_only_survey(client: &Client, token: &str) -> i32 {
+        let survey_id = make_survey(client, token);
+        client
+            .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+            .header(rocket::http::ContentType::JSON)
+            .header(rocket::http::Header::new("Authorization", token.to_owned()))
+            .body(
+                serde_json::to_vec(&SurveyPatch {
+                    status: Some(SurveyStatus::Open),
+                    invite_only: Some(true),
+                    ..Default::default()
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        survey_id
+    }
+
+    fn invite<'c>(
+        client: &'c Client,
+        token: &str,
+        survey_id: i32,
+        params: &InvitationParams,
+    ) -> LocalResponse<'c> {
+        client
+            .post(uri!("/api", create_invitations(survey_id)).to_string())
+            .header(rocket::http::ContentType::JSON)
+            .header(rocket::http::Header::new("Authorization", token.to_owned()))
+            .body(serde_json::to_vec(params).unwrap())
+            .dispatch()
+    }
+
+    fn respond(client: &Client, survey_id: i32, invitation: Option<&str>) -> Status {
+        client
+            .post(
+                uri!(
+                    "/api",
+                    crate::survey_response::create_survey_response(
+                        survey_id,
+                        invitation.map(str::to_owned)
+                    )
+                )
+                .to_string(),
+            )
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn test_invite_only_survey() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_invite_only_survey(&client, &token);
+
+            let response = invite(
+                &client,
+                &token,
+                survey_id,
+                &InvitationParams {
+                    invitees: vec![Invitee {
+                        label: Some("alice".to_string()),
+                        email: None,
+                    }],
+                    count: Some(1),
+                    max_uses: None,
+                },
+            );
+            assert_eq!(response.status(), Status::Created);
+            let created = response.into_json::<Vec<CreatedInvitation>>().unwrap();
+            assert_eq!(created.len(), 2);
+            assert_eq!(created[0].invitation.label.as_deref(), Some("alice"));
+            assert_eq!(created[1].invitation.label, None);
+            assert_ne!(created[0].token, created[1].token);
+
+            assert_eq!(respond(&client, survey_id, None), Status::Forbidden);
+            assert_eq!(respond(&client, survey_id, Some("nope")), Status::Forbidden);
+            assert_eq!(
+                respond(&client, survey_id, Some(&created[0].token)),
+                Status::Ok
+            );
+            // single use by default
+            assert_eq!(
+                respond(&client, survey_id, Some(&created[0].token)),
+                Status::Forbidden
+            );
+
+            let response = client
+                .get(
+                    uri!(
+                        "/api",
+                        crate::survey::export::export_responses(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            let csv = response.into_string().unwrap();
+            let mut lines = csv.lines();
+            assert_eq!(
+                lines.next(),
+                Some("responder,invitation,created_at,updated_at")
+            );
+            assert_eq!(lines.next().unwrap().split(',').nth(1), Some("alice"));
+            assert_eq!(lines.next(), None);
+
+            let response = client
+                .get(uri!("/api", list_invitations(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            let body = response.into_string().unwrap();
+            // tokens are only kept as hashes, so they can't be listed
+            assert!(!body.contains(&created[0].token));
+            let invitations = serde_json::from_str::<Vec<Invitation>>(&body).unwrap();
+            assert_eq!(invitations[0].uses, 1);
+            assert_eq!(invitations[1].uses, 0);
+        });
+    }
+
+    #[test]
+    fn test_revoke_invitation() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_invite_only_survey(&client, &token);
+            let invitations = invite(
+                &client,
+                &token,
+                survey_id,
+                &InvitationParams {
+                    count: Some(1),
+                    max_uses: Some(2),
+                    ..Default::default()
+                },
+            )
+            .into_json::<Vec<CreatedInvitation>>()
+            .unwrap();
+            let invitation = &invitations[0].invitation;
+            let invitation_token = &invitations[0].token;
+
+            assert_eq!(
+                respond(&client, survey_id, Some(invitation_token)),
+                Status::Ok
+            );
+
+            let other_token = create_test_user(&client);
+            let response = client
+                .delete(uri!("/api", revoke_invitation(survey_id, invitation.id)).to_string())
+                .header(rocket::http::Header::new("Authorization", other_token))
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+
+            let response = client
+                .delete(uri!("/api", revoke_invitation(survey_id, invitation.id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+
+            assert_eq!(
+                respond(&client, survey_id, Some(invitation_token)),
+                Status::Forbidden
+            );
+        });
+    }
+
+    #[test]
+    fn test_invitation_params_validation() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_invite_only_survey(&client, &token);
+
+            for params in [
+                InvitationParams::default(),
+                InvitationParams {
+                    count: Some(MAX_INVITATIONS_PER_REQUEST + 1),
+                    ..Default::default()
+                },
+                InvitationParams {
+                    count: Some(1),
+                    max_uses: Some(0),
+                    ..Default::default()
+                },
+                InvitationParams {
+                    invitees: vec![Invitee {
+                        label: None,
+                        email: Some("not an email".to_string()),
+                    }],
+                    ..Default::default()
+                },
+            ] {
+                let response = invite(&client, &token, survey_id, &params);
+                assert_eq!(response.status(), Status::UnprocessableEntity);
+            }
+        });
+    }
+}
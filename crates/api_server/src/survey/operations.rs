@@ -0,0 +1,377 @@
+use diesel::prelude::*;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::ApiErrorResponse;
+use crate::cache::{Cacheable, RaceCheck};
+use crate::db::models::{HistoryAction, Survey, SurveyPatch, SurveyQuestions};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::questions::SurveyQuestion;
+use crate::survey::collab::{self, CollabEvent, Collaboration};
+use crate::survey::{history, SurveyError};
+use crate::validate::{Validate, ValidationError};
+
+/// A change to a single question, which refers to the questions by UUID so that changes
+/// to different questions can be applied in any order.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "content")]
+pub enum QuestionOperation {
+    /// Inserts a question at `index`, or appends it when there is none.
+    Add {
+        question: SurveyQuestion,
+        index: Option<u32>,
+    },
+    /// Moves a question to `index`, counted without the question itself.
+    Move {
+        #[typeshare(serialized_as = "String")]
+        uuid: Uuid,
+        index: u32,
+    },
+    /// Replaces the question with the same UUID, keeping its place.
+    Update { question: SurveyQuestion },
+    Delete {
+        #[typeshare(serialized_as = "String")]
+        uuid: Uuid,
+    },
+}
+
+impl QuestionOperation {
+    pub fn apply(self, questions: &mut SurveyQuestions) -> Result<(), ValidationError> {
+        let questions = &mut questions.0;
+        let position = |questions: &[SurveyQuestion], uuid: Uuid| {
+            questions
+                .iter()
+                .position(|q| q.uuid == uuid)
+                .ok_or(ValidationError::NotFound {
+                    field: "questions".to_string(),
+                    uuid,
+                })
+        };
+        let check_index = |index: u32, len: usize| {
+            let index = index as usize;
+            if index > len {
+                return Err(ValidationError::NotInRange {
+                    field: "index".to_string(),
+                    value: i32::try_from(index).unwrap_or(i32::MAX),
+                    min: 0,
+                    max: i32::try_from(len).unwrap_or(i32::MAX),
+                });
+            }
+            Ok(index)
+        };
+
+        match self {
+            // a duplicate UUID is left to the validation of the resulting questions
+            QuestionOperation::Add { question, index } => {
+                let index = match index {
+                    Some(index) => check_index(index, questions.len())?,
+                    None => questions.len(),
+                };
+                questions.insert(index, question);
+            }
+            QuestionOperation::Move { uuid, index } => {
+                let from = position(questions, uuid)?;
+                let index = check_index(index, questions.len() - 1)?;
+                let question = questions.remove(from);
+                questions.insert(index, question);
+            }
+            QuestionOperation::Update { question } => {
+                let at = position(questions, question.uuid)?;
+                questions[at] = question;
+            }
+            QuestionOperation::Delete { uuid } => {
+                let at = position(questions, uuid)?;
+                questions.remove(at);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the operations in order to the questions of a draft survey, under the lock of
+/// its row, and saves the result if it passes the same validation as [`edit_survey`].
+/// Database errors are returned as is, for the caller to log.
+///
+/// [`edit_survey`]: crate::survey::edit_survey
+pub(crate) fn apply_question_operations(
+    conn: &mut PgConnection,
+    survey_id: i32,
+    user_id: i32,
+    operations: Vec<QuestionOperation>,
+    race_check: Option<RaceCheck>,
+) -> QueryResult<Result<Survey, SurveyError>> {
+    conn.build_transaction()
+        .read_write()
+        .run::<_, diesel::result::Error, _>(|conn| {
+            let Some(survey) = schema::surveys::table
+                .for_update()
+                .find(survey_id)
+                .filter(schema::surveys::deleted_at.is_null())
+                .first::<Survey>(conn)
+                .optional()?
+            else {
+                return Ok(Err(SurveyError::NotFound));
+            };
+            if !collab::can_edit_questions(conn, &survey, user_id)? {
+                return Ok(Err(SurveyError::NotOwner));
+            }
+            if let Some(race_check) = race_check {
+                if !survey.has_no_mid_air_collision(race_check) {
+                    return Ok(Err(SurveyError::RaceError));
+                }
+            }
+            if survey.status.is_published() {
+                return Ok(Err(SurveyError::CantEditPublished));
+            }
+
+            let mut questions = survey.questions.clone();
+            for operation in operations {
+                if let Err(e) = operation.apply(&mut questions) {
+                    return Ok(Err(SurveyError::ValidationError(vec![e])));
+                }
+            }
+            let patch = SurveyPatch {
+                questions: Some(questions),
+                ..Default::default()
+            };
+            if let Err(e) = patch.validate() {
+                return Ok(Err(SurveyError::ValidationError(e)));
+            }
+
+            let questions = patch.questions.unwrap_or_default();
+            let updated = diesel::update(schema::surveys::table)
+                .filter(schema::surveys::id.eq(survey_id))
+                .set(schema::surveys::questions.eq(&questions))
+                .get_result::<Survey>(conn)?;
+            history::record_edit(conn, user_id, HistoryAction::Edit, &survey, &updated)?;
+            Ok(Ok(updated))
+        })
+}
+
+/// Changes individual questions of a draft survey and returns all of its questions
+/// afterwards. Unlike replacing `questions` with `edit_survey`, edits to different questions
+/// don't overwrite each other, so preconditions are optional here even when they are
+/// required for `edit_survey`.
+///
+/// The changes are sent to everyone editing the survey through [`collaborate`], marked
+/// with `session` when they come from one of them.
+///
+/// [`collaborate`]: crate::survey::collaborate
+#[patch("/survey/<survey_id>/questions?<session>", data = "<operations>")]
+pub async fn edit_questions(
+    survey_id: i32,
+    session: Option<Uuid>,
+    claims: Claims,
+    db: Storage,
+    collab: &State<Collaboration>,
+    operations: Json<Vec<QuestionOperation>>,
+    race_check: Option<RaceCheck>,
+) -> Result<Json<SurveyQuestions>, ApiErrorResponse<SurveyError>> {
+    let user_id = claims.user_id();
+    let operations = operations.into_inner();
+    let applied = operations.clone();
+    let updated = db
+        .run(move |conn| apply_question_operations(conn, survey_id, user_id, applied, race_check))
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::Unknown
+        })??;
+    collab.publish(
+        survey_id,
+        CollabEvent::questions(session, operations, &updated),
+    );
+
+    Ok(Json(updated.questions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+
+    use crate::questions::{QText, Question};
+    use crate::test_helpers::*;
+
+    fn text_question(prompt: &str) -> SurveyQuestion {
+        SurveyQuestion {
+            uuid: Uuid::new_v4(),
+            required: false,
+            question: Question::Text(QText {
+                prompt: prompt.to_string(),
+                description: String::new(),
+                multiline: false,
+            }),
+        }
+    }
+
+    fn prompts(questions: &SurveyQuestions) -> Vec<String> {
+        questions
+            .iter()
+            .map(|q| match &q.question {
+                Question::Text(q) => q.prompt.clone(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_operations() {
+        let (a, b, c) = (text_question("a"), text_question("b"), text_question("c"));
+        let mut questions = SurveyQuestions(vec![a.clone(), b.clone()]);
+
+        QuestionOperation::Add {
+            question: c.clone(),
+            index: Some(0),
+        }
+        .apply(&mut questions)
+        .unwrap();
+        assert_eq!(prompts(&questions), ["c", "a", "b"]);
+
+        QuestionOperation::Move {
+            uuid: c.uuid,
+            index: 2,
+        }
+        .apply(&mut questions)
+        .unwrap();
+        assert_eq!(prompts(&questions), ["a", "b", "c"]);
+
+        QuestionOperation::Update {
+            question: SurveyQuestion {
+                uuid: b.uuid,
+                ..text_question("d")
+            },
+        }
+        .apply(&mut questions)
+        .unwrap();
+        assert_eq!(prompts(&questions), ["a", "d", "c"]);
+
+        QuestionOperation::Delete { uuid: a.uuid }
+            .apply(&mut questions)
+            .unwrap();
+        assert_eq!(prompts(&questions), ["d", "c"]);
+
+        assert!(matches!(
+            QuestionOperation::Delete { uuid: a.uuid }.apply(&mut questions),
+            Err(ValidationError::NotFound { uuid, .. }) if uuid == a.uuid
+        ));
+        assert!(matches!(
+            QuestionOperation::Move {
+                uuid: c.uuid,
+                index: 2
+            }
+            .apply(&mut questions),
+            Err(ValidationError::NotInRange {
+                value: 2,
+                max: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            QuestionOperation::Add {
+                question: text_question("e"),
+                index: Some(3)
+            }
+            .apply(&mut questions),
+            Err(ValidationError::NotInRange {
+                value: 3,
+                max: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_edit_questions() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let edit = |token: &str, operations: Vec<QuestionOperation>| {
+                client
+                    .patch(uri!("/api", edit_questions(survey_id, _)).to_string())
+                    .header(ContentType::JSON)
+                    .header(Header::new("Authorization", token.to_owned()))
+                    .body(serde_json::to_vec(&operations).unwrap())
+                    .dispatch()
+            };
+
+            let (a, b) = (text_question("a"), text_question("b"));
+            let response = edit(
+                &token,
+                vec![
+                    QuestionOperation::Add {
+                        question: a.clone(),
+                        index: None,
+                    },
+                    QuestionOperation::Add {
+                        question: b.clone(),
+                        index: None,
+                    },
+                ],
+            );
+            assert_eq!(response.status(), Status::Ok);
+            let questions = response.into_json::<SurveyQuestions>().unwrap();
+            assert_eq!(prompts(&questions), ["a", "b"]);
+
+            // two editors that started from the same questions don't overwrite each other
+            let response = edit(
+                &token,
+                vec![QuestionOperation::Update {
+                    question: SurveyQuestion {
+                        uuid: a.uuid,
+                        ..text_question("a2")
+                    },
+                }],
+            );
+            assert_eq!(response.status(), Status::Ok);
+            let response = edit(
+                &token,
+                vec![QuestionOperation::Update {
+                    question: SurveyQuestion {
+                        uuid: b.uuid,
+                        ..text_question("b2")
+                    },
+                }],
+            );
+            assert_eq!(response.status(), Status::Ok);
+            let questions = response.into_json::<SurveyQuestions>().unwrap();
+            assert_eq!(prompts(&questions), ["a2", "b2"]);
+
+            // the result is validated as a whole, and nothing is saved when it fails
+            let response = edit(
+                &token,
+                vec![
+                    QuestionOperation::Delete { uuid: a.uuid },
+                    QuestionOperation::Add {
+                        question: text_question(""),
+                        index: None,
+                    },
+                ],
+            );
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            let response = edit(&token, vec![QuestionOperation::Delete { uuid: a.uuid }]);
+            let questions = response.into_json::<SurveyQuestions>().unwrap();
+            assert_eq!(prompts(&questions), ["b2"]);
+
+            let other_token = create_test_user(&client);
+            let response = edit(
+                &other_token,
+                vec![QuestionOperation::Delete { uuid: b.uuid }],
+            );
+            assert_eq!(response.status(), Status::Forbidden);
+
+            publish_survey(&client, &token, survey_id);
+            let response = edit(&token, vec![QuestionOperation::Delete { uuid: b.uuid }]);
+            assert_eq!(response.status(), Status::Forbidden);
+        });
+    }
+}
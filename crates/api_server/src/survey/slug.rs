@@ -0,0 +1,133 @@
+use diesel::prelude::*;
+use password_hash::rand_core::{OsRng, RngCore};
+use rocket::http::uri::fmt::{Formatter, FromUriParam, Path, UriDisplay};
+use rocket::request::FromParam;
+
+use crate::db::{schema, Storage};
+
+/// Left out `l`, `o`, `0` and `1`, which are easy to mix up when a link is read aloud.
+const SLUG_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+const GENERATED_SLUG_LENGTH: usize = 16;
+pub const MIN_SLUG_LENGTH: usize = 4;
+pub const MAX_SLUG_LENGTH: usize = 64;
+
+/// How a public route refers to a survey, either by its id or by its slug.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SurveyRef {
+    Id(i32),
+    Slug(String),
+}
+
+impl SurveyRef {
+    /// Looks up the id of the survey. Ids are only accepted when `allow_ids` is set, so
+    /// that they can't be used to enumerate surveys.
+    pub(crate) async fn resolve(&self, db: &Storage, allow_ids: bool) -> Option<i32> {
+        match self {
+            SurveyRef::Id(id) if allow_ids => Some(*id),
+            SurveyRef::Id(_) => None,
+            SurveyRef::Slug(slug) => {
+                let slug = slug.clone();
+                db.run(move |conn| {
+                    schema::surveys::table
+                        .filter(schema::surveys::slug.eq(slug))
+                        .select(schema::surveys::id)
+                        .first::<i32>(conn)
+                        .optional()
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    error!("{e:?}");
+                    None
+                })
+            }
+        }
+    }
+
+    pub fn is_id(&self) -> bool {
+        matches!(self, SurveyRef::Id(_))
+    }
+}
+
+impl<'a> FromParam<'a> for SurveyRef {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        if let Ok(id) = param.parse::<i32>() {
+            return Ok(SurveyRef::Id(id));
+        }
+        if is_valid_slug(param) {
+            Ok(SurveyRef::Slug(param.to_string()))
+        } else {
+            Err(param)
+        }
+    }
+}
+
+impl UriDisplay<Path> for SurveyRef {
+    fn fmt(&self, f: &mut Formatter<'_, Path>) -> std::fmt::Result {
+        match self {
+            SurveyRef::Id(id) => f.write_value(id),
+            SurveyRef::Slug(slug) => f.write_value(slug),
+        }
+    }
+}
+
+impl FromUriParam<Path, i32> for SurveyRef {
+    type Target = i32;
+
+    fn from_uri_param(id: i32) -> i32 {
+        id
+    }
+}
+
+impl<'a> FromUriParam<Path, &'a str> for SurveyRef {
+    type Target = &'a str;
+
+    fn from_uri_param(slug: &'a str) -> &'a str {
+        slug
+    }
+}
+
+/// Slugs are made of lowercase letters, digits and dashes, and can't be all digits, so that
+/// they can't be mistaken for an id.
+pub fn is_valid_slug(slug: &str) -> bool {
+    (MIN_SLUG_LENGTH..=MAX_SLUG_LENGTH).contains(&slug.len())
+        && slug
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && !slug.bytes().all(|b| b.is_ascii_digit())
+}
+
+pub fn generate_slug() -> String {
+    loop {
+        let mut bytes = [0u8; GENERATED_SLUG_LENGTH];
+        OsRng.fill_bytes(&mut bytes);
+        let slug = bytes
+            .iter()
+            .map(|b| SLUG_ALPHABET[*b as usize % SLUG_ALPHABET.len()] as char)
+            .collect::<String>();
+        if is_valid_slug(&slug) {
+            return slug;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slug_params() {
+        assert_eq!(SurveyRef::from_param("42"), Ok(SurveyRef::Id(42)));
+        assert_eq!(
+            SurveyRef::from_param("team-offsite"),
+            Ok(SurveyRef::Slug("team-offsite".to_string()))
+        );
+        assert!(SurveyRef::from_param("Team Offsite").is_err());
+        assert!(SurveyRef::from_param("-offsite").is_err());
+        assert!(SurveyRef::from_param("abc").is_err());
+        assert!(is_valid_slug(&generate_slug()));
+    }
+}
@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use diesel::dsl::{now, IntervalDsl};
+use diesel::prelude::*;
+use rocket::fairing::AdHoc;
+use rocket::serde::json::Json;
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::HistoryAction;
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::survey::{history, SurveyConfig, SurveyError};
+
+/// How often to look for surveys that have been in the trash for too long.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[post("/survey/<survey_id>/restore")]
+pub async fn restore_survey(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<()>, ApiErrorResponse<SurveyError>> {
+    check_trashed_survey(&db, survey_id, &claims).await?;
+
+    let user_id = claims.user_id();
+    db.run(move |conn| {
+        conn.build_transaction()
+            .read_write()
+            .run::<_, diesel::result::Error, _>(|conn| {
+                diesel::update(schema::surveys::table)
+                    .filter(schema::surveys::id.eq(survey_id))
+                    .set(schema::surveys::deleted_at.eq(None::<chrono::NaiveDateTime>))
+                    .execute(conn)?;
+                history::record(
+                    conn,
+                    survey_id,
+                    user_id,
+                    HistoryAction::Restore,
+                    Default::default(),
+                )
+            })
+    })
+    .await
+    .map_err(|e| {
+        error!("{e:?}");
+        SurveyError::Unknown
+    })?;
+
+    Ok(Json(()))
+}
+
+/// Permanently deletes a survey in the trash, along with all of its responses.
+#[delete("/survey/<survey_id>/purge")]
+pub async fn purge_survey(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<()>, ApiErrorResponse<SurveyError>> {
+    check_trashed_survey(&db, survey_id, &claims).await?;
+
+    db.run(move |conn| {
+        diesel::delete(schema::surveys::table)
+            .filter(schema::surveys::id.eq(survey_id))
+            .filter(schema::surveys::deleted_at.is_not_null())
+            .execute(conn)
+    })
+    .await
+    .map_err(|e| {
+        error!("{e:?}");
+        SurveyError::Unknown
+    })?;
+
+    Ok(Json(()))
+}
+
+async fn check_trashed_survey(
+    db: &Storage,
+    survey_id: i32,
+    claims: &Claims,
+) -> Result<(), SurveyError> {
+    let (owner_id, deleted_at) = db
+        .run(move |conn| {
+            schema::surveys::table
+                .find(survey_id)
+                .select((schema::surveys::owner_id, schema::surveys::deleted_at))
+                .first::<(i32, Option<chrono::NaiveDateTime>)>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyError::NotFound
+        })?;
+
+    if owner_id != claims.user_id() {
+        return Err(SurveyError::NotOwner);
+    }
+
+    if deleted_at.is_none() {
+        return Err(SurveyError::NotDeleted);
+    }
+
+    Ok(())
+}
+
+/// Permanently deletes all surveys that have been in the trash for longer than the
+/// retention period. Returns the number of surveys that were purged.
+pub(crate) fn purge_expired_surveys(
+    conn: &mut PgConnection,
+    retention_days: i32,
+) -> QueryResult<usize> {
+    diesel::delete(schema::surveys::table)
+        .filter(schema::surveys::deleted_at.lt((now - retention_days.days()).nullable()))
+        .execute(conn)
+}
+
+/// Periodically purges surveys that have been in the trash for longer than
+/// `trash_retention_days` of the [`SurveyConfig`], which has to be managed first.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Trash Purge", |rocket| async {
+        let Some(config) = rocket.state::<SurveyConfig>() else {
+            error!("survey configuration is not managed, attach it before the trash purge");
+            return Err(rocket);
+        };
+        let retention_days = config.trash_retention_days;
+        // anything less would purge everything in the trash on the next run
+        if retention_days < 1 {
+            error!("trash_retention_days has to be at least 1, got {retention_days}");
+            return Err(rocket);
+        }
+        Ok(rocket.attach(AdHoc::on_liftoff("Trash Purge Worker", move |rocket| {
+        Box::pin(async move {
+            let Some(pool) = Storage::pool(rocket).cloned() else {
+                error!("database pool is not available, surveys in the trash will not be purged");
+                return;
+            };
+            let mut shutdown = rocket.shutdown();
+
+            rocket::tokio::spawn(async move {
+                let mut interval = rocket::tokio::time::interval(PURGE_INTERVAL);
+                loop {
+                    rocket::tokio::select! {
+                        _ = interval.tick() => {},
+                        _ = &mut shutdown => break,
+                    }
+
+                    let Some(conn) = pool.get().await else {
+                        continue;
+                    };
+                    match conn
+                        .run(move |conn| purge_expired_surveys(conn, retention_days))
+                        .await
+                    {
+                        Ok(0) => {}
+                        Ok(purged) => info!("purged {purged} surveys from the trash"),
+                        Err(e) => error!("failed to purge surveys from the trash: {e:?}"),
+                    }
+                }
+            });
+        })
+        })))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use diesel::Connection;
+    use rocket::local::blocking::Client;
+
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+    use crate::user::ListSurveysQuery;
+
+    #[test]
+    fn test_delete_and_restore() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let response = client
+                .delete(uri!("/api", crate::survey::delete_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotFound);
+
+            let response = client
+                .get(uri!(
+                    "/api",
+                    crate::user::list_surveys(ListSurveysQuery {
+                        trashed: Some(true),
+                        ..Default::default()
+                    })
+                ))
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            let list = response
+                .into_json::<crate::db::models::SurveyList>()
+                .unwrap();
+            assert_eq!(list.surveys.len(), 1);
+            assert!(list.surveys[0].deleted_at.is_some());
+
+            let response = client
+                .post(uri!("/api", restore_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_purge_requires_trash() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let response = client
+                .delete(uri!("/api", purge_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Conflict);
+
+            let response = client
+                .delete(uri!("/api", crate::survey::delete_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            let response = client
+                .delete(uri!("/api", purge_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            let response = client
+                .post(uri!("/api", restore_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotFound);
+        });
+    }
+
+    #[test]
+    fn test_restore_not_owner() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            client
+                .delete(uri!("/api", crate::survey::delete_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+
+            let token = create_test_user(&client);
+            let response = client
+                .post(uri!("/api", restore_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Forbidden);
+        });
+    }
+
+    #[test]
+    fn test_purge_expired_surveys() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let kept_survey_id = make_survey(&client, &token);
+            client
+                .delete(uri!("/api", crate::survey::delete_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+
+            let mut conn =
+                PgConnection::establish(&format!("postgres://vscode:notsecure@db/{db_name}"))
+                    .expect("Failed to connect to database");
+            assert_eq!(purge_expired_surveys(&mut conn, 1).unwrap(), 0);
+            assert_eq!(purge_expired_surveys(&mut conn, 0).unwrap(), 1);
+
+            let remaining = schema::surveys::table
+                .select(schema::surveys::id)
+                .load::<i32>(&mut conn)
+                .unwrap();
+            assert_eq!(remaining, vec![kept_survey_id]);
+        });
+    }
+
+    #[test]
+    fn test_retention_days_at_least_one() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket.figment().clone().merge(("trash_retention_days", 0));
+            let Err(error) = Client::tracked(rocket.configure(config)) else {
+                panic!("a retention of 0 days would empty the trash right away");
+            };
+            assert!(matches!(
+                error.kind(),
+                rocket::error::ErrorKind::FailedFairings(_)
+            ));
+        });
+    }
+}
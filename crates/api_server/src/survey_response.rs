@@ -1,38 +1,67 @@
 use diesel::prelude::*;
-use rocket::{http::Status, serde::json::Json};
+use rocket::{http::Status, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 
 use crate::{
-    api::{ApiErrorResponse, ApiOkCacheableResource},
+    api::{ApiErrorResponse, ApiOkCacheableResource, Services},
     cache::{CacheCheck, Cacheable, RaceCheck},
     db::{
         models::{
-            NewSurveyResponse, PatchSurveyResponse, Survey, SurveyResponse,
-            SurveyResponseUpdateCheck, SurveyResponses,
+            FieldChange, HistoryAction, NewSurveyResponse, PatchSurveyResponse, Survey,
+            SurveyChanges, SurveyResponse, SurveyResponses, SurveyStatus, WebhookEvent,
         },
         Storage,
     },
     jwt::Claims,
+    live::{LiveEvent, LiveEventKind, LiveEvents},
+    notifications,
+    rate_limit::RateLimit,
+    survey::{access, invitations, OwnerCheckError, SurveyAccess, SurveyConfig, SurveyRef},
     validate::{Validate, ValidationError},
+    webhook,
+};
+
+pub(crate) mod challenges;
+pub(crate) mod duplicates;
+pub(crate) mod history;
+pub(crate) mod quarantine;
+
+pub use challenges::{Challenges, Submission};
+pub use duplicates::Respondent;
+pub use history::get_response_history;
+pub use quarantine::{
+    discard_quarantined_response, list_quarantined_responses, release_quarantined_response,
 };
 
 #[typeshare]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseAccepted {
     #[typeshare(serialized_as = "String")]
-    responder_uuid: Uuid,
+    pub responder_uuid: Uuid,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Error)]
 pub enum SurveyResponseError {
     #[error("Data race")]
     RaceError,
+    #[error("Edits require If-Match or If-Unmodified-Since")]
+    PreconditionRequired,
     #[error("Survey not found")]
     SurveyNotFound,
     #[error("Survey not published")]
     SurveyNotPublished,
+    #[error("Survey closed")]
+    SurveyClosed,
+    #[error("Survey requires an access token")]
+    PasswordRequired,
+    #[error("Survey requires an invitation")]
+    InvitationRequired,
+    #[error("Invitation is invalid or used up")]
+    InvalidInvitation,
+    #[error("Already responded to this survey")]
+    DuplicateResponse,
     #[error("Survey responder not found")]
     ResponderNotFound,
     #[error("Not survey owner")]
@@ -47,8 +76,14 @@ impl From<SurveyResponseError> for ApiErrorResponse<SurveyResponseError> {
     fn from(value: SurveyResponseError) -> Self {
         let status = match &value {
             SurveyResponseError::RaceError => Status::PreconditionFailed,
+            SurveyResponseError::PreconditionRequired => Status::PreconditionRequired,
             SurveyResponseError::SurveyNotFound => Status::NotFound,
             SurveyResponseError::SurveyNotPublished => Status::Forbidden,
+            SurveyResponseError::SurveyClosed => Status::Forbidden,
+            SurveyResponseError::PasswordRequired => Status::Unauthorized,
+            SurveyResponseError::InvitationRequired => Status::Forbidden,
+            SurveyResponseError::InvalidInvitation => Status::Forbidden,
+            SurveyResponseError::DuplicateResponse => Status::Conflict,
             SurveyResponseError::ResponderNotFound => Status::NotFound,
             SurveyResponseError::NotSurveyOwner => Status::Forbidden,
             SurveyResponseError::ValidationError(_) => Status::UnprocessableEntity,
@@ -61,12 +96,34 @@ impl From<SurveyResponseError> for ApiErrorResponse<SurveyResponseError> {
     }
 }
 
+impl From<OwnerCheckError> for ApiErrorResponse<SurveyResponseError> {
+    fn from(value: OwnerCheckError) -> Self {
+        match value {
+            OwnerCheckError::NotFound => SurveyResponseError::SurveyNotFound,
+            OwnerCheckError::NotOwner => SurveyResponseError::NotSurveyOwner,
+            OwnerCheckError::Unknown => SurveyResponseError::Unknown,
+        }
+        .into()
+    }
+}
+
 impl From<Vec<ValidationError>> for ApiErrorResponse<SurveyResponseError> {
     fn from(value: Vec<ValidationError>) -> Self {
         SurveyResponseError::ValidationError(value).into()
     }
 }
 
+async fn resolve_survey_ref(
+    db: &Storage,
+    config: &SurveyConfig,
+    survey_ref: &SurveyRef,
+) -> Result<i32, SurveyResponseError> {
+    survey_ref
+        .resolve(db, config.public_survey_ids)
+        .await
+        .ok_or(SurveyResponseError::SurveyNotFound)
+}
+
 async fn get_survey_from_db(db: &Storage, survey_id: i32) -> Result<Survey, SurveyResponseError> {
     let survey = crate::survey::get_survey_from_db(db, survey_id)
         .await
@@ -79,122 +136,310 @@ async fn get_survey_from_db(db: &Storage, survey_id: i32) -> Result<Survey, Surv
     Ok(survey)
 }
 
-#[post("/survey/<survey_id>/respond", data = "<survey_response>")]
-pub async fn create_survey_response(
-    db: Storage,
+/// Like [`get_survey_from_db`], but also makes sure the survey is still taking responses.
+async fn get_open_survey_from_db(
+    db: &Storage,
     survey_id: i32,
+) -> Result<Survey, SurveyResponseError> {
+    let survey = get_survey_from_db(db, survey_id).await?;
+
+    if !survey.status.accepts_responses() {
+        return Err(SurveyResponseError::SurveyClosed);
+    }
+
+    Ok(survey)
+}
+
+/// Invite-only surveys require an `invitation` token, which is used up by the response.
+/// Other surveys accept one too, to tell who responded. Responses that fail one of the
+/// [`Challenges`] are accepted all the same, but quarantined until the owner releases them.
+#[post(
+    "/survey/<survey_ref>/respond?<invitation>",
+    data = "<survey_response>"
+)]
+pub async fn create_survey_response(
+    _rate_limit: RateLimit<'_>,
+    services: Services<'_>,
+    survey_ref: SurveyRef,
+    access: Option<SurveyAccess>,
+    respondent: Respondent<'_>,
+    invitation: Option<String>,
     survey_response: Json<SurveyResponses>,
 ) -> Result<Json<ResponseAccepted>, ApiErrorResponse<SurveyResponseError>> {
-    let survey = get_survey_from_db(&db, survey_id).await?;
+    let Services {
+        db,
+        config,
+        webhooks,
+        live,
+        outbox,
+        challenges,
+        ..
+    } = services;
+    let survey_id = resolve_survey_ref(&db, config, &survey_ref).await?;
+    let survey = get_open_survey_from_db(&db, survey_id).await?;
+    if !access::has_access(&survey, access.as_ref()) {
+        return Err(SurveyResponseError::PasswordRequired.into());
+    }
 
     let survey_responses = survey_response.into_inner();
     (&survey.questions, &survey_responses).validate()?;
 
-    let uuid = db
+    let respondent_cookie = survey
+        .duplicate_check
+        .uses_cookie()
+        .then(|| respondent.cookie());
+    let fingerprint = survey
+        .duplicate_check
+        .uses_fingerprint()
+        .then(|| respondent.fingerprint(survey_id))
+        .flatten();
+    let reject_duplicates = survey.reject_duplicates;
+    let quarantine_reason = challenges.check(survey_id, respondent.submission());
+    let opened_token = challenges.opened_token(survey_id, respondent.submission());
+
+    let (uuid, reached_limit, quarantined) = db
         .run(move |conn| {
-            let uuid = Uuid::new_v4();
-            let new_survey_response = NewSurveyResponse {
-                survey_id,
-                responder_uuid: uuid,
-                content: survey_responses,
-            };
-            diesel::insert_into(crate::db::schema::responses::table)
-                .values(&new_survey_response)
-                .execute(conn)
-                .map(|_| uuid)
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    // lock the survey, so that responses coming in at the same time can't
+                    // go over its limit
+                    let (status, response_limit, invite_only) = crate::db::schema::surveys::table
+                        .find(survey_id)
+                        .select((
+                            crate::db::schema::surveys::status,
+                            crate::db::schema::surveys::response_limit,
+                            crate::db::schema::surveys::invite_only,
+                        ))
+                        .for_update()
+                        .first::<(SurveyStatus, Option<i32>, bool)>(conn)?;
+                    if !status.accepts_responses() {
+                        return Ok(Err(SurveyResponseError::SurveyClosed));
+                    }
+                    let response_count = match response_limit {
+                        Some(_) => crate::db::schema::responses::table
+                            .filter(crate::db::schema::responses::survey_id.eq(survey_id))
+                            .filter(crate::db::schema::responses::quarantine_reason.is_null())
+                            .count()
+                            .get_result::<i64>(conn)?,
+                        None => 0,
+                    };
+                    if response_limit.is_some_and(|limit| response_count >= i64::from(limit)) {
+                        return Ok(Err(SurveyResponseError::SurveyClosed));
+                    }
+                    let invitation_id = match invitation {
+                        Some(token) => {
+                            match invitations::consume_invitation(conn, survey_id, &token)? {
+                                Some(invitation_id) => Some(invitation_id),
+                                None => return Ok(Err(SurveyResponseError::InvalidInvitation)),
+                            }
+                        }
+                        None if invite_only => {
+                            return Ok(Err(SurveyResponseError::InvitationRequired))
+                        }
+                        None => None,
+                    };
+                    let duplicate = duplicates::is_duplicate(
+                        conn,
+                        survey_id,
+                        respondent_cookie.as_deref(),
+                        fingerprint.as_deref(),
+                    )?;
+                    if duplicate && reject_duplicates {
+                        return Ok(Err(SurveyResponseError::DuplicateResponse));
+                    }
+                    let mut quarantine_reason = quarantine_reason;
+                    if let Some(token) = &opened_token {
+                        if !challenges::spend_opened_token(conn, survey_id, token)? {
+                            let reason = "opened token was already used";
+                            quarantine_reason = Some(match quarantine_reason {
+                                Some(other) => format!("{other}; {reason}"),
+                                None => reason.to_string(),
+                            });
+                        }
+                    }
+                    let quarantined = quarantine_reason.is_some();
+
+                    let new_survey_response = NewSurveyResponse {
+                        survey_id,
+                        responder_uuid: Uuid::new_v4(),
+                        content: survey_responses,
+                        invitation_id,
+                        duplicate,
+                        respondent_cookie,
+                        fingerprint,
+                        quarantine_reason,
+                        accepted_at: (!quarantined).then(chrono::Utc::now),
+                    };
+                    let created = diesel::insert_into(crate::db::schema::responses::table)
+                        .values(&new_survey_response)
+                        .get_result::<SurveyResponse>(conn)?;
+                    // quarantined responses stay out of sight until they are released
+                    if quarantined {
+                        return Ok(Ok((created.responder_uuid, false, true)));
+                    }
+                    webhook::enqueue_event(
+                        conn,
+                        survey_id,
+                        WebhookEvent::ResponseCreated,
+                        &created,
+                    )?;
+
+                    let reached_limit =
+                        response_limit.is_some_and(|limit| response_count + 1 >= i64::from(limit));
+                    if reached_limit {
+                        diesel::update(crate::db::schema::surveys::table.find(survey_id))
+                            .set(crate::db::schema::surveys::status.eq(SurveyStatus::Closed))
+                            .execute(conn)?;
+                    }
+                    Ok(Ok((created.responder_uuid, reached_limit, false)))
+                })
         })
         .await
         .map_err(|e| {
             error!("{e:?}");
             SurveyResponseError::Unknown
-        })?;
+        })??;
+    if quarantined {
+        return Ok(Json(ResponseAccepted {
+            responder_uuid: uuid,
+        }));
+    }
+    webhooks.wake();
+    live.publish(
+        &db,
+        LiveEvent::new(LiveEventKind::ResponseCreated, survey_id, Some(uuid)),
+    )
+    .await;
+    if reached_limit {
+        notifications::send_limit_alert(&db, outbox, survey_id).await;
+    }
 
     Ok(Json(ResponseAccepted {
         responder_uuid: uuid,
     }))
 }
 
-#[patch("/survey/<survey_id>/respond?<responder>", data = "<survey_response>")]
+#[patch("/survey/<survey_ref>/respond?<responder>", data = "<survey_response>")]
 pub async fn edit_survey_response(
-    db: Storage,
-    survey_id: i32,
+    services: Services<'_>,
+    survey_ref: SurveyRef,
+    access: Option<SurveyAccess>,
     survey_response: Json<SurveyResponses>,
     responder: Uuid,
     race_check: Option<RaceCheck>,
 ) -> Result<Json<()>, ApiErrorResponse<SurveyResponseError>> {
-    if let Some(race_check) = race_check {
-        let old_response = db
-            .run(move |conn| {
-                crate::db::schema::responses::table
-                    .select((crate::db::schema::responses::updated_at,))
-                    .find(responder)
-                    .first::<SurveyResponseUpdateCheck>(conn)
-            })
-            .await
-            .map_err(|e| {
-                error!("{e:?}");
-                SurveyResponseError::Unknown
-            })?;
-
-        if !old_response.has_no_mid_air_collision(race_check) {
-            return Err(SurveyResponseError::RaceError.into());
-        }
+    let Services {
+        db,
+        config,
+        webhooks,
+        live,
+        ..
+    } = services;
+    if race_check.is_none() && config.require_preconditions {
+        return Err(SurveyResponseError::PreconditionRequired.into());
     }
+    let survey_id = resolve_survey_ref(&db, config, &survey_ref).await?;
 
-    let survey = get_survey_from_db(&db, survey_id).await?;
+    let survey = get_open_survey_from_db(&db, survey_id).await?;
+    if !access::has_access(&survey, access.as_ref()) {
+        return Err(SurveyResponseError::PasswordRequired.into());
+    }
 
     let survey_responses = survey_response.into_inner();
     (&survey.questions, &survey_responses).validate()?;
 
-    db.run(move |conn| {
-        conn.build_transaction()
-            .read_write()
-            .run::<_, diesel::result::Error, _>(|conn| {
-                let patch_survey_response = PatchSurveyResponse {
-                    content: survey_responses,
-                };
-                crate::db::schema::responses::table
-                    .for_update()
-                    .filter(crate::db::schema::responses::survey_id.eq(survey_id))
-                    .filter(crate::db::schema::responses::responder_uuid.eq(responder))
-                    .limit(1)
-                    .load::<SurveyResponse>(conn)?;
-                diesel::update(crate::db::schema::responses::table)
-                    .filter(crate::db::schema::responses::survey_id.eq(survey_id))
-                    .filter(crate::db::schema::responses::responder_uuid.eq(responder))
-                    .set(&patch_survey_response)
-                    .execute(conn)?;
-                Ok(())
-            })
-    })
-    .await
-    .map_err(|e| {
-        error!("{e:?}");
-        SurveyResponseError::Unknown
-    })?;
+    let quarantined = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    let patch_survey_response = PatchSurveyResponse {
+                        content: survey_responses,
+                    };
+                    // checked against the locked row, so no other edit can come in between
+                    let Some(current) = crate::db::schema::responses::table
+                        .for_update()
+                        .filter(crate::db::schema::responses::survey_id.eq(survey_id))
+                        .filter(crate::db::schema::responses::responder_uuid.eq(responder))
+                        .first::<SurveyResponse>(conn)
+                        .optional()?
+                    else {
+                        return Ok(Err(SurveyResponseError::ResponderNotFound));
+                    };
+                    if let Some(race_check) = race_check {
+                        if !current.has_no_mid_air_collision(race_check) {
+                            return Ok(Err(SurveyResponseError::RaceError));
+                        }
+                    }
+                    let updated = diesel::update(crate::db::schema::responses::table)
+                        .filter(crate::db::schema::responses::survey_id.eq(survey_id))
+                        .filter(crate::db::schema::responses::responder_uuid.eq(responder))
+                        .set(&patch_survey_response)
+                        .get_result::<SurveyResponse>(conn)?;
+                    history::record_edit(conn, &current, &updated)?;
+                    let quarantined = updated.quarantine_reason.is_some();
+                    if !quarantined {
+                        webhook::enqueue_event(
+                            conn,
+                            survey_id,
+                            WebhookEvent::ResponseUpdated,
+                            &updated,
+                        )?;
+                    }
+                    Ok(Ok(quarantined))
+                })
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyResponseError::Unknown
+        })??;
+    // quarantined responses stay hidden until they are released
+    if quarantined {
+        return Ok(Json(()));
+    }
+    webhooks.wake();
+    live.publish(
+        &db,
+        LiveEvent::new(LiveEventKind::ResponseUpdated, survey_id, Some(responder)),
+    )
+    .await;
 
     Ok(Json(()))
 }
 
-#[get("/survey/<survey_id>/respond?<responder>")]
+#[get("/survey/<survey_ref>/respond?<responder>")]
 pub async fn get_survey_response(
     db: Storage,
-    survey_id: i32,
+    config: &State<SurveyConfig>,
+    survey_ref: SurveyRef,
+    access: Option<SurveyAccess>,
     responder: Uuid,
     cache_check: Option<CacheCheck>,
 ) -> Result<ApiOkCacheableResource<SurveyResponse>, ApiErrorResponse<SurveyResponseError>> {
-    let survey_response = db
+    let survey_id = resolve_survey_ref(&db, config, &survey_ref).await?;
+    let (survey_response, survey) = db
         .run(move |conn| {
             crate::db::schema::responses::table
+                .inner_join(crate::db::schema::surveys::table)
                 .filter(crate::db::schema::responses::survey_id.eq(survey_id))
                 .filter(crate::db::schema::responses::responder_uuid.eq(responder))
-                .first::<SurveyResponse>(conn)
+                .filter(crate::db::schema::surveys::deleted_at.is_null())
+                .select((
+                    crate::db::schema::responses::all_columns,
+                    crate::db::schema::surveys::all_columns,
+                ))
+                .first::<(SurveyResponse, Survey)>(conn)
         })
         .await
         .map_err(|e| {
             error!("{e:?}");
             SurveyResponseError::Unknown
         })?;
+    if !access::has_access(&survey, access.as_ref()) {
+        return Err(SurveyResponseError::PasswordRequired.into());
+    }
 
     if let Some(cache_check) = cache_check {
         if survey_response.is_cache_fresh(cache_check) {
@@ -208,6 +453,7 @@ pub async fn get_survey_response(
 #[delete("/survey/<survey_id>/respond")]
 pub async fn clear_survey_responses(
     db: Storage,
+    live: &State<LiveEvents>,
     survey_id: i32,
     claims: Claims,
 ) -> Result<Json<()>, ApiErrorResponse<SurveyResponseError>> {
@@ -224,10 +470,32 @@ pub async fn clear_survey_responses(
         return Err(SurveyResponseError::SurveyNotPublished.into());
     }
 
+    let user_id = claims.user_id();
     db.run(move |conn| -> anyhow::Result<()> {
-        diesel::delete(crate::db::schema::responses::table)
-            .filter(crate::db::schema::responses::survey_id.eq(survey_id))
-            .execute(conn)?;
+        conn.build_transaction()
+            .read_write()
+            .run::<_, diesel::result::Error, _>(|conn| {
+                let cleared = diesel::delete(crate::db::schema::responses::table)
+                    .filter(crate::db::schema::responses::survey_id.eq(survey_id))
+                    .execute(conn)?;
+                let changes = SurveyChanges(
+                    [(
+                        "responses".to_string(),
+                        FieldChange {
+                            before: cleared.into(),
+                            after: 0.into(),
+                        },
+                    )]
+                    .into(),
+                );
+                crate::survey::history::record(
+                    conn,
+                    survey_id,
+                    user_id,
+                    HistoryAction::ClearResponses,
+                    changes,
+                )
+            })?;
         Ok(())
     })
     .await
@@ -236,13 +504,20 @@ pub async fn clear_survey_responses(
         SurveyResponseError::Unknown
     })?;
 
+    live.publish(
+        &db,
+        LiveEvent::new(LiveEventKind::ResponsesCleared, survey_id, None),
+    )
+    .await;
+
     Ok(Json(()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::models::SurveyResponses;
+    use crate::db::models::{SurveyResponses, SurveyStatus};
+    use crate::survey::export::ExportedSubmission;
     use crate::test_helpers::*;
     use rocket::local::blocking::Client;
     use std::collections::HashMap;
@@ -259,7 +534,7 @@ mod tests {
             let map: HashMap<Uuid, crate::questions::Response> = HashMap::new();
 
             let response = client
-                .post(uri!("/api", create_survey_response(survey_id)).to_string())
+                .post(uri!("/api", create_survey_response(survey_id, _)).to_string())
                 .header(rocket::http::ContentType::JSON)
                 .body(serde_json::to_vec(&SurveyResponses(map)).unwrap())
                 .dispatch();
@@ -268,7 +543,13 @@ mod tests {
 
             // assert there is a response
             let response = client
-                .get(uri!("/api", crate::survey::export::export_responses(survey_id)).to_string())
+                .get(
+                    uri!(
+                        "/api",
+                        crate::survey::export::export_responses(survey_id, _)
+                    )
+                    .to_string(),
+                )
                 .header(rocket::http::ContentType::JSON)
                 .header(rocket::http::Header::new(
                     "Authorization",
@@ -293,7 +574,13 @@ mod tests {
 
             // assert there are no responses
             let response = client
-                .get(uri!("/api", crate::survey::export::export_responses(survey_id)).to_string())
+                .get(
+                    uri!(
+                        "/api",
+                        crate::survey::export::export_responses(survey_id, _)
+                    )
+                    .to_string(),
+                )
                 .header(rocket::http::ContentType::JSON)
                 .header(rocket::http::Header::new("Authorization", owner_token))
                 .dispatch();
@@ -303,4 +590,143 @@ mod tests {
             assert_eq!(csv, "responder,created_at,updated_at\n");
         });
     }
+    #[test]
+    fn test_closed_survey_rejects_responses() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let owner_token = create_test_user(&client);
+            let survey_id = make_survey(&client, &owner_token);
+            publish_survey(&client, &owner_token, survey_id);
+
+            let response = client
+                .post(uri!("/api", create_survey_response(survey_id, _)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            let responder = response
+                .into_json::<ResponseAccepted>()
+                .unwrap()
+                .responder_uuid;
+
+            set_survey_status(&client, &owner_token, survey_id, SurveyStatus::Closed);
+
+            let response = client
+                .post(uri!("/api", create_survey_response(survey_id, _)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Forbidden);
+
+            let response = client
+                .patch(uri!("/api", edit_survey_response(survey_id, responder)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Forbidden);
+
+            let response = client
+                .get(uri!("/api", get_survey_response(survey_id, responder)).to_string())
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+
+            let response = client
+                .get(
+                    uri!(
+                        "/api",
+                        crate::survey::export::export_responses(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .header(rocket::http::Header::new("Authorization", owner_token))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_survey_response_etags() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let owner_token = create_test_user(&client);
+            let survey_id = make_survey(&client, &owner_token);
+            let question = Uuid::nil();
+            let response = client
+                .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .header(rocket::http::Header::new(
+                    "Authorization",
+                    owner_token.clone(),
+                ))
+                .body(
+                    serde_json::to_vec(&crate::db::models::SurveyPatch {
+                        published: Some(true),
+                        questions: Some(crate::db::models::SurveyQuestions(vec![
+                            crate::questions::SurveyQuestion {
+                                uuid: question,
+                                question: crate::questions::Question::Text(
+                                    crate::questions::QText {
+                                        prompt: "test".to_owned(),
+                                        description: "".to_owned(),
+                                        multiline: false,
+                                    },
+                                ),
+                                required: false,
+                            },
+                        ])),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Ok);
+            let answer = |text: &str| {
+                serde_json::to_vec(&SurveyResponses(HashMap::from([(
+                    question,
+                    crate::questions::Response::Text(crate::questions::RText {
+                        text: text.to_owned(),
+                    }),
+                )])))
+                .unwrap()
+            };
+
+            let responder = client
+                .post(uri!("/api", create_survey_response(survey_id, _)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .body(answer("a"))
+                .dispatch()
+                .into_json::<ResponseAccepted>()
+                .unwrap()
+                .responder_uuid;
+
+            let response = client
+                .get(uri!("/api", get_survey_response(survey_id, responder)).to_string())
+                .dispatch();
+            let etag = response.headers().get_one("ETag").unwrap().to_owned();
+            let response = client
+                .get(uri!("/api", get_survey_response(survey_id, responder)).to_string())
+                .header(rocket::http::Header::new("If-None-Match", etag.clone()))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::NotModified);
+
+            let edit = |etag: &str, text: &str| {
+                client
+                    .patch(uri!("/api", edit_survey_response(survey_id, responder)).to_string())
+                    .header(rocket::http::ContentType::JSON)
+                    .header(rocket::http::Header::new("If-Match", etag.to_owned()))
+                    .body(answer(text))
+                    .dispatch()
+                    .status()
+            };
+            assert_eq!(edit(&etag, "b"), rocket::http::Status::Ok);
+            // even within the same second, the edit made the ETag stale
+            assert_eq!(edit(&etag, "c"), rocket::http::Status::PreconditionFailed);
+            assert_eq!(
+                edit("\"nope\"", "c"),
+                rocket::http::Status::PreconditionFailed
+            );
+        });
+    }
 }
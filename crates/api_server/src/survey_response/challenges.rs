@@ -0,0 +1,582 @@
+use chrono::TimeZone;
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use rocket::fairing::AdHoc;
+use rocket::http::Header;
+use rocket::response::{self, Responder};
+use rocket::Request;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config;
+use crate::db::schema;
+
+/// The header that [`crate::survey::get_survey`] sends the opened token in, and that
+/// respondents send it back in.
+pub const OPENED_HEADER: &str = "X-Survey-Opened";
+/// The value of the hidden honeypot field, which people leave empty.
+pub const HONEYPOT_HEADER: &str = "X-Survey-Honeypot";
+/// Sent along with the opened token when a proof of work is required, with the number of
+/// leading zero bits it needs.
+pub const PROOF_OF_WORK_HEADER: &str = "X-Survey-Proof-Of-Work";
+/// The nonce that proves the work, see [`ProofOfWork`].
+pub const PROOF_HEADER: &str = "X-Survey-Proof";
+/// The audience of opened tokens, so that they can't be taken for any other kind of token.
+const OPENED_AUDIENCE: &str = "survey-opened";
+
+/// Configured under `[default.challenges]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChallengeConfig {
+    pub enabled: bool,
+    /// Whether a filled in honeypot field makes a response suspicious.
+    pub honeypot: bool,
+    /// Responses sent sooner than this many seconds after the survey was opened are
+    /// suspicious. `0` turns the check off.
+    pub min_seconds: u64,
+    /// The number of leading zero bits a proof of work needs. `0` turns it off.
+    pub proof_of_work_bits: u32,
+    /// Seconds that an opened token lasts.
+    pub opened_ttl: u64,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            honeypot: true,
+            min_seconds: 3,
+            proof_of_work_bits: 0,
+            opened_ttl: 24 * 60 * 60,
+        }
+    }
+}
+
+/// The claims in an opened token, which tells when a respondent got the survey.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Opened {
+    aud: String,
+    /// Tells tokens apart, so that each only counts for one response.
+    jti: Uuid,
+    survey_id: i32,
+    /// When the survey was opened (as UTC timestamp)
+    opened_at: u64,
+    /// Expiration time (as UTC timestamp)
+    exp: u64,
+}
+
+/// Opened tokens get a key of their own, derived from the secret key, so that a login or
+/// an access token can't pass for one.
+fn opened_key(secret: &str) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(OPENED_AUDIENCE.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// What a submitted response comes with, for the challenges to look at.
+pub struct Submission<'r> {
+    honeypot: Option<&'r str>,
+    opened_token: Option<&'r str>,
+    /// `None` if the token is missing, invalid or expired.
+    opened: Option<Opened>,
+    proof: Option<&'r str>,
+}
+
+impl<'r> Submission<'r> {
+    /// Reads what the challenges need from the headers of the request.
+    pub(crate) fn new(req: &'r Request<'_>) -> Self {
+        let opened_token = req.headers().get_one(OPENED_HEADER);
+        let opened = opened_token.and_then(|token| {
+            let key = opened_key(&req.rocket().config().secret_key.to_string());
+            let key = jsonwebtoken::DecodingKey::from_secret(&key);
+            let mut validation = jsonwebtoken::Validation::default();
+            validation.set_audience(&[OPENED_AUDIENCE]);
+            jsonwebtoken::decode::<Opened>(token, &key, &validation)
+                .ok()
+                .map(|token_data| token_data.claims)
+        });
+        Submission {
+            honeypot: req.headers().get_one(HONEYPOT_HEADER),
+            opened_token,
+            opened,
+            proof: req.headers().get_one(PROOF_HEADER),
+        }
+    }
+
+    /// The claims of the opened token, if it is for `survey_id`.
+    fn opened(&self, survey_id: i32) -> Option<&Opened> {
+        self.opened
+            .as_ref()
+            .filter(|opened| opened.survey_id == survey_id)
+    }
+
+    /// When the survey was opened, if the opened token is for `survey_id`.
+    fn opened_at(&self, survey_id: i32) -> Option<u64> {
+        self.opened(survey_id).map(|opened| opened.opened_at)
+    }
+}
+
+/// A check that responses have to pass to be accepted right away. Responses that fail one
+/// are quarantined for the owner of the survey to review.
+pub trait Challenge: Send + Sync {
+    /// Returns why the submission looks like spam, if it does.
+    fn check(&self, survey_id: i32, submission: &Submission<'_>) -> Option<String>;
+
+    /// A header that tells respondents what the challenge needs, sent with the survey.
+    fn hint(&self) -> Option<Header<'static>> {
+        None
+    }
+}
+
+/// A hidden field that people don't see, so only bots fill it in.
+pub struct Honeypot;
+
+impl Challenge for Honeypot {
+    fn check(&self, _survey_id: i32, submission: &Submission<'_>) -> Option<String> {
+        submission
+            .honeypot
+            .filter(|value| !value.is_empty())
+            .map(|_| "honeypot was filled in".to_string())
+    }
+}
+
+/// People take some time to read and fill in a survey.
+pub struct MinTimeToComplete {
+    pub min_seconds: u64,
+}
+
+impl Challenge for MinTimeToComplete {
+    fn check(&self, survey_id: i32, submission: &Submission<'_>) -> Option<String> {
+        let Some(opened_at) = submission.opened_at(survey_id) else {
+            return Some("opened token is missing or invalid".to_string());
+        };
+        let elapsed = jsonwebtoken::get_current_timestamp().saturating_sub(opened_at);
+        (elapsed < self.min_seconds).then(|| format!("completed in {elapsed}s"))
+    }
+}
+
+/// An opened token that a response is sent with. Each one only counts for one response,
+/// otherwise a bot could wait out [`MinTimeToComplete`] or do the [`ProofOfWork`] once and
+/// then send as many responses as it likes.
+pub struct OpenedToken {
+    jti: Uuid,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Records that a response was sent with `token`. Returns `false` if one already was.
+/// This should run in the same transaction as the response being created, so that the
+/// token can be used again if the response is rejected.
+pub(crate) fn spend_opened_token(
+    conn: &mut PgConnection,
+    survey_id: i32,
+    token: &OpenedToken,
+) -> QueryResult<bool> {
+    // uses of expired tokens are cleaned up here, since an expired token fails the
+    // challenges anyway
+    diesel::delete(schema::opened_token_uses::table)
+        .filter(schema::opened_token_uses::expires_at.lt(chrono::Utc::now()))
+        .execute(conn)?;
+    let inserted = diesel::insert_into(schema::opened_token_uses::table)
+        .values((
+            schema::opened_token_uses::jti.eq(token.jti),
+            schema::opened_token_uses::survey_id.eq(survey_id),
+            schema::opened_token_uses::expires_at.eq(token.expires_at),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(inserted == 1)
+}
+
+/// The respondent has to find a nonce, so that the SHA-256 hash of `<opened token>:<nonce>`
+/// starts with `bits` zero bits. This is cheap for one response, but adds up for a bot
+/// sending many.
+pub struct ProofOfWork {
+    pub bits: u32,
+}
+
+impl Challenge for ProofOfWork {
+    fn check(&self, survey_id: i32, submission: &Submission<'_>) -> Option<String> {
+        let (Some(token), Some(_)) = (submission.opened_token, submission.opened_at(survey_id))
+        else {
+            return Some("opened token is missing or invalid".to_string());
+        };
+        let Some(nonce) = submission.proof else {
+            return Some("proof of work is missing".to_string());
+        };
+        let hash = Sha256::digest(format!("{token}:{nonce}").as_bytes());
+        (leading_zero_bits(&hash) < self.bits).then(|| "proof of work is invalid".to_string())
+    }
+
+    fn hint(&self) -> Option<Header<'static>> {
+        Some(Header::new(PROOF_OF_WORK_HEADER, self.bits.to_string()))
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// The challenges that are turned on. Managing one before [`stage`] replaces the ones
+/// from the config.
+pub struct Challenges {
+    challenges: Vec<Box<dyn Challenge>>,
+    opened_ttl: u64,
+}
+
+impl Challenges {
+    pub fn new(challenges: Vec<Box<dyn Challenge>>, opened_ttl: u64) -> Self {
+        Self {
+            challenges,
+            opened_ttl,
+        }
+    }
+
+    fn from_config(config: &ChallengeConfig) -> Self {
+        let mut challenges: Vec<Box<dyn Challenge>> = Vec::new();
+        if config.enabled {
+            if config.honeypot {
+                challenges.push(Box::new(Honeypot));
+            }
+            if config.min_seconds > 0 {
+                challenges.push(Box::new(MinTimeToComplete {
+                    min_seconds: config.min_seconds,
+                }));
+            }
+            if config.proof_of_work_bits > 0 {
+                challenges.push(Box::new(ProofOfWork {
+                    bits: config.proof_of_work_bits,
+                }));
+            }
+        }
+        Self::new(challenges, config.opened_ttl)
+    }
+
+    /// Why the submission looks like spam, or `None` if it passed every challenge.
+    pub fn check(&self, survey_id: i32, submission: &Submission<'_>) -> Option<String> {
+        let reasons = self
+            .challenges
+            .iter()
+            .filter_map(|challenge| challenge.check(survey_id, submission))
+            .collect::<Vec<_>>();
+        (!reasons.is_empty()).then(|| reasons.join("; "))
+    }
+
+    /// The opened token that the submission comes with, for [`spend_opened_token`]. `None`
+    /// if no challenge is turned on, or if the token isn't valid for `survey_id`.
+    pub fn opened_token(&self, survey_id: i32, submission: &Submission<'_>) -> Option<OpenedToken> {
+        if self.challenges.is_empty() {
+            return None;
+        }
+        let opened = submission.opened(survey_id)?;
+        Some(OpenedToken {
+            jti: opened.jti,
+            expires_at: chrono::Utc
+                .timestamp_opt(i64::try_from(opened.exp).ok()?, 0)
+                .single()?,
+        })
+    }
+
+    /// Adds a new opened token for `survey_id` to `inner`.
+    pub fn issue<R>(&self, secret: &str, survey_id: i32, inner: R) -> WithChallenge<R> {
+        let opened_at = jsonwebtoken::get_current_timestamp();
+        let claims = Opened {
+            aud: OPENED_AUDIENCE.to_string(),
+            jti: Uuid::new_v4(),
+            survey_id,
+            opened_at,
+            exp: opened_at + self.opened_ttl,
+        };
+        let key = jsonwebtoken::EncodingKey::from_secret(&opened_key(secret));
+        let opened_token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key)
+            .map_err(|e| error!("{e:?}"))
+            .ok();
+        WithChallenge {
+            inner,
+            opened_token,
+            hints: self
+                .challenges
+                .iter()
+                .filter_map(|challenge| challenge.hint())
+                .collect(),
+        }
+    }
+}
+
+/// A response with the headers that respondents need to pass the challenges.
+pub struct WithChallenge<R> {
+    inner: R,
+    opened_token: Option<String>,
+    hints: Vec<Header<'static>>,
+}
+
+impl<'r, R> Responder<'r, 'static> for WithChallenge<R>
+where
+    R: Responder<'r, 'static>,
+{
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.inner.respond_to(req)?;
+        if let Some(opened_token) = self.opened_token {
+            response.set_header(Header::new(OPENED_HEADER, opened_token));
+        }
+        for hint in self.hints {
+            response.set_header(hint);
+        }
+        Ok(response)
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Challenges", |rocket| async {
+        if rocket.state::<Challenges>().is_some() {
+            return Ok(rocket);
+        }
+        let Some(config) = config::section::<ChallengeConfig>(rocket.figment(), "challenges")
+        else {
+            return Err(rocket);
+        };
+        Ok(rocket.manage(Challenges::from_config(&config)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::{Build, Rocket};
+
+    use crate::db::models::SurveyResponses;
+    use crate::survey::export::ExportedSubmission;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn test_rocket_with_challenges(db_name: &String, proof_of_work_bits: u32) -> Rocket<Build> {
+        let rocket = test_rocket(db_name);
+        let config = rocket
+            .figment()
+            .clone()
+            .merge(("challenges.enabled", true))
+            .merge(("challenges.min_seconds", 3))
+            .merge(("challenges.proof_of_work_bits", proof_of_work_bits));
+        rocket.configure(config)
+    }
+
+    /// Gets the survey like a respondent, returning the opened token.
+    fn open_survey(client: &Client, survey_id: i32) -> String {
+        let response = client
+            .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response
+            .headers()
+            .get_one(OPENED_HEADER)
+            .unwrap()
+            .to_owned()
+    }
+
+    /// An opened token for a survey that was opened `seconds_ago`.
+    fn opened_token(client: &Client, survey_id: i32, seconds_ago: u64) -> String {
+        let now = jsonwebtoken::get_current_timestamp();
+        let claims = Opened {
+            aud: OPENED_AUDIENCE.to_string(),
+            jti: Uuid::new_v4(),
+            survey_id,
+            opened_at: now - seconds_ago,
+            exp: now + 60,
+        };
+        let key = opened_key(&client.rocket().config().secret_key.to_string());
+        let key = jsonwebtoken::EncodingKey::from_secret(&key);
+        jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+    }
+
+    fn respond(client: &Client, survey_id: i32, headers: &[(&'static str, &str)]) {
+        let mut request = client
+            .post(
+                uri!(
+                    "/api",
+                    crate::survey_response::create_survey_response(survey_id, _)
+                )
+                .to_string(),
+            )
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap());
+        for (name, value) in headers {
+            request = request.header(Header::new(*name, value.to_string()));
+        }
+        // suspicious responses are accepted all the same, so that bots can't tell
+        assert_eq!(request.dispatch().status(), Status::Ok);
+    }
+
+    fn quarantined_reasons(client: &Client, token: &str, survey_id: i32) -> Vec<String> {
+        let response = client
+            .get(
+                uri!(
+                    "/api",
+                    crate::survey_response::quarantine::list_quarantined_responses(survey_id)
+                )
+                .to_string(),
+            )
+            .header(Header::new("Authorization", token.to_owned()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response
+            .into_json::<Vec<crate::survey_response::quarantine::QuarantinedResponse>>()
+            .unwrap()
+            .into_iter()
+            .map(|response| response.reason)
+            .collect()
+    }
+
+    fn exported_count(client: &Client, token: &str, survey_id: i32) -> usize {
+        let response = client
+            .get(
+                uri!(
+                    "/api",
+                    crate::survey::export::export_responses(survey_id, _)
+                )
+                .to_string(),
+            )
+            .header(Header::new("Authorization", token.to_owned()))
+            .dispatch();
+        response.into_string().unwrap().lines().count() - 1
+    }
+
+    #[test]
+    fn test_honeypot_and_time_to_complete() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket_with_challenges(db_name, 0))
+                .expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            let other_survey = make_survey(&client, &token);
+            publish_survey(&client, &token, other_survey);
+
+            // too fast
+            let opened = open_survey(&client, survey_id);
+            respond(&client, survey_id, &[(OPENED_HEADER, &opened)]);
+            // no token at all, or one for another survey
+            respond(&client, survey_id, &[]);
+            let other_opened = opened_token(&client, other_survey, 60);
+            respond(&client, survey_id, &[(OPENED_HEADER, &other_opened)]);
+            // or one signed with the key of login tokens
+            let now = jsonwebtoken::get_current_timestamp();
+            let claims = Opened {
+                aud: OPENED_AUDIENCE.to_string(),
+                jti: Uuid::new_v4(),
+                survey_id,
+                opened_at: now - 60,
+                exp: now + 60,
+            };
+            let key = client.rocket().config().secret_key.to_string();
+            let key = jsonwebtoken::EncodingKey::from_secret(key.as_bytes());
+            let forged = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key);
+            respond(&client, survey_id, &[(OPENED_HEADER, &forged.unwrap())]);
+            // the honeypot was filled in
+            let opened = opened_token(&client, survey_id, 60);
+            respond(
+                &client,
+                survey_id,
+                &[(OPENED_HEADER, &opened), (HONEYPOT_HEADER, "https://spam")],
+            );
+
+            let reasons = quarantined_reasons(&client, &token, survey_id);
+            assert_eq!(reasons.len(), 5);
+            assert!(reasons[0].starts_with("completed in"));
+            assert_eq!(reasons[1], "opened token is missing or invalid");
+            assert_eq!(reasons[2], "opened token is missing or invalid");
+            assert_eq!(reasons[3], "opened token is missing or invalid");
+            assert_eq!(reasons[4], "honeypot was filled in");
+            assert_eq!(exported_count(&client, &token, survey_id), 0);
+
+            let opened = opened_token(&client, survey_id, 60);
+            respond(
+                &client,
+                survey_id,
+                &[(OPENED_HEADER, &opened), (HONEYPOT_HEADER, "")],
+            );
+            assert_eq!(quarantined_reasons(&client, &token, survey_id).len(), 5);
+            assert_eq!(exported_count(&client, &token, survey_id), 1);
+        });
+    }
+
+    #[test]
+    fn test_opened_token_is_single_use() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket_with_challenges(db_name, 0))
+                .expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+
+            let opened = opened_token(&client, survey_id, 60);
+            respond(&client, survey_id, &[(OPENED_HEADER, &opened)]);
+            respond(&client, survey_id, &[(OPENED_HEADER, &opened)]);
+
+            assert_eq!(
+                quarantined_reasons(&client, &token, survey_id),
+                vec!["opened token was already used"]
+            );
+            assert_eq!(exported_count(&client, &token, survey_id), 1);
+        });
+    }
+
+    #[test]
+    fn test_proof_of_work() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket_with_challenges(db_name, 8))
+                .expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .dispatch();
+            assert_eq!(response.headers().get_one(PROOF_OF_WORK_HEADER), Some("8"));
+
+            let opened = opened_token(&client, survey_id, 60);
+            respond(&client, survey_id, &[(OPENED_HEADER, &opened)]);
+            let opened = opened_token(&client, survey_id, 60);
+            let nonce = (0u64..)
+                .map(|nonce| nonce.to_string())
+                .find(|nonce| {
+                    leading_zero_bits(&Sha256::digest(format!("{opened}:{nonce}").as_bytes())) >= 8
+                })
+                .unwrap();
+            respond(
+                &client,
+                survey_id,
+                &[(OPENED_HEADER, &opened), (PROOF_HEADER, &nonce)],
+            );
+
+            assert_eq!(
+                quarantined_reasons(&client, &token, survey_id),
+                vec!["proof of work is missing"]
+            );
+            assert_eq!(exported_count(&client, &token, survey_id), 1);
+        });
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+}
@@ -0,0 +1,265 @@
+use std::net::IpAddr;
+
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::db::schema;
+use crate::survey_response::Submission;
+
+/// The private cookie that tells respondents apart. It only holds a random id.
+const RESPONDENT_COOKIE: &str = "respondent";
+/// What the fingerprint key is derived for, see [`fingerprint_key`].
+const FINGERPRINT_PURPOSE: &str = "respondent-fingerprint";
+
+/// Fingerprints are keyed with their own key, derived from the secret key, so that a
+/// fingerprint can never be the signature of a login or any other token.
+fn fingerprint_key(secret: &str) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(FINGERPRINT_PURPOSE.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// What is known about whoever is responding, to tell whether they already responded and
+/// whether the response looks like spam.
+pub struct Respondent<'r> {
+    cookies: &'r CookieJar<'r>,
+    ip: Option<IpAddr>,
+    user_agent: Option<&'r str>,
+    key: Vec<u8>,
+    submission: Submission<'r>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Respondent<'r> {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Respondent {
+            cookies: req.cookies(),
+            ip: crate::rate_limit::client_ip(req),
+            user_agent: req.headers().get_one("User-Agent"),
+            key: fingerprint_key(&req.rocket().config().secret_key.to_string()),
+            submission: Submission::new(req),
+        })
+    }
+}
+
+impl<'r> Respondent<'r> {
+    /// What the response comes with for the [`crate::survey_response::Challenges`].
+    pub fn submission(&self) -> &Submission<'r> {
+        &self.submission
+    }
+
+    /// The id in the respondent cookie. A new one is set if there is none yet, so that the
+    /// next response from the same browser can be recognized.
+    pub fn cookie(&self) -> String {
+        if let Some(cookie) = self.cookies.get_private(RESPONDENT_COOKIE) {
+            return cookie.value().to_string();
+        }
+        let id = Uuid::new_v4().to_string();
+        let mut cookie = Cookie::new(RESPONDENT_COOKIE, id.clone());
+        cookie.set_same_site(SameSite::Lax);
+        cookie.make_permanent();
+        self.cookies.add_private(cookie);
+        id
+    }
+
+    /// A hash of the address and user agent, keyed with the fingerprint key and scoped to the
+    /// survey, so that it can't be used to follow respondents across surveys. Returns
+    /// `None` if the address is unknown.
+    pub fn fingerprint(&self, survey_id: i32) -> Option<String> {
+        let ip = self.ip?;
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(
+            format!("{survey_id}\n{ip}\n{}", self.user_agent.unwrap_or_default()).as_bytes(),
+        );
+        Some(hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// Whether the survey already has a response with the same cookie or fingerprint. This
+/// should run in the same transaction as the response being created, with the survey
+/// locked.
+pub(crate) fn is_duplicate(
+    conn: &mut PgConnection,
+    survey_id: i32,
+    respondent_cookie: Option<&str>,
+    fingerprint: Option<&str>,
+) -> QueryResult<bool> {
+    use schema::responses::dsl;
+
+    if respondent_cookie.is_none() && fingerprint.is_none() {
+        return Ok(false);
+    }
+    let matches = dsl::respondent_cookie
+        .eq(respondent_cookie)
+        .or(dsl::fingerprint.eq(fingerprint))
+        .assume_not_null();
+    diesel::select(diesel::dsl::exists(
+        dsl::responses
+            .filter(dsl::survey_id.eq(survey_id))
+            .filter(matches),
+    ))
+    .get_result(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+
+    use crate::db::models::{DuplicateCheck, SurveyPatch, SurveyResponses};
+    use crate::survey::export::ExportedSubmission;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn set_duplicate_check(
+        client: &Client,
+        token: &str,
+        survey_id: i32,
+        check: DuplicateCheck,
+        reject: bool,
+    ) {
+        let response = client
+            .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+            .header(rocket::http::ContentType::JSON)
+            .header(Header::new("Authorization", token.to_owned()))
+            .body(
+                serde_json::to_vec(&SurveyPatch {
+                    duplicate_check: Some(check),
+                    reject_duplicates: Some(reject),
+                    ..Default::default()
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    fn respond(client: &Client, survey_id: i32, remote: &str, user_agent: &str) -> Status {
+        client
+            .post(
+                uri!(
+                    "/api",
+                    crate::survey_response::create_survey_response(survey_id, _)
+                )
+                .to_string(),
+            )
+            .remote(remote.parse::<SocketAddr>().unwrap())
+            .header(Header::new("User-Agent", user_agent.to_owned()))
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn test_flag_duplicate_cookie() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            set_duplicate_check(&client, &token, survey_id, DuplicateCheck::Cookie, false);
+
+            // the tracked client sends the cookie back, whatever the address
+            assert_eq!(
+                respond(&client, survey_id, "10.0.0.1:1000", "a"),
+                Status::Ok
+            );
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        crate::survey_response::create_survey_response(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .remote("10.0.0.2:1000".parse::<SocketAddr>().unwrap())
+                .header(rocket::http::ContentType::JSON)
+                .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            // only the owner gets to know
+            let body = response.into_json::<serde_json::Value>().unwrap();
+            assert!(body.get("duplicate").is_none());
+
+            let response = client
+                .get(
+                    uri!(
+                        "/api",
+                        crate::survey::export::export_responses(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", token))
+                .dispatch();
+            let csv = response.into_string().unwrap();
+            let mut lines = csv.lines();
+            assert_eq!(
+                lines.next(),
+                Some("responder,duplicate,created_at,updated_at")
+            );
+            let mut flags = lines
+                .map(|line| line.split(',').nth(1).unwrap().to_owned())
+                .collect::<Vec<_>>();
+            flags.sort();
+            assert_eq!(flags, vec!["false", "true"]);
+        });
+    }
+
+    #[test]
+    fn test_reject_duplicate_fingerprint() {
+        run_test_with_db(|db_name| {
+            let client = Client::untracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            set_duplicate_check(
+                &client,
+                &token,
+                survey_id,
+                DuplicateCheck::Fingerprint,
+                true,
+            );
+
+            assert_eq!(
+                respond(&client, survey_id, "10.0.0.1:1000", "a"),
+                Status::Ok
+            );
+            assert_eq!(
+                respond(&client, survey_id, "10.0.0.1:2000", "a"),
+                Status::Conflict
+            );
+            assert_eq!(
+                respond(&client, survey_id, "10.0.0.1:1000", "b"),
+                Status::Ok
+            );
+            assert_eq!(
+                respond(&client, survey_id, "10.0.0.2:1000", "a"),
+                Status::Ok
+            );
+
+            // other surveys don't see the same fingerprint
+            let other_survey = make_survey(&client, &token);
+            publish_survey(&client, &token, other_survey);
+            set_duplicate_check(&client, &token, other_survey, DuplicateCheck::Both, true);
+            assert_eq!(
+                respond(&client, other_survey, "10.0.0.1:1000", "a"),
+                Status::Ok
+            );
+        });
+    }
+}
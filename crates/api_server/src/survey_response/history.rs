@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use rocket::serde::json::Json;
+use uuid::Uuid;
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{
+    NewResponseHistoryEntry, ResponseChanges, ResponseHistoryEntry, SurveyResponse,
+};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::survey::check_survey_owner;
+use crate::survey_response::SurveyResponseError;
+
+/// Records the edit from `before` to `after`, unless no answer changed. It should be
+/// recorded in the same transaction as the edit itself.
+pub(crate) fn record_edit(
+    conn: &mut PgConnection,
+    before: &SurveyResponse,
+    after: &SurveyResponse,
+) -> QueryResult<()> {
+    let changes = ResponseChanges::between(&before.content, &after.content);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    diesel::insert_into(schema::response_history::table)
+        .values(NewResponseHistoryEntry {
+            responder_uuid: after.responder_uuid,
+            revision: after.revision,
+            changes,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Brings the responses back to what was first submitted, by undoing their edits.
+pub(crate) fn first_submissions<'a>(
+    conn: &mut PgConnection,
+    responses: impl IntoIterator<Item = &'a mut SurveyResponse>,
+) -> QueryResult<()> {
+    let mut responses = responses
+        .into_iter()
+        .map(|r| (r.responder_uuid, r))
+        .collect::<HashMap<Uuid, _>>();
+    let entries = schema::response_history::table
+        .filter(schema::response_history::responder_uuid.eq_any(responses.keys()))
+        .order(schema::response_history::id.desc())
+        .load::<ResponseHistoryEntry>(conn)?;
+
+    for entry in entries {
+        if let Some(response) = responses.get_mut(&entry.responder_uuid) {
+            entry.changes.undo(&mut response.content);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the edits the respondent made to their response, oldest first.
+#[get("/survey/<survey_id>/respond/history?<responder>")]
+pub async fn get_response_history(
+    survey_id: i32,
+    responder: Uuid,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<Vec<ResponseHistoryEntry>>, ApiErrorResponse<SurveyResponseError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let entries = db
+        .run(move |conn| {
+            let found = schema::responses::table
+                .filter(schema::responses::survey_id.eq(survey_id))
+                .filter(schema::responses::responder_uuid.eq(responder))
+                .select(schema::responses::responder_uuid)
+                .first::<Uuid>(conn)
+                .optional()?;
+            if found.is_none() {
+                return Ok(None);
+            }
+            schema::response_history::table
+                .filter(schema::response_history::responder_uuid.eq(responder))
+                .order(schema::response_history::id.asc())
+                .load::<ResponseHistoryEntry>(conn)
+                .map(Some)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyResponseError::Unknown
+        })?
+        .ok_or(SurveyResponseError::ResponderNotFound)?;
+
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+
+    use crate::db::models::{SurveyPatch, SurveyQuestions};
+    use crate::questions::{QText, Question, RText, Response, SurveyQuestion};
+    use crate::survey::export::ExportedSubmission;
+    use crate::survey::SurveyRef;
+    use crate::survey_response::ResponseAccepted;
+    use crate::test_helpers::*;
+
+    fn answer(question: Uuid, text: &str) -> serde_json::Value {
+        serde_json::json!({
+            question.to_string(): {
+                "type": "Text",
+                "content": { "text": text },
+            },
+        })
+    }
+
+    #[test]
+    fn test_response_history() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let question = Uuid::new_v4();
+            let response = client
+                .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&SurveyPatch {
+                        published: Some(true),
+                        questions: Some(SurveyQuestions(vec![SurveyQuestion {
+                            uuid: question,
+                            question: Question::Text(QText {
+                                prompt: "Anything else?".to_owned(),
+                                description: "".to_owned(),
+                                multiline: false,
+                            }),
+                            required: false,
+                        }])),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        crate::survey_response::create_survey_response(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .header(ContentType::JSON)
+                .body(serde_json::to_vec(&answer(question, "first")).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let responder = response
+                .into_json::<ResponseAccepted>()
+                .unwrap()
+                .responder_uuid;
+
+            // the same answers again don't make an entry
+            for text in ["second", "second", "latest"] {
+                let response = client
+                    .patch(
+                        uri!(
+                            "/api",
+                            crate::survey_response::edit_survey_response(survey_id, responder)
+                        )
+                        .to_string(),
+                    )
+                    .header(ContentType::JSON)
+                    .body(serde_json::to_vec(&answer(question, text)).unwrap())
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+            }
+
+            let response = client
+                .get(uri!("/api", get_response_history(survey_id, responder)).to_string())
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let entries = response.into_json::<Vec<ResponseHistoryEntry>>().unwrap();
+            assert_eq!(entries.len(), 2);
+            let change = &entries[0].changes.0[&question];
+            assert_eq!(
+                change.before,
+                Some(Response::Text(RText {
+                    text: "first".to_owned()
+                }))
+            );
+            assert_eq!(
+                change.after,
+                Some(Response::Text(RText {
+                    text: "second".to_owned()
+                }))
+            );
+            assert!(entries[0].revision < entries[1].revision);
+
+            let response = client
+                .get(uri!("/api", get_response_history(survey_id, Uuid::new_v4())).to_string())
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+            let other_token = create_test_user(&client);
+            let response = client
+                .get(uri!("/api", get_response_history(survey_id, responder)).to_string())
+                .header(Header::new("Authorization", other_token))
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+
+            for (submission, text) in [
+                (ExportedSubmission::First, "first"),
+                (ExportedSubmission::Latest, "latest"),
+            ] {
+                let response = client
+                    .get(
+                        uri!(
+                            "/api",
+                            crate::survey::export::export_responses(survey_id, Some(submission))
+                        )
+                        .to_string(),
+                    )
+                    .header(Header::new("Authorization", token.clone()))
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+                let csv = response.into_string().unwrap();
+                assert!(csv.ends_with(&format!(",{text}\n")), "csv: {csv}");
+            }
+        });
+    }
+}
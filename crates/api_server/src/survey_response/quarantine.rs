@@ -0,0 +1,290 @@
+use diesel::prelude::*;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{SurveyResponse, SurveyResponses, WebhookEvent};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::live::{LiveEvent, LiveEventKind, LiveEvents};
+use crate::survey::check_survey_owner;
+use crate::survey_response::SurveyResponseError;
+use crate::webhook::{self, WebhookWorker};
+
+/// A response that failed a challenge, waiting for the owner of the survey to release or
+/// discard it.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedResponse {
+    #[typeshare(serialized_as = "String")]
+    pub responder_uuid: Uuid,
+    pub content: SurveyResponses,
+    #[typeshare(serialized_as = "String")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Why the response looked like spam.
+    pub reason: String,
+}
+
+impl From<SurveyResponse> for QuarantinedResponse {
+    fn from(response: SurveyResponse) -> Self {
+        Self {
+            responder_uuid: response.responder_uuid,
+            content: response.content,
+            created_at: response.created_at,
+            reason: response.quarantine_reason.unwrap_or_default(),
+        }
+    }
+}
+
+/// Lists the quarantined responses of a survey, oldest first.
+#[get("/survey/<survey_id>/quarantine")]
+pub async fn list_quarantined_responses(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<Vec<QuarantinedResponse>>, ApiErrorResponse<SurveyResponseError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let responses = db
+        .run(move |conn| {
+            schema::responses::table
+                .filter(schema::responses::survey_id.eq(survey_id))
+                .filter(schema::responses::quarantine_reason.is_not_null())
+                .order(schema::responses::created_at.asc())
+                .load::<SurveyResponse>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyResponseError::Unknown
+        })?;
+
+    Ok(Json(responses.into_iter().map(Into::into).collect()))
+}
+
+/// Accepts a quarantined response, as if it had just come in. It counts towards the
+/// response limit from then on, but doesn't close the survey.
+#[post("/survey/<survey_id>/quarantine/<responder>/release")]
+pub async fn release_quarantined_response(
+    survey_id: i32,
+    responder: Uuid,
+    claims: Claims,
+    db: Storage,
+    webhooks: &State<WebhookWorker>,
+    live: &State<LiveEvents>,
+) -> Result<Json<()>, ApiErrorResponse<SurveyResponseError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    db.run(move |conn| {
+        conn.build_transaction()
+            .read_write()
+            .run::<_, diesel::result::Error, _>(|conn| {
+                let Some(released) = diesel::update(schema::responses::table)
+                    .filter(schema::responses::survey_id.eq(survey_id))
+                    .filter(schema::responses::responder_uuid.eq(responder))
+                    .filter(schema::responses::quarantine_reason.is_not_null())
+                    .set((
+                        schema::responses::quarantine_reason.eq(None::<String>),
+                        schema::responses::accepted_at.eq(diesel::dsl::now),
+                    ))
+                    .get_result::<SurveyResponse>(conn)
+                    .optional()?
+                else {
+                    return Ok(Err(SurveyResponseError::ResponderNotFound));
+                };
+                webhook::enqueue_event(conn, survey_id, WebhookEvent::ResponseCreated, &released)?;
+                Ok(Ok(()))
+            })
+    })
+    .await
+    .map_err(|e| {
+        error!("{e:?}");
+        SurveyResponseError::Unknown
+    })??;
+    webhooks.wake();
+    live.publish(
+        &db,
+        LiveEvent::new(LiveEventKind::ResponseCreated, survey_id, Some(responder)),
+    )
+    .await;
+
+    Ok(Json(()))
+}
+
+/// Deletes a quarantined response.
+#[delete("/survey/<survey_id>/quarantine/<responder>")]
+pub async fn discard_quarantined_response(
+    survey_id: i32,
+    responder: Uuid,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<()>, ApiErrorResponse<SurveyResponseError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let deleted = db
+        .run(move |conn| {
+            diesel::delete(schema::responses::table)
+                .filter(schema::responses::survey_id.eq(survey_id))
+                .filter(schema::responses::responder_uuid.eq(responder))
+                .filter(schema::responses::quarantine_reason.is_not_null())
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            SurveyResponseError::Unknown
+        })?;
+    if deleted == 0 {
+        return Err(SurveyResponseError::ResponderNotFound.into());
+    }
+
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+
+    use crate::survey::export::ExportedSubmission;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    fn quarantined(client: &Client, token: &str, survey_id: i32) -> Vec<QuarantinedResponse> {
+        let response = client
+            .get(uri!("/api", list_quarantined_responses(survey_id)).to_string())
+            .header(Header::new("Authorization", token.to_owned()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().unwrap()
+    }
+
+    #[test]
+    fn test_release_and_discard() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket.figment().clone().merge(("challenges.enabled", true));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+
+            // without an opened token, responses are quarantined
+            for _ in 0..2 {
+                let response = client
+                    .post(
+                        uri!(
+                            "/api",
+                            crate::survey_response::create_survey_response(survey_id, _)
+                        )
+                        .to_string(),
+                    )
+                    .header(rocket::http::ContentType::JSON)
+                    .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+            }
+            let responses = quarantined(&client, &token, survey_id);
+            assert_eq!(responses.len(), 2);
+
+            // editing them doesn't show them to anyone watching either
+            let mut events = client
+                .rocket()
+                .state::<crate::live::LiveEvents>()
+                .unwrap()
+                .subscribe(survey_id);
+            let response = client
+                .patch(
+                    uri!(
+                        "/api",
+                        crate::survey_response::edit_survey_response(
+                            survey_id,
+                            responses[0].responder_uuid
+                        )
+                    )
+                    .to_string(),
+                )
+                .header(rocket::http::ContentType::JSON)
+                .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert!(events.try_recv().is_err());
+
+            let other_token = create_test_user(&client);
+            let response = client
+                .get(uri!("/api", list_quarantined_responses(survey_id)).to_string())
+                .header(Header::new("Authorization", other_token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        release_quarantined_response(survey_id, responses[0].responder_uuid)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", other_token))
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+
+            let response = client
+                .post(
+                    uri!(
+                        "/api",
+                        release_quarantined_response(survey_id, responses[0].responder_uuid)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let response = client
+                .delete(
+                    uri!(
+                        "/api",
+                        discard_quarantined_response(survey_id, responses[1].responder_uuid)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert!(quarantined(&client, &token, survey_id).is_empty());
+
+            // released responses can't be discarded here anymore
+            let response = client
+                .delete(
+                    uri!(
+                        "/api",
+                        discard_quarantined_response(survey_id, responses[0].responder_uuid)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+
+            let response = client
+                .get(
+                    uri!(
+                        "/api",
+                        crate::survey::export::export_responses(survey_id, _)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", token))
+                .dispatch();
+            let csv = response.into_string().unwrap();
+            assert_eq!(csv.lines().count(), 2);
+            assert!(csv.contains(&responses[0].responder_uuid.to_string()));
+        });
+    }
+}
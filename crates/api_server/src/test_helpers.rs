@@ -1,12 +1,14 @@
+use std::io::{BufRead, BufReader};
 use std::panic::AssertUnwindSafe;
 
 use diesel::{sql_query, Connection, PgConnection, RunQueryDsl};
 use jsonwebtoken::EncodingKey;
-use rocket::local::blocking::Client;
+use rocket::local::blocking::{Client, LocalResponse};
 
 use crate::{
-    db::models::{Survey, SurveyPatch},
+    db::models::{Survey, SurveyPatch, SurveyStatus},
     jwt::Claims,
+    user::ListSurveysQuery,
 };
 
 pub fn create_db_for_tests() -> String {
@@ -50,9 +52,50 @@ pub fn create_test_user(client: &Client) -> String {
     )
 }
 
+pub fn register(client: &Client, username: &str, password: &str) -> String {
+    let response = client
+        .post(uri!("/api", crate::user::register_user))
+        .header(rocket::http::ContentType::JSON)
+        .body(serde_json::json!({ "username": username, "password": password }).to_string())
+        .dispatch();
+    assert_eq!(response.status(), rocket::http::Status::Created);
+    format!(
+        "Bearer {}",
+        response
+            .into_json::<crate::user::UserToken>()
+            .unwrap()
+            .token
+    )
+}
+
+pub fn login(client: &Client, username: &str, password: &str) -> rocket::http::Status {
+    client
+        .post(uri!("/api", crate::user::login_user))
+        .header(rocket::http::ContentType::JSON)
+        .body(serde_json::json!({ "username": username, "password": password }).to_string())
+        .dispatch()
+        .status()
+}
+
+pub fn survey_count(client: &Client, token: &str) -> usize {
+    let response = client
+        .get(uri!(
+            "/api",
+            crate::user::list_surveys(ListSurveysQuery::default())
+        ))
+        .header(rocket::http::Header::new("Authorization", token.to_owned()))
+        .dispatch();
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+    response
+        .into_json::<crate::db::models::SurveyList>()
+        .unwrap()
+        .surveys
+        .len()
+}
+
 pub fn make_jwt(client: &Client, user_id: i32) -> String {
     let key = EncodingKey::from_secret(client.rocket().config().secret_key.to_string().as_bytes());
-    let claims = Claims::new(user_id);
+    let claims = Claims::new(user_id, 0);
     let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
     "Bearer ".to_string() + &token
 }
@@ -76,7 +119,12 @@ pub fn test_rocket(db_name: &String) -> rocket::Rocket<rocket::Build> {
             "databases.survey_app.url",
             format!("postgres://vscode:notsecure@db/{db_name}"),
         ))
-        .merge(("databases.survey_app.pool_size", 1));
+        .merge(("databases.survey_app.pool_size", 1))
+        // tests that are about rate limiting or challenges turn them back on
+        .merge(("rate_limit.enabled", false))
+        .merge(("challenges.enabled", false))
+        // most tests don't care about password strength
+        .merge(("accounts.password_policy.min_length", 1));
     rocket.configure(config)
 }
 
@@ -90,6 +138,9 @@ pub fn bench_rocket(db_name: &String) -> rocket::Rocket<rocket::Build> {
             format!("postgres://vscode:notsecure@db/{db_name}"),
         ))
         .merge(("databases.survey_app.pool_size", 1))
+        .merge(("rate_limit.enabled", false))
+        .merge(("challenges.enabled", false))
+        .merge(("accounts.password_policy.min_length", 1))
         .merge(("secret_key", vec![12u8; 64]));
     rocket.configure(config)
 }
@@ -117,3 +168,72 @@ pub fn publish_survey(client: &Client, token: &str, survey_id: i32) {
         )
         .dispatch();
 }
+
+pub fn set_survey_status(
+    client: &Client,
+    token: &str,
+    survey_id: i32,
+    status: SurveyStatus,
+) -> rocket::http::Status {
+    client
+        .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+        .header(rocket::http::ContentType::JSON)
+        .header(rocket::http::Header::new("Authorization", token.to_owned()))
+        .body(
+            serde_json::to_vec(&SurveyPatch {
+                status: Some(status),
+                ..Default::default()
+            })
+            .unwrap(),
+        )
+        .dispatch()
+        .status()
+}
+
+/// Like [`test_rocket`], but emails are written to files in `mail_dir` instead of being
+/// logged. Read them back with [`read_mail`].
+pub fn test_rocket_with_mailer(
+    db_name: &String,
+    mail_dir: &std::path::Path,
+) -> rocket::Rocket<rocket::Build> {
+    let rocket = test_rocket(db_name);
+    let config = rocket
+        .figment()
+        .clone()
+        .merge(("mailer.transport", "file"))
+        .merge(("mailer.dir", mail_dir));
+    rocket.configure(config)
+}
+
+/// Reads all emails written by the `file` mailer, oldest first.
+pub fn read_mail(mail_dir: &std::path::Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(mail_dir) else {
+        return Vec::new();
+    };
+    let mut paths = entries
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect()
+}
+
+/// Reads an event stream until the next event, returning its name and data.
+pub fn next_event(stream: &mut BufReader<LocalResponse>) -> (String, String) {
+    let (mut name, mut data) = (String::new(), String::new());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim().to_owned();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data = value.trim().to_owned();
+        } else if line.is_empty() && !name.is_empty() {
+            return (name, data);
+        }
+    }
+}
@@ -1,25 +1,43 @@
 use argon2::{Argon2, PasswordHasher};
+use diesel::dsl::{count, max, sql};
 use diesel::prelude::*;
+use diesel::sql_types::{Bool, Text};
 use password_hash::rand_core::OsRng;
 use password_hash::{PasswordHash, PasswordVerifier, SaltString};
 use rocket::config::SecretKey;
+use rocket::form::{FromFormField, ValueField};
+use rocket::http::uri::fmt::{Formatter, Query, UriDisplay};
 use rocket::http::Status;
 use rocket::response::status::Created;
 use rocket::response::Responder;
 use rocket::serde::json::Json;
+use rocket::State;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::api::ApiErrorResponse;
-use crate::db::models::{ListedSurvey, NewUser, User};
+use crate::db::models::{ListedSurvey, NewUser, SurveyList, SurveyStatus, User};
 use crate::db::{schema, Storage};
 use crate::jwt::Claims;
+use crate::rate_limit::RateLimit;
+use crate::validate::{Validate, ValidationError};
+
+pub(crate) mod account;
+pub(crate) mod oidc;
+pub(crate) mod recovery;
+
+pub use account::{change_password, delete_account, rename_user, AccountConfig};
+pub use oidc::{oidc_callback, oidc_login};
+pub use recovery::{request_password_reset, reset_password, set_email, verify_email};
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 64;
 
 #[typeshare]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct UserLoginParams {
-    username: String,
-    password: String,
+    pub(crate) username: String,
+    pub(crate) password: String,
 }
 
 #[typeshare]
@@ -33,6 +51,12 @@ pub struct UserToken {
 pub enum UserLoginError {
     #[error("Invalid credentials")]
     InvalidCredentials,
+    #[error("Too many failed logins, try again later")]
+    TooManyAttempts,
+    #[error("Username is taken")]
+    UsernameTaken,
+    #[error("Validation Error")]
+    ValidationError(Vec<ValidationError>),
     #[error("Internal error")]
     InternalError,
 }
@@ -41,6 +65,9 @@ impl From<UserLoginError> for ApiErrorResponse<UserLoginError> {
     fn from(value: UserLoginError) -> Self {
         let status = match &value {
             UserLoginError::InvalidCredentials => Status::BadRequest,
+            UserLoginError::TooManyAttempts => Status::TooManyRequests,
+            UserLoginError::UsernameTaken => Status::Conflict,
+            UserLoginError::ValidationError(_) => Status::UnprocessableEntity,
             UserLoginError::InternalError => Status::InternalServerError,
         };
         ApiErrorResponse {
@@ -50,31 +77,47 @@ impl From<UserLoginError> for ApiErrorResponse<UserLoginError> {
     }
 }
 
+impl From<Vec<ValidationError>> for ApiErrorResponse<UserLoginError> {
+    fn from(value: Vec<ValidationError>) -> Self {
+        UserLoginError::ValidationError(value).into()
+    }
+}
+
+/// Usernames are letters, digits, dots, dashes and underscores, so that they can be told
+/// apart wherever they are shown.
+pub fn is_valid_username(username: &str) -> bool {
+    (MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.len())
+        && username
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'_'))
+}
+
+/// The username has to pass [`is_valid_username`], and the password the configured
+/// [`PasswordPolicy`](account::PasswordPolicy).
 #[post("/user/register", data = "<user>")]
 pub async fn register_user(
+    _rate_limit: RateLimit<'_>,
     db: Storage,
     user: Json<UserLoginParams>,
+    config: &State<AccountConfig>,
     secret: &SecretKey,
 ) -> Result<Created<Json<UserToken>>, ApiErrorResponse<UserLoginError>> {
     let user_params = user.into_inner();
     if user_params.username.is_empty() || user_params.password.is_empty() {
         return Err(UserLoginError::InvalidCredentials.into());
     }
+    (&user_params, &config.password_policy).validate()?;
 
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let password_hash = argon2
-        .hash_password(user_params.password.as_bytes(), &salt)
-        .map_err(|e| {
-            error!("{e:?}");
-            match e {
-                password_hash::Error::Password => UserLoginError::InvalidCredentials,
-                _ => UserLoginError::InternalError,
-            }
-        })?;
+    let password_hash = hash_password(&user_params.password).map_err(|e| {
+        error!("{e:?}");
+        match e {
+            password_hash::Error::Password => UserLoginError::InvalidCredentials,
+            _ => UserLoginError::InternalError,
+        }
+    })?;
     let user = NewUser {
         username: user_params.username,
-        password_hash: password_hash.to_string(),
+        password_hash,
     };
 
     let users = db
@@ -84,14 +127,22 @@ pub async fn register_user(
                 .get_results::<User>(conn)
         })
         .await
-        .map_err(|e| {
-            error!("{e:?}");
-            UserLoginError::InternalError
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                info,
+            ) if info.constraint_name() == Some("users_username_key") => {
+                UserLoginError::UsernameTaken
+            }
+            e => {
+                error!("{e:?}");
+                UserLoginError::InternalError
+            }
         })?;
     let Some(user) = users.first() else {
         return Err(UserLoginError::InternalError.into());
     };
-    let token = generate_jwt_for_user(secret, user.id).map_err(|e| {
+    let token = generate_jwt_for_user(secret, user.id, user.token_version).map_err(|e| {
         error!("{e:?}");
         UserLoginError::InternalError
     })?;
@@ -100,8 +151,11 @@ pub async fn register_user(
     Ok(Created::new("").body(Json(resp)))
 }
 
+/// Failed logins are counted per username and address, and after a few of them, the next
+/// attempt has to wait for longer and longer.
 #[post("/user/login", data = "<user>")]
 pub async fn login_user(
+    rate_limit: RateLimit<'_>,
     db: Storage,
     user: Json<UserLoginParams>,
     secret: &SecretKey,
@@ -110,8 +164,16 @@ pub async fn login_user(
     if user_params.username.is_empty() || user_params.password.is_empty() {
         return Err(UserLoginError::InvalidCredentials.into());
     }
+    let login_username = user_params.username.clone();
+    if rate_limit
+        .check_login_backoff(&db, &login_username)
+        .await
+        .is_err()
+    {
+        return Err(UserLoginError::TooManyAttempts.into());
+    }
 
-    let user_id = db
+    let user = db
         .run(move |conn| {
             use schema::users::dsl::*;
             let found_users: Vec<User> = schema::users::table
@@ -127,21 +189,17 @@ pub async fn login_user(
             let Some(user) = found_users.first() else {
             return Err(UserLoginError::InternalError);
         };
-            let parsed_hash =
-                PasswordHash::new(user.password_hash.as_str()).map_err(|e| match e {
-                    ::password_hash::Error::Password => UserLoginError::InvalidCredentials,
-                    _ => UserLoginError::InternalError,
-                })?;
-            Argon2::default()
-                .verify_password(user_params.password.as_bytes(), &parsed_hash)
-                .map_err(|e| match e {
-                    ::password_hash::Error::Password => UserLoginError::InvalidCredentials,
-                    _ => UserLoginError::InternalError,
-                })?;
-            Ok(user.id)
+            verify_password(&user_params.password, &user.password_hash).map_err(|e| match e {
+                ::password_hash::Error::Password => UserLoginError::InvalidCredentials,
+                _ => UserLoginError::InternalError,
+            })?;
+            Ok((user.id, user.token_version))
         })
-        .await?;
-    let token = generate_jwt_for_user(secret, user_id).map_err(|e| {
+        .await;
+    // the attempt was already counted as failed by the backoff check
+    let (user_id, token_version) = user?;
+    rate_limit.clear_login_failures(&db, &login_username).await;
+    let token = generate_jwt_for_user(secret, user_id, token_version).map_err(|e| {
         error!("{e:?}");
         UserLoginError::InternalError
     })?;
@@ -150,29 +208,217 @@ pub async fn login_user(
     Ok(Json(resp))
 }
 
-fn generate_jwt_for_user(secret: &SecretKey, user_id: i32) -> anyhow::Result<String> {
-    let claims = Claims::new(user_id);
+/// Hashes a password with Argon2 and a random salt, into the PHC string format.
+pub(crate) fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(password_hash.to_string())
+}
+
+/// Checks a password against a hash made by [`hash_password`]. A wrong password is
+/// [`password_hash::Error::Password`].
+pub(crate) fn verify_password(password: &str, hash: &str) -> Result<(), password_hash::Error> {
+    let parsed_hash = PasswordHash::new(hash)?;
+    Argon2::default().verify_password(password.as_bytes(), &parsed_hash)
+}
+
+/// Issues a token for the user, which stays valid until it expires or the token version of
+/// the user goes up.
+pub(crate) fn generate_jwt_for_user(
+    secret: &SecretKey,
+    user_id: i32,
+    token_version: i32,
+) -> anyhow::Result<String> {
+    let claims = Claims::new(user_id, token_version);
     let key = jsonwebtoken::EncodingKey::from_secret(secret.to_string().as_bytes());
     let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key)?;
     Ok(token)
 }
 
-#[get("/user/surveys")]
+/// How to sort the surveys in a [`SurveyList`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromFormField, UriDisplayQuery)]
+pub enum SurveySort {
+    #[field(value = "created")]
+    Created,
+    #[default]
+    #[field(value = "updated")]
+    Updated,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromFormField, UriDisplayQuery)]
+pub enum SortOrder {
+    #[field(value = "asc")]
+    Asc,
+    #[default]
+    #[field(value = "desc")]
+    Desc,
+}
+
+/// Points at the last survey of a page, so that the next page can start right after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SurveyCursor {
+    timestamp: chrono::NaiveDateTime,
+    id: i32,
+}
+
+impl std::fmt::Display for SurveyCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.timestamp.timestamp_micros(), self.id)
+    }
+}
+
+impl std::str::FromStr for SurveyCursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (micros, id) = s.split_once('_').ok_or(())?;
+        let micros = micros.parse::<i64>().map_err(|_| ())?;
+        let timestamp = chrono::NaiveDateTime::from_timestamp_opt(
+            micros.div_euclid(1_000_000),
+            (micros.rem_euclid(1_000_000) * 1000) as u32,
+        )
+        .ok_or(())?;
+        let id = id.parse().map_err(|_| ())?;
+        Ok(Self { timestamp, id })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for SurveyCursor {
+    fn from_value(field: ValueField<'r>) -> rocket::form::Result<'r, Self> {
+        field
+            .value
+            .parse()
+            .map_err(|_| rocket::form::Error::validation("invalid cursor").into())
+    }
+}
+
+impl UriDisplay<Query> for SurveyCursor {
+    fn fmt(&self, f: &mut Formatter<'_, Query>) -> std::fmt::Result {
+        f.write_value(self.to_string())
+    }
+}
+
+rocket::http::impl_from_uri_param_identity!([Query] SurveyCursor);
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Query parameters for filtering, sorting, and paginating [`list_surveys`].
+#[derive(Debug, Clone, Default, FromForm, UriDisplayQuery)]
+pub struct ListSurveysQuery {
+    /// Full text search over the title, description, and question prompts.
+    pub q: Option<String>,
+    /// Kept for older clients, filters on whether the survey is a draft or not.
+    pub published: Option<bool>,
+    pub status: Option<SurveyStatus>,
+    /// Include archived surveys, which are hidden unless they are asked for with this or
+    /// `status`.
+    pub archived: Option<bool>,
+    /// List the surveys in the trash instead of the active ones.
+    pub trashed: Option<bool>,
+    pub sort: Option<SurveySort>,
+    pub order: Option<SortOrder>,
+    pub cursor: Option<SurveyCursor>,
+    pub limit: Option<i64>,
+}
+
+#[get("/user/surveys?<query..>")]
 pub async fn list_surveys(
     db: Storage,
     claims: Claims,
-) -> Result<Json<Vec<ListedSurvey>>, ApiErrorResponse<UserLoginError>> {
-    let surveys = db
+    query: ListSurveysQuery,
+) -> Result<Json<SurveyList>, ApiErrorResponse<UserLoginError>> {
+    let sort = query.sort.unwrap_or_default();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let mut surveys = db
         .run(move |conn| {
-            schema::surveys::table
-                .filter(schema::surveys::dsl::owner_id.eq(claims.user_id()))
+            use schema::surveys::dsl::*;
+
+            let mut db_query = surveys
+                .left_join(schema::responses::table)
+                .group_by(id)
                 .select((
-                    schema::surveys::dsl::id,
-                    schema::surveys::dsl::title,
-                    schema::surveys::dsl::description,
-                    schema::surveys::dsl::published,
-                    schema::surveys::dsl::owner_id,
+                    id,
+                    slug,
+                    title,
+                    description,
+                    status.ne(SurveyStatus::Draft),
+                    status,
+                    owner_id,
+                    created_at,
+                    updated_at,
+                    count(schema::responses::responder_uuid.nullable()),
+                    max(schema::responses::created_at.nullable()),
+                    deleted_at,
                 ))
+                .filter(owner_id.eq(claims.user_id()))
+                .into_boxed();
+
+            if let Some(q) = query.q.filter(|q| !q.trim().is_empty()) {
+                db_query = db_query.filter(
+                    sql::<Bool>(
+                        "survey_search_document(surveys.title, surveys.description, surveys.questions) @@ websearch_to_tsquery('english', ",
+                    )
+                    .bind::<Text, _>(q)
+                    .sql(")"),
+                );
+            }
+            db_query = if query.trashed.unwrap_or(false) {
+                db_query.filter(deleted_at.is_not_null())
+            } else {
+                db_query.filter(deleted_at.is_null())
+            };
+            if let Some(is_published) = query.published {
+                db_query = if is_published {
+                    db_query.filter(status.ne(SurveyStatus::Draft))
+                } else {
+                    db_query.filter(status.eq(SurveyStatus::Draft))
+                };
+            }
+            if let Some(survey_status) = query.status {
+                db_query = db_query.filter(status.eq(survey_status));
+            } else if !query.archived.unwrap_or(false) {
+                db_query = db_query.filter(status.ne(SurveyStatus::Archived));
+            }
+
+            macro_rules! sort_by {
+                ($column:expr) => {{
+                    let cursor = query.cursor;
+                    match query.order.unwrap_or_default() {
+                        SortOrder::Asc => {
+                            if let Some(cursor) = cursor {
+                                db_query = db_query.filter(
+                                    $column.gt(cursor.timestamp).or($column
+                                        .eq(cursor.timestamp)
+                                        .and(id.gt(cursor.id))),
+                                );
+                            }
+                            db_query.order(($column.asc(), id.asc()))
+                        }
+                        SortOrder::Desc => {
+                            if let Some(cursor) = cursor {
+                                db_query = db_query.filter(
+                                    $column.lt(cursor.timestamp).or($column
+                                        .eq(cursor.timestamp)
+                                        .and(id.lt(cursor.id))),
+                                );
+                            }
+                            db_query.order(($column.desc(), id.desc()))
+                        }
+                    }
+                }};
+            }
+            let db_query = match sort {
+                SurveySort::Created => sort_by!(created_at),
+                SurveySort::Updated => sort_by!(updated_at),
+            };
+
+            db_query
+                .limit(limit + 1)
                 .load::<ListedSurvey>(conn)
         })
         .await
@@ -180,7 +426,27 @@ pub async fn list_surveys(
             error!("{e:?}");
             UserLoginError::InternalError
         })?;
-    Ok(Json(surveys))
+
+    let next_cursor = if surveys.len() > limit as usize {
+        surveys.truncate(limit as usize);
+        surveys.last().map(|survey| {
+            SurveyCursor {
+                timestamp: match sort {
+                    SurveySort::Created => survey.created_at,
+                    SurveySort::Updated => survey.updated_at,
+                },
+                id: survey.id,
+            }
+            .to_string()
+        })
+    } else {
+        None
+    };
+
+    Ok(Json(SurveyList {
+        surveys,
+        next_cursor,
+    }))
 }
 
 #[cfg(test)]
@@ -188,6 +454,7 @@ mod tests {
     use rocket::local::blocking::Client;
 
     use super::*;
+    use crate::survey::SurveyRef;
     use crate::test_helpers::*;
 
     #[test]
@@ -197,7 +464,7 @@ mod tests {
 
             let resp = client
                 .post(uri!("/api", register_user))
-                .body(r#"{"username": "a", "password": "a"}"#)
+                .body(r#"{"username": "alice", "password": "a"}"#)
                 .dispatch();
             assert_eq!(resp.status(), rocket::http::Status::Created);
         })
@@ -210,13 +477,13 @@ mod tests {
 
             let resp = client
                 .post(uri!("/api", register_user))
-                .body(r#"{"username": "a", "password": "a"}"#)
+                .body(r#"{"username": "alice", "password": "a"}"#)
                 .dispatch();
             assert_eq!(resp.status(), rocket::http::Status::Created);
 
             let resp = client
                 .post(uri!("/api", login_user))
-                .body(r#"{"username": "a", "password": "a"}"#)
+                .body(r#"{"username": "alice", "password": "a"}"#)
                 .dispatch();
             assert_eq!(resp.status(), rocket::http::Status::Ok);
         })
@@ -254,6 +521,73 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_user_register_taken() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let resp = client
+                .post(uri!("/api", register_user))
+                .body(r#"{"username": "alice", "password": "a"}"#)
+                .dispatch();
+            assert_eq!(resp.status(), rocket::http::Status::Created);
+
+            let resp = client
+                .post(uri!("/api", register_user))
+                .body(r#"{"username": "alice", "password": "b"}"#)
+                .dispatch();
+            assert_eq!(resp.status(), rocket::http::Status::Conflict);
+        })
+    }
+
+    #[test]
+    fn test_user_register_validation() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("accounts.password_policy.min_length", 8))
+                .merge(("accounts.password_policy.require_digit", true))
+                .merge(("accounts.password_policy.require_symbol", true));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            for (username, password, status) in [
+                ("al", "hunter2!!", rocket::http::Status::UnprocessableEntity),
+                (
+                    "alice smith",
+                    "hunter2!!",
+                    rocket::http::Status::UnprocessableEntity,
+                ),
+                (
+                    "alice",
+                    "hunter2",
+                    rocket::http::Status::UnprocessableEntity,
+                ),
+                (
+                    "alice",
+                    "hunter!!!",
+                    rocket::http::Status::UnprocessableEntity,
+                ),
+                (
+                    "alice",
+                    "hunter222",
+                    rocket::http::Status::UnprocessableEntity,
+                ),
+                ("alice", "hunter2!!", rocket::http::Status::Created),
+            ] {
+                let resp = client
+                    .post(uri!("/api", register_user))
+                    .body(
+                        serde_json::json!({ "username": username, "password": password })
+                            .to_string(),
+                    )
+                    .dispatch();
+                assert_eq!(resp.status(), status, "{username} {password}");
+            }
+        })
+    }
+
     #[test]
     fn test_user_deny_login_blank_credentials() {
         #[allow(clippy::let_unit_value)]
@@ -325,14 +659,15 @@ mod tests {
             make_survey(&client, &token);
 
             let resp = client
-                .get(uri!("/api", list_surveys))
+                .get(uri!("/api", list_surveys(ListSurveysQuery::default())))
                 .header(rocket::http::Header::new("Authorization", token))
                 .dispatch();
             assert_eq!(resp.status(), rocket::http::Status::Ok);
             let list = resp
-                .into_json::<Vec<ListedSurvey>>()
+                .into_json::<SurveyList>()
                 .expect("expected list of surveys");
-            assert_eq!(list.len(), 2);
+            assert_eq!(list.surveys.len(), 2);
+            assert!(list.next_cursor.is_none());
         })
     }
 
@@ -349,14 +684,190 @@ mod tests {
             make_survey(&client, &token2);
 
             let resp = client
-                .get(uri!("/api", list_surveys))
+                .get(uri!("/api", list_surveys(ListSurveysQuery::default())))
                 .header(rocket::http::Header::new("Authorization", token2))
                 .dispatch();
             assert_eq!(resp.status(), rocket::http::Status::Ok);
             let list = resp
-                .into_json::<Vec<ListedSurvey>>()
+                .into_json::<SurveyList>()
                 .expect("expected list of surveys");
-            assert_eq!(list.len(), 1);
+            assert_eq!(list.surveys.len(), 1);
+        })
+    }
+
+    #[test]
+    fn test_list_surveys_search() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            make_survey(&client, &token);
+            let resp = client
+                .patch(uri!("/api", crate::survey::edit_survey(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&crate::db::models::SurveyPatch {
+                        title: Some("Favorite fruits".to_owned()),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(resp.status(), rocket::http::Status::Ok);
+
+            let resp = client
+                .get(uri!(
+                    "/api",
+                    list_surveys(ListSurveysQuery {
+                        q: Some("fruit".to_owned()),
+                        ..Default::default()
+                    })
+                ))
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .dispatch();
+            assert_eq!(resp.status(), rocket::http::Status::Ok);
+            let list = resp.into_json::<SurveyList>().unwrap();
+            assert_eq!(list.surveys.len(), 1);
+            assert_eq!(list.surveys[0].id, survey_id);
+
+            let resp = client
+                .get(uri!(
+                    "/api",
+                    list_surveys(ListSurveysQuery {
+                        published: Some(true),
+                        ..Default::default()
+                    })
+                ))
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            let list = resp.into_json::<SurveyList>().unwrap();
+            assert_eq!(list.surveys.len(), 0);
+        })
+    }
+
+    #[test]
+    fn test_list_surveys_hides_archived() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            make_survey(&client, &token);
+            for status in [
+                SurveyStatus::Open,
+                SurveyStatus::Closed,
+                SurveyStatus::Archived,
+            ] {
+                set_survey_status(&client, &token, survey_id, status);
+            }
+
+            let list_with = |query: ListSurveysQuery| {
+                client
+                    .get(uri!("/api", list_surveys(query)))
+                    .header(rocket::http::Header::new("Authorization", token.clone()))
+                    .dispatch()
+                    .into_json::<SurveyList>()
+                    .unwrap()
+            };
+
+            let list = list_with(ListSurveysQuery::default());
+            assert_eq!(list.surveys.len(), 1);
+            assert_ne!(list.surveys[0].id, survey_id);
+
+            let list = list_with(ListSurveysQuery {
+                archived: Some(true),
+                ..Default::default()
+            });
+            assert_eq!(list.surveys.len(), 2);
+
+            let list = list_with(ListSurveysQuery {
+                status: Some(SurveyStatus::Archived),
+                ..Default::default()
+            });
+            assert_eq!(list.surveys.len(), 1);
+            assert_eq!(list.surveys[0].id, survey_id);
+            assert!(list.surveys[0].published);
+        })
+    }
+
+    #[test]
+    fn test_list_surveys_pagination() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let mut expected = (0..5)
+                .map(|_| make_survey(&client, &token))
+                .collect::<Vec<_>>();
+            expected.reverse();
+
+            let mut seen = vec![];
+            let mut cursor = None;
+            loop {
+                let resp = client
+                    .get(uri!(
+                        "/api",
+                        list_surveys(ListSurveysQuery {
+                            sort: Some(SurveySort::Created),
+                            cursor,
+                            limit: Some(2),
+                            ..Default::default()
+                        })
+                    ))
+                    .header(rocket::http::Header::new("Authorization", token.clone()))
+                    .dispatch();
+                assert_eq!(resp.status(), rocket::http::Status::Ok);
+                let list = resp.into_json::<SurveyList>().unwrap();
+                assert!(list.surveys.len() <= 2);
+                seen.extend(list.surveys.iter().map(|s| s.id));
+                match list.next_cursor {
+                    Some(next) => cursor = Some(next.parse().unwrap()),
+                    None => break,
+                }
+            }
+            assert_eq!(seen, expected);
+        })
+    }
+
+    #[test]
+    fn test_list_surveys_response_stats() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let empty_survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            for _ in 0..3 {
+                let resp = client
+                    .post(
+                        uri!(
+                            "/api",
+                            crate::survey_response::create_survey_response(survey_id, _)
+                        )
+                        .to_string(),
+                    )
+                    .body("{}")
+                    .dispatch();
+                assert_eq!(resp.status(), rocket::http::Status::Ok);
+            }
+
+            let resp = client
+                .get(uri!("/api", list_surveys(ListSurveysQuery::default())))
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            let list = resp.into_json::<SurveyList>().unwrap();
+            let survey = list.surveys.iter().find(|s| s.id == survey_id).unwrap();
+            assert_eq!(survey.response_count, 3);
+            assert!(survey.last_response_at.is_some());
+            let survey = list
+                .surveys
+                .iter()
+                .find(|s| s.id == empty_survey_id)
+                .unwrap();
+            assert_eq!(survey.response_count, 0);
+            assert!(survey.last_response_at.is_none());
         })
     }
 }
@@ -0,0 +1,564 @@
+use diesel::prelude::*;
+use rocket::config::SecretKey;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::api::ApiErrorResponse;
+use crate::config;
+use crate::db::models::{AccountTokenPurpose, FieldChange, HistoryAction, SurveyChanges, User};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::rate_limit::RateLimit;
+use crate::survey::history;
+use crate::user::recovery;
+use crate::user::{generate_jwt_for_user, hash_password, verify_password, UserToken};
+use crate::validate::{Validate, ValidationError};
+
+/// Configured under `[default.accounts]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AccountConfig {
+    /// The page of the frontend that verification emails link to, with the token in
+    /// `?token=`.
+    pub verify_email_url: String,
+    /// The page of the frontend that password reset emails link to, with the token in
+    /// `?token=`.
+    pub reset_password_url: String,
+    /// Seconds that a verification link works for.
+    pub verify_email_ttl: i64,
+    /// Seconds that a password reset link works for.
+    pub reset_password_ttl: i64,
+    pub password_policy: PasswordPolicy,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            verify_email_url: "http://localhost:5173/verify-email".to_string(),
+            reset_password_url: "http://localhost:5173/reset-password".to_string(),
+            verify_email_ttl: 24 * 3600,
+            reset_password_ttl: 3600,
+            password_policy: PasswordPolicy::default(),
+        }
+    }
+}
+
+/// What new passwords have to look like, configured under
+/// `[default.accounts.password_policy]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    /// In characters.
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// Anything that isn't a letter or a digit counts as a symbol.
+    pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error, Serialize, Deserialize)]
+pub enum AccountError {
+    #[error("Current password is wrong")]
+    WrongPassword,
+    #[error("Password can't be empty")]
+    EmptyPassword,
+    #[error("Username can't be empty")]
+    EmptyUsername,
+    #[error("Username is taken")]
+    UsernameTaken,
+    #[error("Account not found")]
+    NotFound,
+    #[error("Surveys can only be transferred to another account")]
+    TransferToSelf,
+    #[error("Transferring surveys requires `transfer_to`")]
+    TransferTargetRequired,
+    #[error("Account to transfer surveys to not found")]
+    TransferTargetNotFound,
+    #[error("Email address is taken")]
+    EmailTaken,
+    #[error("Token is invalid, used or expired")]
+    InvalidToken,
+    #[error("Validation Error")]
+    ValidationError(Vec<ValidationError>),
+    #[error("Internal error")]
+    InternalError,
+}
+
+impl From<AccountError> for ApiErrorResponse<AccountError> {
+    fn from(value: AccountError) -> Self {
+        let status = match &value {
+            AccountError::WrongPassword => Status::Forbidden,
+            AccountError::EmptyPassword => Status::BadRequest,
+            AccountError::EmptyUsername => Status::BadRequest,
+            AccountError::UsernameTaken => Status::Conflict,
+            AccountError::NotFound => Status::NotFound,
+            AccountError::TransferToSelf => Status::BadRequest,
+            AccountError::TransferTargetRequired => Status::BadRequest,
+            AccountError::TransferTargetNotFound => Status::UnprocessableEntity,
+            AccountError::EmailTaken => Status::Conflict,
+            AccountError::InvalidToken => Status::BadRequest,
+            AccountError::ValidationError(_) => Status::UnprocessableEntity,
+            AccountError::InternalError => Status::InternalServerError,
+        };
+        ApiErrorResponse {
+            status,
+            message: value,
+        }
+    }
+}
+
+impl From<Vec<ValidationError>> for ApiErrorResponse<AccountError> {
+    fn from(value: Vec<ValidationError>) -> Self {
+        AccountError::ValidationError(value).into()
+    }
+}
+
+#[typeshare]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChangePasswordParams {
+    current_password: String,
+    pub(crate) new_password: String,
+}
+
+#[typeshare]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RenameParams {
+    pub(crate) username: String,
+}
+
+/// What happens to the surveys of a deleted account.
+#[typeshare]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SurveyDisposal {
+    /// They are given to the account in `transfer_to`, along with their responses.
+    Transfer,
+    /// They are deleted along with the account, and can't be restored.
+    Delete,
+}
+
+#[typeshare]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeleteAccountParams {
+    password: String,
+    surveys: SurveyDisposal,
+    /// The username of the account to transfer the surveys to.
+    #[serde(default)]
+    transfer_to: Option<String>,
+}
+
+/// Loads the user of the token and checks their password, for changes that need it.
+fn verify_user(
+    conn: &mut PgConnection,
+    user_id: i32,
+    password: &str,
+) -> QueryResult<Result<User, AccountError>> {
+    let Some(user) = schema::users::table
+        .find(user_id)
+        .for_update()
+        .first::<User>(conn)
+        .optional()?
+    else {
+        return Ok(Err(AccountError::NotFound));
+    };
+    if let Err(e) = verify_password(password, &user.password_hash) {
+        return Ok(Err(match e {
+            password_hash::Error::Password => AccountError::WrongPassword,
+            e => {
+                error!("{e:?}");
+                AccountError::InternalError
+            }
+        }));
+    }
+    Ok(Ok(user))
+}
+
+/// Changes the password, which revokes every token of the user. A new token is sent back,
+/// so that whoever changed it stays logged in.
+#[put("/user/password", data = "<params>")]
+pub async fn change_password(
+    _rate_limit: RateLimit<'_>,
+    db: Storage,
+    claims: Claims,
+    params: Json<ChangePasswordParams>,
+    config: &State<AccountConfig>,
+    secret: &SecretKey,
+) -> Result<Json<UserToken>, ApiErrorResponse<AccountError>> {
+    let params = params.into_inner();
+    if params.new_password.is_empty() {
+        return Err(AccountError::EmptyPassword.into());
+    }
+    (&params, &config.password_policy).validate()?;
+    let password_hash = hash_password(&params.new_password).map_err(|e| {
+        error!("{e:?}");
+        AccountError::InternalError
+    })?;
+
+    let user_id = claims.user_id();
+    let token_version = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    if let Err(e) = verify_user(conn, user_id, &params.current_password)? {
+                        return Ok(Err(e));
+                    }
+                    let version = diesel::update(schema::users::table.find(user_id))
+                        .set((
+                            schema::users::password_hash.eq(password_hash),
+                            schema::users::token_version.eq(schema::users::token_version + 1),
+                        ))
+                        .returning(schema::users::token_version)
+                        .get_result::<i32>(conn)?;
+                    recovery::discard_tokens(conn, user_id, AccountTokenPurpose::ResetPassword)?;
+                    Ok(Ok(version))
+                })
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            AccountError::InternalError
+        })??;
+
+    let token = generate_jwt_for_user(secret, user_id, token_version).map_err(|e| {
+        error!("{e:?}");
+        AccountError::InternalError
+    })?;
+    Ok(Json(UserToken { token }))
+}
+
+/// Changes the username, which is what the user logs in with.
+#[put("/user/username", data = "<params>")]
+pub async fn rename_user(
+    db: Storage,
+    claims: Claims,
+    params: Json<RenameParams>,
+) -> Result<Json<()>, ApiErrorResponse<AccountError>> {
+    let params = params.into_inner();
+    if params.username.is_empty() {
+        return Err(AccountError::EmptyUsername.into());
+    }
+    params.validate()?;
+    let username = params.username;
+
+    let user_id = claims.user_id();
+    let renamed = db
+        .run(move |conn| {
+            diesel::update(schema::users::table.find(user_id))
+                .set(schema::users::username.eq(username))
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                info,
+            ) if info.constraint_name() == Some("users_username_key") => {
+                AccountError::UsernameTaken
+            }
+            e => {
+                error!("{e:?}");
+                AccountError::InternalError
+            }
+        })?;
+    if renamed == 0 {
+        return Err(AccountError::NotFound.into());
+    }
+
+    Ok(Json(()))
+}
+
+/// Deletes the account. Its surveys are either transferred to another account, or deleted
+/// along with it.
+#[delete("/user", data = "<params>")]
+pub async fn delete_account(
+    _rate_limit: RateLimit<'_>,
+    db: Storage,
+    claims: Claims,
+    params: Json<DeleteAccountParams>,
+) -> Result<Json<()>, ApiErrorResponse<AccountError>> {
+    let params = params.into_inner();
+    let transfer_to = match params.surveys {
+        SurveyDisposal::Transfer => Some(
+            params
+                .transfer_to
+                .ok_or(AccountError::TransferTargetRequired)?,
+        ),
+        SurveyDisposal::Delete => None,
+    };
+
+    let user_id = claims.user_id();
+    db.run(move |conn| {
+        conn.build_transaction()
+            .read_write()
+            .run::<_, diesel::result::Error, _>(|conn| {
+                let user = match verify_user(conn, user_id, &params.password)? {
+                    Ok(user) => user,
+                    Err(e) => return Ok(Err(e)),
+                };
+                if let Some(username) = transfer_to {
+                    let Some(new_owner) = schema::users::table
+                        .filter(schema::users::username.eq(username))
+                        .select(schema::users::id)
+                        .first::<i32>(conn)
+                        .optional()?
+                    else {
+                        return Ok(Err(AccountError::TransferTargetNotFound));
+                    };
+                    if new_owner == user.id {
+                        return Ok(Err(AccountError::TransferToSelf));
+                    }
+                    transfer_surveys(conn, user.id, new_owner)?;
+                }
+                // the surveys that are left go with the account
+                diesel::delete(schema::users::table.find(user.id)).execute(conn)?;
+                Ok(Ok(()))
+            })
+    })
+    .await
+    .map_err(|e| {
+        error!("{e:?}");
+        AccountError::InternalError
+    })??;
+
+    Ok(Json(()))
+}
+
+/// Gives every survey of `from` to `to`, recording it in the history of each survey.
+fn transfer_surveys(conn: &mut PgConnection, from: i32, to: i32) -> QueryResult<()> {
+    let surveys = diesel::update(schema::surveys::table)
+        .filter(schema::surveys::owner_id.eq(from))
+        .set(schema::surveys::owner_id.eq(to))
+        .returning(schema::surveys::id)
+        .get_results::<i32>(conn)?;
+    for survey_id in surveys {
+        let changes = SurveyChanges(
+            [(
+                "owner_id".to_string(),
+                FieldChange {
+                    before: from.into(),
+                    after: to.into(),
+                },
+            )]
+            .into(),
+        );
+        history::record(conn, survey_id, from, HistoryAction::Transfer, changes)?;
+    }
+    Ok(())
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Accounts", |rocket| async {
+        let Some(config) = config::section::<AccountConfig>(rocket.figment(), "accounts") else {
+            return Err(rocket);
+        };
+        Ok(rocket.manage(config))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::http::{ContentType, Header};
+    use rocket::local::blocking::Client;
+
+    use crate::db::models::SurveyHistoryEntry;
+    use crate::survey::SurveyRef;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn test_change_password() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+            let token = register(&client, "alice", "old");
+
+            for (current, new, status) in [
+                ("wrong", "new", Status::Forbidden),
+                ("old", "", Status::BadRequest),
+            ] {
+                let response = client
+                    .put(uri!("/api", change_password))
+                    .header(ContentType::JSON)
+                    .header(Header::new("Authorization", token.clone()))
+                    .body(
+                        serde_json::json!({ "current_password": current, "new_password": new })
+                            .to_string(),
+                    )
+                    .dispatch();
+                assert_eq!(response.status(), status);
+            }
+            assert_eq!(survey_count(&client, &token), 0);
+
+            let response = client
+                .put(uri!("/api", change_password))
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::json!({ "current_password": "old", "new_password": "new" })
+                        .to_string(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let new_token = format!(
+                "Bearer {}",
+                response.into_json::<UserToken>().unwrap().token
+            );
+
+            // the old token is revoked, the new one works
+            let response = client
+                .post(uri!("/api", crate::survey::create_survey))
+                .header(Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+            assert_eq!(survey_count(&client, &new_token), 0);
+
+            assert_eq!(login(&client, "alice", "old"), Status::BadRequest);
+            assert_eq!(login(&client, "alice", "new"), Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_rename_user() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+            let token = register(&client, "alice", "pass");
+            register(&client, "bob", "pass");
+
+            for (username, status) in [
+                ("bob", Status::Conflict),
+                ("", Status::BadRequest),
+                ("carol smith", Status::UnprocessableEntity),
+                ("carol", Status::Ok),
+            ] {
+                let response = client
+                    .put(uri!("/api", rename_user))
+                    .header(ContentType::JSON)
+                    .header(Header::new("Authorization", token.clone()))
+                    .body(serde_json::json!({ "username": username }).to_string())
+                    .dispatch();
+                assert_eq!(response.status(), status);
+            }
+
+            assert_eq!(login(&client, "alice", "pass"), Status::BadRequest);
+            assert_eq!(login(&client, "carol", "pass"), Status::Ok);
+
+            // tokens of users that don't exist are turned away before the route
+            let response = client
+                .put(uri!("/api", rename_user))
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", make_jwt(&client, 58008)))
+                .body(serde_json::json!({ "username": "dave" }).to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+        });
+    }
+
+    #[test]
+    fn test_delete_account() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+            let token = register(&client, "alice", "pass");
+            let other_token = register(&client, "bob", "pass");
+            let survey_id = make_survey(&client, &token);
+
+            let delete = |token: &str, body: serde_json::Value| {
+                client
+                    .delete(uri!("/api", delete_account))
+                    .header(ContentType::JSON)
+                    .header(Header::new("Authorization", token.to_owned()))
+                    .body(body.to_string())
+                    .dispatch()
+                    .status()
+            };
+            for (body, status) in [
+                (
+                    serde_json::json!({ "password": "wrong", "surveys": "delete" }),
+                    Status::Forbidden,
+                ),
+                (
+                    serde_json::json!({ "password": "pass", "surveys": "transfer" }),
+                    Status::BadRequest,
+                ),
+                (
+                    serde_json::json!({
+                        "password": "pass",
+                        "surveys": "transfer",
+                        "transfer_to": "alice",
+                    }),
+                    Status::BadRequest,
+                ),
+                (
+                    serde_json::json!({
+                        "password": "pass",
+                        "surveys": "transfer",
+                        "transfer_to": "nobody",
+                    }),
+                    Status::UnprocessableEntity,
+                ),
+            ] {
+                assert_eq!(delete(&token, body), status);
+            }
+            assert_eq!(login(&client, "alice", "pass"), Status::Ok);
+
+            let status = delete(
+                &token,
+                serde_json::json!({
+                    "password": "pass",
+                    "surveys": "transfer",
+                    "transfer_to": "bob",
+                }),
+            );
+            assert_eq!(status, Status::Ok);
+            assert_eq!(login(&client, "alice", "pass"), Status::BadRequest);
+            let response = client
+                .put(uri!("/api", rename_user))
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token.clone()))
+                .body(serde_json::json!({ "username": "alice2" }).to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+            assert_eq!(survey_count(&client, &other_token), 1);
+            let response = client
+                .get(
+                    uri!(
+                        "/api",
+                        crate::survey::history::get_survey_history(survey_id)
+                    )
+                    .to_string(),
+                )
+                .header(Header::new("Authorization", other_token.clone()))
+                .dispatch();
+            let entries = response.into_json::<Vec<SurveyHistoryEntry>>().unwrap();
+            assert_eq!(entries[0].action, HistoryAction::Transfer);
+
+            // and this time the survey goes with the account
+            let status = delete(
+                &other_token,
+                serde_json::json!({ "password": "pass", "surveys": "delete" }),
+            );
+            assert_eq!(status, Status::Ok);
+            let response = client
+                .get(uri!("/api", crate::survey::get_survey(survey_id)).to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+        });
+    }
+}
@@ -0,0 +1,785 @@
+use std::time::Duration;
+
+use diesel::prelude::*;
+use rocket::config::SecretKey;
+use rocket::fairing::AdHoc;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::api::ApiErrorResponse;
+use crate::config;
+use crate::db::models::{NewExternalIdentity, NewOidcLogin, NewUser, OidcLogin};
+use crate::db::{schema, Storage};
+use crate::rate_limit::RateLimit;
+use crate::user::recovery::{generate_token, normalize_email};
+use crate::user::{
+    generate_jwt_for_user, hash_password, UserToken, MAX_USERNAME_LENGTH, MIN_USERNAME_LENGTH,
+};
+
+/// Holds the `state` of the login that was started in this browser, so that a callback
+/// with the state of a login started somewhere else is turned away.
+const STATE_COOKIE: &str = "oidc_state";
+
+/// Configured under `[default.oidc]`. Logging in through the provider is off until
+/// `enabled` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OidcConfig {
+    pub enabled: bool,
+    /// Has to match the `iss` claim of the ID tokens exactly.
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub client_id: String,
+    /// Left out for public clients, which only rely on PKCE.
+    pub client_secret: Option<String>,
+    /// The page of the frontend that the provider sends users back to. It passes the `code`
+    /// and `state` it gets on to [`oidc_callback`].
+    pub redirect_uri: String,
+    pub scopes: String,
+    /// Seconds that users have to log in at the provider.
+    pub login_ttl: i64,
+    /// Log in as the user with the same verified email address when there is no linked
+    /// account yet, instead of creating a new user. Only do this if the provider can be
+    /// trusted to verify addresses.
+    pub link_by_email: bool,
+    /// Seconds to wait for the provider to answer.
+    pub timeout: u64,
+    /// Lets the token endpoint use plain http, which is only meant for tests against a
+    /// provider on the same machine.
+    pub allow_insecure_token_endpoint: bool,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer: String::new(),
+            authorization_endpoint: String::new(),
+            token_endpoint: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+            redirect_uri: "http://localhost:5173/oidc/callback".to_string(),
+            scopes: "openid profile email".to_string(),
+            login_ttl: 600,
+            link_by_email: false,
+            timeout: 10,
+            allow_insecure_token_endpoint: false,
+        }
+    }
+}
+
+/// The configuration along with the client used to talk to the provider.
+pub struct Oidc {
+    config: OidcConfig,
+    http: reqwest::Client,
+}
+
+#[derive(Debug, Clone, Error, Serialize, Deserialize)]
+pub enum OidcError {
+    #[error("Logging in through a provider is not enabled")]
+    NotEnabled,
+    #[error("The login is unknown or expired")]
+    InvalidState,
+    #[error("The provider didn't accept the login")]
+    ProviderError,
+    #[error("Internal error")]
+    InternalError,
+}
+
+impl From<OidcError> for ApiErrorResponse<OidcError> {
+    fn from(value: OidcError) -> Self {
+        let status = match &value {
+            OidcError::NotEnabled => Status::NotFound,
+            OidcError::InvalidState => Status::BadRequest,
+            OidcError::ProviderError => Status::BadGateway,
+            OidcError::InternalError => Status::InternalServerError,
+        };
+        ApiErrorResponse {
+            status,
+            message: value,
+        }
+    }
+}
+
+/// What the frontend got back from the provider.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcCallbackParams {
+    code: String,
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    preferred_username: Option<String>,
+}
+
+/// The PKCE code challenge for a verifier, with the `S256` method.
+fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Sends the user to the provider to log in. The provider sends them back to the
+/// configured `redirect_uri` afterwards, and the state of the login is kept in a cookie
+/// until then.
+#[get("/user/oidc/login")]
+pub async fn oidc_login(
+    _rate_limit: RateLimit<'_>,
+    db: Storage,
+    cookies: &CookieJar<'_>,
+    oidc: &State<Oidc>,
+) -> Result<Redirect, ApiErrorResponse<OidcError>> {
+    let config = &oidc.config;
+    if !config.enabled {
+        return Err(OidcError::NotEnabled.into());
+    }
+    let mut url = reqwest::Url::parse(&config.authorization_endpoint).map_err(|e| {
+        error!("invalid authorization endpoint: {e:?}");
+        OidcError::InternalError
+    })?;
+
+    let login = NewOidcLogin {
+        state: generate_token(),
+        code_verifier: generate_token(),
+        nonce: generate_token(),
+        expires_at: chrono::Utc::now() + chrono::Duration::seconds(config.login_ttl),
+    };
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_uri)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &login.state)
+        .append_pair("nonce", &login.nonce)
+        .append_pair("code_challenge", &code_challenge(&login.code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    let mut cookie = Cookie::new(STATE_COOKIE, login.state.clone());
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_max_age(rocket::time::Duration::seconds(config.login_ttl));
+
+    db.run(move |conn| {
+        // logins that never came back are cleaned up here, since there's nothing else to
+        // do with them
+        diesel::delete(schema::oidc_logins::table)
+            .filter(schema::oidc_logins::expires_at.lt(chrono::Utc::now()))
+            .execute(conn)?;
+        diesel::insert_into(schema::oidc_logins::table)
+            .values(login)
+            .execute(conn)
+    })
+    .await
+    .map_err(|e| {
+        error!("{e:?}");
+        OidcError::InternalError
+    })?;
+
+    cookies.add_private(cookie);
+    Ok(Redirect::to(url.to_string()))
+}
+
+/// Exchanges the code from the provider for an ID token, and checks that it is meant for
+/// this login.
+///
+/// The signature of the ID token isn't checked, since it comes straight from the token
+/// endpoint, which is what OpenID Connect Core 3.1.3.7 allows for.
+async fn exchange_code(
+    oidc: &Oidc,
+    login: &OidcLogin,
+    code: &str,
+) -> Result<IdTokenClaims, OidcError> {
+    let config = &oidc.config;
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &config.redirect_uri),
+        ("client_id", &config.client_id),
+        ("code_verifier", &login.code_verifier),
+    ];
+    if let Some(client_secret) = &config.client_secret {
+        form.push(("client_secret", client_secret));
+    }
+    let response = oidc
+        .http
+        .post(&config.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| {
+            error!("failed to reach the token endpoint: {e:?}");
+            OidcError::ProviderError
+        })?;
+    let status = response.status();
+    let body = response.bytes().await.map_err(|e| {
+        error!("failed to read the token response: {e:?}");
+        OidcError::ProviderError
+    })?;
+    if !status.is_success() {
+        warn!(
+            "token endpoint answered with {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+        return Err(OidcError::ProviderError);
+    }
+    let tokens = serde_json::from_slice::<TokenResponse>(&body).map_err(|e| {
+        error!("unexpected token response: {e:?}");
+        OidcError::ProviderError
+    })?;
+
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_audience(&[&config.client_id]);
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(
+        &tokens.id_token,
+        &jsonwebtoken::DecodingKey::from_secret(&[]),
+        &validation,
+    )
+    .map_err(|e| {
+        warn!("rejected ID token: {e:?}");
+        OidcError::ProviderError
+    })?
+    .claims;
+    if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+        warn!("rejected ID token: nonce doesn't match");
+        return Err(OidcError::ProviderError);
+    }
+    Ok(claims)
+}
+
+/// Makes a username out of what the provider knows about the user, which doesn't have to
+/// be free yet.
+fn suggested_username(claims: &IdTokenClaims) -> String {
+    let name = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref()?.split('@').next())
+        .unwrap_or_default();
+    let mut username = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .take(MAX_USERNAME_LENGTH - 8)
+        .collect::<String>();
+    if username.len() < MIN_USERNAME_LENGTH {
+        username = "user".to_string();
+    }
+    username
+}
+
+/// The user that the account at the provider is linked to, with their token version.
+fn linked_user(conn: &mut PgConnection, claims: &IdTokenClaims) -> QueryResult<Option<(i32, i32)>> {
+    schema::external_identities::table
+        .inner_join(schema::users::table)
+        .filter(schema::external_identities::issuer.eq(&claims.iss))
+        .filter(schema::external_identities::subject.eq(&claims.sub))
+        .select((schema::users::id, schema::users::token_version))
+        .first::<(i32, i32)>(conn)
+        .optional()
+}
+
+/// Finds the user that the account at the provider is linked to, linking it to a user
+/// first if it's new. Returns their id and token version.
+fn find_or_link_user(
+    conn: &mut PgConnection,
+    config: &OidcConfig,
+    claims: &IdTokenClaims,
+) -> QueryResult<Result<(i32, i32), OidcError>> {
+    if let Some(user) = linked_user(conn, claims)? {
+        return Ok(Ok(user));
+    }
+
+    let existing = match &claims.email {
+        Some(email) if config.link_by_email && claims.email_verified == Some(true) => {
+            schema::users::table
+                .filter(schema::users::email.eq(normalize_email(email)))
+                .select((schema::users::id, schema::users::token_version))
+                .first::<(i32, i32)>(conn)
+                .optional()?
+        }
+        _ => None,
+    };
+    let (user, created) = match existing {
+        Some(user) => (user, false),
+        None => {
+            // nobody knows this password, so the user can only log in through the provider
+            // until they reset it
+            let password_hash = match hash_password(&generate_token()) {
+                Ok(password_hash) => password_hash,
+                Err(e) => {
+                    error!("{e:?}");
+                    return Ok(Err(OidcError::InternalError));
+                }
+            };
+            let base = suggested_username(claims);
+            let mut username = base.clone();
+            let mut n = 1;
+            // a name that is taken in the meantime is skipped like one that was taken before
+            let user = loop {
+                let user = diesel::insert_into(schema::users::table)
+                    .values(NewUser {
+                        username,
+                        password_hash: password_hash.clone(),
+                    })
+                    .on_conflict(schema::users::username)
+                    .do_nothing()
+                    .returning((schema::users::id, schema::users::token_version))
+                    .get_result::<(i32, i32)>(conn)
+                    .optional()?;
+                if let Some(user) = user {
+                    break user;
+                }
+                n += 1;
+                username = format!("{base}-{n}");
+            };
+            (user, true)
+        }
+    };
+
+    let linked = diesel::insert_into(schema::external_identities::table)
+        .values(NewExternalIdentity {
+            user_id: user.0,
+            issuer: claims.iss.clone(),
+            subject: claims.sub.clone(),
+        })
+        .on_conflict((
+            schema::external_identities::issuer,
+            schema::external_identities::subject,
+        ))
+        .do_nothing()
+        .execute(conn)?;
+    if linked == 0 {
+        // another login of the same account linked it first, so that user is the one
+        if created {
+            diesel::delete(schema::users::table.find(user.0)).execute(conn)?;
+        }
+        return Ok(linked_user(conn, claims)?.ok_or(OidcError::InternalError));
+    }
+    Ok(Ok(user))
+}
+
+/// Finishes a login at the provider and logs in as the linked user, the same way
+/// [`login_user`](crate::user::login_user) does. Accounts that log in for the first time
+/// get a new user, or the one with the same verified address if `link_by_email` is set.
+#[post("/user/oidc/callback", data = "<params>")]
+pub async fn oidc_callback(
+    _rate_limit: RateLimit<'_>,
+    db: Storage,
+    cookies: &CookieJar<'_>,
+    params: Json<OidcCallbackParams>,
+    oidc: &State<Oidc>,
+    secret: &SecretKey,
+) -> Result<Json<UserToken>, ApiErrorResponse<OidcError>> {
+    if !oidc.config.enabled {
+        return Err(OidcError::NotEnabled.into());
+    }
+    let OidcCallbackParams { code, state } = params.into_inner();
+    // the login has to have been started in this browser
+    let started_here = cookies
+        .get_private(STATE_COOKIE)
+        .is_some_and(|cookie| cookie.value() == state);
+    if !started_here {
+        return Err(OidcError::InvalidState.into());
+    }
+    cookies.remove_private(Cookie::named(STATE_COOKIE));
+
+    let login = db
+        .run(move |conn| {
+            diesel::delete(schema::oidc_logins::table)
+                .filter(schema::oidc_logins::state.eq(state))
+                .filter(schema::oidc_logins::expires_at.gt(chrono::Utc::now()))
+                .get_result::<OidcLogin>(conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            OidcError::InternalError
+        })?
+        .ok_or(OidcError::InvalidState)?;
+
+    let claims = exchange_code(oidc, &login, &code).await?;
+
+    let config = oidc.config.clone();
+    let (user_id, token_version) = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run(|conn| find_or_link_user(conn, &config, &claims))
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            OidcError::InternalError
+        })??;
+
+    let token = generate_jwt_for_user(secret, user_id, token_version).map_err(|e| {
+        error!("{e:?}");
+        OidcError::InternalError
+    })?;
+    Ok(Json(UserToken { token }))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("OpenID Connect", |rocket| async {
+        // a section that can't be read would otherwise leave the provider disabled
+        let Some(config) = config::section::<OidcConfig>(rocket.figment(), "oidc") else {
+            return Err(rocket);
+        };
+        if config.enabled && !config.allow_insecure_token_endpoint {
+            let https = reqwest::Url::parse(&config.token_endpoint)
+                .is_ok_and(|url| url.scheme() == "https");
+            if !https {
+                error!("the OpenID Connect token endpoint has to be an https URL");
+                return Err(rocket);
+            }
+        }
+        match reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+        {
+            Ok(http) => Ok(rocket.manage(Oidc { config, http })),
+            Err(e) => {
+                error!("failed to create the OpenID Connect client: {e:?}");
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use rocket::http::ContentType;
+    use rocket::local::blocking::{Client, LocalResponse};
+
+    use crate::test_helpers::*;
+
+    const ISSUER: &str = "http://idp.test";
+    const CLIENT_ID: &str = "survey-app";
+
+    /// A server whose provider has its token endpoint at `listener`.
+    fn oidc_rocket(
+        db_name: &String,
+        listener: &TcpListener,
+        link_by_email: bool,
+    ) -> rocket::Rocket<rocket::Build> {
+        let rocket = test_rocket(db_name);
+        let config = rocket
+            .figment()
+            .clone()
+            .merge(("oidc.enabled", true))
+            .merge(("oidc.issuer", ISSUER))
+            .merge(("oidc.authorization_endpoint", format!("{ISSUER}/authorize")))
+            .merge((
+                "oidc.token_endpoint",
+                format!("http://{}/token", listener.local_addr().unwrap()),
+            ))
+            .merge(("oidc.client_id", CLIENT_ID))
+            .merge(("oidc.client_secret", "shh"))
+            .merge(("oidc.link_by_email", link_by_email))
+            .merge(("oidc.allow_insecure_token_endpoint", true));
+        rocket.configure(config)
+    }
+
+    fn oidc_client(db_name: &String, listener: &TcpListener, link_by_email: bool) -> Client {
+        Client::tracked(oidc_rocket(db_name, listener, link_by_email))
+            .expect("valid rocket instance")
+    }
+
+    /// Answers the next request to the token endpoint with `id_token`, like a provider
+    /// would, and returns the form that was sent to it.
+    fn answer_token_request(listener: &TcpListener, id_token: String) -> HashMap<String, String> {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut content_length = 0;
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some((name, value)) if name.eq_ignore_ascii_case("content-length") => {
+                    content_length = value.parse().unwrap();
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let response = serde_json::json!({
+            "access_token": "unused",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        })
+        .to_string();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{response}",
+            response.len()
+        )
+        .unwrap();
+        form_urlencoded(&String::from_utf8(body).unwrap())
+    }
+
+    fn form_urlencoded(query: &str) -> HashMap<String, String> {
+        reqwest::Url::parse(&format!("http://localhost/?{query}"))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect()
+    }
+
+    /// Starts a login, and returns the query of the URL that it redirects to.
+    fn start_login(client: &Client) -> HashMap<String, String> {
+        let response = client.get(uri!("/api", oidc_login)).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        assert!(location.starts_with(&format!("{ISSUER}/authorize?")));
+        form_urlencoded(location.split_once('?').unwrap().1)
+    }
+
+    fn callback<'c>(client: &'c Client, state: &str) -> LocalResponse<'c> {
+        client
+            .post(uri!("/api", oidc_callback))
+            .header(ContentType::JSON)
+            .body(serde_json::json!({ "code": "the-code", "state": state }).to_string())
+            .dispatch()
+    }
+
+    /// Goes through a whole login as the account `subject` at the provider, whose ID token
+    /// has `claims` on top of the usual ones. Returns the token of the user.
+    fn login_as(
+        client: &Client,
+        listener: &TcpListener,
+        subject: &str,
+        claims: serde_json::Value,
+    ) -> String {
+        let query = start_login(client);
+        let mut id_token = serde_json::json!({
+            "iss": ISSUER,
+            "aud": CLIENT_ID,
+            "sub": subject,
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "nonce": query["nonce"],
+        });
+        id_token
+            .as_object_mut()
+            .unwrap()
+            .extend(claims.as_object().unwrap().clone());
+        let id_token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &id_token,
+            &jsonwebtoken::EncodingKey::from_secret(b"provider key"),
+        )
+        .unwrap();
+
+        std::thread::scope(|scope| {
+            let provider = scope.spawn(|| answer_token_request(listener, id_token));
+            let response = callback(client, &query["state"]);
+            let form = provider.join().unwrap();
+            assert_eq!(form["grant_type"], "authorization_code");
+            assert_eq!(form["code"], "the-code");
+            assert_eq!(form["client_id"], CLIENT_ID);
+            assert_eq!(form["client_secret"], "shh");
+            assert_eq!(query["code_challenge_method"], "S256");
+            assert_eq!(
+                code_challenge(&form["code_verifier"]),
+                query["code_challenge"]
+            );
+
+            assert_eq!(response.status(), Status::Ok);
+            format!(
+                "Bearer {}",
+                response.into_json::<UserToken>().unwrap().token
+            )
+        })
+    }
+
+    #[test]
+    fn test_oidc_login() {
+        run_test_with_db(|db_name| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = oidc_client(db_name, &listener, false);
+
+            let claims = serde_json::json!({ "preferred_username": "Jane Doe" });
+            let token = login_as(&client, &listener, "jane", claims.clone());
+            make_survey(&client, &token);
+
+            // logging in again is the same user
+            let token = login_as(&client, &listener, "jane", claims.clone());
+            assert_eq!(survey_count(&client, &token), 1);
+
+            // someone else with the same name gets a user of their own
+            let token = login_as(&client, &listener, "other-jane", claims);
+            assert_eq!(survey_count(&client, &token), 0);
+
+            let mut conn =
+                PgConnection::establish(&format!("postgres://vscode:notsecure@db/{db_name}"))
+                    .expect("Failed to connect to database");
+            let mut usernames = schema::users::table
+                .select(schema::users::username)
+                .load::<String>(&mut conn)
+                .unwrap();
+            usernames.sort();
+            assert_eq!(usernames, ["JaneDoe", "JaneDoe-2"]);
+        })
+    }
+
+    #[test]
+    fn test_oidc_link_by_email() {
+        run_test_with_db(|db_name| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = oidc_client(db_name, &listener, true);
+
+            let token = create_test_user(&client);
+            make_survey(&client, &token);
+            let mut conn =
+                PgConnection::establish(&format!("postgres://vscode:notsecure@db/{db_name}"))
+                    .expect("Failed to connect to database");
+            diesel::update(schema::users::table)
+                .set(schema::users::email.eq("jane@example.com"))
+                .execute(&mut conn)
+                .unwrap();
+
+            // the provider has to vouch for the address
+            let token = login_as(
+                &client,
+                &listener,
+                "unverified",
+                serde_json::json!({ "email": "Jane@example.com", "email_verified": false }),
+            );
+            assert_eq!(survey_count(&client, &token), 0);
+
+            let token = login_as(
+                &client,
+                &listener,
+                "jane",
+                serde_json::json!({ "email": "Jane@example.com", "email_verified": true }),
+            );
+            assert_eq!(survey_count(&client, &token), 1);
+        })
+    }
+
+    #[test]
+    fn test_oidc_rejects_bad_logins() {
+        run_test_with_db(|db_name| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = oidc_client(db_name, &listener, false);
+
+            assert_eq!(callback(&client, "made-up").status(), Status::BadRequest);
+
+            // only the login that was started last in this browser can finish
+            let earlier = start_login(&client);
+            let query = start_login(&client);
+            assert_eq!(
+                callback(&client, &earlier["state"]).status(),
+                Status::BadRequest
+            );
+
+            // an ID token for another login
+            let id_token = jsonwebtoken::encode(
+                &jsonwebtoken::Header::default(),
+                &serde_json::json!({
+                    "iss": ISSUER,
+                    "aud": CLIENT_ID,
+                    "sub": "jane",
+                    "exp": chrono::Utc::now().timestamp() + 300,
+                    "nonce": "another login",
+                }),
+                &jsonwebtoken::EncodingKey::from_secret(b"provider key"),
+            )
+            .unwrap();
+            std::thread::scope(|scope| {
+                let provider = scope.spawn(|| answer_token_request(&listener, id_token));
+                assert_eq!(
+                    callback(&client, &query["state"]).status(),
+                    Status::BadGateway
+                );
+                provider.join().unwrap();
+            });
+
+            // the login is used up either way, even with the cookie still around
+            let response = client
+                .post(uri!("/api", oidc_callback))
+                .header(ContentType::JSON)
+                .private_cookie(Cookie::new(STATE_COOKIE, query["state"].clone()))
+                .body(
+                    serde_json::json!({ "code": "the-code", "state": query["state"] }).to_string(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+        })
+    }
+
+    #[test]
+    fn test_oidc_insecure_token_endpoint() {
+        run_test_with_db(|db_name| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let rocket = oidc_rocket(db_name, &listener, false);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("oidc.allow_insecure_token_endpoint", false));
+            let Err(error) = Client::tracked(rocket.configure(config)) else {
+                panic!("a plain http token endpoint shouldn't be allowed");
+            };
+            assert!(matches!(
+                error.kind(),
+                rocket::error::ErrorKind::FailedFairings(_)
+            ));
+        })
+    }
+
+    #[test]
+    fn test_oidc_invalid_config() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("oidc.enabled", "sometimes"));
+            let Err(error) = Client::tracked(rocket.configure(config)) else {
+                panic!("a section that can't be read shouldn't leave logins disabled");
+            };
+            assert!(matches!(
+                error.kind(),
+                rocket::error::ErrorKind::FailedFairings(_)
+            ));
+        })
+    }
+
+    #[test]
+    fn test_oidc_disabled() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+            let response = client.get(uri!("/api", oidc_login)).dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+            assert_eq!(callback(&client, "whatever").status(), Status::NotFound);
+        })
+    }
+}
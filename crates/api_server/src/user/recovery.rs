@@ -0,0 +1,569 @@
+use diesel::prelude::*;
+use password_hash::rand_core::{OsRng, RngCore};
+use rocket::config::SecretKey;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::api::ApiErrorResponse;
+use crate::db::models::{AccountToken, AccountTokenPurpose, NewAccountToken};
+use crate::db::{schema, Storage};
+use crate::jwt::Claims;
+use crate::mailer::{Email, Outbox};
+use crate::rate_limit::RateLimit;
+use crate::user::account::{AccountConfig, AccountError};
+use crate::user::{generate_jwt_for_user, hash_password, UserToken};
+use crate::validate::Validate;
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailParams {
+    pub email: String,
+}
+
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyEmailParams {
+    token: String,
+}
+
+#[typeshare]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResetPasswordParams {
+    token: String,
+    pub(crate) new_password: String,
+}
+
+pub(super) fn generate_token() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Addresses are compared without regard to case, so they are kept in lowercase.
+pub(super) fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Deletes the unused tokens of the user for a purpose, so that links already sent stop
+/// working.
+pub(super) fn discard_tokens(
+    conn: &mut PgConnection,
+    user_id: i32,
+    purpose: AccountTokenPurpose,
+) -> QueryResult<usize> {
+    diesel::delete(schema::account_tokens::table)
+        .filter(schema::account_tokens::user_id.eq(user_id))
+        .filter(schema::account_tokens::purpose.eq(purpose))
+        .filter(schema::account_tokens::used_at.is_null())
+        .execute(conn)
+}
+
+/// Creates a token for the user, replacing any they had for the same purpose, so that only
+/// the link in the latest email works. Returns the token, which is only kept as a hash.
+fn issue_token(
+    conn: &mut PgConnection,
+    user_id: i32,
+    purpose: AccountTokenPurpose,
+    email: Option<String>,
+    ttl: i64,
+) -> QueryResult<String> {
+    discard_tokens(conn, user_id, purpose)?;
+    let token = generate_token();
+    diesel::insert_into(schema::account_tokens::table)
+        .values(NewAccountToken {
+            user_id,
+            purpose,
+            token_hash: hash_token(&token),
+            email,
+            expires_at: chrono::Utc::now() + chrono::Duration::seconds(ttl),
+        })
+        .execute(conn)?;
+    Ok(token)
+}
+
+/// Uses up a token, which has to be unused and unexpired. This should run in the same
+/// transaction as whatever the token is for.
+fn consume_token(
+    conn: &mut PgConnection,
+    purpose: AccountTokenPurpose,
+    token: &str,
+) -> QueryResult<Result<AccountToken, AccountError>> {
+    Ok(diesel::update(schema::account_tokens::table)
+        .filter(schema::account_tokens::token_hash.eq(hash_token(token)))
+        .filter(schema::account_tokens::purpose.eq(purpose))
+        .filter(schema::account_tokens::used_at.is_null())
+        .filter(schema::account_tokens::expires_at.gt(chrono::Utc::now()))
+        .set(schema::account_tokens::used_at.eq(chrono::Utc::now()))
+        .get_result::<AccountToken>(conn)
+        .optional()?
+        .ok_or(AccountError::InvalidToken))
+}
+
+/// Starts changing the email address of the account. A link to verify the new address is
+/// sent to it, and it only replaces the current one once that link is followed.
+#[put("/user/email", data = "<params>")]
+pub async fn set_email(
+    _rate_limit: RateLimit<'_>,
+    db: Storage,
+    claims: Claims,
+    params: Json<EmailParams>,
+    config: &State<AccountConfig>,
+    outbox: &State<Outbox>,
+) -> Result<Json<()>, ApiErrorResponse<AccountError>> {
+    params.validate()?;
+    let email = normalize_email(&params.email);
+
+    let user_id = claims.user_id();
+    let ttl = config.verify_email_ttl;
+    let pending = email.clone();
+    let token = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    let exists = schema::users::table
+                        .find(user_id)
+                        .select(schema::users::id)
+                        .first::<i32>(conn)
+                        .optional()?;
+                    if exists.is_none() {
+                        return Ok(Err(AccountError::NotFound));
+                    }
+                    let taken = schema::users::table
+                        .filter(schema::users::email.eq(&pending))
+                        .filter(schema::users::id.ne(user_id))
+                        .count()
+                        .get_result::<i64>(conn)?;
+                    if taken > 0 {
+                        return Ok(Err(AccountError::EmailTaken));
+                    }
+                    issue_token(
+                        conn,
+                        user_id,
+                        AccountTokenPurpose::VerifyEmail,
+                        Some(pending),
+                        ttl,
+                    )
+                    .map(Ok)
+                })
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            AccountError::InternalError
+        })??;
+
+    let email = Email {
+        to: email,
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Follow this link to use this address for your Survey App account:\n\n\
+            {}?token={token}\n\nIf you didn't ask for this, you can ignore this email.\n",
+            config.verify_email_url
+        ),
+    };
+    outbox.send(email).await.map_err(|e| {
+        error!("failed to send the verification email: {e:?}");
+        AccountError::InternalError
+    })?;
+
+    Ok(Json(()))
+}
+
+/// Sets the email address of the account to the one the token was sent to.
+#[post("/user/email/verify", data = "<params>")]
+pub async fn verify_email(
+    db: Storage,
+    params: Json<VerifyEmailParams>,
+) -> Result<Json<()>, ApiErrorResponse<AccountError>> {
+    let token = params.into_inner().token;
+    db.run(move |conn| {
+        conn.build_transaction()
+            .read_write()
+            .run::<_, diesel::result::Error, _>(|conn| {
+                let token = match consume_token(conn, AccountTokenPurpose::VerifyEmail, &token)? {
+                    Ok(token) => token,
+                    Err(e) => return Ok(Err(e)),
+                };
+                diesel::update(schema::users::table.find(token.user_id))
+                    .set(schema::users::email.eq(token.email))
+                    .execute(conn)?;
+                Ok(Ok(()))
+            })
+    })
+    .await
+    .map_err(|e| match e {
+        // another account verified the same address since the link was sent
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            info,
+        ) if info.constraint_name() == Some("users_email_key") => AccountError::EmailTaken,
+        e => {
+            error!("{e:?}");
+            AccountError::InternalError
+        }
+    })??;
+
+    Ok(Json(()))
+}
+
+/// Sends a link to reset the password to the account with the email address, if there is
+/// one. The answer is the same either way, so that it can't be used to find out who has
+/// an account.
+#[post("/user/password/reset", data = "<params>")]
+pub async fn request_password_reset(
+    _rate_limit: RateLimit<'_>,
+    db: Storage,
+    params: Json<EmailParams>,
+    config: &State<AccountConfig>,
+    outbox: &State<Outbox>,
+) -> Result<Json<()>, ApiErrorResponse<AccountError>> {
+    params.validate()?;
+    let email = normalize_email(&params.email);
+
+    let ttl = config.reset_password_ttl;
+    let to = email.clone();
+    let token = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    let Some(user_id) = schema::users::table
+                        .filter(schema::users::email.eq(&to))
+                        .select(schema::users::id)
+                        .first::<i32>(conn)
+                        .optional()?
+                    else {
+                        return Ok(None);
+                    };
+                    issue_token(conn, user_id, AccountTokenPurpose::ResetPassword, None, ttl)
+                        .map(Some)
+                })
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            AccountError::InternalError
+        })?;
+
+    if let Some(token) = token {
+        let email = Email {
+            to: email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Follow this link to choose a new password for your Survey App account:\n\n\
+                {}?token={token}\n\nThe link works once, for the next {} minutes. If you \
+                didn't ask for this, you can ignore this email.\n",
+                config.reset_password_url,
+                config.reset_password_ttl / 60
+            ),
+        };
+        // sent in the background, so that how long this takes doesn't give it away either
+        let outbox = outbox.inner().clone();
+        rocket::tokio::spawn(async move {
+            if let Err(e) = outbox.send(email).await {
+                error!("failed to send the password reset email: {e:?}");
+            }
+        });
+    }
+
+    Ok(Json(()))
+}
+
+/// Sets a new password with a token from a password reset email. Like changing the
+/// password, this revokes every token of the user, and a new one is sent back.
+#[post("/user/password/reset/confirm", data = "<params>")]
+pub async fn reset_password(
+    _rate_limit: RateLimit<'_>,
+    db: Storage,
+    params: Json<ResetPasswordParams>,
+    config: &State<AccountConfig>,
+    secret: &SecretKey,
+) -> Result<Json<UserToken>, ApiErrorResponse<AccountError>> {
+    let params = params.into_inner();
+    if params.new_password.is_empty() {
+        return Err(AccountError::EmptyPassword.into());
+    }
+    (&params, &config.password_policy).validate()?;
+    let password_hash = hash_password(&params.new_password).map_err(|e| {
+        error!("{e:?}");
+        AccountError::InternalError
+    })?;
+
+    let (user_id, token_version) = db
+        .run(move |conn| {
+            conn.build_transaction()
+                .read_write()
+                .run::<_, diesel::result::Error, _>(|conn| {
+                    let token = match consume_token(
+                        conn,
+                        AccountTokenPurpose::ResetPassword,
+                        &params.token,
+                    )? {
+                        Ok(token) => token,
+                        Err(e) => return Ok(Err(e)),
+                    };
+                    let version = diesel::update(schema::users::table.find(token.user_id))
+                        .set((
+                            schema::users::password_hash.eq(password_hash),
+                            schema::users::token_version.eq(schema::users::token_version + 1),
+                        ))
+                        .returning(schema::users::token_version)
+                        .get_result::<i32>(conn)?;
+                    discard_tokens(conn, token.user_id, AccountTokenPurpose::ResetPassword)?;
+                    Ok(Ok((token.user_id, version)))
+                })
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            AccountError::InternalError
+        })??;
+
+    let token = generate_jwt_for_user(secret, user_id, token_version).map_err(|e| {
+        error!("{e:?}");
+        AccountError::InternalError
+    })?;
+    Ok(Json(UserToken { token }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::{Path, PathBuf};
+
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+
+    use crate::test_helpers::*;
+
+    fn mail_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("survey_app_mail_{}", uuid::Uuid::new_v4()))
+    }
+
+    /// Waits for the `n`th email, since some are sent in the background.
+    fn nth_mail(mail_dir: &Path, n: usize) -> String {
+        for _ in 0..50 {
+            if let Some(mail) = read_mail(mail_dir).into_iter().nth(n) {
+                return mail;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        panic!("email {n} was never sent");
+    }
+
+    /// Finds the token in the link of an email, which may be split over lines.
+    fn token_in(mail: &str) -> String {
+        let mail = mail.replace("=\r\n", "").replace("=3D", "=");
+        let start = mail.find("?token=").expect("a link with a token") + "?token=".len();
+        mail[start..start + 64].to_string()
+    }
+
+    fn post(client: &Client, uri: String, body: serde_json::Value) -> Status {
+        client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .status()
+    }
+
+    fn set(client: &Client, token: &str, email: &str) -> Status {
+        client
+            .put(uri!("/api", set_email))
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", token.to_owned()))
+            .body(serde_json::json!({ "email": email }).to_string())
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn test_verify_email() {
+        run_test_with_db(|db_name| {
+            let mail_dir = mail_dir();
+            let client = Client::tracked(test_rocket_with_mailer(db_name, &mail_dir))
+                .expect("valid rocket instance");
+            let token = register(&client, "alice", "old");
+            let other_token = register(&client, "bob", "old");
+
+            assert_eq!(
+                set(&client, &token, "not an email"),
+                Status::UnprocessableEntity
+            );
+            assert_eq!(set(&client, &token, "Alice@Example.com"), Status::Ok);
+            let mail = nth_mail(&mail_dir, 0);
+            assert!(mail.contains("To: alice@example.com"));
+            let verification = token_in(&mail);
+
+            // not verified yet, so there is nothing to reset
+            let status = post(
+                &client,
+                uri!("/api", request_password_reset).to_string(),
+                serde_json::json!({ "email": "alice@example.com" }),
+            );
+            assert_eq!(status, Status::Ok);
+
+            for status in [Status::Ok, Status::BadRequest] {
+                let response = post(
+                    &client,
+                    uri!("/api", verify_email).to_string(),
+                    serde_json::json!({ "token": verification }),
+                );
+                assert_eq!(response, status);
+            }
+            assert_eq!(
+                set(&client, &other_token, "alice@example.com"),
+                Status::Conflict
+            );
+
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            let mail = read_mail(&mail_dir);
+            std::fs::remove_dir_all(&mail_dir).ok();
+            assert_eq!(mail.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_reset_password() {
+        run_test_with_db(|db_name| {
+            let mail_dir = mail_dir();
+            let client = Client::tracked(test_rocket_with_mailer(db_name, &mail_dir))
+                .expect("valid rocket instance");
+            let token = register(&client, "alice", "old");
+            assert_eq!(set(&client, &token, "alice@example.com"), Status::Ok);
+            let status = post(
+                &client,
+                uri!("/api", verify_email).to_string(),
+                serde_json::json!({ "token": token_in(&nth_mail(&mail_dir, 0)) }),
+            );
+            assert_eq!(status, Status::Ok);
+
+            for email in ["nobody@example.com", "ALICE@example.com"] {
+                let status = post(
+                    &client,
+                    uri!("/api", request_password_reset).to_string(),
+                    serde_json::json!({ "email": email }),
+                );
+                assert_eq!(status, Status::Ok);
+            }
+            let mail = nth_mail(&mail_dir, 1);
+            assert!(mail.contains("To: alice@example.com"));
+            assert!(mail.contains("Reset your password"));
+            let reset = token_in(&mail);
+
+            let response = client
+                .post(uri!("/api", reset_password))
+                .header(ContentType::JSON)
+                .body(serde_json::json!({ "token": reset, "new_password": "new" }).to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let new_token = format!(
+                "Bearer {}",
+                response.into_json::<UserToken>().unwrap().token
+            );
+
+            // the token only works once, and the old session is logged out
+            let status = post(
+                &client,
+                uri!("/api", reset_password).to_string(),
+                serde_json::json!({ "token": reset, "new_password": "newer" }),
+            );
+            assert_eq!(status, Status::BadRequest);
+            for (token, status) in [(token, Status::Unauthorized), (new_token, Status::Created)] {
+                let response = client
+                    .post(uri!("/api", crate::survey::create_survey))
+                    .header(Header::new("Authorization", token))
+                    .dispatch();
+                assert_eq!(response.status(), status);
+            }
+            assert_eq!(login(&client, "alice", "new"), Status::Ok);
+
+            std::fs::remove_dir_all(&mail_dir).ok();
+        });
+    }
+
+    #[test]
+    fn test_change_password_discards_reset() {
+        run_test_with_db(|db_name| {
+            let mail_dir = mail_dir();
+            let client = Client::tracked(test_rocket_with_mailer(db_name, &mail_dir))
+                .expect("valid rocket instance");
+            let token = register(&client, "alice", "old");
+            assert_eq!(set(&client, &token, "alice@example.com"), Status::Ok);
+            post(
+                &client,
+                uri!("/api", verify_email).to_string(),
+                serde_json::json!({ "token": token_in(&nth_mail(&mail_dir, 0)) }),
+            );
+            post(
+                &client,
+                uri!("/api", request_password_reset).to_string(),
+                serde_json::json!({ "email": "alice@example.com" }),
+            );
+            let reset = token_in(&nth_mail(&mail_dir, 1));
+            std::fs::remove_dir_all(&mail_dir).ok();
+
+            // whoever asked for the link already knew the password, so it stops working
+            let response = client
+                .put(uri!("/api", crate::user::account::change_password))
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", token))
+                .body(
+                    serde_json::json!({ "current_password": "old", "new_password": "new" })
+                        .to_string(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let status = post(
+                &client,
+                uri!("/api", reset_password).to_string(),
+                serde_json::json!({ "token": reset, "new_password": "newer" }),
+            );
+            assert_eq!(status, Status::BadRequest);
+            assert_eq!(login(&client, "alice", "new"), Status::Ok);
+        });
+    }
+
+    #[test]
+    fn test_reset_token_expires() {
+        run_test_with_db(|db_name| {
+            let mail_dir = mail_dir();
+            let rocket = test_rocket_with_mailer(db_name, &mail_dir);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("accounts.reset_password_ttl", 0));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+            let token = register(&client, "alice", "old");
+            assert_eq!(set(&client, &token, "alice@example.com"), Status::Ok);
+            post(
+                &client,
+                uri!("/api", verify_email).to_string(),
+                serde_json::json!({ "token": token_in(&nth_mail(&mail_dir, 0)) }),
+            );
+
+            post(
+                &client,
+                uri!("/api", request_password_reset).to_string(),
+                serde_json::json!({ "email": "alice@example.com" }),
+            );
+            let reset = token_in(&nth_mail(&mail_dir, 1));
+            std::fs::remove_dir_all(&mail_dir).ok();
+
+            let status = post(
+                &client,
+                uri!("/api", reset_password).to_string(),
+                serde_json::json!({ "token": reset, "new_password": "new" }),
+            );
+            assert_eq!(status, Status::BadRequest);
+        });
+    }
+}
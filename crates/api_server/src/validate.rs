@@ -7,6 +7,12 @@ use crate::{
         Choice, IsEmpty, QMultipleChoice, QRating, QText, Question, RMultipleChoice, RRating,
         RText, Response, SurveyQuestion,
     },
+    survey::invitations::{InvitationParams, MAX_INVITATIONS_PER_REQUEST},
+    survey::slug::{is_valid_slug, MAX_SLUG_LENGTH, MIN_SLUG_LENGTH},
+    user::account::{ChangePasswordParams, PasswordPolicy, RenameParams},
+    user::recovery::{EmailParams, ResetPasswordParams},
+    user::{is_valid_username, UserLoginParams, MAX_USERNAME_LENGTH, MIN_USERNAME_LENGTH},
+    webhook::{is_public_ip, WebhookConfig, WebhookParams},
 };
 
 pub trait Validate {
@@ -51,6 +57,242 @@ pub enum ValidationError {
         #[typeshare(serialized_as = "ValidationError")]
         inner: Box<Self>,
     },
+    #[error("Error in row {row}: {inner}")]
+    Row {
+        /// The 1-based row number of the imported record that failed validation.
+        row: u32,
+        #[typeshare(serialized_as = "ValidationError")]
+        inner: Box<Self>,
+    },
+}
+
+impl Validate for (&WebhookParams, &WebhookConfig) {
+    fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let (params, config) = self;
+        let mut errors = Vec::new();
+        match reqwest::Url::parse(&params.url) {
+            Ok(url) if !matches!(url.scheme(), "http" | "https") => {
+                errors.push(ValidationError::BadValue {
+                    field: "url".to_string(),
+                    message: "must be an http or https URL".to_string(),
+                })
+            }
+            // names are checked again once they're resolved, when sending the deliveries
+            Ok(url) if !config.allow_private_addresses && !has_public_host(&url) => {
+                errors.push(ValidationError::BadValue {
+                    field: "url".to_string(),
+                    message: "must not point to a loopback, private or link-local address"
+                        .to_string(),
+                })
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(ValidationError::BadValue {
+                field: "url".to_string(),
+                message: e.to_string(),
+            }),
+        }
+        if params.events.is_empty() {
+            errors.push(ValidationError::Required {
+                field: "events".to_string(),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Whether the host of the URL could be public, going by what's in the URL itself.
+fn has_public_host(url: &reqwest::Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    match host
+        .trim_matches(['[', ']'].as_slice())
+        .parse::<std::net::IpAddr>()
+    {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => host != "localhost" && !host.ends_with(".localhost"),
+    }
+}
+
+impl Validate for EmailParams {
+    fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        match self.email.trim().parse::<lettre::Address>() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(vec![ValidationError::BadValue {
+                field: "email".to_string(),
+                message: e.to_string(),
+            }]),
+        }
+    }
+}
+
+fn validate_username(field: &str, username: &str, errors: &mut Vec<ValidationError>) {
+    if is_valid_username(username) {
+        return;
+    }
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.len()) {
+        errors.push(ValidationError::NotInRange {
+            field: field.to_string(),
+            value: username.len().try_into().unwrap_or(i32::MAX),
+            min: MIN_USERNAME_LENGTH as i32,
+            max: MAX_USERNAME_LENGTH as i32,
+        });
+    } else {
+        errors.push(ValidationError::BadValue {
+            field: field.to_string(),
+            message: "may only contain letters, digits, `.`, `-` and `_`".to_string(),
+        });
+    }
+}
+
+fn validate_password(
+    field: &str,
+    password: &str,
+    policy: &PasswordPolicy,
+    errors: &mut Vec<ValidationError>,
+) {
+    let length = password.chars().count();
+    if length < policy.min_length {
+        errors.push(ValidationError::NotInRange {
+            field: field.to_string(),
+            value: length.try_into().unwrap_or(i32::MAX),
+            min: policy.min_length.try_into().unwrap_or(i32::MAX),
+            max: i32::MAX,
+        });
+    }
+    let mut require = |required: bool, matches: fn(char) -> bool, name: &str| {
+        if required && !password.chars().any(matches) {
+            errors.push(ValidationError::BadValue {
+                field: field.to_string(),
+                message: format!("must contain {name}"),
+            });
+        }
+    };
+    require(
+        policy.require_lowercase,
+        char::is_lowercase,
+        "a lowercase letter",
+    );
+    require(
+        policy.require_uppercase,
+        char::is_uppercase,
+        "an uppercase letter",
+    );
+    require(policy.require_digit, |c| c.is_ascii_digit(), "a digit");
+    require(policy.require_symbol, |c| !c.is_alphanumeric(), "a symbol");
+}
+
+impl Validate for (&UserLoginParams, &PasswordPolicy) {
+    fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let (params, policy) = self;
+        let mut errors = Vec::new();
+        validate_username("username", &params.username, &mut errors);
+        validate_password("password", &params.password, policy, &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Validate for RenameParams {
+    fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        validate_username("username", &self.username, &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Validate for (&ChangePasswordParams, &PasswordPolicy) {
+    fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let (params, policy) = self;
+        let mut errors = Vec::new();
+        validate_password("new_password", &params.new_password, policy, &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Validate for (&ResetPasswordParams, &PasswordPolicy) {
+    fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let (params, policy) = self;
+        let mut errors = Vec::new();
+        validate_password("new_password", &params.new_password, policy, &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Validate for InvitationParams {
+    fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let count = self.count.unwrap_or(0);
+        if count < 0 {
+            errors.push(ValidationError::NotInRange {
+                field: "count".to_string(),
+                value: count,
+                min: 0,
+                max: MAX_INVITATIONS_PER_REQUEST,
+            });
+        }
+        let total = (self.invitees.len() as i32).saturating_add(count.max(0));
+        if total == 0 {
+            errors.push(ValidationError::Required {
+                field: "invitees".to_string(),
+            });
+        } else if total > MAX_INVITATIONS_PER_REQUEST {
+            errors.push(ValidationError::NotInRange {
+                field: "invitees".to_string(),
+                value: total,
+                min: 1,
+                max: MAX_INVITATIONS_PER_REQUEST,
+            });
+        }
+        if let Some(max_uses) = self.max_uses {
+            if max_uses < 1 {
+                errors.push(ValidationError::NotInRange {
+                    field: "max_uses".to_string(),
+                    value: max_uses,
+                    min: 1,
+                    max: i32::MAX,
+                });
+            }
+        }
+        for email in self.invitees.iter().filter_map(|i| i.email.as_deref()) {
+            if let Err(e) = email.parse::<lettre::Address>() {
+                errors.push(ValidationError::BadValue {
+                    field: "email".to_string(),
+                    message: e.to_string(),
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 impl Validate for SurveyPatch {
@@ -63,6 +305,34 @@ impl Validate for SurveyPatch {
                 });
             }
         }
+        if let Some(slug) = &self.slug {
+            if !is_valid_slug(slug) {
+                errors.push(ValidationError::BadValue {
+                    field: "slug".to_string(),
+                    message: format!(
+                        "must be {MIN_SLUG_LENGTH} to {MAX_SLUG_LENGTH} lowercase letters, \
+                        digits or dashes, not all digits, and can't start or end with a dash"
+                    ),
+                });
+            }
+        }
+        if let Some(Some(password)) = &self.access_password {
+            if password.is_empty() {
+                errors.push(ValidationError::Required {
+                    field: "access_password".to_string(),
+                });
+            }
+        }
+        if let Some(Some(limit)) = self.response_limit {
+            if limit < 1 {
+                errors.push(ValidationError::NotInRange {
+                    field: "response_limit".to_string(),
+                    value: limit,
+                    min: 1,
+                    max: i32::MAX,
+                });
+            }
+        }
         if let Some(questions) = &self.questions {
             let mut question_uuids = Vec::new();
             for question in &questions.0 {
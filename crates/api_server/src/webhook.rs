@@ -0,0 +1,659 @@
+use std::net::IpAddr;
+
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use password_hash::rand_core::{OsRng, RngCore};
+use rocket::{http::Status, response::status::Created, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+    api::ApiErrorResponse,
+    db::{
+        models::{NewWebhook, NewWebhookDelivery, Webhook, WebhookDelivery, WebhookEvent},
+        schema, Storage,
+    },
+    jwt::Claims,
+    survey::{check_survey_owner, OwnerCheckError},
+    validate::{Validate, ValidationError},
+};
+
+pub(crate) mod worker;
+
+pub use worker::{stage, WebhookConfig, WebhookWorker};
+
+/// How many deliveries are returned by [`list_webhook_deliveries`].
+const DELIVERY_LOG_LIMIT: i64 = 100;
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum WebhookError {
+    #[error("Survey not found")]
+    SurveyNotFound,
+    #[error("Webhook not found")]
+    WebhookNotFound,
+    #[error("Not survey owner")]
+    NotSurveyOwner,
+    #[error("Validation error")]
+    ValidationError(Vec<ValidationError>),
+    #[error("Internal error")]
+    Unknown,
+}
+
+impl From<WebhookError> for ApiErrorResponse<WebhookError> {
+    fn from(value: WebhookError) -> Self {
+        let status = match &value {
+            WebhookError::SurveyNotFound => Status::NotFound,
+            WebhookError::WebhookNotFound => Status::NotFound,
+            WebhookError::NotSurveyOwner => Status::Forbidden,
+            WebhookError::ValidationError(_) => Status::UnprocessableEntity,
+            WebhookError::Unknown => Status::InternalServerError,
+        };
+        ApiErrorResponse {
+            status,
+            message: value,
+        }
+    }
+}
+
+impl From<OwnerCheckError> for ApiErrorResponse<WebhookError> {
+    fn from(value: OwnerCheckError) -> Self {
+        match value {
+            OwnerCheckError::NotFound => WebhookError::SurveyNotFound,
+            OwnerCheckError::NotOwner => WebhookError::NotSurveyOwner,
+            OwnerCheckError::Unknown => WebhookError::Unknown,
+        }
+        .into()
+    }
+}
+
+impl From<Vec<ValidationError>> for ApiErrorResponse<WebhookError> {
+    fn from(value: Vec<ValidationError>) -> Self {
+        WebhookError::ValidationError(value).into()
+    }
+}
+
+/// Whether the address is on the public internet. Webhooks can't be sent anywhere else,
+/// so that they can't be used to reach the network the server is in.
+pub(crate) fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "this network" and shared address space
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local and link-local
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The body of a request to subscribe to the events of a survey.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookParams {
+    pub url: String,
+    /// Used to sign the deliveries. A random one is generated if it's left out.
+    pub secret: Option<String>,
+    pub events: Vec<WebhookEvent>,
+}
+
+/// The body POSTed to a webhook for every event it is subscribed to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload<T> {
+    pub event: WebhookEvent,
+    pub survey_id: i32,
+    pub occurred_at: chrono::DateTime<chrono::Utc>,
+    pub data: T,
+}
+
+#[post("/survey/<survey_id>/webhooks", data = "<params>")]
+pub async fn create_webhook(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+    params: Json<WebhookParams>,
+    config: &State<WebhookConfig>,
+) -> Result<Created<Json<Webhook>>, ApiErrorResponse<WebhookError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    (&*params, config.inner()).validate()?;
+
+    let params = params.into_inner();
+    let new_webhook = NewWebhook {
+        survey_id,
+        url: params.url,
+        secret: params
+            .secret
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(generate_secret),
+        events: params.events,
+    };
+    let webhook = db
+        .run(move |conn| {
+            diesel::insert_into(schema::webhooks::table)
+                .values(&new_webhook)
+                .get_result::<Webhook>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            WebhookError::Unknown
+        })?;
+
+    let resource_uri = uri!(list_webhook_deliveries(survey_id, webhook.id)).to_string();
+    Ok(Created::new(resource_uri).body(Json(webhook)))
+}
+
+#[get("/survey/<survey_id>/webhooks")]
+pub async fn list_webhooks(
+    survey_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<Vec<Webhook>>, ApiErrorResponse<WebhookError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let webhooks = db
+        .run(move |conn| {
+            schema::webhooks::table
+                .filter(schema::webhooks::survey_id.eq(survey_id))
+                .order(schema::webhooks::id.asc())
+                .load::<Webhook>(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            WebhookError::Unknown
+        })?;
+
+    // the secret is only ever shown when the webhook is created
+    Ok(Json(
+        webhooks
+            .into_iter()
+            .map(|webhook| Webhook {
+                secret: String::new(),
+                ..webhook
+            })
+            .collect(),
+    ))
+}
+
+#[delete("/survey/<survey_id>/webhooks/<webhook_id>")]
+pub async fn delete_webhook(
+    survey_id: i32,
+    webhook_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<()>, ApiErrorResponse<WebhookError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let deleted = db
+        .run(move |conn| {
+            diesel::delete(schema::webhooks::table)
+                .filter(schema::webhooks::id.eq(webhook_id))
+                .filter(schema::webhooks::survey_id.eq(survey_id))
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            WebhookError::Unknown
+        })?;
+    if deleted == 0 {
+        return Err(WebhookError::WebhookNotFound.into());
+    }
+
+    Ok(Json(()))
+}
+
+/// Lists the most recent deliveries of a webhook, newest first.
+#[get("/survey/<survey_id>/webhooks/<webhook_id>/deliveries")]
+pub async fn list_webhook_deliveries(
+    survey_id: i32,
+    webhook_id: i32,
+    claims: Claims,
+    db: Storage,
+) -> Result<Json<Vec<WebhookDelivery>>, ApiErrorResponse<WebhookError>> {
+    check_survey_owner(&db, survey_id, &claims).await?;
+
+    let deliveries = db
+        .run(move |conn| {
+            schema::webhooks::table
+                .filter(schema::webhooks::id.eq(webhook_id))
+                .filter(schema::webhooks::survey_id.eq(survey_id))
+                .select(schema::webhooks::id)
+                .first::<i32>(conn)
+                .optional()?
+                .map(|webhook_id| {
+                    schema::webhook_deliveries::table
+                        .filter(schema::webhook_deliveries::webhook_id.eq(webhook_id))
+                        .order(schema::webhook_deliveries::id.desc())
+                        .limit(DELIVERY_LOG_LIMIT)
+                        .load::<WebhookDelivery>(conn)
+                })
+                .transpose()
+        })
+        .await
+        .map_err(|e| {
+            error!("{e:?}");
+            WebhookError::Unknown
+        })?
+        .ok_or(WebhookError::WebhookNotFound)?;
+
+    Ok(Json(deliveries))
+}
+
+fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    hex::encode(secret)
+}
+
+/// Signs a delivery body with the secret of its webhook. Receivers should compare this to
+/// the `X-Webhook-Signature` header, minus its `sha256=` prefix.
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Queues a delivery of `data` for every webhook of the survey that is subscribed to the
+/// event. This should run in the same transaction as the change that caused the event, and
+/// the [`WebhookWorker`] should be woken up once it is committed.
+pub(crate) fn enqueue_event<T: Serialize>(
+    conn: &mut PgConnection,
+    survey_id: i32,
+    event: WebhookEvent,
+    data: &T,
+) -> QueryResult<usize> {
+    let webhook_ids = schema::webhooks::table
+        .filter(schema::webhooks::survey_id.eq(survey_id))
+        .filter(schema::webhooks::events.contains(vec![event]))
+        .select(schema::webhooks::id)
+        .load::<i32>(conn)?;
+    if webhook_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let payload = serde_json::to_value(WebhookPayload {
+        event,
+        survey_id,
+        occurred_at: chrono::Utc::now(),
+        data,
+    })
+    .map_err(|e| diesel::result::Error::SerializationError(e.into()))?;
+    let deliveries = webhook_ids
+        .into_iter()
+        .map(|webhook_id| NewWebhookDelivery {
+            webhook_id,
+            event,
+            payload: payload.clone(),
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(schema::webhook_deliveries::table)
+        .values(&deliveries)
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use rocket::local::blocking::Client;
+    use uuid::Uuid;
+
+    use crate::db::models::{DeliveryStatus, SurveyResponses};
+    use crate::survey::SurveyRef;
+    use crate::survey_response::ResponseAccepted;
+    use crate::test_helpers::*;
+
+    struct ReceivedRequest {
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    /// Starts a local HTTP server that answers every request with the next status code in
+    /// `statuses`, and sends what it received down the returned channel.
+    fn spawn_receiver(statuses: Vec<u16>) -> (String, mpsc::Receiver<ReceivedRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(statuses) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = HashMap::new();
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((name, value)) => {
+                            headers.insert(name.to_lowercase(), value.to_owned());
+                        }
+                        None => break,
+                    }
+                }
+                let mut body = vec![0; headers["content-length"].parse().unwrap()];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Whatever\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                )
+                .unwrap();
+                let _ = tx.send(ReceivedRequest { headers, body });
+            }
+        });
+        (url, rx)
+    }
+
+    fn create_test_webhook(
+        client: &Client,
+        token: &str,
+        survey_id: i32,
+        url: String,
+        events: Vec<WebhookEvent>,
+    ) -> Webhook {
+        let response = client
+            .post(uri!("/api", create_webhook(survey_id)).to_string())
+            .header(rocket::http::ContentType::JSON)
+            .header(rocket::http::Header::new("Authorization", token.to_owned()))
+            .body(
+                serde_json::to_vec(&WebhookParams {
+                    url,
+                    secret: Some("shh".to_owned()),
+                    events,
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), rocket::http::Status::Created);
+        response.into_json::<Webhook>().unwrap()
+    }
+
+    fn respond(client: &Client, survey_id: i32) -> ResponseAccepted {
+        client
+            .post(
+                uri!(
+                    "/api",
+                    crate::survey_response::create_survey_response(survey_id, _)
+                )
+                .to_string(),
+            )
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+            .dispatch()
+            .into_json::<ResponseAccepted>()
+            .unwrap()
+    }
+
+    fn list_deliveries(
+        client: &Client,
+        token: &str,
+        survey_id: i32,
+        webhook_id: i32,
+    ) -> Vec<WebhookDelivery> {
+        client
+            .get(uri!("/api", list_webhook_deliveries(survey_id, webhook_id)).to_string())
+            .header(rocket::http::Header::new("Authorization", token.to_owned()))
+            .dispatch()
+            .into_json::<Vec<WebhookDelivery>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_webhook_delivery() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("webhooks.allow_private_addresses", true));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            let (url, received) = spawn_receiver(vec![200, 200]);
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            let webhook = create_test_webhook(
+                &client,
+                &token,
+                survey_id,
+                url,
+                vec![WebhookEvent::SurveyPublished, WebhookEvent::ResponseCreated],
+            );
+            assert_eq!(webhook.secret, "shh");
+
+            publish_survey(&client, &token, survey_id);
+            let request = received.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(request.headers["x-webhook-event"], "survey.published");
+
+            let responder = respond(&client, survey_id).responder_uuid;
+            let request = received.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(request.headers["x-webhook-event"], "response.created");
+            assert_eq!(
+                request.headers["x-webhook-signature"],
+                format!("sha256={}", sign_payload("shh", &request.body))
+            );
+            let payload =
+                serde_json::from_slice::<WebhookPayload<serde_json::Value>>(&request.body).unwrap();
+            assert_eq!(payload.event, WebhookEvent::ResponseCreated);
+            assert_eq!(payload.survey_id, survey_id);
+            assert_eq!(payload.data["responder_uuid"], responder.to_string());
+
+            // not subscribed to updates
+            client
+                .patch(
+                    uri!(
+                        "/api",
+                        crate::survey_response::edit_survey_response(survey_id, responder)
+                    )
+                    .to_string(),
+                )
+                .header(rocket::http::ContentType::JSON)
+                .body(serde_json::to_vec(&SurveyResponses(HashMap::new())).unwrap())
+                .dispatch();
+
+            // the log is written after the request is answered, so give it a moment
+            let mut deliveries = vec![];
+            for _ in 0..50 {
+                deliveries = list_deliveries(&client, &token, survey_id, webhook.id);
+                if deliveries
+                    .iter()
+                    .all(|d| d.status == DeliveryStatus::Succeeded)
+                {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            assert_eq!(deliveries.len(), 2);
+            assert_eq!(deliveries[0].event, WebhookEvent::ResponseCreated);
+            assert_eq!(deliveries[0].status, DeliveryStatus::Succeeded);
+            assert_eq!(deliveries[0].response_status, Some(200));
+            assert_eq!(deliveries[1].event, WebhookEvent::SurveyPublished);
+        });
+    }
+
+    #[test]
+    fn test_webhook_retries() {
+        run_test_with_db(|db_name| {
+            let rocket = test_rocket(db_name);
+            let config = rocket
+                .figment()
+                .clone()
+                .merge(("webhooks.retry_delay", 0))
+                .merge(("webhooks.max_attempts", 2))
+                .merge(("webhooks.allow_private_addresses", true));
+            let client = Client::tracked(rocket.configure(config)).expect("valid rocket instance");
+
+            let (url, received) = spawn_receiver(vec![500, 500]);
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+            publish_survey(&client, &token, survey_id);
+            let webhook = create_test_webhook(
+                &client,
+                &token,
+                survey_id,
+                url,
+                vec![WebhookEvent::ResponseCreated],
+            );
+
+            respond(&client, survey_id);
+            let first = received.recv_timeout(Duration::from_secs(10)).unwrap();
+            let second = received.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(
+                first.headers["x-webhook-delivery"],
+                second.headers["x-webhook-delivery"]
+            );
+
+            let mut deliveries = vec![];
+            for _ in 0..50 {
+                deliveries = list_deliveries(&client, &token, survey_id, webhook.id);
+                if deliveries[0].status == DeliveryStatus::Failed {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            assert_eq!(deliveries.len(), 1);
+            assert_eq!(deliveries[0].status, DeliveryStatus::Failed);
+            assert_eq!(deliveries[0].attempts, 2);
+            assert_eq!(deliveries[0].response_status, Some(500));
+        });
+    }
+
+    #[test]
+    fn test_webhook_not_owner() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let token = create_test_user(&client);
+            let response = client
+                .get(uri!("/api", list_webhooks(survey_id)).to_string())
+                .header(rocket::http::Header::new("Authorization", token))
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Forbidden);
+        });
+    }
+
+    #[test]
+    fn test_webhook_validation() {
+        run_test_with_db(|db_name| {
+            let client = Client::tracked(test_rocket(db_name)).expect("valid rocket instance");
+
+            let token = create_test_user(&client);
+            let survey_id = make_survey(&client, &token);
+
+            let response = client
+                .post(uri!("/api", create_webhook(survey_id)).to_string())
+                .header(rocket::http::ContentType::JSON)
+                .header(rocket::http::Header::new("Authorization", token.clone()))
+                .body(
+                    serde_json::to_vec(&WebhookParams {
+                        url: "ftp://example.com".to_owned(),
+                        secret: None,
+                        events: vec![],
+                    })
+                    .unwrap(),
+                )
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::UnprocessableEntity);
+            let body = response.into_json::<serde_json::Value>().unwrap();
+            assert_eq!(
+                body["message"]["ValidationError"].as_array().unwrap().len(),
+                2
+            );
+
+            for url in [
+                "http://127.0.0.1:8000/hook",
+                "http://169.254.169.254/latest/meta-data",
+                "http://10.0.0.1/hook",
+                "http://[::1]/hook",
+                "http://localhost/hook",
+            ] {
+                let response = client
+                    .post(uri!("/api", create_webhook(survey_id)).to_string())
+                    .header(rocket::http::ContentType::JSON)
+                    .header(rocket::http::Header::new("Authorization", token.clone()))
+                    .body(
+                        serde_json::to_vec(&WebhookParams {
+                            url: url.to_owned(),
+                            secret: None,
+                            events: vec![WebhookEvent::ResponseCreated],
+                        })
+                        .unwrap(),
+                    )
+                    .dispatch();
+                assert_eq!(
+                    response.status(),
+                    rocket::http::Status::UnprocessableEntity,
+                    "{url}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_public_ips() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_deliveries_to_private_addresses_fail() {
+        let config = WebhookConfig::default();
+        rocket::tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async {
+                for url in ["http://127.0.0.1:8000/hook", "http://localhost:8000/hook"] {
+                    assert!(
+                        worker::delivery_client(&config, url).await.is_err(),
+                        "{url}"
+                    );
+                }
+            });
+    }
+}
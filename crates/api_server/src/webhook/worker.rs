@@ -0,0 +1,341 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::dsl::now;
+use diesel::prelude::*;
+use rocket::fairing::AdHoc;
+use rocket::tokio::sync::Notify;
+use rocket_sync_db_pools::ConnectionPool;
+use serde::Deserialize;
+
+use crate::config;
+use crate::db::models::{DeliveryStatus, WebhookEvent};
+use crate::db::{schema, Storage};
+use crate::webhook::{is_public_ip, sign_payload};
+
+/// How many deliveries are claimed at a time.
+const BATCH_SIZE: i64 = 10;
+/// Retries back off exponentially, but never wait longer than this.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Configured under `[default.webhooks]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Seconds between looking for due retries, when nothing wakes the worker up sooner.
+    pub poll_interval: u64,
+    /// Seconds to wait before the first retry. Each retry after that waits twice as long.
+    pub retry_delay: u64,
+    /// Deliveries are marked as failed after this many attempts.
+    pub max_attempts: i32,
+    /// Seconds to wait for the receiver to answer.
+    pub timeout: u64,
+    /// Lets webhooks point to loopback, private and link-local addresses. Only meant for
+    /// testing against local receivers, since otherwise anyone who owns a survey can make
+    /// the server send requests into its own network.
+    pub allow_private_addresses: bool,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: 10,
+            retry_delay: 30,
+            max_attempts: 8,
+            timeout: 10,
+            allow_private_addresses: false,
+        }
+    }
+}
+
+impl WebhookConfig {
+    fn retry_delay(&self, attempts: i32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1) as u32);
+        Duration::from_secs(self.retry_delay)
+            .saturating_mul(factor)
+            .min(MAX_RETRY_DELAY)
+    }
+
+    /// How long a claimed delivery is hidden from other workers. If the worker dies before
+    /// recording the outcome, the delivery is picked up again after this.
+    fn lease(&self) -> Duration {
+        Duration::from_secs(self.timeout * 2 + 60)
+    }
+}
+
+/// Handle to the background worker that sends webhook deliveries.
+pub struct WebhookWorker {
+    notify: Arc<Notify>,
+}
+
+impl WebhookWorker {
+    /// Lets the worker know there are new deliveries, instead of waiting for it to poll.
+    pub fn wake(&self) {
+        self.notify.notify_one();
+    }
+}
+
+/// A delivery that has been claimed by a worker, along with where to send it.
+#[derive(Debug, Queryable)]
+struct ClaimedDelivery {
+    id: i32,
+    event: WebhookEvent,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+#[derive(Debug)]
+enum DeliveryOutcome {
+    Delivered { status: u16 },
+    Failed { status: Option<u16>, error: String },
+}
+
+/// Claims the deliveries that are due, so that no other worker sends them at the same time.
+fn claim_due_deliveries(
+    conn: &mut PgConnection,
+    config: &WebhookConfig,
+) -> QueryResult<Vec<ClaimedDelivery>> {
+    use schema::webhook_deliveries::dsl::*;
+
+    let lease_ends_at = chrono::Utc::now() + chrono::Duration::from_std(config.lease()).unwrap();
+    conn.build_transaction().read_write().run(|conn| {
+        let claimed = webhook_deliveries
+            .inner_join(schema::webhooks::table)
+            .filter(status.eq(DeliveryStatus::Pending))
+            .filter(next_attempt_at.le(now))
+            .order(next_attempt_at.asc())
+            .limit(BATCH_SIZE)
+            .select((
+                id,
+                event,
+                payload,
+                attempts,
+                schema::webhooks::url,
+                schema::webhooks::secret,
+            ))
+            .for_update()
+            .skip_locked()
+            .load::<ClaimedDelivery>(conn)?;
+
+        diesel::update(webhook_deliveries)
+            .filter(id.eq_any(claimed.iter().map(|d| d.id).collect::<Vec<_>>()))
+            .set(next_attempt_at.eq(lease_ends_at))
+            .execute(conn)?;
+
+        Ok(claimed)
+    })
+}
+
+/// Makes a client that can only reach the receiver at `url` if it has a public address.
+///
+/// The name is resolved here and the client is pinned to the addresses that were checked,
+/// so that it can't resolve to something else by the time the request is sent. Redirects
+/// aren't followed, since they could point anywhere.
+pub(super) async fn delivery_client(
+    config: &WebhookConfig,
+    url: &str,
+) -> Result<reqwest::Client, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    let host = url.host_str().ok_or("the URL has no host")?;
+    let port = url.port_or_known_default().ok_or("the URL has no port")?;
+    let builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout))
+        .redirect(reqwest::redirect::Policy::none());
+
+    let builder = match host.trim_matches(['[', ']'].as_slice()).parse::<IpAddr>() {
+        Ok(ip) => {
+            if !config.allow_private_addresses && !is_public_ip(ip) {
+                return Err(format!("{ip} is not a public address"));
+            }
+            builder
+        }
+        Err(_) => {
+            let addrs = rocket::tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| format!("failed to resolve {host}: {e}"))?
+                .collect::<Vec<SocketAddr>>();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no addresses"));
+            }
+            if !config.allow_private_addresses {
+                if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+                    return Err(format!(
+                        "{host} resolves to {}, which is not public",
+                        addr.ip()
+                    ));
+                }
+            }
+            builder.resolve_to_addrs(host, &addrs)
+        }
+    };
+    builder.build().map_err(|e| e.to_string())
+}
+
+async fn send_delivery(config: &WebhookConfig, delivery: &ClaimedDelivery) -> DeliveryOutcome {
+    let body = match serde_json::to_vec(&delivery.payload) {
+        Ok(body) => body,
+        Err(e) => {
+            return DeliveryOutcome::Failed {
+                status: None,
+                error: e.to_string(),
+            }
+        }
+    };
+    let signature = format!("sha256={}", sign_payload(&delivery.secret, &body));
+    let http = match delivery_client(config, &delivery.url).await {
+        Ok(http) => http,
+        Err(error) => {
+            return DeliveryOutcome::Failed {
+                status: None,
+                error,
+            }
+        }
+    };
+
+    let response = http
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Event", delivery.event.as_str())
+        .header("X-Webhook-Delivery", delivery.id)
+        .header("X-Webhook-Signature", signature)
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => DeliveryOutcome::Delivered {
+            status: response.status().as_u16(),
+        },
+        Ok(response) => DeliveryOutcome::Failed {
+            status: Some(response.status().as_u16()),
+            error: format!("receiver answered with {}", response.status()),
+        },
+        Err(e) => DeliveryOutcome::Failed {
+            status: None,
+            error: e.to_string(),
+        },
+    }
+}
+
+/// Writes the outcome of an attempt to the delivery log, and schedules a retry if there
+/// are any attempts left.
+fn record_outcome(
+    conn: &mut PgConnection,
+    config: &WebhookConfig,
+    delivery: &ClaimedDelivery,
+    outcome: DeliveryOutcome,
+) -> QueryResult<()> {
+    use schema::webhook_deliveries::dsl::*;
+
+    let attempt = delivery.attempts + 1;
+    let target = webhook_deliveries.find(delivery.id);
+    match outcome {
+        DeliveryOutcome::Delivered { status: code } => diesel::update(target)
+            .set((
+                status.eq(DeliveryStatus::Succeeded),
+                attempts.eq(attempt),
+                response_status.eq(Some(code as i32)),
+                error.eq(None::<String>),
+            ))
+            .execute(conn)?,
+        DeliveryOutcome::Failed {
+            status: code,
+            error: message,
+        } => {
+            let (new_status, delay) = if attempt >= config.max_attempts {
+                (DeliveryStatus::Failed, Duration::ZERO)
+            } else {
+                (DeliveryStatus::Pending, config.retry_delay(attempt))
+            };
+            diesel::update(target)
+                .set((
+                    status.eq(new_status),
+                    attempts.eq(attempt),
+                    next_attempt_at
+                        .eq(chrono::Utc::now() + chrono::Duration::from_std(delay).unwrap()),
+                    response_status.eq(code.map(i32::from)),
+                    error.eq(Some(message)),
+                ))
+                .execute(conn)?
+        }
+    };
+
+    Ok(())
+}
+
+/// Sends deliveries until there are none left that are due.
+async fn drain(storage: &ConnectionPool<Storage, PgConnection>, config: &WebhookConfig) {
+    loop {
+        let Some(conn) = storage.get().await else {
+            return;
+        };
+        let claim_config = config.clone();
+        let claimed = match conn
+            .run(move |conn| claim_due_deliveries(conn, &claim_config))
+            .await
+        {
+            Ok(claimed) if claimed.is_empty() => return,
+            Ok(claimed) => claimed,
+            Err(e) => {
+                error!("failed to claim webhook deliveries: {e:?}");
+                return;
+            }
+        };
+        // don't hold on to a connection while waiting on the receivers
+        drop(conn);
+
+        for delivery in claimed {
+            let outcome = send_delivery(config, &delivery).await;
+            let Some(conn) = storage.get().await else {
+                return;
+            };
+            let record_config = config.clone();
+            if let Err(e) = conn
+                .run(move |conn| record_outcome(conn, &record_config, &delivery, outcome))
+                .await
+            {
+                error!("failed to record webhook delivery: {e:?}");
+            }
+        }
+    }
+}
+
+/// Sends webhook deliveries in the background, with retries.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Webhooks", |rocket| async {
+        let Some(config) = config::section::<WebhookConfig>(rocket.figment(), "webhooks") else {
+            return Err(rocket);
+        };
+        let notify = Arc::new(Notify::new());
+
+        Ok(rocket
+            .manage(config.clone())
+            .manage(WebhookWorker {
+                notify: notify.clone(),
+            })
+            .attach(AdHoc::on_liftoff("Webhook Worker", move |rocket| {
+                Box::pin(async move {
+                    let Some(pool) = Storage::pool(rocket).cloned() else {
+                        error!("database pool is not available, webhooks will not be delivered");
+                        return;
+                    };
+                    let mut shutdown = rocket.shutdown();
+
+                    rocket::tokio::spawn(async move {
+                        let poll_interval = Duration::from_secs(config.poll_interval);
+                        loop {
+                            drain(&pool, &config).await;
+                            rocket::tokio::select! {
+                                _ = notify.notified() => {},
+                                _ = rocket::tokio::time::sleep(poll_interval) => {},
+                                _ = &mut shutdown => break,
+                            }
+                        }
+                    });
+                })
+            })))
+    })
+}
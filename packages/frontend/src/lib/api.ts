@@ -11,10 +11,37 @@ import type {
 	UserLoginParams,
 	UserToken,
 	ValidationError,
-	ListedSurvey,
+	SurveyList,
 	SurveyResponses,
 	ResponseAccepted,
-	SurveyResponse
+	SurveyResponse,
+	SurveyQuestions,
+	QuestionOperation,
+	SurveyStatus,
+	Webhook,
+	WebhookDelivery,
+	WebhookParams,
+	LiveEvent,
+	NotificationParams,
+	NotificationPreferences,
+	CreatedInvitation,
+	Invitation,
+	InvitationParams,
+	SurveyAccessParams,
+	SurveyAccessToken,
+	QuarantinedResponse,
+	CollabEvent,
+	Collaborator,
+	CollaboratorParams,
+	SurveyHistoryEntry,
+	ResponseHistoryEntry,
+	ChangePasswordParams,
+	RenameParams,
+	DeleteAccountParams,
+	EmailParams,
+	VerifyEmailParams,
+	ResetPasswordParams,
+	OidcCallbackParams
 } from './common';
 import { jwt } from '../stores';
 import { browser } from '$app/environment';
@@ -89,6 +116,64 @@ async function apiReqAuth<T>(path: string, options?: ApiRequestOptions): Promise
 	});
 }
 
+/**
+ * Getting a survey tells when it was opened, and whether responses need a proof of work.
+ * Both are sent back with the response, so that it isn't quarantined as spam.
+ */
+function rememberChallenge(survey_id: number | string, headers: Headers) {
+	if (!browser) return;
+	const opened = headers.get('X-Survey-Opened');
+	if (opened) {
+		sessionStorage.setItem(`survey-opened-${survey_id}`, opened);
+	}
+	const bits = headers.get('X-Survey-Proof-Of-Work');
+	if (bits) {
+		sessionStorage.setItem(`survey-proof-of-work-${survey_id}`, bits);
+	} else {
+		sessionStorage.removeItem(`survey-proof-of-work-${survey_id}`);
+	}
+}
+
+async function challengeHeaders(
+	survey_id: number | string,
+	honeypot?: string
+): Promise<Record<string, string>> {
+	const headers: Record<string, string> = honeypot ? { 'X-Survey-Honeypot': honeypot } : {};
+	const opened = browser ? sessionStorage.getItem(`survey-opened-${survey_id}`) : null;
+	if (!opened) return headers;
+	headers['X-Survey-Opened'] = opened;
+	const bits = Number(sessionStorage.getItem(`survey-proof-of-work-${survey_id}`) ?? 0);
+	if (bits > 0) {
+		headers['X-Survey-Proof'] = await proveWork(opened, bits);
+	}
+	return headers;
+}
+
+/** Finds a nonce so that the SHA-256 hash of `<token>:<nonce>` starts with `bits` zero bits. */
+async function proveWork(token: string, bits: number): Promise<string> {
+	const encoder = new TextEncoder();
+	for (let nonce = 0; ; nonce++) {
+		const hash = new Uint8Array(
+			await crypto.subtle.digest('SHA-256', encoder.encode(`${token}:${nonce}`))
+		);
+		let zeros = 0;
+		for (const byte of hash) {
+			zeros += Math.clz32(byte) - 24;
+			if (byte !== 0) break;
+		}
+		if (zeros >= bits) return nonce.toString();
+	}
+}
+
+/**
+ * Tokens for password protected surveys are kept for the rest of the session, so the
+ * password is only asked for once.
+ */
+function surveyAccessHeaders(survey_id: number | string): Record<string, string> {
+	const token = browser ? sessionStorage.getItem(`survey-access-${survey_id}`) : null;
+	return token ? { 'X-Survey-Access': token } : {};
+}
+
 export async function loginUser(
 	params: UserLoginParams,
 	opts?: ExtraOptions
@@ -111,15 +196,163 @@ export async function registerUser(
 	});
 }
 
+/** Revokes every token of the user, the new one that is sent back takes their place. */
+export async function changePassword(
+	params: ChangePasswordParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<UserToken>> {
+	return apiReqAuth(`/api/user/password`, {
+		method: 'PUT',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function renameUser(
+	params: RenameParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/user/username`, {
+		method: 'PUT',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function deleteAccount(
+	params: DeleteAccountParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/user`, {
+		method: 'DELETE',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+/** Sends a link to verify `email`, which becomes the address of the account once followed. */
+export async function setEmail(
+	params: EmailParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/user/email`, {
+		method: 'PUT',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function verifyEmail(
+	params: VerifyEmailParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReq(`/api/user/email/verify`, {
+		method: 'POST',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function requestPasswordReset(
+	params: EmailParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReq(`/api/user/password/reset`, {
+		method: 'POST',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function resetPassword(
+	params: ResetPasswordParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<UserToken>> {
+	return apiReq(`/api/user/password/reset/confirm`, {
+		method: 'POST',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+/**
+ * Where to send the browser to log in through the OpenID Connect provider. It comes back
+ * to the configured `redirect_uri`, which should pass `code` and `state` to
+ * {@link oidcCallback}.
+ */
+export function oidcLoginUrl(): string {
+	return `${API_URL}/api/user/oidc/login`;
+}
+
+export async function oidcCallback(
+	params: OidcCallbackParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<UserToken>> {
+	return apiReq(`/api/user/oidc/callback`, {
+		method: 'POST',
+		// the login has to be finished in the browser that started it, which the cookie shows
+		credentials: 'include',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
 export async function getSurvey(
-	survey_id: number,
+	survey_id: number | string,
 	opts?: ExtraOptions
 ): Promise<ApiResponse<Survey>> {
-	return apiReq(`/api/survey/${survey_id}`, { ...opts });
+	const resp = await apiReq<Response>(`/api/survey/${survey_id}`, {
+		headers: surveyAccessHeaders(survey_id),
+		...opts,
+		raw: true
+	});
+	if (!resp.ok) {
+		return resp;
+	}
+	rememberChallenge(survey_id, resp.value.headers);
+	return { ok: true, value: await resp.value.json() };
 }
 
-export async function getSurveyList(opts?: ExtraOptions): Promise<ApiResponse<ListedSurvey[]>> {
-	return apiReqAuth(`/api/user/surveys`, { ...opts });
+export async function requestSurveyAccess(
+	survey_id: number | string,
+	params: SurveyAccessParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<SurveyAccessToken>> {
+	const resp = await apiReq<SurveyAccessToken>(`/api/survey/${survey_id}/access`, {
+		method: 'POST',
+		body: JSON.stringify(params),
+		...opts
+	});
+	if (resp.ok && browser) {
+		sessionStorage.setItem(`survey-access-${survey_id}`, resp.value.token);
+	}
+	return resp;
+}
+
+export interface SurveyListQuery {
+	q?: string;
+	published?: boolean;
+	status?: SurveyStatus;
+	archived?: boolean;
+	trashed?: boolean;
+	sort?: 'created' | 'updated';
+	order?: 'asc' | 'desc';
+	cursor?: string;
+	limit?: number;
+}
+
+export async function getSurveyList(
+	query?: SurveyListQuery,
+	opts?: ExtraOptions
+): Promise<ApiResponse<SurveyList>> {
+	const params = new URLSearchParams();
+	for (const [key, value] of Object.entries(query ?? {})) {
+		if (value !== undefined) {
+			params.set(key, String(value));
+		}
+	}
+	const search = params.toString();
+	return apiReqAuth(`/api/user/surveys${search ? `?${search}` : ''}`, { ...opts });
 }
 
 export async function getSurveyAuth(
@@ -145,6 +378,24 @@ export async function editSurvey(
 	});
 }
 
+export async function getSurveyHistory(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<SurveyHistoryEntry[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/history`, opts);
+}
+
+export async function revertSurvey(
+	survey_id: number,
+	entry_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/history/${entry_id}/revert`, {
+		method: 'POST',
+		...opts
+	});
+}
+
 export async function deleteSurvey(
 	survey_id: number,
 	opts?: ExtraOptions
@@ -152,38 +403,63 @@ export async function deleteSurvey(
 	return apiReqAuth(`/api/survey/${survey_id}`, { method: 'DELETE', ...opts });
 }
 
-export async function createSurveyResponse(
+export async function restoreSurvey(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/restore`, { method: 'POST', ...opts });
+}
+
+export async function purgeSurvey(
 	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/purge`, { method: 'DELETE', ...opts });
+}
+
+export async function createSurveyResponse(
+	survey_id: number | string,
 	responses: SurveyResponses,
+	invitation?: string,
+	honeypot?: string,
 	opts?: ExtraOptions
 ): Promise<ApiResponse<ResponseAccepted>> {
-	return apiReq(`/api/survey/${survey_id}/respond`, {
+	const query = invitation ? `?invitation=${encodeURIComponent(invitation)}` : '';
+	return apiReq(`/api/survey/${survey_id}/respond${query}`, {
 		method: 'POST',
+		headers: {
+			...surveyAccessHeaders(survey_id),
+			...(await challengeHeaders(survey_id, honeypot))
+		},
+		// the respondent cookie is how duplicate responses are recognized
+		credentials: 'include',
 		body: JSON.stringify(responses),
 		...opts
 	});
 }
 
 export async function editSurveyResponse(
-	survey_id: number,
+	survey_id: number | string,
 	responder: string,
 	responses: SurveyResponses,
 	opts?: ExtraOptions
 ): Promise<ApiResponse<null>> {
 	return apiReq(`/api/survey/${survey_id}/respond?responder=${responder}`, {
 		method: 'PATCH',
+		headers: surveyAccessHeaders(survey_id),
 		body: JSON.stringify(responses),
 		...opts
 	});
 }
 
 export async function getSurveyResponse(
-	survey_id: number,
+	survey_id: number | string,
 	responder: string,
 	opts?: ExtraOptions
 ): Promise<ApiResponse<SurveyResponse>> {
 	return apiReq(`/api/survey/${survey_id}/respond?responder=${responder}`, {
 		method: 'GET',
+		headers: surveyAccessHeaders(survey_id),
 		...opts
 	});
 }
@@ -195,16 +471,49 @@ export async function clearSurveyResponses(
 	return apiReqAuth(`/api/survey/${survey_id}/respond`, { method: 'DELETE', ...opts });
 }
 
+export async function importQuestions(
+	survey_id: number,
+	csv: string,
+	opts?: ExtraOptions
+): Promise<ApiResponse<SurveyQuestions>> {
+	return apiReqAuth(`/api/survey/${survey_id}/import`, {
+		method: 'POST',
+		headers: { 'Content-Type': 'text/csv' },
+		body: csv,
+		...opts
+	});
+}
+
+export async function editQuestions(
+	survey_id: number,
+	operations: QuestionOperation[],
+	session?: string,
+	opts?: ExtraOptions
+): Promise<ApiResponse<SurveyQuestions>> {
+	const query = session ? `?session=${session}` : '';
+	return apiReqAuth(`/api/survey/${survey_id}/questions${query}`, {
+		method: 'PATCH',
+		body: JSON.stringify(operations),
+		...opts
+	});
+}
+
 interface ExportResponse {
 	blob: Blob;
 	filename: string;
 }
 
+/**
+ * Responses that were edited are exported as last submitted, unless `submission` is
+ * 'first'.
+ */
 export async function exportResponses(
 	survey_id: number,
+	submission?: 'first' | 'latest',
 	opts?: ExtraOptions
 ): Promise<ApiResponse<ExportResponse>> {
-	const resp = await apiReqAuth<Response>(`/api/survey/${survey_id}/export`, {
+	const search = submission ? `?submission=${submission}` : '';
+	const resp = await apiReqAuth<Response>(`/api/survey/${survey_id}/export${search}`, {
 		raw: true,
 		...opts
 	});
@@ -215,3 +524,278 @@ export async function exportResponses(
 	}
 	return resp;
 }
+
+export async function createWebhook(
+	survey_id: number,
+	params: WebhookParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<Webhook>> {
+	return apiReqAuth(`/api/survey/${survey_id}/webhooks`, {
+		method: 'POST',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function listWebhooks(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<Webhook[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/webhooks`, { ...opts });
+}
+
+export async function deleteWebhook(
+	survey_id: number,
+	webhook_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/webhooks/${webhook_id}`, {
+		method: 'DELETE',
+		...opts
+	});
+}
+
+export async function listWebhookDeliveries(
+	survey_id: number,
+	webhook_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<WebhookDelivery[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/webhooks/${webhook_id}/deliveries`, { ...opts });
+}
+
+export async function createInvitations(
+	survey_id: number,
+	params: InvitationParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<CreatedInvitation[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/invitations`, {
+		method: 'POST',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function listInvitations(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<Invitation[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/invitations`, { ...opts });
+}
+
+export async function revokeInvitation(
+	survey_id: number,
+	invitation_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/invitations/${invitation_id}`, {
+		method: 'DELETE',
+		...opts
+	});
+}
+
+export async function getNotifications(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<NotificationPreferences>> {
+	return apiReqAuth(`/api/survey/${survey_id}/notifications`, { ...opts });
+}
+
+export async function setNotifications(
+	survey_id: number,
+	params: NotificationParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<NotificationPreferences>> {
+	return apiReqAuth(`/api/survey/${survey_id}/notifications`, {
+		method: 'PUT',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function deleteNotifications(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/notifications`, {
+		method: 'DELETE',
+		...opts
+	});
+}
+
+/** Reads a server-sent event stream, calling `onEvent` with the name and data of each event. */
+async function readEvents(
+	resp: Response,
+	onEvent: (name: string, data: string | undefined) => void,
+	signal: AbortSignal
+) {
+	const reader = resp.body?.pipeThrough(new TextDecoderStream()).getReader();
+	let buffer = '';
+	try {
+		while (reader) {
+			const { value, done } = await reader.read();
+			if (done) break;
+			buffer += value;
+			let end;
+			while ((end = buffer.indexOf('\n\n')) !== -1) {
+				const lines = buffer.slice(0, end).split('\n');
+				buffer = buffer.slice(end + 2);
+				const name = lines.find((l) => l.startsWith('event:'))?.slice(6).trim();
+				const data = lines.find((l) => l.startsWith('data:'))?.slice(5).trim();
+				if (name) onEvent(name, data);
+			}
+		}
+	} catch (e) {
+		if (!signal.aborted) throw e;
+	}
+}
+
+/**
+ * Calls `onEvent` whenever a response to the survey is created, edited or cleared, until
+ * `signal` is aborted. `EventSource` can't send the Authorization header, so the stream is
+ * read with fetch instead. `onEvent` gets `null` if events were missed.
+ */
+export async function watchSurveyResponses(
+	survey_id: number,
+	onEvent: (event: LiveEvent | null) => void,
+	signal: AbortSignal,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	const resp = await apiReqAuth<Response>(`/api/survey/${survey_id}/live`, {
+		raw: true,
+		signal,
+		...opts
+	});
+	if (!resp.ok) {
+		return resp;
+	}
+	await readEvents(
+		resp.value,
+		(name, data) => {
+			if (name === 'lagged') {
+				onEvent(null);
+			} else if (data) {
+				onEvent(JSON.parse(data));
+			}
+		},
+		signal
+	);
+	return { ok: true, value: null };
+}
+
+/**
+ * Opens the survey in the editor as `session` until `signal` is aborted. `onEvent` first
+ * gets a snapshot, then every change to the questions and the other editors. It gets
+ * `null` if events were missed, and the editor should be opened again.
+ */
+export async function collaborate(
+	survey_id: number,
+	session: string,
+	onEvent: (event: CollabEvent | null) => void,
+	signal: AbortSignal,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	const resp = await apiReqAuth<Response>(
+		`/api/survey/${survey_id}/collaborate?session=${session}`,
+		{
+			raw: true,
+			signal,
+			...opts
+		}
+	);
+	if (!resp.ok) {
+		return resp;
+	}
+	await readEvents(
+		resp.value,
+		(name, data) => {
+			if (name === 'lagged') {
+				onEvent(null);
+			} else if (data) {
+				onEvent(JSON.parse(data));
+			}
+		},
+		signal
+	);
+	return { ok: true, value: null };
+}
+
+/** Tells the other editors of the survey which question `session` is working on. */
+export async function focusQuestion(
+	survey_id: number,
+	session: string,
+	question: string | null,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/collaborate?session=${session}`, {
+		method: 'PUT',
+		body: JSON.stringify({ question }),
+		...opts
+	});
+}
+
+export async function listCollaborators(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<Collaborator[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/collaborators`, opts);
+}
+
+export async function addCollaborator(
+	survey_id: number,
+	params: CollaboratorParams,
+	opts?: ExtraOptions
+): Promise<ApiResponse<Collaborator>> {
+	return apiReqAuth(`/api/survey/${survey_id}/collaborators`, {
+		method: 'POST',
+		body: JSON.stringify(params),
+		...opts
+	});
+}
+
+export async function removeCollaborator(
+	survey_id: number,
+	user_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/collaborators/${user_id}`, {
+		method: 'DELETE',
+		...opts
+	});
+}
+
+export async function getResponseHistory(
+	survey_id: number,
+	responder: string,
+	opts?: ExtraOptions
+): Promise<ApiResponse<ResponseHistoryEntry[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/respond/history?responder=${responder}`, opts);
+}
+
+export async function listQuarantinedResponses(
+	survey_id: number,
+	opts?: ExtraOptions
+): Promise<ApiResponse<QuarantinedResponse[]>> {
+	return apiReqAuth(`/api/survey/${survey_id}/quarantine`, opts);
+}
+
+export async function releaseQuarantinedResponse(
+	survey_id: number,
+	responder: string,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/quarantine/${responder}/release`, {
+		method: 'POST',
+		...opts
+	});
+}
+
+export async function discardQuarantinedResponse(
+	survey_id: number,
+	responder: string,
+	opts?: ExtraOptions
+): Promise<ApiResponse<null>> {
+	return apiReqAuth(`/api/survey/${survey_id}/quarantine/${responder}`, {
+		method: 'DELETE',
+		...opts
+	});
+}
@@ -15,6 +15,19 @@ export interface SurveyQuestion {
 
 export type SurveyQuestions = SurveyQuestion[];
 
+/**
+ * A change to a single question, which refers to the questions by UUID so that changes
+ * to different questions can be applied in any order.
+ */
+export type QuestionOperation =
+	/** Inserts a question at `index`, or appends it when there is none. */
+	| { type: 'Add'; content: { question: SurveyQuestion; index?: number } }
+	/** Moves a question to `index`, counted without the question itself. */
+	| { type: 'Move'; content: { uuid: string; index: number } }
+	/** Replaces the question with the same UUID, keeping its place. */
+	| { type: 'Update'; content: { question: SurveyQuestion } }
+	| { type: 'Delete'; content: { uuid: string } };
+
 export type Response =
 	| { type: 'Text'; content: RText }
 	| { type: 'Rating'; content: RRating }
@@ -26,32 +39,116 @@ export interface ApiErrorResponse<R> {
 	message: R;
 }
 
+/**
+ * Where a survey is in its lifecycle.
+ *
+ * A survey starts out as a draft, is opened to start collecting responses, then closed
+ * once it's done. Closed surveys can still be viewed and exported, and can be archived
+ * to hide them from the list of surveys.
+ */
+export enum SurveyStatus {
+	Draft = 'draft',
+	Open = 'open',
+	Closed = 'closed',
+	Archived = 'archived'
+}
+
+/** How to tell that a response comes from someone who already responded to the survey. */
+export enum DuplicateCheck {
+	Off = 'off',
+	/** A signed cookie that is set on the first response. */
+	Cookie = 'cookie',
+	/** A hash of the address and user agent of the respondent. */
+	Fingerprint = 'fingerprint',
+	/** Either of the above. */
+	Both = 'both'
+}
+
 export interface Survey {
 	id: number;
+	/** Used in public links instead of the id, so that surveys can't be enumerated. */
+	slug: string;
 	title: string;
 	description: string;
+	/** Kept for older clients, this is true for any survey that is not a draft. */
 	published: boolean;
+	status: SurveyStatus;
 	owner_id: number;
 	questions: SurveyQuestions;
 	created_at: string;
 	updated_at: string;
+	/** The survey is closed once it has this many responses. */
+	response_limit?: number;
+	/** Responding requires an invitation token. */
+	invite_only: boolean;
+	/** Respondents have to exchange the access password for a token first. */
+	password_protected: boolean;
+	/** How repeat responses from the same respondent are recognized. */
+	duplicate_check: DuplicateCheck;
+	/** Whether repeat responses are rejected, instead of only being flagged. */
+	reject_duplicates: boolean;
+	/** Goes up by one with every change. */
+	revision: number;
+	/** Only included when the survey is requested by its owner. */
+	response_count?: number;
+	/** Only included when the survey is requested by its owner. */
+	last_response_at?: string;
 }
 
 /** Represents a partial update to a survey */
 export interface SurveyPatch {
 	title?: string;
 	description?: string;
+	/**
+	 * Kept for older clients, publishing opens the survey and unpublishing turns it back
+	 * into a draft. Ignored if `status` is set.
+	 */
 	published?: boolean;
+	status?: SurveyStatus;
 	questions?: SurveyQuestions;
+	/** Set to `null` to remove the limit. */
+	response_limit?: number;
+	invite_only?: boolean;
+	slug?: string;
+	/** Set to `null` to remove the password. */
+	access_password?: string;
+	duplicate_check?: DuplicateCheck;
+	reject_duplicates?: boolean;
+}
+
+export interface SurveyAccessParams {
+	password: string;
+}
+
+export interface SurveyAccessToken {
+	/** Sent back in the `X-Survey-Access` header. */
+	token: string;
+	/** Expiration time (as UTC timestamp) */
+	exp: number;
 }
 
 /** Used to list surveys, like on the page where you can see all your surveys */
 export interface ListedSurvey {
 	id: number;
+	slug: string;
 	title: string;
 	description: string;
+	/** Kept for older clients, this is true for any survey that is not a draft. */
 	published: boolean;
+	status: SurveyStatus;
 	owner_id: number;
+	created_at: string;
+	updated_at: string;
+	response_count: number;
+	last_response_at?: string;
+	/** When the survey was moved to the trash, if it has been. */
+	deleted_at?: string;
+}
+
+/** A page of surveys, along with the cursor to use to get the next page. */
+export interface SurveyList {
+	surveys: ListedSurvey[];
+	next_cursor?: string;
 }
 
 export interface SurveyResponse {
@@ -60,6 +157,22 @@ export interface SurveyResponse {
 	content: SurveyResponses;
 	created_at: string;
 	updated_at: string;
+	/** The invitation that was used to respond, if any. */
+	invitation_id?: number;
+	/** Goes up by one with every change. */
+	revision: number;
+}
+
+/**
+ * A response that failed a challenge, waiting for the owner of the survey to release or
+ * discard it.
+ */
+export interface QuarantinedResponse {
+	responder_uuid: string;
+	content: SurveyResponses;
+	created_at: string;
+	/** Why the response looked like spam. */
+	reason: string;
 }
 
 export interface QText {
@@ -99,6 +212,149 @@ export interface RMultipleChoice {
 	selected: string[];
 }
 
+/** Something that happened to a survey that webhooks can subscribe to. */
+export enum WebhookEvent {
+	ResponseCreated = 'response.created',
+	ResponseUpdated = 'response.updated',
+	SurveyPublished = 'survey.published'
+}
+
+/** A subscription to the events of a survey, which are POSTed to `url`. */
+export interface Webhook {
+	id: number;
+	survey_id: number;
+	url: string;
+	/** Only included when the webhook is created. */
+	secret?: string;
+	events: WebhookEvent[];
+	created_at: string;
+	updated_at: string;
+}
+
+export enum DeliveryStatus {
+	/** Waiting for its first attempt, or for a retry. */
+	Pending = 'pending',
+	Succeeded = 'succeeded',
+	/** Gave up after too many attempts. */
+	Failed = 'failed'
+}
+
+/** An entry in the delivery log of a webhook. */
+export interface WebhookDelivery {
+	id: number;
+	webhook_id: number;
+	event: WebhookEvent;
+	payload: any;
+	status: DeliveryStatus;
+	attempts: number;
+	next_attempt_at: string;
+	/** The HTTP status code of the last attempt, if it got that far. */
+	response_status?: number;
+	/** Why the last attempt failed. */
+	error?: string;
+	created_at: string;
+	updated_at: string;
+}
+
+/** The body of a request to subscribe to the events of a survey. */
+export interface WebhookParams {
+	url: string;
+	/** Used to sign the deliveries. A random one is generated if it's left out. */
+	secret?: string;
+	events: WebhookEvent[];
+}
+
+/** How often the owner of a survey is emailed about new responses. */
+export enum DigestFrequency {
+	Off = 'off',
+	Hourly = 'hourly',
+	Daily = 'daily'
+}
+
+/** What the owner of a survey wants to be emailed about. */
+export interface NotificationPreferences {
+	survey_id: number;
+	digest: DigestFrequency;
+	/** Whether to send an email when the survey reaches its response limit. */
+	limit_alert: boolean;
+	/** The next digest covers the responses created after this. */
+	last_digest_at: string;
+	created_at: string;
+	updated_at: string;
+}
+
+/**
+ * The body of a request to set up the notifications of a survey. Notifications are sent
+ * to the verified email address of the owner's account.
+ */
+export interface NotificationParams {
+	/** Digests are off unless this is set. */
+	digest?: DigestFrequency;
+	/** Defaults to true. */
+	limit_alert?: boolean;
+}
+
+/** A token that lets someone respond to an invite-only survey, a limited number of times. */
+export interface Invitation {
+	id: number;
+	survey_id: number;
+	/** Shown in the export, to tell who responded. */
+	label?: string;
+	email?: string;
+	max_uses: number;
+	uses: number;
+	/** Revoked invitations can't be used anymore. */
+	revoked_at?: string;
+	created_at: string;
+	updated_at: string;
+}
+
+/**
+ * A new invitation, along with its token. Only a hash of the token is kept, so this is
+ * the only time it can be seen.
+ */
+export interface CreatedInvitation {
+	token: string;
+	invitation: Invitation;
+}
+
+/**
+ * Someone to invite. Both fields are only there to help the owner keep track of who
+ * responded, nothing is sent to the email address.
+ */
+export interface Invitee {
+	label?: string;
+	email?: string;
+}
+
+/** The body of a request to create a batch of invitations. */
+export interface InvitationParams {
+	/** One invitation is created for each invitee. */
+	invitees?: Invitee[];
+	/** How many invitations to create that aren't tied to anyone, on top of the invitees. */
+	count?: number;
+	/** How many responses each invitation can be used for. Defaults to 1. */
+	max_uses?: number;
+}
+
+export enum LiveEventKind {
+	ResponseCreated = 'response.created',
+	ResponseUpdated = 'response.updated',
+	ResponsesCleared = 'responses.cleared'
+}
+
+/**
+ * Something that happened to the responses of a survey. Only ids are sent, so that
+ * events stay well under the size limit of a NOTIFY payload.
+ */
+export interface LiveEvent {
+	event: LiveEventKind;
+	survey_id: number;
+	/** Not set when the responses are cleared. */
+	responder_uuid?: string;
+	occurred_at: string;
+}
+
 export interface ResponseAccepted {
 	responder_uuid: string;
 }
@@ -112,6 +368,49 @@ export interface UserToken {
 	token: string;
 }
 
+export interface ChangePasswordParams {
+	current_password: string;
+	new_password: string;
+}
+
+export interface RenameParams {
+	username: string;
+}
+
+export interface EmailParams {
+	email: string;
+}
+
+export interface VerifyEmailParams {
+	token: string;
+}
+
+export interface ResetPasswordParams {
+	token: string;
+	new_password: string;
+}
+
+/** What the frontend got back from the provider. */
+export interface OidcCallbackParams {
+	code: string;
+	state: string;
+}
+
+/** What happens to the surveys of a deleted account. */
+export enum SurveyDisposal {
+	/** They are given to the account in `transfer_to`, along with their responses. */
+	Transfer = 'transfer',
+	/** They are deleted along with the account, and can't be restored. */
+	Delete = 'delete'
+}
+
+export interface DeleteAccountParams {
+	password: string;
+	surveys: SurveyDisposal;
+	/** The username of the account to transfer the surveys to. */
+	transfer_to?: string;
+}
+
 export type ValidationError =
 	| {
 			type: 'Required';
@@ -164,4 +463,114 @@ export type ValidationError =
 				uuid: string;
 				inner: ValidationError;
 			};
+	  }
+	| {
+			type: 'Row';
+			data: {
+				/** The 1-based row number of the imported record that failed validation. */
+				row: number;
+				inner: ValidationError;
+			};
 	  };
+
+/**
+ * Someone with the survey open in the editor. Every open editor is its own session, so
+ * the same account can edit from several places at once.
+ */
+export interface Editor {
+	session: string;
+	user_id: number;
+	username: string;
+	/** The question the editor is working on, if any. */
+	question?: string;
+}
+
+/** Sent to everyone editing a survey through `collaborate`. */
+export type CollabEvent =
+	/** Sent first, with everything needed to start editing. */
+	| {
+			type: 'Snapshot';
+			content: { questions: SurveyQuestions; revision: number; editors: Editor[] };
+	  }
+	/**
+	 * The questions were changed, through `edit_questions` or by replacing them.
+	 * Editors can drop changes with a revision they already have.
+	 */
+	| {
+			type: 'Questions';
+			content: {
+				/** The editor that made the change, if it came from one. */
+				session?: string;
+				/** Empty when the questions were replaced as a whole. */
+				operations: QuestionOperation[];
+				questions: SurveyQuestions;
+				revision: number;
+			};
+	  }
+	/** An editor joined or moved to another question. */
+	| { type: 'Presence'; content: Editor }
+	| { type: 'Left'; content: { session: string } };
+
+export interface FocusParams {
+	question?: string;
+}
+
+export interface CollaboratorParams {
+	username: string;
+}
+
+/** What was done to a survey, as recorded in its history. */
+export enum HistoryAction {
+	Edit = 'edit',
+	Publish = 'publish',
+	Delete = 'delete',
+	Restore = 'restore',
+	ClearResponses = 'clear_responses',
+	Revert = 'revert',
+	/** The survey was given to another user, when its owner deleted their account. */
+	Transfer = 'transfer'
+}
+
+/** The value of a field before and after a change. */
+export interface FieldChange {
+	before: any;
+	after: any;
+}
+
+/** An entry in the history of a survey. */
+export interface SurveyHistoryEntry {
+	id: number;
+	survey_id: number;
+	/** The user that did it, unless their account was deleted since. */
+	actor_id?: number;
+	action: HistoryAction;
+	changes: Record<string, FieldChange>;
+	created_at: string;
+}
+
+/**
+ * The answer to a question before and after a response was edited. An answer that is
+ * missing on one side was added or removed by the edit.
+ */
+export interface AnswerChange {
+	before?: Response;
+	after?: Response;
+}
+
+/** An edit in the history of a response. */
+export interface ResponseHistoryEntry {
+	id: number;
+	responder_uuid: string;
+	/** The revision of the response the edit made. */
+	revision: number;
+	/** The answers that changed, by question UUID. */
+	changes: Record<string, AnswerChange>;
+	created_at: string;
+}
+
+/** Someone that the owner of a survey lets edit its questions along with them. */
+export interface Collaborator {
+	user_id: number;
+	username: string;
+	created_at: string;
+}
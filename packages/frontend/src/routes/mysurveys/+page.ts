@@ -12,6 +12,6 @@ export const load = (async () => {
 	}
 
 	return {
-		surveys: response.value
+		surveys: response.value.surveys
 	};
 }) satisfies PageLoad;
@@ -1,12 +1,17 @@
-import { error } from '@sveltejs/kit';
+import { error, redirect } from '@sveltejs/kit';
 import type { PageLoad } from './$types';
 import { getSurvey, getSurveyResponse } from '$lib/api';
 import type { SurveyResponses } from '$lib/common';
 
+// access tokens for password protected surveys are only kept in the browser
+export const ssr = false;
+
 export const load = (async ({ params, fetch, url }) => {
-	const surveyId = parseInt(params.slug);
-	const response = await getSurvey(surveyId, { fetch });
+	const response = await getSurvey(params.slug, { fetch });
 	if (!response.ok) {
+		if (response.error.message === 'PasswordRequired') {
+			throw redirect(303, `/survey/${params.slug}/unlock?next=${encodeURIComponent(url.search)}`);
+		}
 		// TODO: make status codes accessible instead?
 		if (response.error.message === 'NotFound') {
 			throw error(404, 'Survey not found');
@@ -21,7 +26,7 @@ export const load = (async ({ params, fetch, url }) => {
 	if (url.searchParams.has('responder')) {
 		const responder = url.searchParams.get('responder');
 		if (!responder) throw error(400, 'responder query param must be non-empty string if it exists');
-		const response = await getSurveyResponse(surveyId, responder, { fetch });
+		const response = await getSurveyResponse(params.slug, responder, { fetch });
 		if (!response.ok) {
 			// TODO: make status codes accessible instead?
 			if (response.error.message === 'NotFound') {
@@ -37,7 +42,7 @@ export const load = (async ({ params, fetch, url }) => {
 
 	return {
 		slug: params.slug,
-		surveyId,
+		surveyId: response.value.id,
 		survey: response.value,
 		surveyResponse
 	};